@@ -22,3 +22,4 @@ macro_rules! impl_wrapping_extra {
 impl_wrapping_extra!(u8);
 impl_wrapping_extra!(u16);
 impl_wrapping_extra!(u32);
+impl_wrapping_extra!(u64);
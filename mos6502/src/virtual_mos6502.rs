@@ -42,6 +42,12 @@ pub trait Interface: Sized + Context {
         Private::set_nmi_latch( self, state )
     }
 
+    #[must_use]
+    #[inline]
+    fn nmi_latch( &self ) -> bool {
+        Private::nmi_latch( self )
+    }
+
     #[inline]
     fn set_irq_line( &mut self, state: bool ) {
         Private::set_irq_line( self, state )
@@ -953,6 +959,11 @@ trait Private: Sized + Context {
         self.state_mut().nmi_latch_state = state;
     }
 
+    #[must_use]
+    fn nmi_latch( &self ) -> bool {
+        self.state().nmi_latch_state
+    }
+
     fn set_irq_line( &mut self, state: bool ) {
         self.state_mut().irq_line_state = state;
     }
@@ -126,7 +126,9 @@ def generate_test_code_for_node name, test_tree_node, depth = 1
         output "static ROM: &'static [u8] = include_bytes!( \"#{rom_path}\" );"
         output ""
 
-        output "harness::standard_testcase::< T >( ROM, \"#{test[ :test ][ :expected_framebuffer_md5sum ]}\", #{test[ :test ][ :elapsed_frames ]} );"
+        expected_audio_md5sum = test[ :test ][ :expected_audio_md5sum ]
+        expected_audio_md5sum = expected_audio_md5sum ? "Some( \"#{expected_audio_md5sum}\" )" : "None"
+        output "harness::standard_testcase::< T >( ROM, \"#{test[ :test ][ :expected_framebuffer_md5sum ]}\", #{expected_audio_md5sum}, #{test[ :test ][ :elapsed_frames ]} );"
         output "}"
     end
 
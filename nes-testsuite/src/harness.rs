@@ -1,8 +1,6 @@
 use std::error::Error;
 
-fn md5sum< T: AsRef< [u8] > >( data: T ) -> String {
-    use md5;
-    let raw_digest = md5::compute( data.as_ref() );
+fn hex( raw_digest: md5::Digest ) -> String {
     let mut digest = String::with_capacity( 2 * 16 );
     for byte in raw_digest.iter() {
         digest.push_str( &format!( "{:02x}", byte ) );
@@ -10,13 +8,21 @@ fn md5sum< T: AsRef< [u8] > >( data: T ) -> String {
     digest
 }
 
+fn md5sum< T: AsRef< [u8] > >( data: T ) -> String {
+    hex( md5::compute( data.as_ref() ) )
+}
+
 pub struct TestcaseState {
     cycle: u64,
     frame: u64,
     running: bool,
     frame_limit: u64,
     expected_framebuffer_md5sum: Option< String >,
-    framebuffer_md5sum: Option< String >
+    framebuffer_md5sum: Option< String >,
+    audio_sample_count: u64,
+    audio_hasher: Option< md5::Context >,
+    expected_audio_md5sum: Option< String >,
+    audio_md5sum: Option< String >
 }
 
 impl TestcaseState {
@@ -27,7 +33,11 @@ impl TestcaseState {
             running: true,
             frame_limit: 0,
             expected_framebuffer_md5sum: None,
-            framebuffer_md5sum: None
+            framebuffer_md5sum: None,
+            audio_sample_count: 0,
+            audio_hasher: None,
+            expected_audio_md5sum: None,
+            audio_md5sum: None
         }
     }
 }
@@ -40,6 +50,21 @@ pub trait EmulatorInterface: Sized {
     fn get_framebuffer( &mut self, output: &mut [u8] );
 
     fn testcase_state( &mut self ) -> &mut TestcaseState;
+
+    // Should be called for every generated audio sample. The samples are expected
+    // to be already quantized to 16-bit, so that the hashes are not affected by
+    // insignificant floating point noise.
+    fn on_audio_sample( &mut self, sample: i16 ) {
+        let state = self.testcase_state();
+        if state.running == false {
+            return;
+        }
+
+        state.audio_sample_count += 1;
+        if let Some( ref mut hasher ) = state.audio_hasher {
+            hasher.consume( &sample.to_le_bytes() );
+        }
+    }
 }
 
 #[inline]
@@ -62,14 +87,26 @@ pub fn on_frame< T: EmulatorInterface >( emulator: &mut T ) {
         let mut framebuffer = [0; 256 * 240];
         emulator.get_framebuffer( &mut framebuffer );
         emulator.testcase_state().framebuffer_md5sum = Some( md5sum( &framebuffer[..] ) );
+
+        if let Some( hasher ) = emulator.testcase_state().audio_hasher.take() {
+            emulator.testcase_state().audio_md5sum = Some( hex( hasher.compute() ) );
+        }
     }
 }
 
-pub fn standard_testcase< T: EmulatorInterface >( rom: &'static [u8], expected_framebuffer_md5sum: &str, frame_limit: u64 ) {
+pub fn standard_testcase< T: EmulatorInterface >( rom: &'static [u8], expected_framebuffer_md5sum: &str, expected_audio_md5sum: Option< &str >, frame_limit: u64 ) {
     let mut state = TestcaseState::new();
     state.frame_limit = frame_limit;
     state.expected_framebuffer_md5sum = Some( expected_framebuffer_md5sum.to_owned() );
+    if let Some( expected_audio_md5sum ) = expected_audio_md5sum {
+        state.expected_audio_md5sum = Some( expected_audio_md5sum.to_owned() );
+        state.audio_hasher = Some( md5::Context::new() );
+    }
+
+    run_testcase::< T >( rom, state );
+}
 
+fn run_testcase< T: EmulatorInterface >( rom: &'static [u8], state: TestcaseState ) {
     let mut interface = T::new( rom, state ).unwrap();
     while interface.testcase_state().running {
         let cycles_before = interface.testcase_state().cycle;
@@ -84,4 +121,12 @@ pub fn standard_testcase< T: EmulatorInterface >( rom: &'static [u8], expected_f
     let expected_framebuffer_md5sum = interface.testcase_state().expected_framebuffer_md5sum.take();
     let actual_framebuffer_md5sum = interface.testcase_state().framebuffer_md5sum.take();
     assert_eq!( expected_framebuffer_md5sum, actual_framebuffer_md5sum );
+
+    if interface.testcase_state().expected_audio_md5sum.is_some() {
+        assert!( interface.testcase_state().audio_sample_count > 0, "You need to call EmulatorInterface::on_audio_sample for every audio sample!" );
+
+        let expected_audio_md5sum = interface.testcase_state().expected_audio_md5sum.take();
+        let actual_audio_md5sum = interface.testcase_state().audio_md5sum.take();
+        assert_eq!( expected_audio_md5sum, actual_audio_md5sum );
+    }
 }
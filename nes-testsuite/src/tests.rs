@@ -6,42 +6,42 @@ pub mod apu_test {
     pub fn testcase_1_len_ctr< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/apu_test/1-len_ctr.nes" );
 
-        harness::standard_testcase::< T >( ROM, "a6a60165f8a7b10d1d2036e007ced46c", 19 );
+        harness::standard_testcase::< T >( ROM, "a6a60165f8a7b10d1d2036e007ced46c", Some( "b4843a09e1165cc2cc410cc34c9eb9f5" ), 19 );
     }
     pub fn testcase_2_len_table< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/apu_test/2-len_table.nes" );
 
-        harness::standard_testcase::< T >( ROM, "e082be73c51e77a560a0670cb014bf7f", 15 );
+        harness::standard_testcase::< T >( ROM, "e082be73c51e77a560a0670cb014bf7f", Some( "8dc5f18a0a1a72138325327d9894d170" ), 15 );
     }
     pub fn testcase_3_irq_flag< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/apu_test/3-irq_flag.nes" );
 
-        harness::standard_testcase::< T >( ROM, "df67bf9e0aa4961ce02ee3eb701d769d", 19 );
+        harness::standard_testcase::< T >( ROM, "df67bf9e0aa4961ce02ee3eb701d769d", Some( "f2c8b1124007702a3a92239816aa47bc" ), 19 );
     }
     pub fn testcase_4_jitter< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/apu_test/4-jitter.nes" );
 
-        harness::standard_testcase::< T >( ROM, "5511965c38804478a2a2fd5e0ba14f95", 18 );
+        harness::standard_testcase::< T >( ROM, "5511965c38804478a2a2fd5e0ba14f95", Some( "9164405e7f25e08afb82e4ec068dcfea" ), 18 );
     }
     pub fn testcase_5_len_timing< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/apu_test/5-len_timing.nes" );
 
-        harness::standard_testcase::< T >( ROM, "9416144226b82e3b7251d9ae933001c6", 112 );
+        harness::standard_testcase::< T >( ROM, "9416144226b82e3b7251d9ae933001c6", Some( "2bbc74e7584006023c1ce9df3eece077" ), 112 );
     }
     pub fn testcase_6_irq_flag_timing< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/apu_test/6-irq_flag_timing.nes" );
 
-        harness::standard_testcase::< T >( ROM, "b474f7a1ca18a1c045be117d725b80eb", 20 );
+        harness::standard_testcase::< T >( ROM, "b474f7a1ca18a1c045be117d725b80eb", Some( "c4103bab1e09a2d18ec9789adc4b0fbf" ), 20 );
     }
     pub fn testcase_7_dmc_basics< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/apu_test/7-dmc_basics.nes" );
 
-        harness::standard_testcase::< T >( ROM, "3d7a08151b0d82655e5d81636851d9ef", 23 );
+        harness::standard_testcase::< T >( ROM, "3d7a08151b0d82655e5d81636851d9ef", Some( "eaa91eeb4453e413e28de4e378e1388a" ), 23 );
     }
     pub fn testcase_8_dmc_rates< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/apu_test/8-dmc_rates.nes" );
 
-        harness::standard_testcase::< T >( ROM, "c5f193bb4fd2e53b4961ce5b9931804e", 27 );
+        harness::standard_testcase::< T >( ROM, "c5f193bb4fd2e53b4961ce5b9931804e", Some( "2ce99c263709d668bee1f2c92041cf35" ), 27 );
     }
 }
 pub mod blargg_apu_2005_07_30 {
@@ -49,37 +49,37 @@ pub mod blargg_apu_2005_07_30 {
     pub fn testcase_01_len_ctr< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/blargg_apu_2005.07.30/01.len_ctr.nes" );
 
-        harness::standard_testcase::< T >( ROM, "0941a56e4c62c6026264952a9bfaea35", 25 );
+        harness::standard_testcase::< T >( ROM, "0941a56e4c62c6026264952a9bfaea35", Some( "2843705b0f8a4b4342edaa784d953e0d" ), 25 );
     }
     pub fn testcase_02_len_table< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/blargg_apu_2005.07.30/02.len_table.nes" );
 
-        harness::standard_testcase::< T >( ROM, "0941a56e4c62c6026264952a9bfaea35", 12 );
+        harness::standard_testcase::< T >( ROM, "0941a56e4c62c6026264952a9bfaea35", Some( "a91ea687a6abaa7925f7d29c190b4907" ), 12 );
     }
     pub fn testcase_03_irq_flag< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/blargg_apu_2005.07.30/03.irq_flag.nes" );
 
-        harness::standard_testcase::< T >( ROM, "0941a56e4c62c6026264952a9bfaea35", 17 );
+        harness::standard_testcase::< T >( ROM, "0941a56e4c62c6026264952a9bfaea35", Some( "c5d88635b589380fe62144ec3d670f60" ), 17 );
     }
     pub fn testcase_04_clock_jitter< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/blargg_apu_2005.07.30/04.clock_jitter.nes" );
 
-        harness::standard_testcase::< T >( ROM, "0941a56e4c62c6026264952a9bfaea35", 16 );
+        harness::standard_testcase::< T >( ROM, "0941a56e4c62c6026264952a9bfaea35", Some( "3218e988df35dcbfdcc6936d28baaf9c" ), 16 );
     }
     pub fn testcase_05_len_timing_mode0< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/blargg_apu_2005.07.30/05.len_timing_mode0.nes" );
 
-        harness::standard_testcase::< T >( ROM, "0941a56e4c62c6026264952a9bfaea35", 21 );
+        harness::standard_testcase::< T >( ROM, "0941a56e4c62c6026264952a9bfaea35", Some( "ce9baef881fe1401c67d2bbe8cc02e45" ), 21 );
     }
     pub fn testcase_06_len_timing_mode1< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/blargg_apu_2005.07.30/06.len_timing_mode1.nes" );
 
-        harness::standard_testcase::< T >( ROM, "0941a56e4c62c6026264952a9bfaea35", 24 );
+        harness::standard_testcase::< T >( ROM, "0941a56e4c62c6026264952a9bfaea35", Some( "55035c6d171792ecded326ec146053e1" ), 24 );
     }
     pub fn testcase_07_irq_flag_timing< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/blargg_apu_2005.07.30/07.irq_flag_timing.nes" );
 
-        harness::standard_testcase::< T >( ROM, "0941a56e4c62c6026264952a9bfaea35", 18 );
+        harness::standard_testcase::< T >( ROM, "0941a56e4c62c6026264952a9bfaea35", Some( "65002f8516e1f8200f5b0e8e2a0910ba" ), 18 );
     }
 }
 pub mod blargg_ppu_tests_2005_09_15b {
@@ -87,17 +87,17 @@ pub mod blargg_ppu_tests_2005_09_15b {
     pub fn testcase_palette_ram< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/blargg_ppu_tests_2005.09.15b/palette_ram.nes" );
 
-        harness::standard_testcase::< T >( ROM, "0941a56e4c62c6026264952a9bfaea35", 20 );
+        harness::standard_testcase::< T >( ROM, "0941a56e4c62c6026264952a9bfaea35", None, 20 );
     }
     pub fn testcase_vbl_clear_time< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/blargg_ppu_tests_2005.09.15b/vbl_clear_time.nes" );
 
-        harness::standard_testcase::< T >( ROM, "0941a56e4c62c6026264952a9bfaea35", 25 );
+        harness::standard_testcase::< T >( ROM, "0941a56e4c62c6026264952a9bfaea35", None, 25 );
     }
     pub fn testcase_vram_access< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/blargg_ppu_tests_2005.09.15b/vram_access.nes" );
 
-        harness::standard_testcase::< T >( ROM, "0941a56e4c62c6026264952a9bfaea35", 20 );
+        harness::standard_testcase::< T >( ROM, "0941a56e4c62c6026264952a9bfaea35", None, 20 );
     }
 }
 pub mod branch_timing_tests {
@@ -105,17 +105,17 @@ pub mod branch_timing_tests {
     pub fn testcase_1_branch_basics< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/branch_timing_tests/1.Branch_Basics.nes" );
 
-        harness::standard_testcase::< T >( ROM, "d0ba5841977f79774975eded5685d18c", 13 );
+        harness::standard_testcase::< T >( ROM, "d0ba5841977f79774975eded5685d18c", None, 13 );
     }
     pub fn testcase_2_backward_branch< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/branch_timing_tests/2.Backward_Branch.nes" );
 
-        harness::standard_testcase::< T >( ROM, "f8fedc8a1651b25f4f08ff40e6ece447", 16 );
+        harness::standard_testcase::< T >( ROM, "f8fedc8a1651b25f4f08ff40e6ece447", None, 16 );
     }
     pub fn testcase_3_forward_branch< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/branch_timing_tests/3.Forward_Branch.nes" );
 
-        harness::standard_testcase::< T >( ROM, "9e4f736ac2ce791126ce2e6c3174b930", 15 );
+        harness::standard_testcase::< T >( ROM, "9e4f736ac2ce791126ce2e6c3174b930", None, 15 );
     }
 }
 pub mod dmc_dma_during_read4 {
@@ -123,7 +123,7 @@ pub mod dmc_dma_during_read4 {
     pub fn testcase_read_write_2007< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/dmc_dma_during_read4/read_write_2007.nes" );
 
-        harness::standard_testcase::< T >( ROM, "289318c88b069a9e5a2f98ab4c283622", 18 );
+        harness::standard_testcase::< T >( ROM, "289318c88b069a9e5a2f98ab4c283622", None, 18 );
     }
 }
 pub mod holy_diver_batman {
@@ -131,7 +131,7 @@ pub mod holy_diver_batman {
     pub fn testcase_m1_p128k_c128k_w8k< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/holy_diver_batman/M1_P128K_C128K_W8K.nes" );
 
-        harness::standard_testcase::< T >( ROM, "6fca84256a8b97bd5cfa920365a9fd3b", 84 );
+        harness::standard_testcase::< T >( ROM, "6fca84256a8b97bd5cfa920365a9fd3b", None, 84 );
     }
 }
 pub mod instr_misc {
@@ -139,17 +139,17 @@ pub mod instr_misc {
     pub fn testcase_01_abs_x_wrap< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/instr_misc/01-abs_x_wrap.nes" );
 
-        harness::standard_testcase::< T >( ROM, "668c9056dd5ebd4323c8731394afe88a", 11 );
+        harness::standard_testcase::< T >( ROM, "668c9056dd5ebd4323c8731394afe88a", None, 11 );
     }
     pub fn testcase_02_branch_wrap< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/instr_misc/02-branch_wrap.nes" );
 
-        harness::standard_testcase::< T >( ROM, "167f0376a521b5e6bbfdf3e4d5e142ff", 11 );
+        harness::standard_testcase::< T >( ROM, "167f0376a521b5e6bbfdf3e4d5e142ff", None, 11 );
     }
     pub fn testcase_03_dummy_reads< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/instr_misc/03-dummy_reads.nes" );
 
-        harness::standard_testcase::< T >( ROM, "e4b3faaf58413bedc1b7df11a6a8cc3f", 58 );
+        harness::standard_testcase::< T >( ROM, "e4b3faaf58413bedc1b7df11a6a8cc3f", None, 58 );
     }
 }
 pub mod oam_read {
@@ -157,7 +157,7 @@ pub mod oam_read {
     pub fn testcase_oam_read< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/oam_read/oam_read.nes" );
 
-        harness::standard_testcase::< T >( ROM, "c9b29cadea705ba03162b95030da598f", 34 );
+        harness::standard_testcase::< T >( ROM, "c9b29cadea705ba03162b95030da598f", None, 34 );
     }
 }
 pub mod sprite_hit_tests_2005_10_05 {
@@ -165,57 +165,57 @@ pub mod sprite_hit_tests_2005_10_05 {
     pub fn testcase_01_basics< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/sprite_hit_tests_2005.10.05/01.basics.nes" );
 
-        harness::standard_testcase::< T >( ROM, "388ab951797a627282c6a43b8aca0692", 33 );
+        harness::standard_testcase::< T >( ROM, "388ab951797a627282c6a43b8aca0692", None, 33 );
     }
     pub fn testcase_02_alignment< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/sprite_hit_tests_2005.10.05/02.alignment.nes" );
 
-        harness::standard_testcase::< T >( ROM, "efe0e20c14ae43c7b3c511d2ab08950c", 31 );
+        harness::standard_testcase::< T >( ROM, "efe0e20c14ae43c7b3c511d2ab08950c", None, 31 );
     }
     pub fn testcase_03_corners< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/sprite_hit_tests_2005.10.05/03.corners.nes" );
 
-        harness::standard_testcase::< T >( ROM, "7ff7c9bc044afd3855c82da016504423", 21 );
+        harness::standard_testcase::< T >( ROM, "7ff7c9bc044afd3855c82da016504423", None, 21 );
     }
     pub fn testcase_04_flip< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/sprite_hit_tests_2005.10.05/04.flip.nes" );
 
-        harness::standard_testcase::< T >( ROM, "c1bc9a362d15c196efd55704c33f7fc9", 19 );
+        harness::standard_testcase::< T >( ROM, "c1bc9a362d15c196efd55704c33f7fc9", None, 19 );
     }
     pub fn testcase_05_left_clip< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/sprite_hit_tests_2005.10.05/05.left_clip.nes" );
 
-        harness::standard_testcase::< T >( ROM, "e8ab4d728f702d0ad1c2c00e8208dc38", 29 );
+        harness::standard_testcase::< T >( ROM, "e8ab4d728f702d0ad1c2c00e8208dc38", None, 29 );
     }
     pub fn testcase_06_right_edge< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/sprite_hit_tests_2005.10.05/06.right_edge.nes" );
 
-        harness::standard_testcase::< T >( ROM, "cd8c9e59befaa11d5a261492fe0d2125", 23 );
+        harness::standard_testcase::< T >( ROM, "cd8c9e59befaa11d5a261492fe0d2125", None, 23 );
     }
     pub fn testcase_07_screen_bottom< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/sprite_hit_tests_2005.10.05/07.screen_bottom.nes" );
 
-        harness::standard_testcase::< T >( ROM, "6e946157b0a4575ad28dd77d6a0998bd", 24 );
+        harness::standard_testcase::< T >( ROM, "6e946157b0a4575ad28dd77d6a0998bd", None, 24 );
     }
     pub fn testcase_08_double_height< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/sprite_hit_tests_2005.10.05/08.double_height.nes" );
 
-        harness::standard_testcase::< T >( ROM, "b8cb45dfdf1c7fed572aa910d03c48db", 20 );
+        harness::standard_testcase::< T >( ROM, "b8cb45dfdf1c7fed572aa910d03c48db", None, 20 );
     }
     pub fn testcase_09_timing_basics< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/sprite_hit_tests_2005.10.05/09.timing_basics.nes" );
 
-        harness::standard_testcase::< T >( ROM, "ab27927fd0393001a12acd99d13020cc", 63 );
+        harness::standard_testcase::< T >( ROM, "ab27927fd0393001a12acd99d13020cc", None, 63 );
     }
     pub fn testcase_10_timing_order< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/sprite_hit_tests_2005.10.05/10.timing_order.nes" );
 
-        harness::standard_testcase::< T >( ROM, "1fa4915b3c7ef833bdc5b7a1c7d77055", 59 );
+        harness::standard_testcase::< T >( ROM, "1fa4915b3c7ef833bdc5b7a1c7d77055", None, 59 );
     }
     pub fn testcase_11_edge_timing< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/sprite_hit_tests_2005.10.05/11.edge_timing.nes" );
 
-        harness::standard_testcase::< T >( ROM, "1cc6471b69a9b850851ca2c127261dfc", 54 );
+        harness::standard_testcase::< T >( ROM, "1cc6471b69a9b850851ca2c127261dfc", None, 54 );
     }
 }
 pub mod sprite_hit_timing {
@@ -223,7 +223,7 @@ pub mod sprite_hit_timing {
     pub fn testcase_sprite_hit_timing< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/sprite_hit_timing/sprite_hit_timing.nes" );
 
-        harness::standard_testcase::< T >( ROM, "ffe8f3026500a839ba7d528ac52d21b0", 92 );
+        harness::standard_testcase::< T >( ROM, "ffe8f3026500a839ba7d528ac52d21b0", None, 92 );
     }
 }
 pub mod vbl_nmi_timing {
@@ -231,37 +231,37 @@ pub mod vbl_nmi_timing {
     pub fn testcase_1_frame_basics< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/vbl_nmi_timing/1.frame_basics.nes" );
 
-        harness::standard_testcase::< T >( ROM, "b6c0c4c438349b0f37df7df15679f103", 177 );
+        harness::standard_testcase::< T >( ROM, "b6c0c4c438349b0f37df7df15679f103", None, 177 );
     }
     pub fn testcase_2_vbl_timing< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/vbl_nmi_timing/2.vbl_timing.nes" );
 
-        harness::standard_testcase::< T >( ROM, "4390eee80911ddcef3c5ba633ba96780", 152 );
+        harness::standard_testcase::< T >( ROM, "4390eee80911ddcef3c5ba633ba96780", None, 152 );
     }
     pub fn testcase_3_even_odd_frames< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/vbl_nmi_timing/3.even_odd_frames.nes" );
 
-        harness::standard_testcase::< T >( ROM, "cfa81bdc8309c4ff06f75fd1cc87d3e0", 97 );
+        harness::standard_testcase::< T >( ROM, "cfa81bdc8309c4ff06f75fd1cc87d3e0", None, 97 );
     }
     pub fn testcase_4_vbl_clear_timing< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/vbl_nmi_timing/4.vbl_clear_timing.nes" );
 
-        harness::standard_testcase::< T >( ROM, "c0e78f389727610faf0bf868fc0edac9", 115 );
+        harness::standard_testcase::< T >( ROM, "c0e78f389727610faf0bf868fc0edac9", None, 115 );
     }
     pub fn testcase_5_nmi_suppression< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/vbl_nmi_timing/5.nmi_suppression.nes" );
 
-        harness::standard_testcase::< T >( ROM, "2ff7a07783430542eff3169942a4e617", 164 );
+        harness::standard_testcase::< T >( ROM, "2ff7a07783430542eff3169942a4e617", None, 164 );
     }
     pub fn testcase_6_nmi_disable< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/vbl_nmi_timing/6.nmi_disable.nes" );
 
-        harness::standard_testcase::< T >( ROM, "45e1c4cf3394f6ba15083b5ab25e72ff", 107 );
+        harness::standard_testcase::< T >( ROM, "45e1c4cf3394f6ba15083b5ab25e72ff", None, 107 );
     }
     pub fn testcase_7_nmi_timing< T: harness::EmulatorInterface >() {
         static ROM: &'static [u8] = include_bytes!( "../roms/vbl_nmi_timing/7.nmi_timing.nes" );
 
-        harness::standard_testcase::< T >( ROM, "bce0c0c99010f60d94a9c80f3f7532b3", 107 );
+        harness::standard_testcase::< T >( ROM, "bce0c0c99010f60d94a9c80f3f7532b3", None, 107 );
     }
 }
 
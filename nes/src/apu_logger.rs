@@ -0,0 +1,338 @@
+use alloc::vec::Vec;
+
+/*
+    A logger for the writes to the APU registers, meant for ripping music.
+
+    Every write to 0x4000...0x4017 is recorded with the CPU cycle on which
+    it happened, along with every byte the DMC channel fetches from memory.
+    The log can then be exported as a VGM file which uses the NES APU
+    command set; the bytes fetched by the DMC are embedded into the file
+    as NES APU RAM data blocks, so the samples can also be played back.
+
+    The VGM's timeline starts at the first event logged.
+*/
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ApuRegisterWrite {
+    pub cpu_cycle: u64,
+    pub address: u16,
+    pub value: u8
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct DmcSampleFetch {
+    pub cpu_cycle: u64,
+    pub address: u16,
+    pub value: u8
+}
+
+pub struct ApuLogger {
+    register_writes: Vec< ApuRegisterWrite >,
+    dmc_sample_fetches: Vec< DmcSampleFetch >
+}
+
+const NTSC_CPU_CLOCK: u64 = 1789772;
+const VGM_SAMPLE_RATE: u64 = 44100;
+const VGM_VERSION: u32 = 0x161;
+const VGM_HEADER_SIZE: usize = 0x100;
+
+const VGM_COMMAND_NES_APU_WRITE: u8 = 0xB4;
+const VGM_COMMAND_WAIT: u8 = 0x61;
+const VGM_COMMAND_WAIT_NTSC_FRAME: u8 = 0x62;
+const VGM_COMMAND_WAIT_PAL_FRAME: u8 = 0x63;
+const VGM_COMMAND_WAIT_SHORT: u8 = 0x70;
+const VGM_COMMAND_END: u8 = 0x66;
+const VGM_COMMAND_DATA_BLOCK: u8 = 0x67;
+const VGM_DATA_BLOCK_NES_APU_RAM: u8 = 0xC2;
+
+// The DMC can only fetch from 0x8000...0xFFFF.
+const DMC_MEMORY_BASE: u16 = 0x8000;
+const DMC_MEMORY_SIZE: usize = 0x8000;
+
+enum Event {
+    Write( ApuRegisterWrite ),
+    Fetch( DmcSampleFetch )
+}
+
+impl Event {
+    fn cpu_cycle( &self ) -> u64 {
+        match *self {
+            Event::Write( write ) => write.cpu_cycle,
+            Event::Fetch( fetch ) => fetch.cpu_cycle
+        }
+    }
+}
+
+impl ApuLogger {
+    pub fn new() -> ApuLogger {
+        ApuLogger {
+            register_writes: Vec::new(),
+            dmc_sample_fetches: Vec::new()
+        }
+    }
+
+    pub fn clear( &mut self ) {
+        self.register_writes.clear();
+        self.dmc_sample_fetches.clear();
+    }
+
+    #[inline]
+    pub fn is_empty( &self ) -> bool {
+        self.register_writes.is_empty() && self.dmc_sample_fetches.is_empty()
+    }
+
+    #[inline]
+    pub fn register_writes( &self ) -> &[ApuRegisterWrite] {
+        &self.register_writes
+    }
+
+    #[inline]
+    pub fn dmc_sample_fetches( &self ) -> &[DmcSampleFetch] {
+        &self.dmc_sample_fetches
+    }
+
+    // Meant to be called from `Context::on_apu_register_write`.
+    pub fn log_register_write( &mut self, cpu_cycle: u64, address: u16, value: u8 ) {
+        if address < 0x4000 || address > 0x4017 {
+            return;
+        }
+
+        self.register_writes.push( ApuRegisterWrite {
+            cpu_cycle: cpu_cycle,
+            address: address,
+            value: value
+        });
+    }
+
+    // Meant to be called from `Context::on_dmc_sample_fetch`.
+    pub fn log_dmc_sample_fetch( &mut self, cpu_cycle: u64, address: u16, value: u8 ) {
+        if address < DMC_MEMORY_BASE {
+            return;
+        }
+
+        self.dmc_sample_fetches.push( DmcSampleFetch {
+            cpu_cycle: cpu_cycle,
+            address: address,
+            value: value
+        });
+    }
+
+    fn events( &self ) -> Vec< Event > {
+        let mut events = Vec::with_capacity( self.register_writes.len() + self.dmc_sample_fetches.len() );
+        let mut writes = self.register_writes.iter().peekable();
+        let mut fetches = self.dmc_sample_fetches.iter().peekable();
+        loop {
+            let take_write = match (writes.peek(), fetches.peek()) {
+                (Some( write ), Some( fetch )) => write.cpu_cycle <= fetch.cpu_cycle,
+                (Some( _ ), None) => true,
+                (None, Some( _ )) => false,
+                (None, None) => break
+            };
+
+            if take_write {
+                events.push( Event::Write( *writes.next().unwrap() ) );
+            } else {
+                events.push( Event::Fetch( *fetches.next().unwrap() ) );
+            }
+        }
+
+        events
+    }
+
+    /*
+        The VGM player plays the DMC samples from its own memory, so the bytes
+        the DMC fetched need to be uploaded before the sample which uses them
+        is started. We upload them right before the last write to 0x4012, 0x4013
+        or 0x4015 which preceded the fetch. If the same address had already
+        been fetched since that write with a different value (e.g. because
+        the game switched banks in the middle of a looping sample) then
+        the new value is uploaded right before the fetch itself.
+    */
+    fn schedule_dmc_uploads( events: &[Event] ) -> Vec< Vec< (u16, u8) > > {
+        let mut uploads = Vec::with_capacity( events.len() );
+        uploads.resize( events.len(), Vec::new() );
+
+        let mut memory: Vec< Option< u8 > > = Vec::with_capacity( DMC_MEMORY_SIZE );
+        memory.resize( DMC_MEMORY_SIZE, None );
+
+        let mut fetched_in_segment: Vec< u32 > = Vec::with_capacity( DMC_MEMORY_SIZE );
+        fetched_in_segment.resize( DMC_MEMORY_SIZE, 0 );
+
+        let mut segment = 1;
+        let mut segment_start = 0;
+        for (index, event) in events.iter().enumerate() {
+            match *event {
+                Event::Write( write ) => {
+                    if write.address == 0x4012 || write.address == 0x4013 || write.address == 0x4015 {
+                        segment += 1;
+                        segment_start = index;
+                    }
+                },
+                Event::Fetch( fetch ) => {
+                    let offset = (fetch.address - DMC_MEMORY_BASE) as usize;
+                    if memory[ offset ] != Some( fetch.value ) {
+                        if fetched_in_segment[ offset ] == segment {
+                            uploads[ index ].push( (fetch.address, fetch.value) );
+                        } else {
+                            uploads[ segment_start ].push( (fetch.address, fetch.value) );
+                        }
+                        memory[ offset ] = Some( fetch.value );
+                    }
+                    fetched_in_segment[ offset ] = segment;
+                }
+            }
+        }
+
+        uploads
+    }
+
+    pub fn to_vgm( &self ) -> Vec< u8 > {
+        let events = self.events();
+        let uploads = ApuLogger::schedule_dmc_uploads( &events );
+
+        let mut output = Vec::new();
+        output.resize( VGM_HEADER_SIZE, 0 );
+
+        let start_cycle = events.first().map( |event| event.cpu_cycle() ).unwrap_or( 0 );
+        let mut emitted_samples = 0;
+        for (event, mut upload) in events.iter().zip( uploads.into_iter() ) {
+            let samples = (event.cpu_cycle() - start_cycle) * VGM_SAMPLE_RATE / NTSC_CPU_CLOCK;
+            write_wait( &mut output, samples - emitted_samples );
+            emitted_samples = samples;
+
+            upload.sort_by_key( |&(address, _)| address );
+            write_data_blocks( &mut output, &upload );
+
+            if let Event::Write( write ) = *event {
+                // 0x4014 (sprite DMA) and 0x4016 (gamepads) are not a part of the APU.
+                if write.address != 0x4014 && write.address != 0x4016 {
+                    output.push( VGM_COMMAND_NES_APU_WRITE );
+                    output.push( (write.address - 0x4000) as u8 );
+                    output.push( write.value );
+                }
+            }
+        }
+
+        output.push( VGM_COMMAND_END );
+
+        let length = output.len();
+        write_u32( &mut output[ 0x00.. ], u32::from_le_bytes( *b"Vgm " ) );
+        write_u32( &mut output[ 0x04.. ], (length - 0x04) as u32 ); // EOF offset.
+        write_u32( &mut output[ 0x08.. ], VGM_VERSION );
+        write_u32( &mut output[ 0x18.. ], emitted_samples as u32 ); // Total number of samples.
+        write_u32( &mut output[ 0x34.. ], (VGM_HEADER_SIZE - 0x34) as u32 ); // Data offset.
+        write_u32( &mut output[ 0x84.. ], NTSC_CPU_CLOCK as u32 ); // NES APU clock.
+
+        output
+    }
+
+    #[cfg(feature = "std")]
+    pub fn save_vgm< P: AsRef< std::path::Path > >( &self, path: P ) -> std::io::Result< () > {
+        std::fs::write( path, self.to_vgm() )
+    }
+}
+
+#[inline]
+fn write_u32( output: &mut [u8], value: u32 ) {
+    output[ 0..4 ].copy_from_slice( &value.to_le_bytes() );
+}
+
+fn write_wait( output: &mut Vec< u8 >, mut samples: u64 ) {
+    while samples > 0 {
+        let chunk = if samples <= 16 {
+            output.push( VGM_COMMAND_WAIT_SHORT + (samples - 1) as u8 );
+            samples
+        } else if samples == 735 {
+            output.push( VGM_COMMAND_WAIT_NTSC_FRAME );
+            samples
+        } else if samples == 882 {
+            output.push( VGM_COMMAND_WAIT_PAL_FRAME );
+            samples
+        } else {
+            let chunk = if samples > 0xFFFF { 0xFFFF } else { samples };
+            output.push( VGM_COMMAND_WAIT );
+            output.extend_from_slice( &(chunk as u16).to_le_bytes() );
+            chunk
+        };
+
+        samples -= chunk;
+    }
+}
+
+// Writes the given (sorted) bytes as data blocks of consecutive addresses.
+fn write_data_blocks( output: &mut Vec< u8 >, bytes: &[(u16, u8)] ) {
+    let mut index = 0;
+    while index < bytes.len() {
+        let start_address = bytes[ index ].0;
+        let mut end = index + 1;
+        while end < bytes.len() && bytes[ end ].0 as usize == start_address as usize + (end - index) {
+            end += 1;
+        }
+
+        output.push( VGM_COMMAND_DATA_BLOCK );
+        output.push( VGM_COMMAND_END );
+        output.push( VGM_DATA_BLOCK_NES_APU_RAM );
+        output.extend_from_slice( &(2 + (end - index) as u32).to_le_bytes() );
+        output.extend_from_slice( &start_address.to_le_bytes() );
+        output.extend( bytes[ index..end ].iter().map( |&(_, value)| value ) );
+
+        index = end;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ApuLogger;
+
+    fn read_u32( data: &[u8], offset: usize ) -> u32 {
+        u32::from_le_bytes( [data[ offset ], data[ offset + 1 ], data[ offset + 2 ], data[ offset + 3 ]] )
+    }
+
+    #[test]
+    fn vgm_register_writes() {
+        let mut logger = ApuLogger::new();
+        logger.log_register_write( 1000, 0x4000, 0x3F );
+        logger.log_register_write( 1000, 0x4014, 0x02 );
+        logger.log_register_write( 1000 + 29830, 0x4017, 0x40 ); // One NTSC frame later.
+        logger.log_register_write( 1000 + 29830 + 406, 0x4015, 0x0F ); // Ten samples later.
+        logger.log_register_write( 1000 + 29830 + 406, 0x4020, 0x00 ); // Ignored.
+
+        let data = logger.to_vgm();
+        assert_eq!( &data[ 0..4 ], b"Vgm " );
+        assert_eq!( read_u32( &data, 0x04 ) as usize, data.len() - 4 );
+        assert_eq!( read_u32( &data, 0x08 ), 0x161 );
+        assert_eq!( read_u32( &data, 0x18 ), 735 + 10 );
+        assert_eq!( read_u32( &data, 0x34 ), 0x100 - 0x34 );
+        assert_eq!( read_u32( &data, 0x84 ), 1789772 );
+        assert_eq!( &data[ 0x100.. ], &[
+            0xB4, 0x00, 0x3F,
+            0x62,
+            0xB4, 0x17, 0x40,
+            0x79,
+            0xB4, 0x15, 0x0F,
+            0x66
+        ][..] );
+    }
+
+    #[test]
+    fn vgm_dmc_data_blocks() {
+        let mut logger = ApuLogger::new();
+        logger.log_register_write( 0, 0x4012, 0x00 );
+        logger.log_register_write( 0, 0x4013, 0x00 );
+        logger.log_register_write( 0, 0x4015, 0x10 );
+        logger.log_dmc_sample_fetch( 1, 0xC000, 0xAA );
+        logger.log_dmc_sample_fetch( 2, 0xC001, 0xBB );
+        logger.log_dmc_sample_fetch( 3, 0xC000, 0xAA ); // Looped; already uploaded.
+        logger.log_dmc_sample_fetch( 4, 0xC001, 0xCC ); // Bankswitched.
+
+        let data = logger.to_vgm();
+        assert_eq!( &data[ 0x100.. ], &[
+            0xB4, 0x12, 0x00,
+            0xB4, 0x13, 0x00,
+            0x67, 0x66, 0xC2, 0x04, 0x00, 0x00, 0x00, 0x00, 0xC0, 0xAA, 0xBB,
+            0xB4, 0x15, 0x10,
+            0x67, 0x66, 0xC2, 0x03, 0x00, 0x00, 0x00, 0x01, 0xC0, 0xCC,
+            0x66
+        ][..] );
+    }
+}
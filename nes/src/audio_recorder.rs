@@ -0,0 +1,281 @@
+use std::io::{self, Write, Seek, SeekFrom};
+use std::fs::File;
+use std::path::Path;
+
+/*
+    A simple recorder for the audio samples generated by the emulator.
+
+    The samples are generated as a single (mono) channel of 32-bit floats
+    in the -1.0...1.0 range, at the sample rate of the emulated APU.
+    They can be written either into a RIFF WAVE file or as headerless
+    (raw) little-endian PCM data.
+
+    The WAVE header is written upfront with a zero length; the length
+    fields are patched in when the recording is finished (or the
+    recorder is dropped), which is why we need the output to be seekable.
+*/
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum AudioFormat {
+    Wav16,
+    WavFloat,
+    Raw16,
+    RawFloat
+}
+
+impl AudioFormat {
+    #[inline]
+    fn has_header( self ) -> bool {
+        match self {
+            AudioFormat::Wav16 | AudioFormat::WavFloat => true,
+            AudioFormat::Raw16 | AudioFormat::RawFloat => false
+        }
+    }
+
+    #[inline]
+    fn is_float( self ) -> bool {
+        match self {
+            AudioFormat::WavFloat | AudioFormat::RawFloat => true,
+            AudioFormat::Wav16 | AudioFormat::Raw16 => false
+        }
+    }
+
+    #[inline]
+    fn bytes_per_sample( self ) -> u32 {
+        if self.is_float() { 4 } else { 2 }
+    }
+
+    pub fn extension( self ) -> &'static str {
+        match self {
+            AudioFormat::Wav16 | AudioFormat::WavFloat => "wav",
+            AudioFormat::Raw16 => "s16le",
+            AudioFormat::RawFloat => "f32le"
+        }
+    }
+}
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+
+const RIFF_HEADER_SIZE: u32 = 12;
+const FMT_CHUNK_SIZE: u32 = 8 + 16;
+const FACT_CHUNK_SIZE: u32 = 8 + 4;
+const DATA_CHUNK_HEADER_SIZE: u32 = 8;
+
+pub struct AudioRecorder< W: Write + Seek > {
+    writer: Option< W >,
+    format: AudioFormat,
+    sample_rate: u32,
+    sample_count: u32,
+    buffer: Vec< u8 >
+}
+
+impl AudioRecorder< io::BufWriter< File > > {
+    pub fn create< P: AsRef< Path > >( path: P, format: AudioFormat, sample_rate: u32 ) -> io::Result< Self > {
+        let fp = File::create( path )?;
+        AudioRecorder::new( io::BufWriter::new( fp ), format, sample_rate )
+    }
+}
+
+impl< W: Write + Seek > AudioRecorder< W > {
+    pub fn new( mut writer: W, format: AudioFormat, sample_rate: u32 ) -> io::Result< Self > {
+        if format.has_header() {
+            write_wave_header( &mut writer, format, sample_rate, 0 )?;
+        }
+
+        Ok( AudioRecorder {
+            writer: Some( writer ),
+            format: format,
+            sample_rate: sample_rate,
+            sample_count: 0,
+            buffer: Vec::new()
+        })
+    }
+
+    #[inline]
+    pub fn format( &self ) -> AudioFormat {
+        self.format
+    }
+
+    #[inline]
+    pub fn sample_rate( &self ) -> u32 {
+        self.sample_rate
+    }
+
+    #[inline]
+    pub fn sample_count( &self ) -> u32 {
+        self.sample_count
+    }
+
+    #[inline]
+    pub fn push_sample( &mut self, sample: f32 ) -> io::Result< () > {
+        self.push_samples( &[sample] )
+    }
+
+    pub fn push_samples( &mut self, samples: &[f32] ) -> io::Result< () > {
+        self.buffer.clear();
+        for &sample in samples {
+            if self.format.is_float() {
+                self.buffer.extend_from_slice( &sample.to_bits().to_le_bytes() );
+            } else {
+                self.buffer.extend_from_slice( &float_to_i16( sample ).to_le_bytes() );
+            }
+        }
+
+        let writer = match self.writer {
+            Some( ref mut writer ) => writer,
+            None => unreachable!()
+        };
+
+        writer.write_all( &self.buffer )?;
+        self.sample_count += samples.len() as u32;
+
+        Ok(())
+    }
+
+    // Finalizes the recording and returns the underlying writer.
+    pub fn finish( mut self ) -> io::Result< W > {
+        self.finalize()?;
+        Ok( self.writer.take().unwrap() )
+    }
+
+    fn finalize( &mut self ) -> io::Result< () > {
+        let writer = match self.writer {
+            Some( ref mut writer ) => writer,
+            None => return Ok(())
+        };
+
+        if self.format.has_header() {
+            let position = writer.seek( SeekFrom::Current( 0 ) )?;
+            writer.seek( SeekFrom::Start( 0 ) )?;
+            write_wave_header( writer, self.format, self.sample_rate, self.sample_count )?;
+            writer.seek( SeekFrom::Start( position ) )?;
+        }
+
+        writer.flush()
+    }
+}
+
+impl< W: Write + Seek > Drop for AudioRecorder< W > {
+    fn drop( &mut self ) {
+        let _ = self.finalize();
+    }
+}
+
+#[inline]
+pub fn float_to_i16( sample: f32 ) -> i16 {
+    if sample >= 1.0 {
+        32767
+    } else if sample <= -1.0 {
+        -32768
+    } else {
+        (sample * 32767.0) as i16
+    }
+}
+
+fn write_wave_header< W: Write >( writer: &mut W, format: AudioFormat, sample_rate: u32, sample_count: u32 ) -> io::Result< () > {
+    let bytes_per_sample = format.bytes_per_sample();
+    let data_size = sample_count * bytes_per_sample;
+
+    // Non-PCM formats are supposed to have a 'fact' chunk.
+    let extra_size = if format.is_float() { FACT_CHUNK_SIZE } else { 0 };
+    let riff_size = RIFF_HEADER_SIZE - 8 + FMT_CHUNK_SIZE + extra_size + DATA_CHUNK_HEADER_SIZE + data_size;
+
+    let format_tag = if format.is_float() { WAVE_FORMAT_IEEE_FLOAT } else { WAVE_FORMAT_PCM };
+    let channel_count: u16 = 1;
+
+    let mut header = Vec::with_capacity( 64 );
+    header.extend_from_slice( b"RIFF" );
+    header.extend_from_slice( &riff_size.to_le_bytes() );
+    header.extend_from_slice( b"WAVE" );
+
+    header.extend_from_slice( b"fmt " );
+    header.extend_from_slice( &(FMT_CHUNK_SIZE - 8).to_le_bytes() );
+    header.extend_from_slice( &format_tag.to_le_bytes() );
+    header.extend_from_slice( &channel_count.to_le_bytes() );
+    header.extend_from_slice( &sample_rate.to_le_bytes() );
+    header.extend_from_slice( &(sample_rate * bytes_per_sample * channel_count as u32).to_le_bytes() ); // Byte rate.
+    header.extend_from_slice( &(bytes_per_sample as u16 * channel_count).to_le_bytes() ); // Block align.
+    header.extend_from_slice( &(bytes_per_sample as u16 * 8).to_le_bytes() ); // Bits per sample.
+
+    if format.is_float() {
+        header.extend_from_slice( b"fact" );
+        header.extend_from_slice( &(FACT_CHUNK_SIZE - 8).to_le_bytes() );
+        header.extend_from_slice( &sample_count.to_le_bytes() );
+    }
+
+    header.extend_from_slice( b"data" );
+    header.extend_from_slice( &data_size.to_le_bytes() );
+
+    writer.write_all( &header )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AudioRecorder, AudioFormat};
+    use std::io::Cursor;
+
+    fn read_u16( data: &[u8], offset: usize ) -> u16 {
+        u16::from_le_bytes( [data[ offset ], data[ offset + 1 ]] )
+    }
+
+    fn read_u32( data: &[u8], offset: usize ) -> u32 {
+        u32::from_le_bytes( [data[ offset ], data[ offset + 1 ], data[ offset + 2 ], data[ offset + 3 ]] )
+    }
+
+    #[test]
+    fn wav_16bit() {
+        let mut recorder = AudioRecorder::new( Cursor::new( Vec::new() ), AudioFormat::Wav16, 44100 ).unwrap();
+        recorder.push_samples( &[0.0, 1.0, -1.0, 0.5] ).unwrap();
+        recorder.push_sample( 2.0 ).unwrap();
+        let data = recorder.finish().unwrap().into_inner();
+
+        assert_eq!( &data[ 0..4 ], b"RIFF" );
+        assert_eq!( read_u32( &data, 4 ) as usize, data.len() - 8 );
+        assert_eq!( &data[ 8..12 ], b"WAVE" );
+        assert_eq!( &data[ 12..16 ], b"fmt " );
+        assert_eq!( read_u16( &data, 20 ), 1 ); // PCM
+        assert_eq!( read_u16( &data, 22 ), 1 ); // Mono
+        assert_eq!( read_u32( &data, 24 ), 44100 );
+        assert_eq!( read_u32( &data, 28 ), 44100 * 2 );
+        assert_eq!( read_u16( &data, 34 ), 16 );
+        assert_eq!( &data[ 36..40 ], b"data" );
+        assert_eq!( read_u32( &data, 40 ), 5 * 2 );
+        assert_eq!( data.len(), 44 + 5 * 2 );
+
+        assert_eq!( read_u16( &data, 44 ) as i16, 0 );
+        assert_eq!( read_u16( &data, 46 ) as i16, 32767 );
+        assert_eq!( read_u16( &data, 48 ) as i16, -32768 );
+        assert_eq!( read_u16( &data, 50 ) as i16, 16383 );
+        assert_eq!( read_u16( &data, 52 ) as i16, 32767 ); // Clipped.
+    }
+
+    #[test]
+    fn wav_float() {
+        let mut recorder = AudioRecorder::new( Cursor::new( Vec::new() ), AudioFormat::WavFloat, 48000 ).unwrap();
+        recorder.push_samples( &[0.25, -0.75] ).unwrap();
+        let data = recorder.finish().unwrap().into_inner();
+
+        assert_eq!( read_u32( &data, 4 ) as usize, data.len() - 8 );
+        assert_eq!( read_u16( &data, 20 ), 3 ); // IEEE float
+        assert_eq!( read_u32( &data, 24 ), 48000 );
+        assert_eq!( read_u16( &data, 34 ), 32 );
+        assert_eq!( &data[ 36..40 ], b"fact" );
+        assert_eq!( read_u32( &data, 44 ), 2 );
+        assert_eq!( &data[ 48..52 ], b"data" );
+        assert_eq!( read_u32( &data, 52 ), 2 * 4 );
+        assert_eq!( f32::from_bits( read_u32( &data, 56 ) ), 0.25 );
+        assert_eq!( f32::from_bits( read_u32( &data, 60 ) ), -0.75 );
+    }
+
+    #[test]
+    fn raw() {
+        let mut recorder = AudioRecorder::new( Cursor::new( Vec::new() ), AudioFormat::Raw16, 44100 ).unwrap();
+        recorder.push_samples( &[1.0, -1.0] ).unwrap();
+        assert_eq!( recorder.finish().unwrap().into_inner(), vec![ 0xFF, 0x7F, 0x00, 0x80 ] );
+
+        let mut recorder = AudioRecorder::new( Cursor::new( Vec::new() ), AudioFormat::RawFloat, 44100 ).unwrap();
+        recorder.push_sample( 1.0 ).unwrap();
+        assert_eq!( recorder.finish().unwrap().into_inner(), 1.0_f32.to_bits().to_le_bytes().to_vec() );
+    }
+}
@@ -10,7 +10,7 @@ pub trait Context: Sized {
     fn is_on_odd_cycle( &self ) -> bool;
 
     fn write_sprite_list_ram( &mut self, offset: u8, value: u8 );
-    fn on_delta_modulation_dma_finished( &mut self, value: u8 );
+    fn on_delta_modulation_dma_finished( &mut self, address: u16, value: u8 );
 }
 
 pub trait Interface: Sized + Context {
@@ -139,7 +139,7 @@ trait Private: Sized + Context {
                 let value = self.fetch( address );
 
                 self.state_mut().dmc_dma_requested = false;
-                self.on_delta_modulation_dma_finished( value );
+                self.on_delta_modulation_dma_finished( address, value );
             } else if sprite_dma_ready_to_read == true {
                 self.progress_dmc_dma_if_necessary( &mut dmc_dma_progress );
                 let address = self.state().sprite_dma_source_address + sprite_dma_current_offset;
@@ -240,7 +240,7 @@ mod tests {
             self.cycle += 1;
         }
 
-        fn on_delta_modulation_dma_finished( &mut self, value: u8 ) {
+        fn on_delta_modulation_dma_finished( &mut self, _: u16, value: u8 ) {
             self.actions.push( Action::DeltaModulationDmaFinished( value ) );
         }
     }
@@ -0,0 +1,267 @@
+use core::cmp::max;
+use alloc::vec::Vec;
+
+use rom::LoadError;
+
+/*
+    The disk images of the Famicom Disk System.
+
+    An image is a sequence of disk sides of 65500 bytes each, optionally
+    preceded by a 16 byte header (starting with "FDS\x1A", followed by
+    the number of the sides). Every side contains only the blocks
+    which are stored on it:
+
+        1                 - the disk header, 56 bytes long, starting with "\x01*NINTENDO-HVC*",
+        2                 - the file count, 2 bytes long,
+        3                 - the file header, 16 bytes long; bytes 13 and 14 hold the size of the file,
+        4                 - the file data, 1 byte longer than the size from the previous file header.
+
+    On the actual disk every block is preceded by a gap and a start mark and
+    followed by a CRC, so before the drive can use a side we convert it
+    into such a raw form, and then convert it back when saving.
+*/
+
+pub const DISK_SIDE_SIZE: usize = 65500;
+
+const HEADER_SIZE: usize = 16;
+
+// The gaps are measured in bits on the disk.
+const LEADING_GAP_SIZE: usize = 28300 / 8;
+const BLOCK_GAP_SIZE: usize = 976 / 8;
+
+// Roughly how much fits on one side of a disk; at the drive's
+// speed it takes a little over six seconds to read it all.
+const RAW_DISK_SIDE_SIZE: usize = 80000;
+
+const START_MARK: u8 = 0x80;
+
+fn block_length( block_type: u8, file_size: usize ) -> Option< usize > {
+    match block_type {
+        1 => Some( 56 ),
+        2 => Some( 2 ),
+        3 => Some( 16 ),
+        4 => Some( 1 + file_size ),
+        _ => None
+    }
+}
+
+// The CRC is calculated bit by bit, with the least significant bit first.
+pub fn update_crc( crc: u16, value: u8 ) -> u16 {
+    let mut crc = crc;
+    for bit in 0..8 {
+        let carry = crc & 1 != 0;
+        crc >>= 1;
+        if carry {
+            crc ^= 0x8408;
+        }
+        if value & (1 << bit) != 0 {
+            crc ^= 0x8000;
+        }
+    }
+
+    crc
+}
+
+fn to_raw_side( side: &[u8] ) -> Vec< u8 > {
+    let mut raw = Vec::with_capacity( RAW_DISK_SIDE_SIZE );
+    raw.resize( LEADING_GAP_SIZE, 0 );
+
+    let mut position = 0;
+    let mut file_size = 0;
+    while position < side.len() {
+        let block_type = side[ position ];
+        let length = match block_length( block_type, file_size ) {
+            Some( length ) if position + length <= side.len() => length,
+            _ => break
+        };
+
+        let block = &side[ position..position + length ];
+        if block_type == 3 {
+            file_size = block[ 13 ] as usize | (block[ 14 ] as usize) << 8;
+        }
+
+        // The CRC covers the start mark and is finished by feeding two more zeros through it.
+        let crc = Some( START_MARK ).iter().chain( block.iter() ).chain( [0, 0].iter() ).fold( 0, |crc, &value| update_crc( crc, value ) );
+
+        raw.push( START_MARK );
+        raw.extend_from_slice( block );
+        raw.push( crc as u8 );
+        raw.push( (crc >> 8) as u8 );
+        raw.resize( raw.len() + BLOCK_GAP_SIZE, 0 );
+
+        position += length;
+    }
+
+    let size = max( raw.len(), RAW_DISK_SIDE_SIZE );
+    raw.resize( size, 0 );
+    raw
+}
+
+fn from_raw_side( raw: &[u8] ) -> Vec< u8 > {
+    let mut side = Vec::with_capacity( DISK_SIDE_SIZE );
+
+    let mut position = 0;
+    let mut file_size = 0;
+    loop {
+        // Skip the gap.
+        while position < raw.len() && raw[ position ] == 0 {
+            position += 1;
+        }
+
+        position += 1;
+        if position >= raw.len() {
+            break;
+        }
+
+        let block_type = raw[ position ];
+        let length = match block_length( block_type, file_size ) {
+            Some( length ) if position + length <= raw.len() && side.len() + length <= DISK_SIDE_SIZE => length,
+            _ => break
+        };
+
+        let block = &raw[ position..position + length ];
+        if block_type == 3 {
+            file_size = block[ 13 ] as usize | (block[ 14 ] as usize) << 8;
+        }
+
+        side.extend_from_slice( block );
+
+        // Skip the block and its CRC.
+        position += length + 2;
+    }
+
+    side.resize( DISK_SIDE_SIZE, 0 );
+    side
+}
+
+pub struct FdsDisk {
+    has_header: bool,
+    sides: Vec< Vec< u8 > >
+}
+
+impl FdsDisk {
+    pub fn load( data: &[u8] ) -> Result< Self, LoadError > {
+        let has_header = data.len() >= 4 && &data[ 0..4 ] == b"FDS\x1A";
+        let data = if has_header {
+            if data.len() < HEADER_SIZE {
+                return Err( LoadError::new( "unexpected end of file" ) );
+            }
+
+            &data[ HEADER_SIZE.. ]
+        } else {
+            data
+        };
+
+        if data.is_empty() || data.len() % DISK_SIDE_SIZE != 0 {
+            return Err( LoadError::new( "the size of the disk image is not a multiple of 65500 bytes" ) );
+        }
+
+        let mut sides = Vec::new();
+        for side in data.chunks( DISK_SIDE_SIZE ) {
+            if &side[ 0..15 ] != b"\x01*NINTENDO-HVC*" {
+                return Err( LoadError::new( "not a Famicom Disk System disk image" ) );
+            }
+
+            sides.push( to_raw_side( side ) );
+        }
+
+        Ok( FdsDisk {
+            has_header: has_header,
+            sides: sides
+        })
+    }
+
+    pub fn side_count( &self ) -> usize {
+        self.sides.len()
+    }
+
+    pub fn side( &self, index: usize ) -> &[u8] {
+        &self.sides[ index ]
+    }
+
+    pub fn side_mut( &mut self, index: usize ) -> &mut [u8] {
+        &mut self.sides[ index ]
+    }
+
+    // Converts the disk back into the same format it was loaded from.
+    pub fn to_image( &self ) -> Vec< u8 > {
+        let mut image = Vec::with_capacity( HEADER_SIZE + self.sides.len() * DISK_SIDE_SIZE );
+        if self.has_header {
+            image.extend_from_slice( b"FDS\x1A" );
+            image.push( self.sides.len() as u8 );
+            image.resize( HEADER_SIZE, 0 );
+        }
+
+        for raw in &self.sides {
+            image.extend_from_slice( &from_raw_side( raw ) );
+        }
+
+        image
+    }
+}
+
+// Creates a single sided disk image with one file of the given size,
+// where every byte of the file contains the lower 8 bits of its offset.
+#[cfg(test)]
+pub fn create_test_disk( file_size: usize ) -> Vec< u8 > {
+    let mut side = Vec::new();
+
+    let mut disk_header = vec![ 0; 56 ];
+    disk_header[ 0..15 ].copy_from_slice( b"\x01*NINTENDO-HVC*" );
+    side.extend_from_slice( &disk_header );
+
+    side.extend_from_slice( &[2, 1] );
+
+    let mut file_header = vec![ 0; 16 ];
+    file_header[ 0 ] = 3;
+    file_header[ 3..11 ].copy_from_slice( b"TESTFILE" );
+    file_header[ 13 ] = file_size as u8;
+    file_header[ 14 ] = (file_size >> 8) as u8;
+    side.extend_from_slice( &file_header );
+
+    side.push( 4 );
+    for offset in 0..file_size {
+        side.push( offset as u8 );
+    }
+
+    side.resize( DISK_SIDE_SIZE, 0 );
+    side
+}
+
+#[test]
+fn test_disk_image_roundtrip() {
+    let image = create_test_disk( 300 );
+    let disk = FdsDisk::load( &image ).unwrap();
+    assert_eq!( disk.side_count(), 1 );
+    assert!( disk.to_image() == image );
+
+    let mut image_with_header = b"FDS\x1A\x01".to_vec();
+    image_with_header.resize( HEADER_SIZE, 0 );
+    image_with_header.extend_from_slice( &image );
+    let disk = FdsDisk::load( &image_with_header ).unwrap();
+    assert!( disk.to_image() == image_with_header );
+}
+
+#[test]
+fn test_disk_image_conversion_to_raw() {
+    let disk = FdsDisk::load( &create_test_disk( 300 ) ).unwrap();
+    let raw = disk.side( 0 );
+    assert_eq!( raw.len(), RAW_DISK_SIDE_SIZE );
+    assert!( raw[ ..LEADING_GAP_SIZE ].iter().all( |&value| value == 0 ) );
+    assert_eq!( raw[ LEADING_GAP_SIZE ], START_MARK );
+    assert_eq!( &raw[ LEADING_GAP_SIZE + 1..LEADING_GAP_SIZE + 16 ], b"\x01*NINTENDO-HVC*" );
+
+    // When the CRC is fed through the CRC calculation the result is zero.
+    let block = &raw[ LEADING_GAP_SIZE..LEADING_GAP_SIZE + 1 + 56 + 2 ];
+    assert_eq!( block.iter().fold( 0, |crc, &value| update_crc( crc, value ) ), 0 );
+
+    let second_block = LEADING_GAP_SIZE + 1 + 56 + 2 + BLOCK_GAP_SIZE;
+    assert_eq!( &raw[ second_block..second_block + 3 ], &[START_MARK, 2, 1] );
+}
+
+#[test]
+fn test_invalid_disk_images_are_rejected() {
+    assert!( FdsDisk::load( &[] ).is_err() );
+    assert!( FdsDisk::load( &vec![ 0; DISK_SIDE_SIZE ] ).is_err() );
+    assert!( FdsDisk::load( &create_test_disk( 10 )[ ..1000 ] ).is_err() );
+}
@@ -0,0 +1,369 @@
+use core::cmp::min;
+
+use emumisc::{BitExtra, is_b6_set, is_b7_set};
+use float::{F32, u8_to_f32};
+
+// The master volume from 0x4089, in 1/36ths.
+static MASTER_VOLUME_TABLE: [u32; 4] = [36, 24, 17, 14];
+
+// How the modulation counter is adjusted for every value in the modulation table;
+// the value 4 resets the counter to zero instead.
+static MODULATION_ADJUSTMENT_TABLE: [i32; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+
+/*
+    The gain of both the volume and of the modulation unit is either set directly,
+    or is slowly increased or decreased up to 32 or down to 0 by an envelope.
+*/
+struct Envelope {
+    speed: u8,
+    is_increasing: bool,
+    is_disabled: bool,
+    gain: u8,
+    timer: u32
+}
+
+impl Envelope {
+    const fn new() -> Envelope {
+        Envelope {
+            speed: 0,
+            is_increasing: false,
+            is_disabled: true,
+            gain: 0,
+            timer: 0
+        }
+    }
+
+    fn poke_control( &mut self, value: u8, master_speed: u8 ) {
+        self.speed = value.get_bits( 0b0011_1111 );
+        self.is_increasing = is_b6_set( value );
+        self.is_disabled = is_b7_set( value );
+        if self.is_disabled {
+            self.gain = self.speed;
+        }
+
+        self.reset_timer( master_speed );
+    }
+
+    fn reset_timer( &mut self, master_speed: u8 ) {
+        self.timer = 8 * (self.speed as u32 + 1) * master_speed as u32;
+    }
+
+    fn clock( &mut self, master_speed: u8 ) -> bool {
+        if self.is_disabled || master_speed == 0 {
+            return false;
+        }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+
+        if self.timer > 0 {
+            return false;
+        }
+
+        self.reset_timer( master_speed );
+        if self.is_increasing && self.gain < 32 {
+            self.gain += 1;
+        } else if self.is_increasing == false && self.gain > 0 {
+            self.gain -= 1;
+        }
+
+        true
+    }
+}
+
+/*
+    The sound channel of the Famicom Disk System; a single channel which plays a 64 step
+    wavetable of 6-bit samples, with its pitch modulated by a second 64 step table.
+
+    The registers are:
+
+        0x4040 - 0x407F   - the wavetable; only writable when enabled by 0x4089,
+        0x4080            - the volume envelope; bits 0 - 5 - speed (or the gain if disabled),
+                            bit 6 - increase (1) or decrease (0), bit 7 - disable,
+        0x4082            - bits 0 - 7 of the frequency,
+        0x4083            - bits 0 - 3 - bits 8 - 11 of the frequency,
+                            bit 6 - halt both envelopes, bit 7 - halt the wave and reset its position,
+        0x4084            - the modulation envelope; the same as 0x4080,
+        0x4085            - the 7-bit signed modulation counter,
+        0x4086            - bits 0 - 7 of the modulation frequency,
+        0x4087            - bits 0 - 3 - bits 8 - 11 of the modulation frequency,
+                            bit 7 - halt the modulation,
+        0x4088            - appends a 3-bit value twice to the modulation table;
+                            only works when the modulation is halted,
+        0x4089            - bits 0 - 1 - master volume, bit 7 - enables writes to the wavetable,
+        0x408A            - the speed multiplier of the envelopes.
+
+    The gains of the envelopes can be read at 0x4090 and 0x4092.
+*/
+pub struct FdsAudio {
+    wavetable: [u8; 64],
+    wave_position: u8,
+    wave_accumulator: u16,
+    frequency: u16,
+    is_wave_halted: bool,
+    is_wavetable_writable: bool,
+
+    modulation_table: [u8; 64],
+    modulation_position: u8,
+    modulation_accumulator: u16,
+    modulation_frequency: u16,
+    modulation_counter: i32,
+    modulation_pitch: i32,
+    is_modulation_halted: bool,
+
+    volume_envelope: Envelope,
+    modulation_envelope: Envelope,
+    are_envelopes_halted: bool,
+    envelope_speed: u8,
+
+    master_volume: u8,
+    output: u8
+}
+
+impl FdsAudio {
+    pub const fn new() -> FdsAudio {
+        FdsAudio {
+            wavetable: [0; 64],
+            wave_position: 0,
+            wave_accumulator: 0,
+            frequency: 0,
+            is_wave_halted: true,
+            is_wavetable_writable: false,
+
+            modulation_table: [0; 64],
+            modulation_position: 0,
+            modulation_accumulator: 0,
+            modulation_frequency: 0,
+            modulation_counter: 0,
+            modulation_pitch: 0,
+            is_modulation_halted: true,
+
+            volume_envelope: Envelope::new(),
+            modulation_envelope: Envelope::new(),
+            are_envelopes_halted: false,
+            envelope_speed: 0xE8,
+
+            master_volume: 0,
+            output: 0
+        }
+    }
+
+    pub fn peek_register( &self, address: u16 ) -> u8 {
+        match address {
+            0x4040..=0x407F => {
+                if self.is_wavetable_writable {
+                    self.wavetable[ (address & 0x3F) as usize ]
+                } else {
+                    self.wavetable[ self.wave_position as usize ]
+                }
+            },
+            0x4090 => self.volume_envelope.gain,
+            0x4092 => self.modulation_envelope.gain,
+            _ => (address >> 8) as u8
+        }
+    }
+
+    pub fn poke_register( &mut self, address: u16, value: u8 ) {
+        match address {
+            0x4040..=0x407F => {
+                if self.is_wavetable_writable {
+                    self.wavetable[ (address & 0x3F) as usize ] = value.get_bits( 0b0011_1111 );
+                }
+            },
+            0x4080 => self.volume_envelope.poke_control( value, self.envelope_speed ),
+            0x4082 => self.frequency.replace_bits( 0x00FF, value as u16 ),
+            0x4083 => {
+                self.frequency.replace_bits( 0x0F00, value.get_bits( 0b0000_1111 ) as u16 );
+                self.are_envelopes_halted = is_b6_set( value );
+                self.is_wave_halted = is_b7_set( value );
+                if self.is_wave_halted {
+                    self.wave_position = 0;
+                    self.wave_accumulator = 0;
+                }
+
+                if self.are_envelopes_halted {
+                    self.volume_envelope.reset_timer( self.envelope_speed );
+                    self.modulation_envelope.reset_timer( self.envelope_speed );
+                }
+            },
+            0x4084 => self.modulation_envelope.poke_control( value, self.envelope_speed ),
+            0x4085 => self.set_modulation_counter( value.get_bits( 0b0111_1111 ) as i32 ),
+            0x4086 => self.modulation_frequency.replace_bits( 0x00FF, value as u16 ),
+            0x4087 => {
+                self.modulation_frequency.replace_bits( 0x0F00, value.get_bits( 0b0000_1111 ) as u16 );
+                self.is_modulation_halted = is_b7_set( value );
+                if self.is_modulation_halted {
+                    self.modulation_accumulator = 0;
+                }
+            },
+            0x4088 => {
+                if self.is_modulation_halted {
+                    let value = value.get_bits( 0b0000_0111 );
+                    self.modulation_table[ self.modulation_position as usize ] = value;
+                    self.modulation_table[ (self.modulation_position + 1) as usize & 0x3F ] = value;
+                    self.modulation_position = (self.modulation_position + 2) & 0x3F;
+                }
+            },
+            0x4089 => {
+                self.master_volume = value.get_bits( 0b0000_0011 );
+                self.is_wavetable_writable = is_b7_set( value );
+            },
+            0x408A => self.envelope_speed = value,
+            _ => {}
+        }
+    }
+
+    // The counter is a 7-bit signed value.
+    fn set_modulation_counter( &mut self, value: i32 ) {
+        self.modulation_counter = ((value + 64) & 0x7F) - 64;
+    }
+
+    fn update_modulation_pitch( &mut self ) {
+        // This is how the hardware does it, rounding included.
+        let mut value = self.modulation_counter * self.modulation_envelope.gain as i32;
+        let remainder = value & 0x0F;
+        value >>= 4;
+        if remainder > 0 && value & 0x80 == 0 {
+            value += if self.modulation_counter < 0 { -1 } else { 2 };
+        }
+
+        if value >= 192 {
+            value -= 256;
+        } else if value < -64 {
+            value += 256;
+        }
+
+        value *= self.frequency as i32;
+        let remainder = value & 0x3F;
+        value >>= 6;
+        if remainder >= 32 {
+            value += 1;
+        }
+
+        self.modulation_pitch = value;
+    }
+
+    fn clock_modulation( &mut self ) -> bool {
+        if self.is_modulation_halted || self.modulation_frequency == 0 {
+            return false;
+        }
+
+        let (accumulator, overflow) = self.modulation_accumulator.overflowing_add( self.modulation_frequency );
+        self.modulation_accumulator = accumulator;
+        if overflow == false {
+            return false;
+        }
+
+        let entry = self.modulation_table[ self.modulation_position as usize ];
+        if entry == 4 {
+            self.set_modulation_counter( 0 );
+        } else {
+            let counter = self.modulation_counter + MODULATION_ADJUSTMENT_TABLE[ entry as usize ];
+            self.set_modulation_counter( counter );
+        }
+
+        self.modulation_position = (self.modulation_position + 1) & 0x3F;
+        true
+    }
+
+    // Called on every CPU cycle.
+    pub fn clock( &mut self ) {
+        if self.is_wave_halted == false && self.are_envelopes_halted == false {
+            self.volume_envelope.clock( self.envelope_speed );
+            if self.modulation_envelope.clock( self.envelope_speed ) {
+                self.update_modulation_pitch();
+            }
+        }
+
+        if self.clock_modulation() {
+            self.update_modulation_pitch();
+        }
+
+        // While the wavetable is writable the output is held.
+        if self.is_wavetable_writable == false {
+            let gain = min( self.volume_envelope.gain, 32 ) as u32;
+            let level = gain * MASTER_VOLUME_TABLE[ self.master_volume as usize ];
+            self.output = (self.wavetable[ self.wave_position as usize ] as u32 * level / 1152) as u8;
+        }
+
+        if self.is_wave_halted || self.is_wavetable_writable {
+            return;
+        }
+
+        let pitch = self.frequency as i32 + self.modulation_pitch;
+        if pitch > 0 {
+            let (accumulator, overflow) = self.wave_accumulator.overflowing_add( pitch as u16 );
+            self.wave_accumulator = accumulator;
+            if overflow {
+                self.wave_position = (self.wave_position + 1) & 0x3F;
+            }
+        }
+    }
+
+    // At its loudest this is about 2.4 times as loud as a single square channel of the APU.
+    pub fn output( &self ) -> F32 {
+        u8_to_f32( self.output ) / f32!(175.0)
+    }
+}
+
+#[test]
+fn test_fds_audio_plays_the_wavetable() {
+    let mut audio = FdsAudio::new();
+
+    // A square wave at the maximum volume.
+    audio.poke_register( 0x4089, 0x80 );
+    for index in 0..64 {
+        audio.poke_register( 0x4040 + index, if index < 32 { 63 } else { 0 } );
+    }
+    audio.poke_register( 0x4089, 0x00 );
+    audio.poke_register( 0x4080, 0x80 | 32 );
+    audio.poke_register( 0x4082, 0x00 );
+    audio.poke_register( 0x4083, 0x01 );
+
+    // With the frequency of 256 the wave position advances every 256 cycles.
+    let mut outputs = [0; 64];
+    for output in outputs.iter_mut() {
+        for _ in 0..256 {
+            audio.clock();
+        }
+        *output = audio.output;
+    }
+
+    assert_eq!( outputs[ 0 ], 63 );
+    assert_eq!( outputs[ 30 ], 63 );
+    assert_eq!( outputs[ 32 ], 0 );
+    assert_eq!( outputs[ 62 ], 0 );
+
+    // Halting the wave resets its position.
+    audio.poke_register( 0x4083, 0x81 );
+    audio.clock();
+    assert_eq!( audio.wave_position, 0 );
+}
+
+#[test]
+fn test_fds_audio_modulation() {
+    let mut audio = FdsAudio::new();
+    audio.poke_register( 0x4087, 0x80 );
+    for _ in 0..32 {
+        audio.poke_register( 0x4088, 1 );
+    }
+
+    audio.poke_register( 0x4082, 0x00 );
+    audio.poke_register( 0x4083, 0x01 );
+    audio.poke_register( 0x4084, 0x80 | 32 );
+    audio.poke_register( 0x4086, 0x00 );
+    audio.poke_register( 0x4087, 0x08 );
+
+    // The modulation unit is clocked every 32 cycles, and every time the counter increases by one.
+    for _ in 0..32 * 3 {
+        audio.clock();
+    }
+
+    assert_eq!( audio.modulation_counter, 3 );
+    assert!( audio.modulation_pitch > 0 );
+
+    // The counter wraps around as a 7-bit signed value.
+    audio.poke_register( 0x4085, 0x7F );
+    assert_eq!( audio.modulation_counter, -1 );
+}
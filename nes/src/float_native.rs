@@ -18,3 +18,7 @@ pub fn u8_to_f32( value: u8 ) -> F32 {
 pub fn f32_to_u32( value: F32 ) -> u32 {
     value as u32
 }
+
+pub fn i32_to_f32( value: i32 ) -> F32 {
+    value as f32
+}
@@ -12,3 +12,14 @@ pub const fn u8_to_f32( value: u8 ) -> F32 {
 pub const fn f32_to_u32( value: F32 ) -> u32 {
     value.to_u32()
 }
+
+#[cfg(feature = "softfloat")]
+#[inline]
+pub fn i32_to_f32( value: i32 ) -> F32 {
+    let magnitude = F32::from_u32( value.unsigned_abs() );
+    if value < 0 {
+        F32::from_u32( 0 ) - magnitude
+    } else {
+        magnitude
+    }
+}
@@ -4,6 +4,7 @@ use alloc::vec::Vec;
 use emumisc::{PeekPoke, At};
 use rom::Mirroring;
 use mappers::Mapper;
+use mapper_info::{MapperInfo, BankMapping, MemoryKind, NametableMirroring};
 
 // This is a generic memory mapper that is used to simplify actual
 // emulated mappers.
@@ -17,7 +18,10 @@ pub struct GenericMapper {
     ppu_flags: [MapFlag; 12],
 
     // For maximum flexibility we just dump everything into one big vector.
-    memory: Vec< u8 >
+    memory: Vec< u8 >,
+
+    // Where every kind of memory starts in the vector; only used for debugging.
+    regions: Vec< (u32, MemoryKind) >
 }
 
 impl fmt::Debug for GenericMapper {
@@ -115,6 +119,26 @@ pub mod bank {
         Ox2C00
     }
 
+    // The banks which cover the pattern tables, in order.
+    pub static PPU_1K_PATTERN_BANKS: [PPU_1K; 8] = [
+        PPU_1K::Ox0000,
+        PPU_1K::Ox0400,
+        PPU_1K::Ox0800,
+        PPU_1K::Ox0C00,
+        PPU_1K::Ox1000,
+        PPU_1K::Ox1400,
+        PPU_1K::Ox1800,
+        PPU_1K::Ox1C00
+    ];
+
+    // The banks which cover the background tilemaps, in order.
+    pub static PPU_1K_TILEMAP_BANKS: [PPU_1K; 4] = [
+        PPU_1K::Ox2000,
+        PPU_1K::Ox2400,
+        PPU_1K::Ox2800,
+        PPU_1K::Ox2C00
+    ];
+
     #[derive(Copy, Clone, PartialEq, Eq, Debug)]
     pub enum PPU_4K {
         Ox0000 = 0,
@@ -161,7 +185,8 @@ impl GenericMapper {
             ppu_offsets: PREBAKED_PPU_OFFSETS,
             ppu_flags: [MapFlag::empty(); 12],
 
-            memory: Vec::new()
+            memory: Vec::new(),
+            regions: Vec::new()
         }
     }
 
@@ -191,8 +216,16 @@ impl GenericMapper {
         self.memory.len()
     }
 
+    // Marks that everything which is added to the memory from now on is of the given kind.
+    #[inline]
+    pub fn begin_region( &mut self, kind: MemoryKind ) {
+        let offset = self.memory.len() as u32;
+        self.regions.push( (offset, kind) );
+    }
+
     #[inline]
     pub fn initialize_save_ram( &mut self ) {
+        self.begin_region( MemoryKind::SaveRam );
         let offset = self.memory.len() as u32;
         self.extend_empty( 8 * 1024 );
         self.set_cpu_8k_bank( bank::CPU_8K::Ox6000, offset );
@@ -203,6 +236,7 @@ impl GenericMapper {
     pub fn initialize_rom( &mut self, rom: &[u8] ) -> u32 {
         assert!( rom.len() >= 16 * 1024 );
 
+        self.begin_region( MemoryKind::Rom );
         let offset = self.memory.len() as u32;
         self.extend( rom );
         self.set_cpu_lower_16k_bank( offset );
@@ -222,6 +256,7 @@ impl GenericMapper {
 
     #[inline]
     pub fn initialize_video_rom( &mut self, video_rom: &[u8] ) -> u32 {
+        self.begin_region( MemoryKind::VideoMemory );
         let offset = self.memory.len() as u32;
         if video_rom.len() > 0 {
             self.extend( video_rom );
@@ -239,6 +274,7 @@ impl GenericMapper {
 
     #[inline]
     pub fn initialize_background_tilemaps( &mut self, mirroring: Mirroring ) -> u32 {
+        self.begin_region( MemoryKind::BackgroundTilemaps );
         let offset = self.memory.len() as u32;
         match mirroring {
             Mirroring::Horizontal | Mirroring::Vertical => {
@@ -456,6 +492,21 @@ impl GenericMapper {
         self.set_ppu_4k_bank( bank::PPU_4K::Ox2000, internal_address );
     }
 
+    #[inline]
+    pub fn peek_memory( &self, internal_address: u32 ) -> u8 {
+        self.memory.peek( internal_address )
+    }
+
+    #[inline]
+    pub fn memory_region( &self, internal_address: u32, size: u32 ) -> &[u8] {
+        &self.memory[ internal_address as usize..(internal_address + size) as usize ]
+    }
+
+    #[inline]
+    pub fn memory_region_mut( &mut self, internal_address: u32, size: u32 ) -> &mut [u8] {
+        &mut self.memory[ internal_address as usize..(internal_address + size) as usize ]
+    }
+
     #[inline]
     pub fn peek_cpu_memory_space( &self, address: u16 ) -> u8 {
         let actual_address = self.translate_cpu_address( address );
@@ -493,6 +544,68 @@ impl GenericMapper {
     }
 }
 
+impl GenericMapper {
+    fn describe_bank( &self, address: u16, size: u16, offset: i32, flags: MapFlag ) -> BankMapping {
+        let mut bank = BankMapping {
+            address: address,
+            size: size,
+            memory: None,
+            offset: 0,
+            is_writable: false
+        };
+
+        if flags.contains( MapFlag::Mapped ) == false {
+            return bank;
+        }
+
+        let internal_address = (offset + address as i32) as u32;
+        let (start, kind) = self.regions.iter().rev()
+            .find( |&&(start, _)| start <= internal_address )
+            .cloned()
+            .unwrap_or( (0, MemoryKind::Other) );
+
+        bank.memory = Some( kind );
+        bank.offset = internal_address - start;
+        bank.is_writable = flags.contains( MapFlag::Writable );
+        bank
+    }
+
+    fn describe_mirroring( banks: &[BankMapping] ) -> NametableMirroring {
+        let mut pages = [0; 4];
+        for (page, bank) in pages.iter_mut().zip( banks ) {
+            if bank.memory != Some( MemoryKind::BackgroundTilemaps ) {
+                return NametableMirroring::Other;
+            }
+
+            *page = bank.offset / 1024;
+        }
+
+        match pages {
+            [0, 0, 1, 1] => NametableMirroring::Horizontal,
+            [0, 1, 0, 1] => NametableMirroring::Vertical,
+            [0, 0, 0, 0] => NametableMirroring::OnlyLowerBank,
+            [1, 1, 1, 1] => NametableMirroring::OnlyUpperBank,
+            [0, 1, 2, 3] => NametableMirroring::FourScreen,
+            _ => NametableMirroring::Other
+        }
+    }
+
+    // Describes the current mappings; the mappers then add their own registers to this.
+    pub fn describe( &self, name: &'static str, number: Option< u8 > ) -> MapperInfo {
+        let mut info = MapperInfo::new( name, number );
+        for (index, (&offset, &flags)) in self.cpu_offsets.iter().zip( self.cpu_flags.iter() ).enumerate() {
+            info.cpu_banks.push( self.describe_bank( 0x6000 + index as u16 * 0x2000, 0x2000, offset, flags ) );
+        }
+
+        for (index, (&offset, &flags)) in self.ppu_offsets.iter().zip( self.ppu_flags.iter() ).enumerate() {
+            info.ppu_banks.push( self.describe_bank( index as u16 * 0x0400, 0x0400, offset, flags ) );
+        }
+
+        info.mirroring = Self::describe_mirroring( &info.ppu_banks[ 8.. ] );
+        info
+    }
+}
+
 impl Mapper for GenericMapper {
     #[inline]
     fn peek_rom( &self, address: u16 ) -> u8 {
@@ -523,6 +636,11 @@ impl Mapper for GenericMapper {
     fn poke_video_memory( &mut self, address: u16, value: u8 ) {
         self.poke_ppu_memory_space( address, value )
     }
+
+    // On its own this is only used for the boards without a mapper.
+    fn info( &self ) -> MapperInfo {
+        self.describe( "NROM", Some( 0 ) )
+    }
 }
 
 use core::ops::Sub;
@@ -546,10 +664,12 @@ fn wraparound< T: Sub< Output = T > + PartialOrd + Copy >( limit: T, mut value:
 pub struct BankedGenericMapper {
     inner: GenericMapper,
 
+    internal_save_ram_offset: u32,
     internal_rom_bank_offset: u32,
     internal_video_rom_offset: u32,
     internal_background_tilemaps_offset: u32,
 
+    save_ram_size: u32,
     rom_size: u32,
     video_rom_size: u32,
     default_mirroring: Mirroring
@@ -560,10 +680,12 @@ impl BankedGenericMapper {
         BankedGenericMapper {
             inner: GenericMapper::new(),
 
+            internal_save_ram_offset: 0,
             internal_rom_bank_offset: 0,
             internal_video_rom_offset: 0,
             internal_background_tilemaps_offset: 0,
 
+            save_ram_size: 0,
             rom_size: 0,
             video_rom_size: 0,
             default_mirroring: Mirroring::Horizontal
@@ -572,11 +694,19 @@ impl BankedGenericMapper {
 
     pub fn from_rom( rom: NesRom ) -> Result< Self, LoadError > {
         let mut mapper = Self::empty();
+        mapper.internal_save_ram_offset = mapper.inner.total_memory_size() as u32;
         mapper.inner.initialize_save_ram();
+
+        // Some boards have more than 8kb of save RAM which is then banked.
+        if rom.save_ram_length > 8 * 1024 {
+            mapper.inner.extend_empty( rom.save_ram_length - 8 * 1024 );
+        }
+
         mapper.internal_rom_bank_offset = mapper.inner.initialize_rom( &rom.rom[..] );
         mapper.internal_video_rom_offset = mapper.inner.initialize_video_rom( &rom.video_rom[..] );
         mapper.internal_background_tilemaps_offset = mapper.inner.initialize_background_tilemaps( rom.mirroring );
 
+        mapper.save_ram_size = mapper.internal_rom_bank_offset - mapper.internal_save_ram_offset;
         mapper.rom_size = mapper.internal_video_rom_offset - mapper.internal_rom_bank_offset;
         mapper.video_rom_size = mapper.internal_background_tilemaps_offset - mapper.internal_video_rom_offset;
         mapper.default_mirroring = rom.mirroring;
@@ -604,62 +734,132 @@ impl BankedGenericMapper {
 
     #[inline]
     pub fn last_rom_16k_bank( &self ) -> u8 {
-        (self.rom_size / 16 * 1024 - 1) as u8
+        (self.rom_size / (16 * 1024) - 1) as u8
+    }
+
+    #[inline]
+    pub fn last_rom_8k_bank( &self ) -> u8 {
+        (self.rom_size / (8 * 1024) - 1) as u8
+    }
+
+    #[inline]
+    pub fn save_ram_8k_bank_count( &self ) -> u32 {
+        self.save_ram_size / (8 * 1024)
+    }
+
+    #[inline]
+    pub fn rom_16k_bank_count( &self ) -> u32 {
+        self.rom_size / (16 * 1024)
+    }
+
+    #[inline]
+    pub fn rom_8k_bank_count( &self ) -> u32 {
+        self.rom_size / (8 * 1024)
+    }
+
+    #[inline]
+    pub fn rom_32k_bank_count( &self ) -> u32 {
+        self.rom_size / (32 * 1024)
+    }
+
+    #[inline]
+    pub fn video_rom_1k_bank_count( &self ) -> u32 {
+        self.video_rom_size / 1024
+    }
+
+    #[inline]
+    pub fn video_rom_4k_bank_count( &self ) -> u32 {
+        self.video_rom_size / (4 * 1024)
     }
 
     #[inline]
-    pub fn rom_16k_bank_count( &self ) -> u8 {
-        (self.rom_size / (16 * 1024)) as u8
+    pub fn video_rom_8k_bank_count( &self ) -> u32 {
+        self.video_rom_size / (8 * 1024)
     }
 
     #[inline]
-    pub fn rom_32k_bank_count( &self ) -> u8 {
-        (self.rom_size / (32 * 1024)) as u8
+    pub fn set_cpu_save_ram_8k_bank_to_bank( &mut self, bank: u8 ) {
+        self.set_cpu_8k_bank_to_save_ram_bank( bank::CPU_8K::Ox6000, bank );
     }
 
     #[inline]
-    pub fn video_rom_4k_bank_count( &self ) -> u8 {
-        (self.video_rom_size / (4 * 1024)) as u8
+    pub fn set_cpu_8k_bank_to_save_ram_bank( &mut self, cpu_bank: bank::CPU_8K, bank: u8 ) {
+        let bank = wraparound( self.save_ram_8k_bank_count(), bank as u32 );
+        self.inner.set_cpu_8k_bank( cpu_bank, self.internal_save_ram_offset + bank * 8 * 1024 );
     }
 
     #[inline]
-    pub fn video_rom_8k_bank_count( &self ) -> u8 {
-        (self.video_rom_size / (8 * 1024)) as u8
+    pub fn set_cpu_8k_bank_to_bank( &mut self, cpu_bank: bank::CPU_8K, bank: u8 ) {
+        let bank = wraparound( self.rom_8k_bank_count(), bank as u32 );
+        self.inner.set_cpu_8k_bank( cpu_bank, self.internal_rom_bank_offset + bank * 8 * 1024 );
     }
 
     #[inline]
     pub fn set_cpu_lower_16k_bank_to_bank( &mut self, bank: u8 ) {
-        let bank = wraparound( self.rom_16k_bank_count(), bank ) as u32;
+        let bank = wraparound( self.rom_16k_bank_count(), bank as u32 );
         self.inner.set_cpu_lower_16k_bank( self.internal_rom_bank_offset + bank * 16 * 1024 );
     }
 
     #[inline]
     pub fn set_cpu_upper_16k_bank_to_bank( &mut self, bank: u8 ) {
-        let bank = wraparound( self.rom_16k_bank_count(), bank ) as u32;
+        let bank = wraparound( self.rom_16k_bank_count(), bank as u32 );
         self.inner.set_cpu_upper_16k_bank( self.internal_rom_bank_offset + bank * 16 * 1024 );
     }
 
     #[inline]
     pub fn set_cpu_32k_bank_to_bank( &mut self, bank: u8 ) {
-        let bank = wraparound( self.rom_32k_bank_count(), bank ) as u32;
+        let bank = wraparound( self.rom_32k_bank_count(), bank as u32 );
         self.inner.set_cpu_32k_bank( self.internal_rom_bank_offset + bank * 32 * 1024 );
     }
 
+    #[inline]
+    pub fn set_ppu_1k_bank_to_bank( &mut self, ppu_bank: bank::PPU_1K, bank: u16 ) {
+        let bank = wraparound( self.video_rom_1k_bank_count(), bank as u32 );
+        self.inner.set_ppu_1k_bank( ppu_bank, self.internal_video_rom_offset + bank * 1024 );
+    }
+
+    // Maps one of the two 1kb pages of the console's internal
+    // nametable RAM anywhere in the PPU's address space.
+    #[inline]
+    pub fn set_ppu_1k_bank_to_background_tilemap( &mut self, ppu_bank: bank::PPU_1K, page: u8 ) {
+        let page = (page & 1) as u32;
+        self.inner.set_ppu_1k_bank( ppu_bank, self.internal_background_tilemaps_offset + page * 1024 );
+        self.inner.set_ppu_1k_writable( ppu_bank, true );
+    }
+
+    // Reads the VROM directly, bypassing the current mapping.
+    #[inline]
+    pub fn peek_video_rom( &self, offset: u32 ) -> u8 {
+        let offset = offset % self.video_rom_size;
+        self.inner.peek_memory( self.internal_video_rom_offset + offset )
+    }
+
+    // Direct access to the whole ROM, for the boards which can reprogram it.
+    #[inline]
+    pub fn rom( &self ) -> &[u8] {
+        self.inner.memory_region( self.internal_rom_bank_offset, self.rom_size )
+    }
+
+    #[inline]
+    pub fn rom_mut( &mut self ) -> &mut [u8] {
+        self.inner.memory_region_mut( self.internal_rom_bank_offset, self.rom_size )
+    }
+
     #[inline]
     pub fn set_ppu_lower_4k_bank_to_bank( &mut self, bank: u8 ) {
-        let bank = wraparound( self.video_rom_4k_bank_count(), bank ) as u32;
+        let bank = wraparound( self.video_rom_4k_bank_count(), bank as u32 );
         self.inner.set_ppu_lower_4k_bank( self.internal_video_rom_offset + bank * 4 * 1024 );
     }
 
     #[inline]
     pub fn set_ppu_upper_4k_bank_to_bank( &mut self, bank: u8 ) {
-        let bank = wraparound( self.video_rom_4k_bank_count(), bank ) as u32;
+        let bank = wraparound( self.video_rom_4k_bank_count(), bank as u32 );
         self.inner.set_ppu_upper_4k_bank( self.internal_video_rom_offset + bank * 4 * 1024 );
     }
 
     #[inline]
     pub fn set_ppu_8k_bank_to_bank( &mut self, bank: u8 ) {
-        let bank = wraparound( self.video_rom_8k_bank_count(), bank ) as u32;
+        let bank = wraparound( self.video_rom_8k_bank_count(), bank as u32 );
         self.inner.set_ppu_8k_bank( self.internal_video_rom_offset + bank * 8 * 1024 );
     }
 
@@ -702,10 +902,84 @@ impl BankedGenericMapper {
         self.inner.set_cpu_8k_writable( bank, is_writable );
     }
 
+    pub fn describe( &self, name: &'static str, number: Option< u8 > ) -> MapperInfo {
+        self.inner.describe( name, number )
+    }
+
+    #[inline]
+    pub fn set_ppu_1k_writable( &mut self, bank: bank::PPU_1K, is_writable: bool ) {
+        self.inner.set_ppu_1k_writable( bank, is_writable );
+    }
+
     #[inline]
     pub fn set_ppu_4k_writable( &mut self, bank: bank::PPU_4K, is_writable: bool ) {
         self.inner.set_ppu_4k_writable( bank, is_writable );
     }
+
+    // On boards with bus conflicts both the CPU and the ROM drive
+    // the data bus when a register is written, so the value
+    // which ends up being written is a bitwise AND of both.
+    #[inline]
+    pub fn bus_conflict( &self, address: u16, value: u8 ) -> u8 {
+        value & self.inner.peek_cpu_memory_space( address )
+    }
+}
+
+/*
+    Creates a ROM where the first byte of every 16kb ROM bank and of every
+    4kb VROM bank contains the index of that bank, and the second byte of every
+    8kb ROM bank and of every 1kb VROM bank contains the index of that bank.
+
+    The mappers with smaller banks can use `test_rom_8k_bank` and `test_vrom_1k_bank`
+    to check which bank is mapped where.
+*/
+#[cfg(test)]
+pub fn create_test_rom( mapper: u8, rom_size: usize, video_rom_size: usize ) -> NesRom {
+    use rom::Region;
+
+    let mut rom = vec![ 0; rom_size ];
+    for bank in 0..rom_size / (16 * 1024) {
+        rom[ bank * 16 * 1024 ] = bank as u8;
+    }
+
+    for bank in 0..rom_size / (8 * 1024) {
+        rom[ bank * 8 * 1024 + 1 ] = bank as u8;
+    }
+
+    let mut video_rom = vec![ 0; video_rom_size ];
+    for bank in 0..video_rom_size / (4 * 1024) {
+        video_rom[ bank * 4 * 1024 ] = bank as u8;
+    }
+
+    for bank in 0..video_rom_size / 1024 {
+        video_rom[ bank * 1024 + 1 ] = bank as u8;
+    }
+
+    NesRom {
+        mapper: mapper,
+        submapper: 0,
+        rom: rom,
+        video_rom: video_rom,
+        save_ram_length: 8 * 1024,
+        mirroring: Mirroring::Horizontal,
+        mirroring_bit: false,
+        has_battery: false,
+        has_trainer: false,
+        region: Region::Ntsc,
+        input_device: 0
+    }
+}
+
+// The index of the 8kb bank of the ROM from `create_test_rom` mapped at the given address.
+#[cfg(test)]
+pub fn test_rom_8k_bank< M: Mapper >( mapper: &M, address: u16 ) -> u8 {
+    mapper.peek_rom( (address & !0x1FFF) + 1 )
+}
+
+// The index of the 1kb bank of the VROM from `create_test_rom` mapped at the given address.
+#[cfg(test)]
+pub fn test_vrom_1k_bank< M: Mapper >( mapper: &M, address: u16 ) -> u8 {
+    mapper.peek_video_memory( (address & !0x3FF) + 1 )
 }
 
 impl Mapper for BankedGenericMapper {
@@ -847,3 +1121,56 @@ fn test_generic_mapper_ppu_banks() {
     assert_eq!( mapper.peek_video_memory( 0x2C00 ), 5 );
     assert_eq!( mapper.peek_video_memory( 0x2C01 ), 6 );
 }
+
+#[test]
+fn test_describe_banks() {
+    let mut mapper = BankedGenericMapper::from_rom( create_test_rom( 2, 8 * 16 * 1024, 8 * 1024 ) ).unwrap();
+    mapper.set_cpu_lower_16k_bank_to_bank( 3 );
+    mapper.set_cpu_upper_16k_bank_to_bank( 7 );
+    mapper.set_vertical_mirroring();
+
+    let info = mapper.describe( "UxROM", Some( 2 ) );
+    assert_eq!( info.cpu_banks.len(), 5 );
+    assert_eq!( info.ppu_banks.len(), 12 );
+    assert_eq!( info.cpu_banks[ 0 ].memory, Some( MemoryKind::SaveRam ) );
+    assert_eq!( info.cpu_banks[ 0 ].is_writable, true );
+    assert_eq!( info.cpu_banks[ 2 ].memory, Some( MemoryKind::Rom ) );
+    assert_eq!( info.cpu_banks[ 2 ].offset, 3 * 16 * 1024 + 8 * 1024 );
+    assert_eq!( info.cpu_banks[ 2 ].is_writable, false );
+    assert_eq!( info.mirroring, NametableMirroring::Vertical );
+
+    assert_eq!( info.rom_offset( 0x6000 ), None );
+    assert_eq!( info.rom_offset( 0x8010 ), Some( 3 * 16 * 1024 + 0x10 ) );
+    assert_eq!( info.rom_offset( 0xFFFC ), Some( 8 * 16 * 1024 - 4 ) );
+    assert_eq!( info.video_memory_offset( 0x1234 ), Some( 0x1234 ) );
+    assert_eq!( info.video_memory_offset( 0x2000 ), None );
+    assert_eq!( info.file_offset( 0x8010 ), None );
+
+    let mut info = info;
+    info.rom_file_offset = Some( 16 + 512 );
+    info.video_rom_file_offset = Some( 16 + 512 + 8 * 16 * 1024 );
+    assert_eq!( info.file_offset( 0x6000 ), None );
+    assert_eq!( info.file_offset( 0x8010 ), Some( 16 + 512 + 3 * 16 * 1024 + 0x10 ) );
+    assert_eq!( info.video_file_offset( 0x1234 ), Some( 16 + 512 + 8 * 16 * 1024 + 0x1234 ) );
+
+    mapper.set_only_upper_bank_mirroring();
+    assert_eq!( mapper.describe( "UxROM", Some( 2 ) ).mirroring, NametableMirroring::OnlyUpperBank );
+}
+
+#[test]
+fn test_banked_generic_mapper_with_2mb_rom() {
+    // With 2MB there are 256 banks of 8kb which doesn't fit in an `u8`.
+    let mut mapper = BankedGenericMapper::from_rom( create_test_rom( 0, 2048 * 1024, 2048 * 1024 ) ).unwrap();
+    assert_eq!( mapper.rom_8k_bank_count(), 256 );
+    assert_eq!( mapper.video_rom_8k_bank_count(), 256 );
+    assert_eq!( mapper.last_rom_8k_bank(), 255 );
+
+    mapper.set_cpu_8k_bank_to_bank( bank::CPU_8K::Ox8000, 255 );
+    mapper.set_cpu_upper_16k_bank_to_bank( 127 );
+    mapper.set_ppu_8k_bank_to_bank( 255 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0x8000 ), 255 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0xC000 ), 254 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0xE000 ), 255 );
+    assert_eq!( test_vrom_1k_bank( &mapper, 0x0000 ), (255 * 8) as u8 );
+    assert_eq!( test_vrom_1k_bank( &mapper, 0x1C00 ), (255 * 8 + 7) as u8 );
+}
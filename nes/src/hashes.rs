@@ -0,0 +1,129 @@
+/*
+    The checksums used to identify the ROMs in the ROM database,
+    and by the PNG encoder.
+*/
+
+const fn generate_crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut value = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            value = if value & 1 != 0 { 0xEDB88320 ^ (value >> 1) } else { value >> 1 };
+            bit += 1;
+        }
+
+        table[ index ] = value;
+        index += 1;
+    }
+
+    table
+}
+
+static CRC32_TABLE: [u32; 256] = generate_crc32_table();
+
+pub fn crc32( data: &[u8] ) -> u32 {
+    let mut crc = 0xFFFFFFFF;
+    for &byte in data {
+        crc = CRC32_TABLE[ ((crc ^ byte as u32) & 0xFF) as usize ] ^ (crc >> 8);
+    }
+
+    crc ^ 0xFFFFFFFF
+}
+
+pub fn sha1( data: &[u8] ) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+    let bit_length = (data.len() as u64).wrapping_mul( 8 );
+
+    // The message is padded with a single set bit, then zeros, and then
+    // its length in bits, so that it's a multiple of 64 bytes long.
+    let padded_length = (data.len() + 9 + 63) / 64 * 64;
+    let mut block = [0; 64];
+    let mut offset = 0;
+    while offset < padded_length {
+        for (index, byte) in block.iter_mut().enumerate() {
+            let position = offset + index;
+            *byte =
+                if position < data.len() {
+                    data[ position ]
+                } else if position == data.len() {
+                    0x80
+                } else if position >= padded_length - 8 {
+                    (bit_length >> ((padded_length - 1 - position) * 8)) as u8
+                } else {
+                    0
+                };
+        }
+
+        sha1_block( &mut state, &block );
+        offset += 64;
+    }
+
+    let mut output = [0; 20];
+    for (index, word) in state.iter().enumerate() {
+        output[ index * 4..index * 4 + 4 ].copy_from_slice( &word.to_be_bytes() );
+    }
+
+    output
+}
+
+fn sha1_block( state: &mut [u32; 5], block: &[u8; 64] ) {
+    let mut words = [0u32; 80];
+    for index in 0..16 {
+        words[ index ] = u32::from_be_bytes( [block[ index * 4 ], block[ index * 4 + 1 ], block[ index * 4 + 2 ], block[ index * 4 + 3 ]] );
+    }
+
+    for index in 16..80 {
+        words[ index ] = (words[ index - 3 ] ^ words[ index - 8 ] ^ words[ index - 14 ] ^ words[ index - 16 ]).rotate_left( 1 );
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e] = *state;
+    for (index, &word) in words.iter().enumerate() {
+        let (f, k) = match index {
+            0..=19 => ((b & c) | (!b & d), 0x5A827999),
+            20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+            40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+            _ => (b ^ c ^ d, 0xCA62C1D6)
+        };
+
+        let value = a.rotate_left( 5 ).wrapping_add( f ).wrapping_add( e ).wrapping_add( k ).wrapping_add( word );
+        e = d;
+        d = c;
+        c = b.rotate_left( 30 );
+        b = a;
+        a = value;
+    }
+
+    state[ 0 ] = state[ 0 ].wrapping_add( a );
+    state[ 1 ] = state[ 1 ].wrapping_add( b );
+    state[ 2 ] = state[ 2 ].wrapping_add( c );
+    state[ 3 ] = state[ 3 ].wrapping_add( d );
+    state[ 4 ] = state[ 4 ].wrapping_add( e );
+}
+
+#[test]
+fn test_crc32() {
+    assert_eq!( crc32( b"123456789" ), 0xCBF43926 );
+    assert_eq!( crc32( b"" ), 0 );
+}
+
+#[test]
+fn test_sha1() {
+    assert_eq!( sha1( b"abc" ), [
+        0xa9, 0x99, 0x3e, 0x36, 0x47, 0x06, 0x81, 0x6a, 0xba, 0x3e,
+        0x25, 0x71, 0x78, 0x50, 0xc2, 0x6c, 0x9c, 0xd0, 0xd8, 0x9d
+    ]);
+
+    assert_eq!( sha1( b"" ), [
+        0xda, 0x39, 0xa3, 0xee, 0x5e, 0x6b, 0x4b, 0x0d, 0x32, 0x55,
+        0xbf, 0xef, 0x95, 0x60, 0x18, 0x90, 0xaf, 0xd8, 0x07, 0x09
+    ]);
+
+    // Exactly one block of input, which forces the padding into a second block.
+    let data: [u8; 64] = [b'a'; 64];
+    assert_eq!( sha1( &data ), [
+        0x00, 0x98, 0xba, 0x82, 0x4b, 0x5c, 0x16, 0x42, 0x7b, 0xd7,
+        0xa1, 0x12, 0x2a, 0x5a, 0x44, 0x2a, 0x25, 0xec, 0x64, 0x4d
+    ]);
+}
@@ -34,6 +34,9 @@ extern crate nes_testsuite;
 #[macro_use]
 extern crate rp2c02_testsuite;
 
+#[cfg(test)]
+extern crate miniz_oxide;
+
 #[cfg_attr(feature = "softfloat", macro_use)]
 extern crate softfloat;
 
@@ -56,18 +59,74 @@ mod virtual_apu;
 mod virtual_nes;
 mod rom;
 mod mappers;
+mod mapper_info;
+mod hashes;
+mod rom_database;
+mod rom_patch;
 mod generic_mapper;
 mod mapper_mmc1;
+mod mapper_mmc2;
+mod mapper_mmc5;
 mod mapper_uxrom;
 mod mapper_unrom512;
 mod mapper_axrom;
+mod mapper_cnrom;
+mod mapper_gxrom;
+mod mapper_color_dreams;
+mod mapper_bnrom;
+mod mapper_camerica;
+mod mapper_vrc2;
+mod mapper_fme7;
+mod mapper_vrc7;
+mod mapper_namco163;
+mod mapper_fds;
+mod fds;
+mod fds_audio;
+mod vrc_irq;
+mod opll;
 mod orphan;
 mod dma;
 mod filter;
+mod apu_logger;
+mod ppu_viewer;
+mod ppu_event_log;
+mod scaler;
+
+#[cfg(feature = "std")]
+mod audio_recorder;
+
+#[cfg(feature = "std")]
+mod ntsc_filter;
+
+#[cfg(feature = "std")]
+mod palette_generator;
+
+#[cfg(feature = "std")]
+mod png;
 
 #[cfg(test)]
 mod testsuite;
 
 pub use virtual_nes::{Interface, State, Context, Button, ControllerPort, Error};
-pub use rp2c02::{Framebuffer, Palette};
-pub use rom::LoadError;
+pub use rp2c02::{Framebuffer, Palette, RenderingOptions};
+pub use rom::{LoadError, Mirroring, Region};
+pub use rom_database::{RomDatabase, RomDatabaseEntry};
+pub use rom_patch::apply_patch;
+pub use mappers::AudioOptions;
+pub use mapper_info::{MapperInfo, BankMapping, MemoryKind, NametableMirroring};
+pub use apu_logger::{ApuLogger, ApuRegisterWrite, DmcSampleFetch};
+pub use ppu_viewer::{PpuViewer, SpriteInfo};
+pub use ppu_event_log::{PpuEventLog, PpuEvent, PpuEventKind};
+pub use scaler::Scaler;
+
+#[cfg(feature = "std")]
+pub use audio_recorder::{AudioRecorder, AudioFormat};
+
+#[cfg(feature = "std")]
+pub use ntsc_filter::{NtscFilter, NtscSettings};
+
+#[cfg(feature = "std")]
+pub use palette_generator::PaletteSettings;
+
+#[cfg(feature = "std")]
+pub use png::{Overscan, encode_png};
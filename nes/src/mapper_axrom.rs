@@ -2,15 +2,19 @@ use emumisc::BitExtra;
 use rom::{NesRom, LoadError};
 use generic_mapper::BankedGenericMapper;
 use mappers::Mapper;
+use mapper_info::MapperInfo;
 
 pub struct MapperAxROM {
-    inner: BankedGenericMapper
+    inner: BankedGenericMapper,
+    has_bus_conflicts: bool
 }
 
 impl MapperAxROM {
     pub fn from_rom( rom: NesRom ) -> Result< Self, LoadError > {
+        let has_bus_conflicts = rom.has_bus_conflicts( false ); // See `NesRom::has_bus_conflicts`.
         let mut mapper = MapperAxROM {
-            inner: BankedGenericMapper::from_rom( rom )?
+            inner: BankedGenericMapper::from_rom( rom )?,
+            has_bus_conflicts: has_bus_conflicts
         };
 
         mapper.inner.set_cpu_32k_bank_to_bank( 0 );
@@ -33,7 +37,13 @@ impl Mapper for MapperAxROM {
         self.inner.peek_rom( address )
     }
 
-    fn poke_rom( &mut self, _: u16, value: u8 ) {
+    fn poke_rom( &mut self, address: u16, value: u8 ) {
+        let value = if self.has_bus_conflicts {
+            self.inner.bus_conflict( address, value )
+        } else {
+            value
+        };
+
         let rom_bank = value.get_bits( 0b0000_0111 );
         let tilemap_bank = value.get_bits( 0b0001_0000 );
 
@@ -52,4 +62,23 @@ impl Mapper for MapperAxROM {
     fn poke_video_memory( &mut self, address: u16, value: u8 ) {
         self.inner.poke_video_memory( address, value )
     }
+
+    fn info( &self ) -> MapperInfo {
+        self.inner.describe( "AxROM", Some( 7 ) )
+    }
+}
+
+#[test]
+fn test_axrom_bus_conflicts() {
+    use generic_mapper::create_test_rom;
+
+    for &(submapper, has_bus_conflicts) in &[(0, false), (1, false), (2, true)] {
+        let mut rom = create_test_rom( 7, 128 * 1024, 0 );
+        rom.submapper = submapper;
+        rom.rom[ 0x7FFF ] = 0x01;
+
+        let mut mapper = MapperAxROM::from_rom( rom ).unwrap();
+        mapper.poke_rom( 0xFFFF, 3 );
+        assert_eq!( mapper.peek_rom( 0x8000 ), if has_bus_conflicts { 2 } else { 6 } );
+    }
 }
@@ -0,0 +1,165 @@
+use emumisc::BitExtra;
+use rom::{NesRom, LoadError};
+use generic_mapper::BankedGenericMapper;
+use mappers::Mapper;
+use mapper_info::MapperInfo;
+
+/*
+    Mapper 34 is used by two completely different boards:
+
+        BNROM    - the 32kb ROM bank is switched by writing to 0x8000 - 0xFFFF;
+                   the board has VRAM instead of VROM and has bus conflicts,
+        NINA-001 - the registers are at 0x7FFD - 0x7FFF, in the save RAM
+                   address space (the writes also go through to the RAM);
+                   0x7FFD selects the 32kb ROM bank, and 0x7FFE and 0x7FFF
+                   select the lower and upper 4kb VROM banks.
+
+    The NES 2.0 submapper tells us which one it is; if it's not
+    specified we use the fact that BNROM has no VROM to tell them apart.
+*/
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Board {
+    BNROM,
+    NINA001
+}
+
+pub struct MapperBNROM {
+    inner: BankedGenericMapper,
+    board: Board
+}
+
+impl MapperBNROM {
+    pub fn from_rom( rom: NesRom ) -> Result< Self, LoadError > {
+        let board = match rom.submapper {
+            1 => Board::NINA001,
+            2 => Board::BNROM,
+            _ if rom.video_rom.len() > 8 * 1024 => Board::NINA001,
+            _ => Board::BNROM
+        };
+
+        let mut mapper = MapperBNROM {
+            inner: BankedGenericMapper::from_rom( rom )?,
+            board: board
+        };
+
+        mapper.inner.set_cpu_32k_bank_to_bank( 0 );
+        mapper.inner.set_ppu_lower_4k_bank_to_bank( 0 );
+        mapper.inner.set_ppu_upper_4k_bank_to_bank( 1 );
+
+        Ok( mapper )
+    }
+}
+
+impl Mapper for MapperBNROM {
+    fn peek_sram( &self, address: u16 ) -> u8 {
+        self.inner.peek_sram( address )
+    }
+
+    fn poke_sram( &mut self, address: u16, value: u8 ) {
+        self.inner.poke_sram( address, value );
+        if self.board != Board::NINA001 {
+            return;
+        }
+
+        match address {
+            0x7FFD => self.inner.set_cpu_32k_bank_to_bank( value.get_bits( 0b0000_0001 ) ),
+            0x7FFE => self.inner.set_ppu_lower_4k_bank_to_bank( value.get_bits( 0b0000_1111 ) ),
+            0x7FFF => self.inner.set_ppu_upper_4k_bank_to_bank( value.get_bits( 0b0000_1111 ) ),
+            _ => {}
+        }
+    }
+
+    fn peek_rom( &self, address: u16 ) -> u8 {
+        self.inner.peek_rom( address )
+    }
+
+    fn poke_rom( &mut self, address: u16, value: u8 ) {
+        if self.board != Board::BNROM {
+            return;
+        }
+
+        let value = self.inner.bus_conflict( address, value );
+        self.inner.set_cpu_32k_bank_to_bank( value );
+    }
+
+    fn peek_video_memory( &self, address: u16 ) -> u8 {
+        self.inner.peek_video_memory( address )
+    }
+
+    fn poke_video_memory( &mut self, address: u16, value: u8 ) {
+        self.inner.poke_video_memory( address, value )
+    }
+
+    fn info( &self ) -> MapperInfo {
+        let name = match self.board {
+            Board::BNROM => "BNROM",
+            Board::NINA001 => "NINA-001"
+        };
+
+        self.inner.describe( name, Some( 34 ) )
+    }
+}
+
+#[test]
+fn test_bnrom_banks() {
+    use generic_mapper::create_test_rom;
+
+    let mut rom = create_test_rom( 34, 128 * 1024, 0 );
+    for bank in 0..4 {
+        rom.rom[ bank * 32 * 1024 + 0x7FFF ] = 0xFF;
+        rom.rom[ bank * 32 * 1024 + 0x7FFE ] = 0x01;
+    }
+
+    let mut mapper = MapperBNROM::from_rom( rom ).unwrap();
+    assert_eq!( mapper.peek_rom( 0x8000 ), 0 );
+    assert_eq!( mapper.peek_rom( 0xC000 ), 1 );
+
+    mapper.poke_rom( 0xFFFF, 2 );
+    assert_eq!( mapper.peek_rom( 0x8000 ), 4 );
+    assert_eq!( mapper.peek_rom( 0xC000 ), 5 );
+
+    mapper.poke_rom( 0xFFFF, 3 );
+    assert_eq!( mapper.peek_rom( 0x8000 ), 6 );
+    assert_eq!( mapper.peek_rom( 0xC000 ), 7 );
+
+    // The bit 1 is masked out by the bus conflict.
+    mapper.poke_rom( 0xFFFE, 3 );
+    assert_eq!( mapper.peek_rom( 0x8000 ), 2 );
+    assert_eq!( mapper.peek_rom( 0xC000 ), 3 );
+
+    // These don't do anything on BNROM.
+    mapper.poke_sram( 0x7FFD, 0 );
+    assert_eq!( mapper.peek_rom( 0x8000 ), 2 );
+
+    // It has VRAM.
+    mapper.poke_video_memory( 0x1000, 123 );
+    assert_eq!( mapper.peek_video_memory( 0x1000 ), 123 );
+}
+
+#[test]
+fn test_nina001_banks() {
+    use generic_mapper::create_test_rom;
+
+    let rom = create_test_rom( 34, 64 * 1024, 64 * 1024 );
+    let mut mapper = MapperBNROM::from_rom( rom ).unwrap();
+    assert_eq!( mapper.peek_rom( 0x8000 ), 0 );
+    assert_eq!( mapper.peek_rom( 0xC000 ), 1 );
+    assert_eq!( mapper.peek_video_memory( 0x0000 ), 0 );
+    assert_eq!( mapper.peek_video_memory( 0x1000 ), 1 );
+
+    mapper.poke_sram( 0x7FFD, 1 );
+    mapper.poke_sram( 0x7FFE, 15 );
+    mapper.poke_sram( 0x7FFF, 3 );
+    assert_eq!( mapper.peek_rom( 0x8000 ), 2 );
+    assert_eq!( mapper.peek_rom( 0xC000 ), 3 );
+    assert_eq!( mapper.peek_video_memory( 0x0000 ), 15 );
+    assert_eq!( mapper.peek_video_memory( 0x1000 ), 3 );
+
+    // The writes also go to the save RAM.
+    assert_eq!( mapper.peek_sram( 0x7FFE ), 15 );
+
+    // Writes to the ROM don't do anything.
+    mapper.poke_rom( 0x8000, 0 );
+    assert_eq!( mapper.peek_rom( 0x8000 ), 2 );
+}
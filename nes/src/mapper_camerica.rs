@@ -0,0 +1,144 @@
+use emumisc::BitExtra;
+use rom::{NesRom, LoadError, Mirroring};
+use generic_mapper::BankedGenericMapper;
+use mappers::Mapper;
+use mapper_info::MapperInfo;
+
+// Used by the Camerica/Codemasters boards. This is basically UxROM
+// with the register moved to 0xC000 - 0xFFFF, and without bus conflicts.
+//
+// On the BF9097 board (used only by Fire Hawk, NES 2.0 submapper 1)
+// the one-screen mirroring is selected by writing to 0x9000 - 0x9FFF.
+// The other boards have the mirroring hardwired; when there's no submapper
+// we assume it's a BF9097 if the header says horizontal mirroring,
+// which is what the dumps of Fire Hawk have.
+
+pub struct MapperCamerica {
+    inner: BankedGenericMapper,
+    has_mirroring_register: bool
+}
+
+impl MapperCamerica {
+    pub fn from_rom( rom: NesRom ) -> Result< Self, LoadError > {
+        let has_mirroring_register = match rom.submapper {
+            0 => rom.mirroring == Mirroring::Horizontal,
+            1 => true,
+            _ => false
+        };
+
+        let mut mapper = MapperCamerica {
+            inner: BankedGenericMapper::from_rom( rom )?,
+            has_mirroring_register: has_mirroring_register
+        };
+
+        let last_bank = mapper.inner.last_rom_16k_bank();
+        mapper.inner.set_cpu_lower_16k_bank_to_bank( 0 );
+        mapper.inner.set_cpu_upper_16k_bank_to_bank( last_bank );
+
+        Ok( mapper )
+    }
+}
+
+impl Mapper for MapperCamerica {
+    fn peek_sram( &self, address: u16 ) -> u8 {
+        self.inner.peek_sram( address )
+    }
+
+    fn poke_sram( &mut self, address: u16, value: u8 ) {
+        self.inner.poke_sram( address, value )
+    }
+
+    fn peek_rom( &self, address: u16 ) -> u8 {
+        self.inner.peek_rom( address )
+    }
+
+    fn poke_rom( &mut self, address: u16, value: u8 ) {
+        match address {
+            0x9000..=0x9FFF if self.has_mirroring_register => {
+                if value.get_bits( 0b0001_0000 ) == 0 {
+                    self.inner.set_only_lower_bank_mirroring();
+                } else {
+                    self.inner.set_only_upper_bank_mirroring();
+                }
+            },
+            0xC000..=0xFFFF => {
+                self.inner.set_cpu_lower_16k_bank_to_bank( value.get_bits( 0b0000_1111 ) );
+            },
+            _ => {}
+        }
+    }
+
+    fn peek_video_memory( &self, address: u16 ) -> u8 {
+        self.inner.peek_video_memory( address )
+    }
+
+    fn poke_video_memory( &mut self, address: u16, value: u8 ) {
+        self.inner.poke_video_memory( address, value )
+    }
+
+    fn info( &self ) -> MapperInfo {
+        self.inner.describe( "Camerica", Some( 71 ) )
+    }
+}
+
+#[test]
+fn test_camerica_banks() {
+    use generic_mapper::create_test_rom;
+
+    let mut rom = create_test_rom( 71, 128 * 1024, 0 );
+    for bank in 0..8 {
+        rom.rom[ bank * 16 * 1024 + 0x3FFF ] = 0x00;
+    }
+
+    let mut mapper = MapperCamerica::from_rom( rom ).unwrap();
+    assert_eq!( mapper.peek_rom( 0x8000 ), 0 );
+    assert_eq!( mapper.peek_rom( 0xC000 ), 7 );
+
+    // No bus conflicts here.
+    mapper.poke_rom( 0xFFFF, 5 );
+    assert_eq!( mapper.peek_rom( 0x8000 ), 5 );
+    assert_eq!( mapper.peek_rom( 0xC000 ), 7 );
+
+    mapper.poke_rom( 0xC000, 2 );
+    assert_eq!( mapper.peek_rom( 0x8000 ), 2 );
+    assert_eq!( mapper.peek_rom( 0xC000 ), 7 );
+
+    // This is the mirroring register.
+    mapper.poke_rom( 0x9000, 0b0001_0000 );
+    assert_eq!( mapper.peek_rom( 0x8000 ), 2 );
+
+    mapper.poke_video_memory( 0x2000, 1 );
+    mapper.poke_video_memory( 0x2400, 2 );
+    assert_eq!( mapper.peek_video_memory( 0x2000 ), 2 );
+    assert_eq!( mapper.peek_video_memory( 0x2C00 ), 2 );
+
+    mapper.poke_rom( 0x9000, 0 );
+    mapper.poke_video_memory( 0x2000, 3 );
+    assert_eq!( mapper.peek_video_memory( 0x2800 ), 3 );
+    assert_eq!( mapper.peek_video_memory( 0x2C00 ), 3 );
+}
+
+#[test]
+fn test_camerica_hardwired_mirroring() {
+    use generic_mapper::create_test_rom;
+
+    let mut rom = create_test_rom( 71, 128 * 1024, 0 );
+    rom.mirroring = Mirroring::Vertical;
+    let mut mapper = MapperCamerica::from_rom( rom ).unwrap();
+    mapper.poke_rom( 0x9000, 0b0001_0000 );
+    mapper.poke_video_memory( 0x2000, 1 );
+    mapper.poke_video_memory( 0x2400, 2 );
+    assert_eq!( mapper.peek_video_memory( 0x2800 ), 1 );
+    assert_eq!( mapper.peek_video_memory( 0x2C00 ), 2 );
+
+    // The submapper 1 is always the BF9097.
+    let mut rom = create_test_rom( 71, 128 * 1024, 0 );
+    rom.mirroring = Mirroring::Vertical;
+    rom.submapper = 1;
+    let mut mapper = MapperCamerica::from_rom( rom ).unwrap();
+    mapper.poke_rom( 0x9000, 0b0001_0000 );
+    mapper.poke_video_memory( 0x2000, 1 );
+    mapper.poke_video_memory( 0x2400, 2 );
+    assert_eq!( mapper.peek_video_memory( 0x2000 ), 2 );
+    assert_eq!( mapper.peek_video_memory( 0x2800 ), 2 );
+}
@@ -0,0 +1,106 @@
+use rom::{NesRom, LoadError};
+use generic_mapper::BankedGenericMapper;
+use mappers::Mapper;
+use mapper_info::MapperInfo;
+
+// The ROM is fixed, and the whole 8kb of VROM is switched
+// by writing to 0x8000 - 0xFFFF.
+
+pub struct MapperCNROM {
+    inner: BankedGenericMapper,
+    has_bus_conflicts: bool
+}
+
+impl MapperCNROM {
+    pub fn from_rom( rom: NesRom ) -> Result< Self, LoadError > {
+        let has_bus_conflicts = rom.has_bus_conflicts( false ); // See `NesRom::has_bus_conflicts`.
+        let mut mapper = MapperCNROM {
+            inner: BankedGenericMapper::from_rom( rom )?,
+            has_bus_conflicts: has_bus_conflicts
+        };
+
+        mapper.inner.set_ppu_8k_bank_to_bank( 0 );
+
+        Ok( mapper )
+    }
+}
+
+impl Mapper for MapperCNROM {
+    fn peek_sram( &self, address: u16 ) -> u8 {
+        self.inner.peek_sram( address )
+    }
+
+    fn poke_sram( &mut self, address: u16, value: u8 ) {
+        self.inner.poke_sram( address, value )
+    }
+
+    fn peek_rom( &self, address: u16 ) -> u8 {
+        self.inner.peek_rom( address )
+    }
+
+    fn poke_rom( &mut self, address: u16, value: u8 ) {
+        let value = if self.has_bus_conflicts {
+            self.inner.bus_conflict( address, value )
+        } else {
+            value
+        };
+
+        self.inner.set_ppu_8k_bank_to_bank( value );
+    }
+
+    fn peek_video_memory( &self, address: u16 ) -> u8 {
+        self.inner.peek_video_memory( address )
+    }
+
+    fn poke_video_memory( &mut self, address: u16, value: u8 ) {
+        self.inner.poke_video_memory( address, value )
+    }
+
+    fn info( &self ) -> MapperInfo {
+        self.inner.describe( "CNROM", Some( 3 ) )
+    }
+}
+
+#[test]
+fn test_cnrom_banks() {
+    use generic_mapper::create_test_rom;
+
+    let mut rom = create_test_rom( 3, 32 * 1024, 32 * 1024 );
+    rom.submapper = 2;
+    rom.rom[ 0x7FFF ] = 0xFF;
+    rom.rom[ 0x7FFE ] = 0x02;
+
+    let mut mapper = MapperCNROM::from_rom( rom ).unwrap();
+    assert_eq!( mapper.peek_rom( 0x8000 ), 0 );
+    assert_eq!( mapper.peek_rom( 0xC000 ), 1 );
+    assert_eq!( mapper.peek_video_memory( 0x0000 ), 0 );
+    assert_eq!( mapper.peek_video_memory( 0x1000 ), 1 );
+
+    mapper.poke_rom( 0xFFFF, 3 );
+    assert_eq!( mapper.peek_video_memory( 0x0000 ), 6 );
+    assert_eq!( mapper.peek_video_memory( 0x1000 ), 7 );
+
+    // The value is ANDed with what's in the ROM at the address of the write.
+    mapper.poke_rom( 0xFFFE, 3 );
+    assert_eq!( mapper.peek_video_memory( 0x0000 ), 4 );
+    assert_eq!( mapper.peek_video_memory( 0x1000 ), 5 );
+
+    assert_eq!( mapper.peek_rom( 0x8000 ), 0 );
+    assert_eq!( mapper.peek_rom( 0xC000 ), 1 );
+}
+
+#[test]
+fn test_cnrom_without_bus_conflicts() {
+    use generic_mapper::create_test_rom;
+
+    for &submapper in &[0, 1] {
+        let mut rom = create_test_rom( 3, 32 * 1024, 32 * 1024 );
+        rom.submapper = submapper;
+        rom.rom[ 0x7FFE ] = 0x02;
+
+        let mut mapper = MapperCNROM::from_rom( rom ).unwrap();
+        mapper.poke_rom( 0xFFFE, 3 );
+        assert_eq!( mapper.peek_video_memory( 0x0000 ), 6 );
+        assert_eq!( mapper.peek_video_memory( 0x1000 ), 7 );
+    }
+}
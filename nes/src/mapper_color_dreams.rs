@@ -0,0 +1,91 @@
+use emumisc::BitExtra;
+use rom::{NesRom, LoadError};
+use generic_mapper::BankedGenericMapper;
+use mappers::Mapper;
+use mapper_info::MapperInfo;
+
+// This is basically GxROM with the ROM and VROM
+// bank bits swapped around, and with more of them.
+// The board has bus conflicts.
+
+pub struct MapperColorDreams {
+    inner: BankedGenericMapper
+}
+
+impl MapperColorDreams {
+    pub fn from_rom( rom: NesRom ) -> Result< Self, LoadError > {
+        let mut mapper = MapperColorDreams {
+            inner: BankedGenericMapper::from_rom( rom )?
+        };
+
+        mapper.inner.set_cpu_32k_bank_to_bank( 0 );
+        mapper.inner.set_ppu_8k_bank_to_bank( 0 );
+
+        Ok( mapper )
+    }
+}
+
+impl Mapper for MapperColorDreams {
+    fn peek_sram( &self, address: u16 ) -> u8 {
+        self.inner.peek_sram( address )
+    }
+
+    fn poke_sram( &mut self, address: u16, value: u8 ) {
+        self.inner.poke_sram( address, value )
+    }
+
+    fn peek_rom( &self, address: u16 ) -> u8 {
+        self.inner.peek_rom( address )
+    }
+
+    fn poke_rom( &mut self, address: u16, value: u8 ) {
+        let value = self.inner.bus_conflict( address, value );
+        let rom_bank = value.get_bits( 0b0000_0011 );
+        let vrom_bank = value.get_bits( 0b1111_0000 );
+
+        self.inner.set_cpu_32k_bank_to_bank( rom_bank );
+        self.inner.set_ppu_8k_bank_to_bank( vrom_bank );
+    }
+
+    fn peek_video_memory( &self, address: u16 ) -> u8 {
+        self.inner.peek_video_memory( address )
+    }
+
+    fn poke_video_memory( &mut self, address: u16, value: u8 ) {
+        self.inner.poke_video_memory( address, value )
+    }
+
+    fn info( &self ) -> MapperInfo {
+        self.inner.describe( "Color Dreams", Some( 11 ) )
+    }
+}
+
+#[test]
+fn test_color_dreams_banks() {
+    use generic_mapper::create_test_rom;
+
+    let mut rom = create_test_rom( 11, 128 * 1024, 128 * 1024 );
+    for bank in 0..4 {
+        rom.rom[ bank * 32 * 1024 + 0x7FFF ] = 0xFF;
+        rom.rom[ bank * 32 * 1024 + 0x7FFE ] = 0xF0;
+    }
+
+    let mut mapper = MapperColorDreams::from_rom( rom ).unwrap();
+    assert_eq!( mapper.peek_rom( 0x8000 ), 0 );
+    assert_eq!( mapper.peek_rom( 0xC000 ), 1 );
+    assert_eq!( mapper.peek_video_memory( 0x0000 ), 0 );
+    assert_eq!( mapper.peek_video_memory( 0x1000 ), 1 );
+
+    mapper.poke_rom( 0xFFFF, 0b1010_0010 );
+    assert_eq!( mapper.peek_rom( 0x8000 ), 4 );
+    assert_eq!( mapper.peek_rom( 0xC000 ), 5 );
+    assert_eq!( mapper.peek_video_memory( 0x0000 ), 20 );
+    assert_eq!( mapper.peek_video_memory( 0x1000 ), 21 );
+
+    // The ROM bank bits are masked out by the bus conflict.
+    mapper.poke_rom( 0xFFFE, 0b0011_0011 );
+    assert_eq!( mapper.peek_rom( 0x8000 ), 0 );
+    assert_eq!( mapper.peek_rom( 0xC000 ), 1 );
+    assert_eq!( mapper.peek_video_memory( 0x0000 ), 6 );
+    assert_eq!( mapper.peek_video_memory( 0x1000 ), 7 );
+}
@@ -0,0 +1,726 @@
+use alloc::vec::Vec;
+
+use emumisc::{is_b0_set, is_b1_set, is_b2_set, is_b3_set, is_b4_set, is_b6_set, is_b7_set};
+use rom::{LoadError, Mirroring};
+use mappers::Mapper;
+use mapper_info::{MapperInfo, MemoryKind};
+use generic_mapper::{bank, GenericMapper};
+use fds::{FdsDisk, update_crc};
+use fds_audio::FdsAudio;
+use float::F32;
+
+/*
+    The Famicom Disk System.
+
+    The RAM adapter has 32kb of RAM at 0x6000 - 0xDFFF, the 8kb BIOS at 0xE000 - 0xFFFF,
+    and 8kb of VRAM. The registers are:
+
+        0x4020            - bits 0 - 7 of the timer's reload value,
+        0x4021            - bits 8 - 15 of the timer's reload value,
+        0x4022            - the timer control; bit 0 - repeat, bit 1 - enable,
+        0x4023            - bit 0 - enables the disk registers, bit 1 - enables the sound registers,
+        0x4024            - the data to write to the disk,
+        0x4025            - the drive control; bit 0 - motor on, bit 1 - reset the transfer,
+                            bit 2 - read (1) or write (0), bit 3 - mirroring; 0 - vertical, 1 - horizontal,
+                            bit 4 - transfer the CRC, bit 6 - start the transfer,
+                            bit 7 - trigger an IRQ after every byte,
+        0x4026            - the output of the expansion port,
+        0x4030            - (read) bit 0 - the timer's IRQ, bit 1 - a byte was transferred,
+                            bit 4 - CRC error, bit 6 - the head reached the end of the disk,
+                            bit 7 - the transfer is enabled; reading acknowledges the IRQs,
+        0x4031            - (read) the data read from the disk,
+        0x4032            - (read) bit 0 - no disk inserted, bit 1 - the disk is not ready,
+                            bit 2 - the disk is write protected,
+        0x4033            - (read) the input of the expansion port; bit 7 - battery is good,
+        0x4040 - 0x4097   - the sound channel; see `FdsAudio`.
+
+    Once the motor is turned on the drive moves the head to the start of the disk,
+    and then goes through it at a constant speed of a byte every 150 CPU cycles,
+    whether there is anything to transfer or not, until it reaches its end.
+*/
+
+// How many CPU cycles it takes to transfer a single byte.
+const BYTE_TRANSFER_CYCLES: u32 = 150;
+
+// How many CPU cycles it takes to move the head back to the start of the disk.
+const HEAD_REWIND_CYCLES: u32 = 50000;
+
+// How long the drive stays empty when the disk is swapped; this is
+// plenty of time for the BIOS to notice that the disk was ejected.
+const DISK_SWAP_CYCLES: u32 = 1000000;
+
+pub struct MapperFDS {
+    inner: GenericMapper,
+    background_tilemaps_offset: u32,
+
+    disk: FdsDisk,
+    is_disk_modified: bool,
+    inserted_side: Option< usize >,
+    requested_side: Option< usize >,
+    disk_swap_delay: u32,
+
+    are_disk_registers_enabled: bool,
+    are_sound_registers_enabled: bool,
+
+    timer_reload_value: u16,
+    timer_counter: u16,
+    is_timer_repeating: bool,
+    is_timer_enabled: bool,
+    timer_irq_pending: bool,
+
+    is_motor_on: bool,
+    is_transfer_reset: bool,
+    is_read_mode: bool,
+    is_crc_control: bool,
+    was_crc_control: bool,
+    is_transfer_enabled: bool,
+    is_disk_irq_enabled: bool,
+    disk_irq_pending: bool,
+
+    is_scanning: bool,
+    is_at_end_of_head: bool,
+    has_gap_ended: bool,
+    is_byte_transferred: bool,
+    head_position: usize,
+    transfer_delay: u32,
+    crc: u16,
+    read_data: u8,
+    write_data: u8,
+    external_port: u8,
+
+    audio: FdsAudio
+}
+
+impl MapperFDS {
+    pub fn new( bios: &[u8], disk_image: &[u8] ) -> Result< Self, LoadError > {
+        if bios.len() != 8 * 1024 {
+            return Err( LoadError::new( "the Famicom Disk System's BIOS must be exactly 8kb" ) );
+        }
+
+        let disk = FdsDisk::load( disk_image )?;
+
+        let mut inner = GenericMapper::new();
+        inner.begin_region( MemoryKind::SaveRam );
+        let ram_offset = inner.total_memory_size() as u32;
+        inner.extend_empty( 32 * 1024 );
+        inner.begin_region( MemoryKind::Rom );
+        let bios_offset = inner.total_memory_size() as u32;
+        inner.extend( bios );
+        inner.initialize_video_rom( &[] );
+        let background_tilemaps_offset = inner.initialize_background_tilemaps( Mirroring::Horizontal );
+
+        inner.set_cpu_8k_bank( bank::CPU_8K::Ox6000, ram_offset );
+        inner.set_cpu_8k_bank( bank::CPU_8K::Ox8000, ram_offset + 0x2000 );
+        inner.set_cpu_8k_bank( bank::CPU_8K::OxA000, ram_offset + 0x4000 );
+        inner.set_cpu_8k_bank( bank::CPU_8K::OxC000, ram_offset + 0x6000 );
+        inner.set_cpu_8k_bank( bank::CPU_8K::OxE000, bios_offset );
+        inner.set_cpu_8k_writable( bank::CPU_8K::Ox6000, true );
+        inner.set_cpu_8k_writable( bank::CPU_8K::Ox8000, true );
+        inner.set_cpu_8k_writable( bank::CPU_8K::OxA000, true );
+        inner.set_cpu_8k_writable( bank::CPU_8K::OxC000, true );
+
+        Ok( MapperFDS {
+            inner: inner,
+            background_tilemaps_offset: background_tilemaps_offset,
+
+            disk: disk,
+            is_disk_modified: false,
+            inserted_side: Some( 0 ),
+            requested_side: Some( 0 ),
+            disk_swap_delay: 0,
+
+            are_disk_registers_enabled: false,
+            are_sound_registers_enabled: false,
+
+            timer_reload_value: 0,
+            timer_counter: 0,
+            is_timer_repeating: false,
+            is_timer_enabled: false,
+            timer_irq_pending: false,
+
+            is_motor_on: false,
+            is_transfer_reset: false,
+            is_read_mode: true,
+            is_crc_control: false,
+            was_crc_control: false,
+            is_transfer_enabled: false,
+            is_disk_irq_enabled: false,
+            disk_irq_pending: false,
+
+            is_scanning: false,
+            is_at_end_of_head: true,
+            has_gap_ended: false,
+            is_byte_transferred: false,
+            head_position: 0,
+            transfer_delay: 0,
+            crc: 0,
+            read_data: 0,
+            write_data: 0,
+            external_port: 0,
+
+            audio: FdsAudio::new()
+        })
+    }
+
+    fn poke_drive_control( &mut self, value: u8 ) {
+        self.is_motor_on = is_b0_set( value );
+        self.is_transfer_reset = is_b1_set( value );
+        self.is_read_mode = is_b2_set( value );
+        if is_b3_set( value ) {
+            self.inner.set_horizontal_mirroring( self.background_tilemaps_offset );
+        } else {
+            self.inner.set_vertical_mirroring( self.background_tilemaps_offset );
+        }
+        self.is_crc_control = is_b4_set( value );
+        self.is_transfer_enabled = is_b6_set( value );
+        self.is_disk_irq_enabled = is_b7_set( value );
+        self.disk_irq_pending = false;
+    }
+
+    fn peek_disk_status( &mut self ) -> u8 {
+        // We always generate valid CRCs when loading a disk
+        // image, so there is never a CRC error to report.
+        let value =
+            (self.timer_irq_pending as u8) |
+            (self.is_byte_transferred as u8) << 1 |
+            (self.is_at_end_of_head as u8) << 6 |
+            (self.is_transfer_enabled as u8) << 7;
+
+        self.is_byte_transferred = false;
+        self.timer_irq_pending = false;
+        self.disk_irq_pending = false;
+
+        value
+    }
+
+    fn peek_drive_status( &self ) -> u8 {
+        let is_inserted = self.inserted_side.is_some();
+        ((is_inserted == false) as u8) |
+        ((is_inserted == false || self.is_scanning == false) as u8) << 1 |
+        ((is_inserted == false) as u8) << 2 |
+        0x40
+    }
+
+    fn clock_timer( &mut self ) {
+        if self.is_timer_enabled == false {
+            return;
+        }
+
+        if self.timer_counter == 0 {
+            self.timer_irq_pending = true;
+            self.timer_counter = self.timer_reload_value;
+            if self.is_timer_repeating == false {
+                self.is_timer_enabled = false;
+            }
+        } else {
+            self.timer_counter -= 1;
+        }
+    }
+
+    fn clock_drive( &mut self ) {
+        if self.disk_swap_delay > 0 {
+            self.disk_swap_delay -= 1;
+            if self.disk_swap_delay == 0 {
+                self.inserted_side = self.requested_side;
+            }
+        }
+
+        let side = match self.inserted_side {
+            Some( side ) if self.is_motor_on => side,
+            _ => {
+                self.is_at_end_of_head = true;
+                self.is_scanning = false;
+                return;
+            }
+        };
+
+        if self.is_transfer_reset && self.is_scanning == false {
+            return;
+        }
+
+        if self.is_at_end_of_head {
+            self.is_at_end_of_head = false;
+            self.head_position = 0;
+            self.has_gap_ended = false;
+            self.transfer_delay = HEAD_REWIND_CYCLES;
+            return;
+        }
+
+        if self.transfer_delay > 0 {
+            self.transfer_delay -= 1;
+            return;
+        }
+
+        self.is_scanning = true;
+        if self.is_read_mode {
+            self.read_byte( side );
+        } else {
+            self.write_byte( side );
+        }
+        self.was_crc_control = self.is_crc_control;
+
+        self.head_position += 1;
+        if self.head_position >= self.disk.side( side ).len() {
+            self.is_motor_on = false;
+        } else {
+            self.transfer_delay = BYTE_TRANSFER_CYCLES - 1;
+        }
+    }
+
+    fn read_byte( &mut self, side: usize ) {
+        let value = self.disk.side( side )[ self.head_position ];
+        if self.was_crc_control == false {
+            self.crc = update_crc( self.crc, value );
+        }
+
+        if self.is_transfer_enabled == false {
+            self.has_gap_ended = false;
+            self.crc = 0;
+        } else if self.has_gap_ended == false {
+            // The start mark is not transferred; the first
+            // byte transferred is the first byte of the block.
+            if value != 0 {
+                self.has_gap_ended = true;
+            }
+        } else {
+            self.read_data = value;
+            self.transfer_byte();
+        }
+    }
+
+    fn write_byte( &mut self, side: usize ) {
+        let mut value = 0;
+        if self.is_crc_control {
+            if self.was_crc_control == false {
+                self.crc = update_crc( self.crc, 0 );
+                self.crc = update_crc( self.crc, 0 );
+            }
+
+            value = self.crc as u8;
+            self.crc >>= 8;
+        } else {
+            if self.is_transfer_enabled {
+                value = self.write_data;
+                self.crc = update_crc( self.crc, value );
+            } else {
+                self.crc = 0;
+            }
+
+            self.transfer_byte();
+        }
+
+        self.disk.side_mut( side )[ self.head_position ] = value;
+        self.is_disk_modified = true;
+        self.has_gap_ended = false;
+    }
+
+    fn transfer_byte( &mut self ) {
+        self.is_byte_transferred = true;
+        if self.is_disk_irq_enabled {
+            self.disk_irq_pending = true;
+        }
+    }
+}
+
+impl Mapper for MapperFDS {
+    fn peek_rom( &self, address: u16 ) -> u8 {
+        self.inner.peek_rom( address )
+    }
+
+    fn poke_rom( &mut self, address: u16, value: u8 ) {
+        self.inner.poke_rom( address, value )
+    }
+
+    fn peek_sram( &self, address: u16 ) -> u8 {
+        self.inner.peek_sram( address )
+    }
+
+    fn poke_sram( &mut self, address: u16, value: u8 ) {
+        self.inner.poke_sram( address, value )
+    }
+
+    fn peek_expansion_rom( &mut self, address: u16 ) -> u8 {
+        match address {
+            0x4030 if self.are_disk_registers_enabled => self.peek_disk_status(),
+            0x4031 if self.are_disk_registers_enabled => {
+                self.is_byte_transferred = false;
+                self.disk_irq_pending = false;
+                self.read_data
+            },
+            0x4032 if self.are_disk_registers_enabled => self.peek_drive_status(),
+            0x4033 if self.are_disk_registers_enabled => 0x80,
+            0x4040..=0x4097 if self.are_sound_registers_enabled => self.audio.peek_register( address ),
+            _ => {
+                // Open bus.
+                (address >> 8) as u8
+            }
+        }
+    }
+
+    fn poke_expansion_rom( &mut self, address: u16, value: u8 ) {
+        match address {
+            0x4020 => {
+                self.timer_reload_value = (self.timer_reload_value & 0xFF00) | value as u16;
+            },
+            0x4021 => {
+                self.timer_reload_value = (self.timer_reload_value & 0x00FF) | (value as u16) << 8;
+            },
+            0x4022 => {
+                self.is_timer_repeating = is_b0_set( value );
+                self.is_timer_enabled = is_b1_set( value ) && self.are_disk_registers_enabled;
+                if self.is_timer_enabled {
+                    self.timer_counter = self.timer_reload_value;
+                } else {
+                    self.timer_irq_pending = false;
+                }
+            },
+            0x4023 => {
+                self.are_disk_registers_enabled = is_b0_set( value );
+                self.are_sound_registers_enabled = is_b1_set( value );
+                if self.are_disk_registers_enabled == false {
+                    self.is_timer_enabled = false;
+                    self.timer_irq_pending = false;
+                    self.disk_irq_pending = false;
+                }
+            },
+            0x4024 if self.are_disk_registers_enabled => {
+                self.write_data = value;
+                self.is_byte_transferred = false;
+                self.disk_irq_pending = false;
+            },
+            0x4025 if self.are_disk_registers_enabled => self.poke_drive_control( value ),
+            0x4026 if self.are_disk_registers_enabled => self.external_port = value,
+            0x4040..=0x4097 if self.are_sound_registers_enabled => self.audio.poke_register( address, value ),
+            _ => {
+                #[cfg(feature = "log")]
+                warn!( "Unhandled write to the expansion ROM at 0x{:04X} (value=0x{:02X})", address, value );
+            }
+        }
+    }
+
+    fn peek_video_memory( &self, address: u16 ) -> u8 {
+        self.inner.peek_video_memory( address )
+    }
+
+    fn poke_video_memory( &mut self, address: u16, value: u8 ) {
+        self.inner.poke_video_memory( address, value )
+    }
+
+    fn on_cpu_cycle( &mut self ) {
+        self.clock_timer();
+        self.clock_drive();
+        self.audio.clock();
+    }
+
+    fn irq_line( &self ) -> bool {
+        self.timer_irq_pending || self.disk_irq_pending
+    }
+
+    fn audio_output( &self ) -> F32 {
+        self.audio.output()
+    }
+
+    fn disk_side_count( &self ) -> usize {
+        self.disk.side_count()
+    }
+
+    fn disk_side( &self ) -> Option< usize > {
+        self.requested_side
+    }
+
+    // The disk is ejected first, and then the new side is inserted after a while.
+    fn set_disk_side( &mut self, side: Option< usize > ) {
+        let side = side.filter( |&side| side < self.disk.side_count() );
+        if side == self.requested_side {
+            return;
+        }
+
+        self.requested_side = side;
+        self.inserted_side = None;
+        self.disk_swap_delay = if side.is_some() { DISK_SWAP_CYCLES } else { 0 };
+    }
+
+    fn is_disk_modified( &self ) -> bool {
+        self.is_disk_modified
+    }
+
+    fn disk_image( &self ) -> Option< Vec< u8 > > {
+        Some( self.disk.to_image() )
+    }
+
+    fn restore_disk_image( &mut self, data: &[u8] ) -> Result< (), LoadError > {
+        let disk = FdsDisk::load( data )?;
+        if disk.side_count() != self.disk.side_count() {
+            return Err( LoadError::new( "the number of the disk sides doesn't match the loaded disk" ) );
+        }
+
+        self.disk = disk;
+        self.is_disk_modified = false;
+        Ok(())
+    }
+
+    fn info( &self ) -> MapperInfo {
+        let mut info = self.inner.describe( "FDS", None );
+        info.add_register( "disk inserted", self.inserted_side.is_some() as u32 );
+        info.add_register( "disk side", self.inserted_side.unwrap_or( 0 ) as u32 );
+        info.add_register( "head position", self.head_position as u32 );
+        info.add_register( "motor on", self.is_motor_on as u32 );
+        info.add_register( "read mode", self.is_read_mode as u32 );
+        info.add_register( "transfer enabled", self.is_transfer_enabled as u32 );
+        info.add_register( "disk IRQ enabled", self.is_disk_irq_enabled as u32 );
+        info.add_register( "timer reload value", self.timer_reload_value as u32 );
+        info.add_register( "timer counter", self.timer_counter as u32 );
+        info.add_register( "timer enabled", self.is_timer_enabled as u32 );
+        info.add_register( "timer repeating", self.is_timer_repeating as u32 );
+        info
+    }
+}
+
+#[cfg(test)]
+fn create_test_mapper( file_size: usize ) -> MapperFDS {
+    use fds::create_test_disk;
+
+    let mut mapper = MapperFDS::new( &[0; 8 * 1024], &create_test_disk( file_size ) ).unwrap();
+    mapper.poke_expansion_rom( 0x4023, 0b11 );
+    mapper
+}
+
+#[cfg(test)]
+fn wait_for_byte( mapper: &mut MapperFDS ) {
+    for _ in 0..1000000 {
+        mapper.on_cpu_cycle();
+        if mapper.is_byte_transferred {
+            return;
+        }
+    }
+
+    panic!( "timeout while waiting for a byte from the disk" );
+}
+
+#[test]
+fn test_fds_memory() {
+    let mut mapper = create_test_mapper( 1 );
+    mapper.poke_sram( 0x6000, 0x11 );
+    mapper.poke_rom( 0xDFFF, 0x22 );
+    mapper.poke_rom( 0xE000, 0x33 );
+    mapper.poke_video_memory( 0x1FFF, 0x44 );
+    assert_eq!( mapper.peek_sram( 0x6000 ), 0x11 );
+    assert_eq!( mapper.peek_rom( 0xDFFF ), 0x22 );
+    assert_eq!( mapper.peek_rom( 0xE000 ), 0x00 );
+    assert_eq!( mapper.peek_video_memory( 0x1FFF ), 0x44 );
+
+    mapper.poke_expansion_rom( 0x4025, 0b0000_1000 );
+    mapper.poke_video_memory( 0x2000, 0x55 );
+    assert_eq!( mapper.peek_video_memory( 0x2400 ), 0x55 );
+    mapper.poke_expansion_rom( 0x4025, 0b0000_0000 );
+    assert_eq!( mapper.peek_video_memory( 0x2800 ), 0x55 );
+}
+
+#[test]
+fn test_fds_info() {
+    let mapper = create_test_mapper( 16 );
+    let info = mapper.info();
+    assert_eq!( info.number, None );
+    assert_eq!( info.cpu_banks[ 1 ].memory, Some( MemoryKind::SaveRam ) );
+    assert_eq!( info.cpu_banks[ 1 ].offset, 0x2000 );
+    assert_eq!( info.rom_offset( 0x8000 ), None );
+    assert_eq!( info.rom_offset( 0xE123 ), Some( 0x0123 ) );
+    assert_eq!( info.register( "disk inserted" ), Some( 1 ) );
+}
+
+#[test]
+fn test_fds_timer_irq() {
+    let mut mapper = create_test_mapper( 1 );
+    mapper.poke_expansion_rom( 0x4020, 2 );
+    mapper.poke_expansion_rom( 0x4021, 0 );
+    mapper.poke_expansion_rom( 0x4022, 0b11 );
+    for _ in 0..2 {
+        mapper.on_cpu_cycle();
+        assert!( mapper.irq_line() == false );
+    }
+    mapper.on_cpu_cycle();
+    assert!( mapper.irq_line() );
+    assert_eq!( mapper.peek_expansion_rom( 0x4030 ) & 1, 1 );
+    assert!( mapper.irq_line() == false );
+
+    // It repeats.
+    for _ in 0..3 {
+        mapper.on_cpu_cycle();
+    }
+    assert!( mapper.irq_line() );
+}
+
+#[test]
+fn test_fds_read_disk() {
+    let mut mapper = create_test_mapper( 4 );
+    assert_eq!( mapper.peek_expansion_rom( 0x4032 ) & 0b11, 0b10 );
+
+    mapper.poke_expansion_rom( 0x4025, 0b0010_0101 );
+    while mapper.peek_expansion_rom( 0x4032 ) & 0b10 != 0 {
+        mapper.on_cpu_cycle();
+    }
+
+    // The first block is the disk header.
+    mapper.poke_expansion_rom( 0x4025, 0b1110_0101 );
+    let mut data = Vec::new();
+    for _ in 0..15 {
+        wait_for_byte( &mut mapper );
+        assert!( mapper.irq_line() );
+        data.push( mapper.peek_expansion_rom( 0x4031 ) );
+        assert!( mapper.irq_line() == false );
+    }
+    assert_eq!( &data[..], b"\x01*NINTENDO-HVC*" );
+
+    // The bytes arrive at a constant rate.
+    let mut cycles = 0;
+    loop {
+        mapper.on_cpu_cycle();
+        cycles += 1;
+        if mapper.is_byte_transferred {
+            break;
+        }
+    }
+    assert_eq!( cycles, BYTE_TRANSFER_CYCLES );
+}
+
+#[test]
+fn test_fds_write_disk() {
+    let mut mapper = create_test_mapper( 4 );
+    assert!( mapper.is_disk_modified() == false );
+
+    // Skip the disk header by reading it.
+    mapper.poke_expansion_rom( 0x4025, 0b0010_0101 );
+    while mapper.peek_expansion_rom( 0x4032 ) & 0b10 != 0 {
+        mapper.on_cpu_cycle();
+    }
+    mapper.poke_expansion_rom( 0x4025, 0b0110_0101 );
+    for _ in 0..56 + 2 {
+        wait_for_byte( &mut mapper );
+        mapper.peek_expansion_rom( 0x4031 );
+    }
+
+    // Then overwrite the file count block, gap included.
+    mapper.poke_expansion_rom( 0x4024, 0 );
+    mapper.poke_expansion_rom( 0x4025, 0b0010_0001 );
+    for _ in 0..976 / 8 {
+        wait_for_byte( &mut mapper );
+        mapper.poke_expansion_rom( 0x4024, 0 );
+    }
+
+    mapper.poke_expansion_rom( 0x4024, 0x80 );
+    mapper.poke_expansion_rom( 0x4025, 0b0110_0001 );
+    for &value in &[2, 0] {
+        wait_for_byte( &mut mapper );
+        mapper.poke_expansion_rom( 0x4024, value );
+    }
+    wait_for_byte( &mut mapper );
+
+    // The CRC is written by the drive itself.
+    mapper.poke_expansion_rom( 0x4025, 0b0111_0001 );
+    for _ in 0..BYTE_TRANSFER_CYCLES * 2 {
+        mapper.on_cpu_cycle();
+    }
+    mapper.poke_expansion_rom( 0x4025, 0b0010_0000 );
+
+    assert!( mapper.is_disk_modified() );
+    let image = mapper.disk_image().unwrap();
+    assert_eq!( &image[ 56..58 ], &[2, 0] );
+    assert_eq!( image[ 58 ], 3 );
+
+    // The block was written at the same place as the original one, with a valid CRC.
+    let side = mapper.disk.side( 0 );
+    let block = side.windows( 3 ).position( |window| window == &[0x80, 2, 0] ).unwrap();
+    assert_eq!( side[ block..block + 5 ].iter().fold( 0, |crc, &value| update_crc( crc, value ) ), 0 );
+    assert_eq!( &side[ block + 5 + 976 / 8..block + 5 + 976 / 8 + 2 ], &[0x80, 3] );
+
+    // The saved image can be restored over the original one.
+    let mut mapper = create_test_mapper( 4 );
+    mapper.restore_disk_image( &image ).unwrap();
+    assert!( mapper.is_disk_modified() == false );
+    assert_eq!( mapper.disk_image().unwrap(), image );
+
+    let mut two_sides = image.clone();
+    two_sides.extend_from_slice( &image );
+    assert!( mapper.restore_disk_image( &two_sides ).is_err() );
+}
+
+#[test]
+fn test_fds_disk_side_switching() {
+    let mut mapper = create_test_mapper( 1 );
+    assert_eq!( mapper.disk_side_count(), 1 );
+    assert_eq!( mapper.disk_side(), Some( 0 ) );
+
+    mapper.set_disk_side( None );
+    assert_eq!( mapper.disk_side(), None );
+    assert_eq!( mapper.peek_expansion_rom( 0x4032 ) & 0b111, 0b111 );
+
+    // There's no second side.
+    mapper.set_disk_side( Some( 1 ) );
+    assert_eq!( mapper.disk_side(), None );
+
+    mapper.set_disk_side( Some( 0 ) );
+    assert_eq!( mapper.peek_expansion_rom( 0x4032 ) & 0b001, 0b001 );
+    for _ in 0..DISK_SWAP_CYCLES {
+        mapper.on_cpu_cycle();
+    }
+    assert_eq!( mapper.peek_expansion_rom( 0x4032 ) & 0b101, 0b000 );
+}
+
+#[test]
+fn test_fds_stub_bios_reads_the_disk() {
+    use fds::create_test_disk;
+    use virtual_nes::{State, Context, Interface};
+
+    struct Instance {
+        state: State
+    }
+
+    impl Context for Instance {
+        fn state_mut( &mut self ) -> &mut State {
+            &mut self.state
+        }
+
+        fn state( &self ) -> &State {
+            &self.state
+        }
+    }
+
+    // Reads the first 16 bytes of the disk into the zero page.
+    let program = [
+        0xA9, 0x01,       // LDA #$01
+        0x8D, 0x23, 0x40, // STA $4023 ; Enable the disk registers.
+        0xA9, 0x25,       // LDA #$25
+        0x8D, 0x25, 0x40, // STA $4025 ; Turn on the motor in the read mode.
+        0xAD, 0x32, 0x40, // LDA $4032
+        0x29, 0x02,       // AND #$02
+        0xD0, 0xF9,       // BNE $E00A ; Wait until the disk is ready.
+        0xA9, 0x65,       // LDA #$65
+        0x8D, 0x25, 0x40, // STA $4025 ; Start the transfer.
+        0xA2, 0x00,       // LDX #$00
+        0xAD, 0x30, 0x40, // LDA $4030
+        0x29, 0x02,       // AND #$02
+        0xF0, 0xF9,       // BEQ $E018 ; Wait for a byte.
+        0xAD, 0x31, 0x40, // LDA $4031
+        0x95, 0x00,       // STA $00,X
+        0xE8,             // INX
+        0xE0, 0x10,       // CPX #$10
+        0xD0, 0xEF,       // BNE $E018
+        0x4C, 0x29, 0xE0  // JMP $E029
+    ];
+
+    let mut bios = vec![ 0; 8 * 1024 ];
+    bios[ ..program.len() ].copy_from_slice( &program );
+    bios[ 0x1FFC ] = 0x00; // The reset vector.
+    bios[ 0x1FFD ] = 0xE0;
+
+    let mut instance = Instance { state: State::new() };
+    instance.load_fds( &bios, &create_test_disk( 1 ) ).unwrap();
+    assert_eq!( instance.disk_side_count(), 1 );
+    for _ in 0..60 {
+        instance.execute_for_a_frame().unwrap();
+    }
+
+    let data: Vec< u8 > = (0..16).map( |address| instance.peek_memory( address ) ).collect();
+    assert_eq!( &data[ ..15 ], b"\x01*NINTENDO-HVC*" );
+    assert!( instance.is_disk_modified() == false );
+}
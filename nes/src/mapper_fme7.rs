@@ -0,0 +1,518 @@
+use core::cmp::max;
+
+use emumisc::{BitExtra, is_b0_set, is_b1_set, is_b2_set, is_b3_set, is_b4_set, is_b6_set, is_b7_set};
+use rom::{NesRom, LoadError};
+use mappers::Mapper;
+use mapper_info::MapperInfo;
+use generic_mapper::{bank, BankedGenericMapper};
+use float::{F32, u8_to_f32};
+
+/*
+    Sunsoft FME-7 and 5B (used by Batman: Return of the Joker, Gimmick! and others).
+
+    The 5B is a FME-7 with an additional sound chip; since the writes
+    to its registers are harmless on a FME-7 we always emulate it.
+
+    The registers are:
+
+        0x8000 - 0x9FFF   - command,
+        0xA000 - 0xBFFF   - parameter of the command,
+        0xC000 - 0xDFFF   - (5B only) audio register select,
+        0xE000 - 0xFFFF   - (5B only) audio register write.
+
+    The commands are:
+
+        0x0 - 0x7         - the VROM 1kb banks,
+        0x8               - the bank at 0x6000; bit 7 - RAM enable, bit 6 - RAM (1) or ROM (0),
+                            bits 0 - 5 - the bank,
+        0x9 - 0xB         - the ROM 8kb banks at 0x8000, 0xA000 and 0xC000,
+        0xC               - mirroring; 0 - vertical, 1 - horizontal,
+                            2 - one screen lower bank, 3 - one screen upper bank,
+        0xD               - IRQ control; bit 0 - IRQ enable, bit 7 - counter enable;
+                            any write acknowledges the IRQ,
+        0xE, 0xF          - the lower and the upper byte of the IRQ counter.
+
+    The bank at 0xE000 is fixed to the last bank.
+
+    The IRQ counter is decremented on every CPU cycle and triggers
+    the IRQ when it wraps around from 0 to 0xFFFF.
+*/
+
+// The 5B has a logarithmic DAC with 1.5dB steps.
+static VOLUME_TABLE: [u8; 32] = [
+      0,   1,   2,   2,   2,   3,   3,   4,   5,   6,   7,   8,  10,  11,  14,  16,
+     19,  23,  27,  32,  38,  45,  54,  64,  76,  90, 108, 128, 152, 181, 215, 255
+];
+
+struct ToneChannel {
+    period: u16,
+    counter: u16,
+    output: bool,
+    volume: u8,
+    uses_envelope: bool
+}
+
+impl ToneChannel {
+    const fn new() -> ToneChannel {
+        ToneChannel {
+            period: 0,
+            counter: 0,
+            output: false,
+            volume: 0,
+            uses_envelope: false
+        }
+    }
+
+    fn clock( &mut self ) {
+        self.counter += 1;
+        if self.counter >= max( self.period, 1 ) {
+            self.counter = 0;
+            self.output = !self.output;
+        }
+    }
+}
+
+/*
+    The sound chip of the 5B is a YM2149F (a variant of the AY-3-8910)
+    with three square wave channels, a noise generator and an envelope.
+
+    The registers are:
+
+        0x0 - 0x5         - the 12-bit periods of the channels A, B and C,
+        0x6               - the noise period,
+        0x7               - bits 0 - 2 disable the tone of the channels,
+                            bits 3 - 5 disable the noise of the channels,
+        0x8 - 0xA         - the volume of the channels; bit 4 uses the envelope instead,
+        0xB, 0xC          - the 16-bit envelope period,
+        0xD               - the envelope shape; bit 0 - hold, bit 1 - alternate,
+                            bit 2 - attack, bit 3 - continue.
+
+    Everything is clocked every 16 CPU cycles.
+*/
+struct Sunsoft5B {
+    selected_register: u8,
+    divider: u8,
+
+    channels: [ToneChannel; 3],
+    mixer: u8,
+
+    noise_period: u8,
+    noise_counter: u8,
+    noise_shift_register: u32,
+
+    envelope_period: u16,
+    envelope_counter: u16,
+    envelope_shape: u8,
+    envelope_step: u8,
+    is_envelope_attacking: bool,
+    is_envelope_holding: bool
+}
+
+impl Sunsoft5B {
+    const fn new() -> Sunsoft5B {
+        Sunsoft5B {
+            selected_register: 0,
+            divider: 0,
+
+            channels: [ToneChannel::new(), ToneChannel::new(), ToneChannel::new()],
+            mixer: 0,
+
+            noise_period: 0,
+            noise_counter: 0,
+            noise_shift_register: 1,
+
+            envelope_period: 0,
+            envelope_counter: 0,
+            envelope_shape: 0,
+            envelope_step: 0,
+            is_envelope_attacking: false,
+            is_envelope_holding: false
+        }
+    }
+
+    fn poke_register( &mut self, value: u8 ) {
+        match self.selected_register {
+            register @ 0x0..=0x5 => {
+                let channel = &mut self.channels[ (register / 2) as usize ];
+                if register & 1 == 0 {
+                    channel.period.replace_bits( 0x00FF, value as u16 );
+                } else {
+                    channel.period.replace_bits( 0x0F00, value.get_bits( 0x0F ) as u16 );
+                }
+            },
+            0x6 => self.noise_period = value.get_bits( 0x1F ),
+            0x7 => self.mixer = value,
+            register @ 0x8..=0xA => {
+                let channel = &mut self.channels[ (register - 0x8) as usize ];
+                channel.volume = value.get_bits( 0x0F );
+                channel.uses_envelope = is_b4_set( value );
+            },
+            0xB => self.envelope_period.replace_bits( 0x00FF, value as u16 ),
+            0xC => self.envelope_period.replace_bits( 0xFF00, value as u16 ),
+            0xD => {
+                self.envelope_shape = value.get_bits( 0x0F );
+                self.envelope_counter = 0;
+                self.envelope_step = 0;
+                self.is_envelope_attacking = is_b2_set( value );
+                self.is_envelope_holding = false;
+            },
+            _ => {}
+        }
+    }
+
+    fn clock( &mut self ) {
+        self.divider += 1;
+        if self.divider < 16 {
+            return;
+        }
+
+        self.divider = 0;
+        for channel in self.channels.iter_mut() {
+            channel.clock();
+        }
+
+        // The noise is clocked at half the rate of the tone channels.
+        self.noise_counter += 1;
+        if self.noise_counter >= max( self.noise_period, 1 ) * 2 {
+            self.noise_counter = 0;
+            let feedback = (self.noise_shift_register ^ (self.noise_shift_register >> 3)) & 1;
+            self.noise_shift_register = (self.noise_shift_register >> 1) | (feedback << 16);
+        }
+
+        self.envelope_counter += 1;
+        if self.envelope_counter >= max( self.envelope_period, 1 ) {
+            self.envelope_counter = 0;
+            self.clock_envelope();
+        }
+    }
+
+    fn clock_envelope( &mut self ) {
+        if self.is_envelope_holding {
+            return;
+        }
+
+        self.envelope_step += 1;
+        if self.envelope_step < 32 {
+            return;
+        }
+
+        let shape = self.envelope_shape;
+        if is_b3_set( shape ) == false {
+            // Go silent at the end of the first cycle.
+            self.is_envelope_attacking = false;
+            self.is_envelope_holding = true;
+            self.envelope_step = 31;
+        } else {
+            if is_b1_set( shape ) {
+                self.is_envelope_attacking = !self.is_envelope_attacking;
+            }
+
+            if is_b0_set( shape ) {
+                self.is_envelope_holding = true;
+                self.envelope_step = 31;
+            } else {
+                self.envelope_step = 0;
+            }
+        }
+    }
+
+    fn envelope_level( &self ) -> u8 {
+        if self.is_envelope_attacking {
+            self.envelope_step
+        } else {
+            31 - self.envelope_step
+        }
+    }
+
+    fn channel_output( &self, index: usize ) -> u8 {
+        let channel = &self.channels[ index ];
+        let is_tone_disabled = self.mixer & (1 << index) != 0;
+        let is_noise_disabled = self.mixer & (1 << (index + 3)) != 0;
+        let is_high = (channel.output || is_tone_disabled) && (self.noise_shift_register & 1 != 0 || is_noise_disabled);
+        if is_high == false {
+            return 0;
+        }
+
+        let level = if channel.uses_envelope {
+            self.envelope_level()
+        } else if channel.volume == 0 {
+            0
+        } else {
+            channel.volume * 2 + 1
+        };
+
+        VOLUME_TABLE[ level as usize ]
+    }
+
+    fn output( &self ) -> F32 {
+        let sum = u8_to_f32( self.channel_output( 0 ) ) + u8_to_f32( self.channel_output( 1 ) ) + u8_to_f32( self.channel_output( 2 ) );
+        sum / f32!(1700.0)
+    }
+}
+
+pub struct MapperFME7 {
+    inner: BankedGenericMapper,
+
+    selected_command: u8,
+    is_save_ram_selected: bool,
+    is_save_ram_enabled: bool,
+
+    irq_counter: u16,
+    is_irq_counter_enabled: bool,
+    is_irq_enabled: bool,
+    irq_pending: bool,
+
+    audio: Sunsoft5B
+}
+
+impl MapperFME7 {
+    pub fn from_rom( rom: NesRom ) -> Result< Self, LoadError > {
+        let mut mapper = MapperFME7 {
+            inner: BankedGenericMapper::from_rom( rom )?,
+
+            selected_command: 0,
+            is_save_ram_selected: false,
+            is_save_ram_enabled: false,
+
+            irq_counter: 0,
+            is_irq_counter_enabled: false,
+            is_irq_enabled: false,
+            irq_pending: false,
+
+            audio: Sunsoft5B::new()
+        };
+
+        let last_bank = mapper.inner.last_rom_8k_bank();
+        mapper.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::Ox6000, 0 );
+        mapper.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::Ox8000, 0 );
+        mapper.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::OxA000, 0 );
+        mapper.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::OxC000, 0 );
+        mapper.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::OxE000, last_bank );
+        mapper.inner.set_cpu_8k_writable( bank::CPU_8K::Ox6000, false );
+
+        Ok( mapper )
+    }
+
+    fn execute_command( &mut self, value: u8 ) {
+        match self.selected_command {
+            command @ 0x0..=0x7 => {
+                self.inner.set_ppu_1k_bank_to_bank( bank::PPU_1K_PATTERN_BANKS[ command as usize ], value as u16 );
+            },
+            0x8 => {
+                let bank = value.get_bits( 0b0011_1111 );
+                self.is_save_ram_selected = is_b6_set( value );
+                self.is_save_ram_enabled = is_b7_set( value );
+                if self.is_save_ram_selected {
+                    self.inner.set_cpu_8k_bank_to_save_ram_bank( bank::CPU_8K::Ox6000, bank );
+                    self.inner.set_cpu_8k_writable( bank::CPU_8K::Ox6000, self.is_save_ram_enabled );
+                } else {
+                    self.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::Ox6000, bank );
+                    self.inner.set_cpu_8k_writable( bank::CPU_8K::Ox6000, false );
+                }
+            },
+            0x9 => self.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::Ox8000, value.get_bits( 0b0011_1111 ) ),
+            0xA => self.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::OxA000, value.get_bits( 0b0011_1111 ) ),
+            0xB => self.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::OxC000, value.get_bits( 0b0011_1111 ) ),
+            0xC => {
+                match value.get_bits( 0b0000_0011 ) {
+                    0 => self.inner.set_vertical_mirroring(),
+                    1 => self.inner.set_horizontal_mirroring(),
+                    2 => self.inner.set_only_lower_bank_mirroring(),
+                    _ => self.inner.set_only_upper_bank_mirroring()
+                }
+            },
+            0xD => {
+                self.is_irq_enabled = is_b0_set( value );
+                self.is_irq_counter_enabled = is_b7_set( value );
+                self.irq_pending = false;
+            },
+            0xE => self.irq_counter.replace_bits( 0x00FF, value as u16 ),
+            _ => self.irq_counter.replace_bits( 0xFF00, value as u16 )
+        }
+    }
+}
+
+impl Mapper for MapperFME7 {
+    fn peek_rom( &self, address: u16 ) -> u8 {
+        self.inner.peek_rom( address )
+    }
+
+    fn poke_rom( &mut self, address: u16, value: u8 ) {
+        match address {
+            0x8000..=0x9FFF => self.selected_command = value.get_bits( 0b0000_1111 ),
+            0xA000..=0xBFFF => self.execute_command( value ),
+            0xC000..=0xDFFF => self.audio.selected_register = value.get_bits( 0b0000_1111 ),
+            _ => self.audio.poke_register( value )
+        }
+    }
+
+    fn peek_sram( &self, address: u16 ) -> u8 {
+        if self.is_save_ram_selected && self.is_save_ram_enabled == false {
+            // Open bus.
+            return (address >> 8) as u8;
+        }
+
+        self.inner.peek_sram( address )
+    }
+
+    fn poke_sram( &mut self, address: u16, value: u8 ) {
+        self.inner.poke_sram( address, value )
+    }
+
+    fn peek_video_memory( &self, address: u16 ) -> u8 {
+        self.inner.peek_video_memory( address )
+    }
+
+    fn poke_video_memory( &mut self, address: u16, value: u8 ) {
+        self.inner.poke_video_memory( address, value )
+    }
+
+    fn on_cpu_cycle( &mut self ) {
+        if self.is_irq_counter_enabled {
+            self.irq_counter = self.irq_counter.wrapping_sub( 1 );
+            if self.irq_counter == 0xFFFF && self.is_irq_enabled {
+                self.irq_pending = true;
+            }
+        }
+
+        self.audio.clock();
+    }
+
+    fn irq_line( &self ) -> bool {
+        self.irq_pending
+    }
+
+    fn audio_output( &self ) -> F32 {
+        self.audio.output()
+    }
+
+    fn info( &self ) -> MapperInfo {
+        let mut info = self.inner.describe( "FME-7", Some( 69 ) );
+        info.add_register( "command", self.selected_command as u32 );
+        info.add_register( "RAM selected", self.is_save_ram_selected as u32 );
+        info.add_register( "RAM enabled", self.is_save_ram_enabled as u32 );
+        info.add_register( "IRQ counter", self.irq_counter as u32 );
+        info.add_register( "IRQ counter enabled", self.is_irq_counter_enabled as u32 );
+        info.add_register( "IRQ enabled", self.is_irq_enabled as u32 );
+        info
+    }
+}
+
+#[test]
+fn test_fme7_banks() {
+    use generic_mapper::{create_test_rom, test_rom_8k_bank, test_vrom_1k_bank};
+
+    let rom = create_test_rom( 69, 256 * 1024, 256 * 1024 );
+    let mut mapper = MapperFME7::from_rom( rom ).unwrap();
+    assert_eq!( test_rom_8k_bank( &mapper, 0x8000 ), 0 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0xC000 ), 0 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0xE000 ), 31 );
+
+    mapper.poke_rom( 0x8000, 0x9 );
+    mapper.poke_rom( 0xA000, 5 );
+    mapper.poke_rom( 0x8000, 0xB );
+    mapper.poke_rom( 0xA000, 30 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0x8000 ), 5 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0xC000 ), 30 );
+
+    mapper.poke_rom( 0x8000, 0x0 );
+    mapper.poke_rom( 0xA000, 9 );
+    mapper.poke_rom( 0x8000, 0x7 );
+    mapper.poke_rom( 0xA000, 12 );
+    assert_eq!( test_vrom_1k_bank( &mapper, 0x0000 ), 9 );
+    assert_eq!( test_vrom_1k_bank( &mapper, 0x1C00 ), 12 );
+
+    // ROM at 0x6000.
+    mapper.poke_rom( 0x8000, 0x8 );
+    mapper.poke_rom( 0xA000, 7 );
+    assert_eq!( mapper.peek_sram( 0x6001 ), 7 );
+    mapper.poke_sram( 0x6001, 0xAA );
+    assert_eq!( mapper.peek_sram( 0x6001 ), 7 );
+
+    // RAM at 0x6000.
+    mapper.poke_rom( 0xA000, 0b1100_0000 );
+    mapper.poke_sram( 0x6000, 0xAA );
+    assert_eq!( mapper.peek_sram( 0x6000 ), 0xAA );
+
+    mapper.poke_rom( 0xA000, 0b0100_0000 );
+    mapper.poke_sram( 0x6000, 0xBB );
+    assert_eq!( mapper.peek_sram( 0x6000 ), 0x60 );
+    mapper.poke_rom( 0xA000, 0b1100_0000 );
+    assert_eq!( mapper.peek_sram( 0x6000 ), 0xAA );
+}
+
+#[test]
+fn test_fme7_irq() {
+    use generic_mapper::create_test_rom;
+
+    let rom = create_test_rom( 69, 128 * 1024, 128 * 1024 );
+    let mut mapper = MapperFME7::from_rom( rom ).unwrap();
+    mapper.poke_rom( 0x8000, 0xE );
+    mapper.poke_rom( 0xA000, 3 );
+    mapper.poke_rom( 0x8000, 0xF );
+    mapper.poke_rom( 0xA000, 0 );
+    mapper.poke_rom( 0x8000, 0xD );
+    mapper.poke_rom( 0xA000, 0x81 );
+
+    for _ in 0..3 {
+        mapper.on_cpu_cycle();
+        assert!( mapper.irq_line() == false );
+    }
+
+    mapper.on_cpu_cycle();
+    assert!( mapper.irq_line() );
+
+    mapper.poke_rom( 0xA000, 0x81 );
+    assert!( mapper.irq_line() == false );
+}
+
+#[test]
+fn test_sunsoft_5b_audio() {
+    let mut audio = Sunsoft5B::new();
+    assert!( audio.output() == f32!(0.0) );
+
+    // The channel A, with the tone enabled, the noise disabled and the maximum volume.
+    let writes = [(0x0, 0x10), (0x1, 0x00), (0x7, 0b111_110), (0x8, 0x0F)];
+    for &(register, value) in writes.iter() {
+        audio.selected_register = register;
+        audio.poke_register( value );
+    }
+
+    let mut was_high = false;
+    let mut was_low = false;
+    for _ in 0..16 * 16 * 4 {
+        audio.clock();
+        if audio.output() > f32!(0.0) {
+            was_high = true;
+        } else {
+            was_low = true;
+        }
+    }
+
+    assert!( was_high && was_low );
+
+    // An envelope which decays and then stays silent.
+    audio.selected_register = 0x8;
+    audio.poke_register( 0x10 );
+    audio.selected_register = 0xB;
+    audio.poke_register( 1 );
+    audio.selected_register = 0xD;
+    audio.poke_register( 0b0000 );
+    assert_eq!( audio.envelope_level(), 31 );
+    for _ in 0..16 * 32 {
+        audio.clock();
+    }
+    assert_eq!( audio.envelope_level(), 0 );
+    for _ in 0..16 * 64 {
+        audio.clock();
+    }
+    assert_eq!( audio.envelope_level(), 0 );
+
+    // An envelope which rises and then holds.
+    audio.poke_register( 0b1101 );
+    assert_eq!( audio.envelope_level(), 0 );
+    for _ in 0..16 * 64 {
+        audio.clock();
+    }
+    assert_eq!( audio.envelope_level(), 31 );
+}
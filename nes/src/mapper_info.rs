@@ -0,0 +1,120 @@
+use alloc::vec::Vec;
+
+/*
+    A snapshot of the mapper's state for the debugging tools; see `Mapper::info`.
+
+    The banks are described in terms of the memory chips on the cartridge, so
+    that the CPU and the PPU addresses can be translated into offsets into
+    the ROM and the VROM. Those are relative to the start of the PRG and the CHR
+    data; the offsets into the iNES file itself, which also account for the header
+    and the trainer, are only known to the emulator which loaded the file.
+*/
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum MemoryKind {
+    SaveRam,
+    Rom,
+    // The VROM, or the VRAM on the boards which don't have any VROM.
+    VideoMemory,
+    // The console's internal nametable RAM, and any extra nametable RAM on the cartridge.
+    BackgroundTilemaps,
+    Other
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct BankMapping {
+    pub address: u16,
+    pub size: u16,
+    // `None` when nothing is mapped at the given address.
+    pub memory: Option< MemoryKind >,
+    // Where the bank starts, relative to the start of the memory it's mapped to.
+    pub offset: u32,
+    pub is_writable: bool
+}
+
+impl BankMapping {
+    fn contains( &self, address: u16 ) -> bool {
+        address >= self.address && (address - self.address) < self.size
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum NametableMirroring {
+    Horizontal,
+    Vertical,
+    OnlyLowerBank,
+    OnlyUpperBank,
+    FourScreen,
+    Other
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MapperInfo {
+    pub name: &'static str,
+    // The iNES mapper number; `None` for the Famicom Disk System.
+    pub number: Option< u8 >,
+    // 0x6000 - 0xFFFF, in 8kb banks.
+    pub cpu_banks: Vec< BankMapping >,
+    // 0x0000 - 0x2FFF, in 1kb banks.
+    pub ppu_banks: Vec< BankMapping >,
+    pub mirroring: NametableMirroring,
+    pub irq_line: bool,
+    // Where the ROM and the VROM start in the iNES file the mapper was loaded
+    // from; `None` when there is no such file, or no VROM in it.
+    pub rom_file_offset: Option< u32 >,
+    pub video_rom_file_offset: Option< u32 >,
+    // The mapper specific registers, in no particular format.
+    pub registers: Vec< (&'static str, u32) >
+}
+
+impl MapperInfo {
+    pub fn new( name: &'static str, number: Option< u8 > ) -> Self {
+        MapperInfo {
+            name: name,
+            number: number,
+            cpu_banks: Vec::new(),
+            ppu_banks: Vec::new(),
+            mirroring: NametableMirroring::Other,
+            irq_line: false,
+            rom_file_offset: None,
+            video_rom_file_offset: None,
+            registers: Vec::new()
+        }
+    }
+
+    pub fn add_register( &mut self, name: &'static str, value: u32 ) {
+        self.registers.push( (name, value) );
+    }
+
+    pub fn register( &self, name: &str ) -> Option< u32 > {
+        self.registers.iter().find( |&&(register, _)| register == name ).map( |&(_, value)| value )
+    }
+
+    fn translate( banks: &[BankMapping], address: u16, memory: MemoryKind ) -> Option< u32 > {
+        banks.iter()
+            .find( |bank| bank.contains( address ) )
+            .filter( |bank| bank.memory == Some( memory ) )
+            .map( |bank| bank.offset + (address - bank.address) as u32 )
+    }
+
+    // Translates a CPU address into an offset into the ROM, if the ROM is currently mapped there.
+    // The offset is relative to the start of the ROM, not to the start of the iNES file.
+    pub fn rom_offset( &self, address: u16 ) -> Option< u32 > {
+        Self::translate( &self.cpu_banks, address, MemoryKind::Rom )
+    }
+
+    // Translates a PPU address into an offset into the VROM (or the VRAM), if it's currently mapped there.
+    pub fn video_memory_offset( &self, address: u16 ) -> Option< u32 > {
+        Self::translate( &self.ppu_banks, address, MemoryKind::VideoMemory )
+    }
+
+    // Translates a CPU address into an offset into the iNES file, if the ROM is currently mapped there.
+    pub fn file_offset( &self, address: u16 ) -> Option< u32 > {
+        Some( self.rom_file_offset? + self.rom_offset( address )? )
+    }
+
+    // Translates a PPU address into an offset into the iNES file, if the VROM is currently mapped there.
+    pub fn video_file_offset( &self, address: u16 ) -> Option< u32 > {
+        Some( self.video_rom_file_offset? + self.video_memory_offset( address )? )
+    }
+}
@@ -1,13 +1,26 @@
+use core::cmp::min;
+
 use emumisc::{BitExtra, is_b0_set, is_b7_set};
 use rom::{NesRom, LoadError};
 use mappers::Mapper;
+use mapper_info::MapperInfo;
 use generic_mapper::{bank, BankedGenericMapper};
 use memory_map::LOWER_ROM_ADDRESS;
 
-// FIXME: This implementation of MMC1 is not yet accurate.
-// At very least the serial writes to the serial port
-// should be ignored, and they are not currently.
-// This does break a few games.
+/*
+    Besides the plain SxROM boards this also supports the ones
+    which repurpose the upper bits of the VROM bank registers:
+
+        SUROM - 512kb of ROM; bit 4 selects the 256kb half of the ROM,
+        SOROM - 16kb of save RAM; bit 3 selects the save RAM bank,
+        SXROM - 512kb of ROM and 32kb of save RAM; bit 4 selects the 256kb
+                half of the ROM while bits 2 and 3 select the save RAM bank.
+
+    On the real hardware in the independent VROM switching mode the register
+    which is used depends on the address the PPU is currently fetching from;
+    here we always use the lower VROM bank register since the games
+    write the same value to both of them anyway.
+*/
 
 const SHIFT_REGISTER_DEFAULT_VALUE: u8 = 0b10000;
 
@@ -32,7 +45,9 @@ pub struct MapperMMC1 {
     vrom_switching_mode: SwitchingModeForVROM,
     selected_rom_bank: u8,
     selected_lower_vrom_bank: u8,
-    selected_upper_vrom_bank: u8
+    selected_upper_vrom_bank: u8,
+
+    cycles_since_last_write: u32
 }
 
 impl MapperMMC1 {
@@ -45,7 +60,9 @@ impl MapperMMC1 {
             vrom_switching_mode: SwitchingModeForVROM::Independent,
             selected_rom_bank: 0,
             selected_lower_vrom_bank: 0,
-            selected_upper_vrom_bank: 1
+            selected_upper_vrom_bank: 1,
+
+            cycles_since_last_write: !0
         };
 
         mapper.update_mapping();
@@ -53,23 +70,38 @@ impl MapperMMC1 {
     }
 
     fn update_mapping( &mut self ) {
+        let outer_rom_bank = if self.inner.rom_16k_bank_count() > 16 {
+            self.selected_lower_vrom_bank & 0b10000
+        } else {
+            0
+        };
+
+        let rom_bank = outer_rom_bank | self.selected_rom_bank;
         match self.rom_switching_mode {
             SwitchingModeForROM::Fused => {
-                let bank = (self.selected_rom_bank / 2) * 2;
+                let bank = (rom_bank / 2) * 2;
                 self.inner.set_cpu_lower_16k_bank_to_bank( bank );
                 self.inner.set_cpu_upper_16k_bank_to_bank( bank.wrapping_add( 1 ) );
             },
             SwitchingModeForROM::OnlyLower => {
-                let last_bank = self.inner.last_rom_16k_bank();
-                self.inner.set_cpu_lower_16k_bank_to_bank( self.selected_rom_bank );
+                let last_bank = outer_rom_bank | min( self.inner.last_rom_16k_bank(), 0b01111 );
+                self.inner.set_cpu_lower_16k_bank_to_bank( rom_bank );
                 self.inner.set_cpu_upper_16k_bank_to_bank( last_bank );
             },
             SwitchingModeForROM::OnlyUpper => {
-                self.inner.set_cpu_lower_16k_bank_to_bank( 0 ); // First bank.
-                self.inner.set_cpu_upper_16k_bank_to_bank( self.selected_rom_bank );
+                self.inner.set_cpu_lower_16k_bank_to_bank( outer_rom_bank ); // First bank.
+                self.inner.set_cpu_upper_16k_bank_to_bank( rom_bank );
             }
         }
 
+        let save_ram_bank = match self.inner.save_ram_8k_bank_count() {
+            2 => self.selected_lower_vrom_bank.get_bits( 0b01000 ),
+            4 => self.selected_lower_vrom_bank.get_bits( 0b01100 ),
+            _ => 0
+        };
+
+        self.inner.set_cpu_save_ram_8k_bank_to_bank( save_ram_bank );
+
         match self.vrom_switching_mode {
             SwitchingModeForVROM::Fused => {
                 let bank = (self.selected_lower_vrom_bank / 2) * 2;
@@ -103,6 +135,15 @@ impl Mapper for MapperMMC1 {
         // MMC1 is configured through a serial port, connected
         // to a shift register.
 
+        // Writes on consecutive cycles (e.g. the double write
+        // done by read-modify-write instructions) are ignored
+        // except for the first one.
+        let is_consecutive_write = self.cycles_since_last_write == 1;
+        self.cycles_since_last_write = 0;
+        if is_consecutive_write {
+            return;
+        }
+
         if is_b7_set( value ) {
             // The shift register is reset when a value with 7th bit
             // set is written.
@@ -183,6 +224,32 @@ impl Mapper for MapperMMC1 {
     fn poke_video_memory( &mut self, address: u16, value: u8 ) {
         self.inner.poke_video_memory( address, value )
     }
+
+    fn on_cpu_cycle( &mut self ) {
+        self.cycles_since_last_write = self.cycles_since_last_write.saturating_add( 1 );
+    }
+
+    fn info( &self ) -> MapperInfo {
+        let rom_switching_mode = match self.rom_switching_mode {
+            SwitchingModeForROM::Fused => 0,
+            SwitchingModeForROM::OnlyUpper => 2,
+            SwitchingModeForROM::OnlyLower => 3
+        };
+
+        let vrom_switching_mode = match self.vrom_switching_mode {
+            SwitchingModeForVROM::Fused => 0,
+            SwitchingModeForVROM::Independent => 1
+        };
+
+        let mut info = self.inner.describe( "MMC1", Some( 1 ) );
+        info.add_register( "shift register", self.shift_register as u32 );
+        info.add_register( "ROM switching mode", rom_switching_mode );
+        info.add_register( "VROM switching mode", vrom_switching_mode );
+        info.add_register( "ROM bank", self.selected_rom_bank as u32 );
+        info.add_register( "lower VROM bank", self.selected_lower_vrom_bank as u32 );
+        info.add_register( "upper VROM bank", self.selected_upper_vrom_bank as u32 );
+        info
+    }
 }
 
 #[cfg(test)]
@@ -190,7 +257,7 @@ mod tests {
     use super::MapperMMC1;
 
     use generic_mapper::bank;
-    use rom::{Mirroring, NesRom};
+    use rom::{Mirroring, NesRom, Region};
     use memory_map::{
         SRAM_ADDRESS,
         LOWER_ROM_ADDRESS,
@@ -200,6 +267,7 @@ mod tests {
     };
 
     use mappers::Mapper;
+    use virtual_nes::{State, Context, Interface};
 
     const ROM_BANK_SIZE: usize = 16 * 1024;
     const VROM_BANK_SIZE: usize = 4 * 1024;
@@ -256,10 +324,16 @@ mod tests {
 
         let rom = NesRom {
             mapper: 1,
+            submapper: 0,
             rom: rom,
             video_rom: vrom,
             save_ram_length: 8 * 1024,
-            mirroring: Mirroring::Horizontal
+            mirroring: Mirroring::Horizontal,
+            mirroring_bit: false,
+            has_battery: false,
+            has_trainer: false,
+            region: Region::Ntsc,
+            input_device: 0
         };
 
         let mut mapper = MapperMMC1::from_rom( rom ).unwrap();
@@ -497,4 +571,166 @@ mod tests {
         assert_eq!( mapper.peek_video_memory( UPPER_VROM_ADDRESS + 0 ), 140 );
         assert_eq!( mapper.peek_video_memory( UPPER_VROM_ADDRESS + 1 ), 141 );
     }
+
+    #[test]
+    fn consecutive_writes_are_ignored() {
+        let mut mapper = setup();
+        write_reg( &mut mapper, CONTROL_REG, ROM_SWITCHING_MODE_ONLY_LOWER );
+        write_reg( &mut mapper, ROM_BANK_REG, 0 );
+
+        mapper.on_cpu_cycle();
+        mapper.on_cpu_cycle();
+        mapper.poke_rom( 0xE000, 1 );
+        for _ in 0..4 {
+            // These are done on the cycle right after the previous write.
+            mapper.on_cpu_cycle();
+            mapper.poke_rom( 0xE000, 1 ); // Should be ignored.
+        }
+
+        for _ in 0..4 {
+            mapper.on_cpu_cycle();
+            mapper.on_cpu_cycle();
+            mapper.poke_rom( 0xE000, 0 );
+        }
+
+        assert_eq!( mapper.peek_rom( LOWER_ROM_ADDRESS + 0 ), 20 );
+        assert_eq!( mapper.peek_rom( UPPER_ROM_ADDRESS + 0 ), 40 );
+    }
+
+    struct Instance {
+        state: State
+    }
+
+    impl Context for Instance {
+        fn state_mut( &mut self ) -> &mut State {
+            &mut self.state
+        }
+
+        fn state( &self ) -> &State {
+            &self.state
+        }
+    }
+
+    #[test]
+    fn read_modify_write_instruction_writes_only_once() {
+        // A 128kb SNROM cartridge where every bank has its index at 0x0100.
+        let mut rom = vec![ 0; 16 + 8 * ROM_BANK_SIZE ];
+        rom[ 0..4 ].copy_from_slice( b"NES\x1A" );
+        rom[ 4 ] = 8;
+        rom[ 6 ] = 0x10;
+        for bank in 0..8 {
+            rom[ 16 + bank * ROM_BANK_SIZE + 0x0100 ] = bank as u8;
+        }
+
+        let program = [
+            0xA9, 0x80,       // LDA #$80
+            0x8D, 0x00, 0x80, // STA $8000 ; Reset the shift register.
+            0xEE, 0x00, 0xE0, // INC $E000 ; Writes 0 and then 1 on the next cycle.
+            0xA9, 0x01,       // LDA #$01
+            0x8D, 0x00, 0xE0, // STA $E000
+            0xA9, 0x00,       // LDA #$00
+            0x8D, 0x00, 0xE0, // STA $E000
+            0x8D, 0x00, 0xE0, // STA $E000
+            0x8D, 0x00, 0xE0, // STA $E000
+            0xAD, 0x00, 0x81, // LDA $8100
+            0x85, 0x00,       // STA $00
+            0x4C, 0x1D, 0xC0  // JMP $C01D
+        ];
+
+        let last_bank = 16 + 7 * ROM_BANK_SIZE;
+        rom[ last_bank..last_bank + program.len() ].copy_from_slice( &program );
+        rom[ last_bank + 0x3FFC ] = 0x00; // The reset vector.
+        rom[ last_bank + 0x3FFD ] = 0xC0;
+
+        let mut instance = Instance { state: State::new() };
+        instance.load_rom( &rom ).unwrap();
+        instance.execute_for_a_frame().unwrap();
+
+        // If the second write of INC wasn't ignored we'd have switched to the bank #6.
+        assert_eq!( instance.peek_memory( 0x0000 ), 2 );
+    }
+
+    fn setup_sxrom( rom_bank_count: usize, save_ram_length: u32 ) -> MapperMMC1 {
+        let mut rom = vec![ 0; rom_bank_count * ROM_BANK_SIZE ];
+        for bank in 0..rom_bank_count {
+            rom[ bank * ROM_BANK_SIZE ] = bank as u8;
+        }
+
+        let rom = NesRom {
+            mapper: 1,
+            submapper: 0,
+            rom: rom,
+            video_rom: Vec::new(),
+            save_ram_length: save_ram_length,
+            mirroring: Mirroring::Horizontal,
+            mirroring_bit: false,
+            has_battery: false,
+            has_trainer: false,
+            region: Region::Ntsc,
+            input_device: 0
+        };
+
+        let mut mapper = MapperMMC1::from_rom( rom ).unwrap();
+        mapper.poke_rom( 0x8000, RESET_SHIFT_REGISTER );
+        write_reg( &mut mapper, CONTROL_REG, ROM_SWITCHING_MODE_ONLY_LOWER | VROM_SWITCHING_MODE_FUSED );
+        mapper
+    }
+
+    #[test]
+    fn surom_rom_halves() {
+        let mut mapper = setup_sxrom( 32, 8 * 1024 );
+
+        write_reg( &mut mapper, ROM_BANK_REG, 3 );
+        assert_eq!( mapper.peek_rom( LOWER_ROM_ADDRESS ), 3 );
+        assert_eq!( mapper.peek_rom( UPPER_ROM_ADDRESS ), 15 );
+
+        write_reg( &mut mapper, LOWER_VROM_BANK_REG, 0b10000 );
+        assert_eq!( mapper.peek_rom( LOWER_ROM_ADDRESS ), 19 );
+        assert_eq!( mapper.peek_rom( UPPER_ROM_ADDRESS ), 31 );
+
+        write_reg( &mut mapper, CONTROL_REG, ROM_SWITCHING_MODE_ONLY_UPPER );
+        assert_eq!( mapper.peek_rom( LOWER_ROM_ADDRESS ), 16 );
+        assert_eq!( mapper.peek_rom( UPPER_ROM_ADDRESS ), 19 );
+
+        write_reg( &mut mapper, CONTROL_REG, ROM_SWITCHING_MODE_FUSED_A );
+        assert_eq!( mapper.peek_rom( LOWER_ROM_ADDRESS ), 18 );
+        assert_eq!( mapper.peek_rom( UPPER_ROM_ADDRESS ), 19 );
+
+        write_reg( &mut mapper, LOWER_VROM_BANK_REG, 0 );
+        assert_eq!( mapper.peek_rom( LOWER_ROM_ADDRESS ), 2 );
+        assert_eq!( mapper.peek_rom( UPPER_ROM_ADDRESS ), 3 );
+    }
+
+    fn test_save_ram_banks( mapper: &mut MapperMMC1, banks: &[u8] ) {
+        for (index, &bank) in banks.iter().enumerate() {
+            write_reg( mapper, LOWER_VROM_BANK_REG, bank );
+            mapper.poke_sram( SRAM_ADDRESS, index as u8 + 1 );
+        }
+
+        for (index, &bank) in banks.iter().enumerate() {
+            write_reg( mapper, LOWER_VROM_BANK_REG, bank );
+            assert_eq!( mapper.peek_sram( SRAM_ADDRESS ), index as u8 + 1 );
+        }
+    }
+
+    #[test]
+    fn sorom_save_ram_banks() {
+        let mut mapper = setup_sxrom( 16, 16 * 1024 );
+        test_save_ram_banks( &mut mapper, &[0b00000, 0b01000] );
+
+        // The other bits are ignored.
+        write_reg( &mut mapper, LOWER_VROM_BANK_REG, 0b00111 );
+        assert_eq!( mapper.peek_sram( SRAM_ADDRESS ), 1 );
+    }
+
+    #[test]
+    fn sxrom_save_ram_banks() {
+        let mut mapper = setup_sxrom( 32, 32 * 1024 );
+        test_save_ram_banks( &mut mapper, &[0b00000, 0b00100, 0b01000, 0b01100] );
+
+        // The bit 4 still selects the ROM half.
+        write_reg( &mut mapper, LOWER_VROM_BANK_REG, 0b11000 );
+        assert_eq!( mapper.peek_sram( SRAM_ADDRESS ), 3 );
+        assert_eq!( mapper.peek_rom( UPPER_ROM_ADDRESS ), 31 );
+    }
 }
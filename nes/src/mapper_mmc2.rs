@@ -0,0 +1,277 @@
+use emumisc::BitExtra;
+use rom::{NesRom, LoadError};
+use mappers::Mapper;
+use mapper_info::MapperInfo;
+use generic_mapper::{bank, BankedGenericMapper};
+
+/*
+    MMC2 (used by Punch-Out!!) and MMC4 (used by Fire Emblem
+    and a few other games) are almost the same chip.
+
+    Each 4kb half of the VROM has two bank registers, and which one
+    is used depends on a latch which is automatically flipped when
+    the PPU reads the last row of the tile 0xFD or 0xFE:
+
+        0x0FD8            - the lower latch is set to 0xFD,
+        0x0FE8            - the lower latch is set to 0xFE,
+        0x1FD8 - 0x1FDF   - the upper latch is set to 0xFD,
+        0x1FE8 - 0x1FEF   - the upper latch is set to 0xFE.
+
+    On MMC4 the lower latch also responds to the whole 8 byte range.
+    The bank switch takes effect only after the triggering read.
+
+    The registers are:
+
+        0xA000 - 0xAFFF   - the ROM bank at 0x8000; it's a 8kb bank on MMC2
+                            with the rest of the ROM fixed to the last three banks,
+                            and a 16kb bank on MMC4 with the rest fixed to the last bank,
+        0xB000 - 0xBFFF   - the lower VROM bank used when the lower latch is 0xFD,
+        0xC000 - 0xCFFF   - the lower VROM bank used when the lower latch is 0xFE,
+        0xD000 - 0xDFFF   - the upper VROM bank used when the upper latch is 0xFD,
+        0xE000 - 0xEFFF   - the upper VROM bank used when the upper latch is 0xFE,
+        0xF000 - 0xFFFF   - mirroring; 0 - vertical, 1 - horizontal.
+*/
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Chip {
+    MMC2,
+    MMC4
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Latch {
+    FD,
+    FE
+}
+
+pub struct MapperMMC2 {
+    inner: BankedGenericMapper,
+    chip: Chip,
+
+    lower_latch: Latch,
+    upper_latch: Latch,
+    selected_lower_vrom_banks: [u8; 2],
+    selected_upper_vrom_banks: [u8; 2]
+}
+
+impl MapperMMC2 {
+    fn new( rom: NesRom, chip: Chip ) -> Result< Self, LoadError > {
+        let mut mapper = MapperMMC2 {
+            inner: BankedGenericMapper::from_rom( rom )?,
+            chip: chip,
+
+            lower_latch: Latch::FE,
+            upper_latch: Latch::FE,
+            selected_lower_vrom_banks: [0, 0],
+            selected_upper_vrom_banks: [0, 0]
+        };
+
+        match chip {
+            Chip::MMC2 => {
+                // With less than four banks the fixed banks wrap around
+                // the same way the unconnected upper bank bits make them do.
+                let last_bank = mapper.inner.last_rom_8k_bank();
+                mapper.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::Ox8000, 0 );
+                mapper.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::OxA000, last_bank.wrapping_sub( 2 ) );
+                mapper.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::OxC000, last_bank.wrapping_sub( 1 ) );
+                mapper.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::OxE000, last_bank );
+            },
+            Chip::MMC4 => {
+                let last_bank = mapper.inner.last_rom_16k_bank();
+                mapper.inner.set_cpu_lower_16k_bank_to_bank( 0 );
+                mapper.inner.set_cpu_upper_16k_bank_to_bank( last_bank );
+            }
+        }
+
+        mapper.update_vrom_mapping();
+        Ok( mapper )
+    }
+
+    pub fn mmc2_from_rom( rom: NesRom ) -> Result< Self, LoadError > {
+        Self::new( rom, Chip::MMC2 )
+    }
+
+    pub fn mmc4_from_rom( rom: NesRom ) -> Result< Self, LoadError > {
+        Self::new( rom, Chip::MMC4 )
+    }
+
+    fn update_vrom_mapping( &mut self ) {
+        let lower_bank = self.selected_lower_vrom_banks[ self.lower_latch as usize ];
+        let upper_bank = self.selected_upper_vrom_banks[ self.upper_latch as usize ];
+        self.inner.set_ppu_lower_4k_bank_to_bank( lower_bank );
+        self.inner.set_ppu_upper_4k_bank_to_bank( upper_bank );
+    }
+
+    fn update_latches( &mut self, address: u16 ) {
+        let (latch, value) = match (address, self.chip) {
+            (0x0FD8, _) => (&mut self.lower_latch, Latch::FD),
+            (0x0FE8, _) => (&mut self.lower_latch, Latch::FE),
+            (0x0FD9..=0x0FDF, Chip::MMC4) => (&mut self.lower_latch, Latch::FD),
+            (0x0FE9..=0x0FEF, Chip::MMC4) => (&mut self.lower_latch, Latch::FE),
+            (0x1FD8..=0x1FDF, _) => (&mut self.upper_latch, Latch::FD),
+            (0x1FE8..=0x1FEF, _) => (&mut self.upper_latch, Latch::FE),
+            _ => return
+        };
+
+        if *latch != value {
+            *latch = value;
+            self.update_vrom_mapping();
+        }
+    }
+}
+
+impl Mapper for MapperMMC2 {
+    fn peek_sram( &self, address: u16 ) -> u8 {
+        self.inner.peek_sram( address )
+    }
+
+    fn poke_sram( &mut self, address: u16, value: u8 ) {
+        self.inner.poke_sram( address, value )
+    }
+
+    fn peek_rom( &self, address: u16 ) -> u8 {
+        self.inner.peek_rom( address )
+    }
+
+    fn poke_rom( &mut self, address: u16, value: u8 ) {
+        match address {
+            0xA000..=0xAFFF => {
+                let rom_bank = value.get_bits( 0b0000_1111 );
+                match self.chip {
+                    Chip::MMC2 => self.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::Ox8000, rom_bank ),
+                    Chip::MMC4 => self.inner.set_cpu_lower_16k_bank_to_bank( rom_bank )
+                }
+            },
+            0xB000..=0xBFFF => self.selected_lower_vrom_banks[ Latch::FD as usize ] = value.get_bits( 0b0001_1111 ),
+            0xC000..=0xCFFF => self.selected_lower_vrom_banks[ Latch::FE as usize ] = value.get_bits( 0b0001_1111 ),
+            0xD000..=0xDFFF => self.selected_upper_vrom_banks[ Latch::FD as usize ] = value.get_bits( 0b0001_1111 ),
+            0xE000..=0xEFFF => self.selected_upper_vrom_banks[ Latch::FE as usize ] = value.get_bits( 0b0001_1111 ),
+            0xF000..=0xFFFF => {
+                if value.get_bits( 0b0000_0001 ) == 0 {
+                    self.inner.set_vertical_mirroring();
+                } else {
+                    self.inner.set_horizontal_mirroring();
+                }
+            },
+            _ => {}
+        }
+
+        self.update_vrom_mapping();
+    }
+
+    fn peek_video_memory( &self, address: u16 ) -> u8 {
+        self.inner.peek_video_memory( address )
+    }
+
+    fn poke_video_memory( &mut self, address: u16, value: u8 ) {
+        self.inner.poke_video_memory( address, value )
+    }
+
+    fn fetch_video_memory( &mut self, address: u16 ) -> u8 {
+        let value = self.inner.peek_video_memory( address );
+        self.update_latches( address );
+        value
+    }
+
+    fn info( &self ) -> MapperInfo {
+        let (name, number) = match self.chip {
+            Chip::MMC2 => ("MMC2", 9),
+            Chip::MMC4 => ("MMC4", 10)
+        };
+
+        let latch_value = |latch| match latch {
+            Latch::FD => 0xFD,
+            Latch::FE => 0xFE
+        };
+
+        let mut info = self.inner.describe( name, Some( number ) );
+        info.add_register( "lower latch", latch_value( self.lower_latch ) );
+        info.add_register( "upper latch", latch_value( self.upper_latch ) );
+        info.add_register( "lower VROM bank 0xFD", self.selected_lower_vrom_banks[ Latch::FD as usize ] as u32 );
+        info.add_register( "lower VROM bank 0xFE", self.selected_lower_vrom_banks[ Latch::FE as usize ] as u32 );
+        info.add_register( "upper VROM bank 0xFD", self.selected_upper_vrom_banks[ Latch::FD as usize ] as u32 );
+        info.add_register( "upper VROM bank 0xFE", self.selected_upper_vrom_banks[ Latch::FE as usize ] as u32 );
+        info
+    }
+}
+
+#[cfg(test)]
+fn setup_vrom_banks( mapper: &mut MapperMMC2 ) {
+    mapper.poke_rom( 0xB000, 1 );
+    mapper.poke_rom( 0xC000, 2 );
+    mapper.poke_rom( 0xD000, 3 );
+    mapper.poke_rom( 0xE000, 4 );
+}
+
+#[test]
+fn test_mmc2_banks() {
+    use generic_mapper::{create_test_rom, test_rom_8k_bank};
+
+    let rom = create_test_rom( 9, 128 * 1024, 128 * 1024 );
+    let mut mapper = MapperMMC2::mmc2_from_rom( rom ).unwrap();
+    assert_eq!( test_rom_8k_bank( &mapper, 0x8000 ), 0 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0xA000 ), 13 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0xC000 ), 14 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0xE000 ), 15 );
+
+    mapper.poke_rom( 0xA000, 5 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0x8000 ), 5 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0xC000 ), 14 );
+
+    setup_vrom_banks( &mut mapper );
+    assert_eq!( mapper.peek_video_memory( 0x0000 ), 2 );
+    assert_eq!( mapper.peek_video_memory( 0x1000 ), 4 );
+
+    // The switch happens only after the read.
+    assert_eq!( mapper.fetch_video_memory( 0x0FD8 ), 0 );
+    assert_eq!( mapper.peek_video_memory( 0x0000 ), 1 );
+    assert_eq!( mapper.peek_video_memory( 0x1000 ), 4 );
+
+    // On MMC2 the lower latch responds only to a single address.
+    mapper.fetch_video_memory( 0x0FE9 );
+    assert_eq!( mapper.peek_video_memory( 0x0000 ), 1 );
+    mapper.fetch_video_memory( 0x0FE8 );
+    assert_eq!( mapper.peek_video_memory( 0x0000 ), 2 );
+
+    mapper.fetch_video_memory( 0x1FDD );
+    assert_eq!( mapper.peek_video_memory( 0x0000 ), 2 );
+    assert_eq!( mapper.peek_video_memory( 0x1000 ), 3 );
+    mapper.fetch_video_memory( 0x1FEF );
+    assert_eq!( mapper.peek_video_memory( 0x1000 ), 4 );
+
+    // Peeking doesn't flip the latches.
+    mapper.peek_video_memory( 0x1FD8 );
+    assert_eq!( mapper.peek_video_memory( 0x1000 ), 4 );
+}
+
+#[test]
+fn test_mmc2_small_rom() {
+    use generic_mapper::{create_test_rom, test_rom_8k_bank};
+
+    let rom = create_test_rom( 9, 16 * 1024, 128 * 1024 );
+    let mapper = MapperMMC2::mmc2_from_rom( rom ).unwrap();
+    assert_eq!( test_rom_8k_bank( &mapper, 0x8000 ), 0 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0xA000 ), 1 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0xC000 ), 0 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0xE000 ), 1 );
+}
+
+#[test]
+fn test_mmc4_banks() {
+    use generic_mapper::create_test_rom;
+
+    let rom = create_test_rom( 10, 128 * 1024, 128 * 1024 );
+    let mut mapper = MapperMMC2::mmc4_from_rom( rom ).unwrap();
+    assert_eq!( mapper.peek_rom( 0x8000 ), 0 );
+    assert_eq!( mapper.peek_rom( 0xC000 ), 7 );
+
+    mapper.poke_rom( 0xA000, 5 );
+    assert_eq!( mapper.peek_rom( 0x8000 ), 5 );
+    assert_eq!( mapper.peek_rom( 0xC000 ), 7 );
+
+    setup_vrom_banks( &mut mapper );
+    mapper.fetch_video_memory( 0x0FDF );
+    assert_eq!( mapper.peek_video_memory( 0x0000 ), 1 );
+    mapper.fetch_video_memory( 0x0FEA );
+    assert_eq!( mapper.peek_video_memory( 0x0000 ), 2 );
+}
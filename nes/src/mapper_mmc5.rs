@@ -0,0 +1,1083 @@
+use core::cmp::max;
+
+use emumisc::{BitExtra, is_b0_set, is_b1_set, is_b5_set, is_b6_set, is_b7_set};
+use rom::{NesRom, LoadError, Mirroring};
+use mappers::Mapper;
+use mapper_info::MapperInfo;
+use generic_mapper::{bank, BankedGenericMapper};
+use virtual_apu::{VolumeGenerator, LENGTH_COUNTER_LOOKUP_TABLE, SQUARE_CHANNEL_DUTY_TABLE};
+use float::{F32, u8_to_f32};
+
+/*
+    MMC5 (ExROM; used by Castlevania III, the Koei games and a few others)
+    is the most complex of the Nintendo made mappers.
+
+    The registers are:
+
+        0x5000 - 0x5007   - two pulse channels, just like the APU's
+                            except without the frequency sweep,
+        0x5010            - PCM mode; only the write mode is emulated,
+        0x5011            - raw PCM output,
+        0x5015            - pulse channel enable/status,
+        0x5100            - ROM mode; 0 - one 32kb bank, 1 - two 16kb banks,
+                            2 - one 16kb bank and two 8kb banks, 3 - four 8kb banks,
+        0x5101            - VROM mode; 0 - 8kb banks, 1 - 4kb banks,
+                            2 - 2kb banks, 3 - 1kb banks,
+        0x5102, 0x5103    - RAM write protection; the RAM is only writable
+                            when these are set to 0b10 and 0b01 respectively,
+        0x5104            - ExRAM mode; 0 - extra nametable, 1 - extended attributes,
+                            2 - general purpose RAM, 3 - general purpose ROM,
+        0x5105            - nametable mapping; two bits for every nametable,
+                            0 - CIRAM page 0, 1 - CIRAM page 1, 2 - ExRAM, 3 - fill mode,
+        0x5106            - the tile used by the fill mode,
+        0x5107            - the palette used by the fill mode,
+        0x5113            - the RAM bank at 0x6000,
+        0x5114 - 0x5117   - the ROM banks; the bit 7 selects ROM (1) or RAM (0),
+                            and the last register always selects ROM,
+        0x5120 - 0x5127   - the VROM banks of the set A,
+        0x5128 - 0x512B   - the VROM banks of the set B,
+        0x5130            - the upper bits of the VROM banks,
+        0x5200            - vertical split control,
+        0x5201            - vertical split scroll,
+        0x5202            - vertical split VROM bank,
+        0x5203            - the scanline on which the IRQ is triggered,
+        0x5204            - IRQ enable (write) and status (read),
+        0x5205, 0x5206    - unsigned 8x8 multiplier,
+        0x5C00 - 0x5FFF   - ExRAM.
+
+    The scanline IRQ works just like on the real chip: the start of a scanline
+    is detected when the PPU reads the same nametable address three times in a row,
+    which happens only when the two dummy nametable fetches at the end of a scanline
+    are followed by the first fetch of the next one, and the end of the frame
+    is detected when the PPU doesn't read anything for three CPU cycles.
+
+    When 8x16 sprites are enabled the set A is used to fetch the sprites
+    and the set B is used to fetch the background. To tell which fetch
+    is which the mapper snoops on the writes to PPUCTRL and PPUMASK
+    and counts the fetches the PPU makes since the start of the scanline.
+*/
+
+// The length and envelope counters are clocked at a fixed rate of 240Hz.
+const FRAME_COUNTER_PERIOD: u16 = 7457;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum ChrSet {
+    A,
+    B
+}
+
+struct Pulse {
+    enabled: bool,
+    duty: u8,
+    period: u16,
+    timer: u16,
+    current_duty_position: u8,
+    length_counter: u8,
+    length_counter_disabled: bool,
+    volume_generator: VolumeGenerator
+}
+
+impl Pulse {
+    const fn new() -> Pulse {
+        Pulse {
+            enabled: false,
+            duty: 0,
+            period: 0,
+            timer: 0,
+            current_duty_position: 0,
+            length_counter: 0,
+            length_counter_disabled: false,
+            volume_generator: VolumeGenerator::new()
+        }
+    }
+
+    fn poke_control( &mut self, value: u8 ) {
+        self.volume_generator.poke_control( value );
+        self.length_counter_disabled = is_b5_set( value );
+        self.duty = value.get_bits( 0b11000000 );
+    }
+
+    fn poke_period_low( &mut self, value: u8 ) {
+        self.period.replace_bits( 0b00011111111, value as u16 );
+    }
+
+    fn poke_period_high( &mut self, value: u8 ) {
+        if self.enabled {
+            self.length_counter = LENGTH_COUNTER_LOOKUP_TABLE[ value.get_bits( 0b11111000 ) as usize ];
+        }
+
+        self.period.replace_bits( 0b11100000000, value.get_bits( 0b00000111 ) as u16 );
+        self.timer = self.period + 1;
+        self.current_duty_position = 0;
+        self.volume_generator.reset();
+    }
+
+    fn set_enabled( &mut self, value: bool ) {
+        self.enabled = value;
+        if value == false {
+            self.length_counter = 0;
+        }
+    }
+
+    fn clock_timer( &mut self ) {
+        if self.timer == 0 {
+            if self.current_duty_position == 0 {
+                self.current_duty_position = 7;
+            } else {
+                self.current_duty_position -= 1;
+            }
+            self.timer = self.period.wrapping_add( 1 );
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_frame( &mut self ) {
+        self.volume_generator.clock();
+        if self.length_counter_disabled == false && self.length_counter != 0 {
+            self.length_counter -= 1;
+        }
+    }
+
+    // Unlike on the APU the short periods are not silenced.
+    fn output( &self ) -> u8 {
+        if self.length_counter == 0 {
+            0
+        } else {
+            SQUARE_CHANNEL_DUTY_TABLE[ self.duty as usize ][ self.current_duty_position as usize ] * self.volume_generator.volume()
+        }
+    }
+}
+
+pub struct MapperMMC5 {
+    inner: BankedGenericMapper,
+
+    rom_mode: u8,
+    vrom_mode: u8,
+    save_ram_protect: [u8; 2],
+    extended_ram_mode: u8,
+    nametable_mapping: u8,
+    fill_tile: u8,
+    fill_attributes: u8,
+    selected_rom_banks: [u8; 5],
+    selected_vrom_banks: [u16; 12],
+    vrom_upper_bits: u8,
+    last_written_chr_set: ChrSet,
+    mapped_chr_set: ChrSet,
+
+    split_control: u8,
+    split_scroll: u8,
+    split_vrom_bank: u8,
+    split_tile: u8,
+
+    irq_scanline: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    in_frame: bool,
+    scanline_counter: u8,
+    last_fetch_address: u16,
+    repeated_fetch_count: u8,
+    idle_cycle_count: u8,
+
+    multiplicand: u8,
+    multiplier: u8,
+
+    nametables: [u8; 2048],
+    extended_ram: [u8; 1024],
+
+    has_big_sprites: bool,
+    is_rendering_enabled: bool,
+    is_fetching: bool,
+    scanline: u16,
+    fetch_index: u16,
+    extended_attributes: u8,
+
+    pulse_1: Pulse,
+    pulse_2: Pulse,
+    pcm_output: u8,
+    is_pcm_in_read_mode: bool,
+    is_on_odd_cycle: bool,
+    frame_counter: u16
+}
+
+impl MapperMMC5 {
+    pub fn from_rom( mut rom: NesRom ) -> Result< Self, LoadError > {
+        // The boards came with up to 64kb of RAM, and since the iNES header
+        // can't describe how it's split between the chips we just use the maximum.
+        rom.save_ram_length = max( rom.save_ram_length, 64 * 1024 );
+
+        let nametable_mapping = match rom.mirroring {
+            Mirroring::Horizontal => 0b01_01_00_00,
+            _ => 0b01_00_01_00
+        };
+
+        let mut mapper = MapperMMC5 {
+            inner: BankedGenericMapper::from_rom( rom )?,
+
+            rom_mode: 3,
+            vrom_mode: 0,
+            save_ram_protect: [0, 0],
+            extended_ram_mode: 0,
+            nametable_mapping: nametable_mapping,
+            fill_tile: 0,
+            fill_attributes: 0,
+            selected_rom_banks: [0, 0, 0, 0, 0xFF],
+            selected_vrom_banks: [0; 12],
+            vrom_upper_bits: 0,
+            last_written_chr_set: ChrSet::A,
+            mapped_chr_set: ChrSet::A,
+
+            split_control: 0,
+            split_scroll: 0,
+            split_vrom_bank: 0,
+            split_tile: 0,
+
+            irq_scanline: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            in_frame: false,
+            scanline_counter: 0,
+            last_fetch_address: 0,
+            repeated_fetch_count: 0,
+            idle_cycle_count: 0,
+
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+
+            nametables: [0; 2048],
+            extended_ram: [0; 1024],
+
+            has_big_sprites: false,
+            is_rendering_enabled: false,
+            is_fetching: false,
+            scanline: 0,
+            fetch_index: 0,
+            extended_attributes: 0,
+
+            pulse_1: Pulse::new(),
+            pulse_2: Pulse::new(),
+            pcm_output: 0,
+            is_pcm_in_read_mode: false,
+            is_on_odd_cycle: false,
+            frame_counter: 0
+        };
+
+        mapper.update_rom_mapping();
+        mapper.map_chr_set( ChrSet::A );
+        Ok( mapper )
+    }
+
+    fn is_save_ram_writable( &self ) -> bool {
+        self.save_ram_protect[ 0 ] & 0b11 == 0b10 && self.save_ram_protect[ 1 ] & 0b11 == 0b01
+    }
+
+    fn map_rom_8k_bank( &mut self, cpu_bank: bank::CPU_8K, value: u8 ) {
+        if is_b7_set( value ) {
+            self.inner.set_cpu_8k_bank_to_bank( cpu_bank, value.get_bits( 0b01111111 ) );
+            self.inner.set_cpu_8k_writable( cpu_bank, false );
+        } else {
+            let is_writable = self.is_save_ram_writable();
+            self.inner.set_cpu_8k_bank_to_save_ram_bank( cpu_bank, value.get_bits( 0b00000111 ) );
+            self.inner.set_cpu_8k_writable( cpu_bank, is_writable );
+        }
+    }
+
+    fn update_rom_mapping( &mut self ) {
+        let banks = self.selected_rom_banks;
+        self.map_rom_8k_bank( bank::CPU_8K::Ox6000, banks[ 0 ] & 0b01111111 );
+
+        // The last bank always maps the ROM.
+        match self.rom_mode {
+            0 => {
+                let bank = (banks[ 4 ] & 0b11111100) | 0x80;
+                self.map_rom_8k_bank( bank::CPU_8K::Ox8000, bank );
+                self.map_rom_8k_bank( bank::CPU_8K::OxA000, bank | 1 );
+                self.map_rom_8k_bank( bank::CPU_8K::OxC000, bank | 2 );
+                self.map_rom_8k_bank( bank::CPU_8K::OxE000, bank | 3 );
+            },
+            1 => {
+                let lower_bank = banks[ 2 ] & 0b11111110;
+                let upper_bank = (banks[ 4 ] & 0b11111110) | 0x80;
+                self.map_rom_8k_bank( bank::CPU_8K::Ox8000, lower_bank );
+                self.map_rom_8k_bank( bank::CPU_8K::OxA000, lower_bank | 1 );
+                self.map_rom_8k_bank( bank::CPU_8K::OxC000, upper_bank );
+                self.map_rom_8k_bank( bank::CPU_8K::OxE000, upper_bank | 1 );
+            },
+            2 => {
+                let lower_bank = banks[ 2 ] & 0b11111110;
+                self.map_rom_8k_bank( bank::CPU_8K::Ox8000, lower_bank );
+                self.map_rom_8k_bank( bank::CPU_8K::OxA000, lower_bank | 1 );
+                self.map_rom_8k_bank( bank::CPU_8K::OxC000, banks[ 3 ] );
+                self.map_rom_8k_bank( bank::CPU_8K::OxE000, banks[ 4 ] | 0x80 );
+            },
+            _ => {
+                self.map_rom_8k_bank( bank::CPU_8K::Ox8000, banks[ 1 ] );
+                self.map_rom_8k_bank( bank::CPU_8K::OxA000, banks[ 2 ] );
+                self.map_rom_8k_bank( bank::CPU_8K::OxC000, banks[ 3 ] );
+                self.map_rom_8k_bank( bank::CPU_8K::OxE000, banks[ 4 ] | 0x80 );
+            }
+        }
+    }
+
+    fn vrom_1k_bank( &self, set: ChrSet, index: usize ) -> u16 {
+        let banks = &self.selected_vrom_banks;
+        let offset = index as u16;
+        match (set, self.vrom_mode) {
+            (ChrSet::A, 0) => banks[ 7 ] * 8 + offset,
+            (ChrSet::A, 1) => banks[ 3 + (index & 4) ] * 4 + (offset & 3),
+            (ChrSet::A, 2) => banks[ 1 + (index & 6) ] * 2 + (offset & 1),
+            (ChrSet::A, _) => banks[ index ],
+
+            // The set B only covers 4kb, which is then mirrored in both halves.
+            (ChrSet::B, 0) => banks[ 11 ] * 8 + offset,
+            (ChrSet::B, 1) => banks[ 11 ] * 4 + (offset & 3),
+            (ChrSet::B, 2) => banks[ 9 + (index & 2) ] * 2 + (offset & 1),
+            (ChrSet::B, _) => banks[ 8 + (index & 3) ]
+        }
+    }
+
+    fn detect_scanline( &mut self, address: u16 ) {
+        self.idle_cycle_count = 0;
+        if address >= 0x2000 && address < 0x3000 && address == self.last_fetch_address {
+            self.repeated_fetch_count = self.repeated_fetch_count.saturating_add( 1 );
+        } else {
+            self.repeated_fetch_count = 0;
+        }
+
+        self.last_fetch_address = address;
+        if self.repeated_fetch_count != 2 {
+            return;
+        }
+
+        if self.in_frame {
+            self.scanline_counter = self.scanline_counter.wrapping_add( 1 );
+            if self.scanline_counter == self.irq_scanline {
+                self.irq_pending = true;
+            }
+        } else {
+            self.in_frame = true;
+            self.irq_pending = false;
+            self.scanline_counter = 0;
+        }
+    }
+
+    fn map_chr_set( &mut self, set: ChrSet ) {
+        for index in 0..8 {
+            let bank = self.vrom_1k_bank( set, index );
+            self.inner.set_ppu_1k_bank_to_bank( bank::PPU_1K_PATTERN_BANKS[ index ], bank );
+        }
+
+        self.mapped_chr_set = set;
+    }
+
+    fn select_chr_set( &mut self, set: ChrSet ) {
+        if self.mapped_chr_set != set {
+            self.map_chr_set( set );
+        }
+    }
+
+    // Outside of rendering the set which was written to last is used,
+    // but only if 8x16 sprites are enabled.
+    fn chr_set_outside_of_rendering( &self ) -> ChrSet {
+        if self.has_big_sprites {
+            self.last_written_chr_set
+        } else {
+            ChrSet::A
+        }
+    }
+
+    fn update_vrom_mapping( &mut self ) {
+        let set = if self.is_fetching && self.has_big_sprites {
+            self.mapped_chr_set
+        } else {
+            self.chr_set_outside_of_rendering()
+        };
+
+        self.map_chr_set( set );
+    }
+
+    fn is_in_split( &self, column: u16 ) -> bool {
+        if is_b7_set( self.split_control ) == false {
+            return false;
+        }
+
+        let threshold = self.split_control.get_bits( 0b00011111 ) as u16;
+        if is_b6_set( self.split_control ) {
+            column >= threshold
+        } else {
+            column < threshold
+        }
+    }
+
+    // In the split region the nametable and the attributes are fetched from the ExRAM
+    // and the patterns from a separate 4kb bank, with its own vertical scroll.
+    fn fetch_split( &mut self, address: u16, kind: u16, column: u16, line: u16 ) -> u8 {
+        let y = (line + self.split_scroll as u16) % 240;
+        let column = column & 31;
+        match kind {
+            0 => {
+                self.split_tile = self.extended_ram[ ((y / 8) * 32 + column) as usize ];
+                self.split_tile
+            },
+            1 => {
+                let attributes = self.extended_ram[ (0x3C0 + (y / 32) * 8 + column / 4) as usize ];
+                let shift = ((y / 16) & 1) * 4 + ((column / 2) & 1) * 2;
+                ((attributes >> shift) & 0b11) * 0x55
+            },
+            _ => {
+                let offset = self.split_vrom_bank as u32 * 4 * 1024 + self.split_tile as u32 * 16 + (address & 0b1000) as u32 + (y & 0b111) as u32;
+                self.inner.peek_video_rom( offset )
+            }
+        }
+    }
+
+    // In the extended attribute mode every tile has its own
+    // palette and 4kb VROM bank, which are taken from the ExRAM.
+    fn fetch_with_extended_attributes( &mut self, address: u16, kind: u16 ) -> u8 {
+        match kind {
+            0 => {
+                self.extended_attributes = self.extended_ram[ (address & 0x3FF) as usize ];
+                self.peek_video_memory( address )
+            },
+            1 => (self.extended_attributes >> 6) * 0x55,
+            _ => {
+                let bank = ((self.vrom_upper_bits as u32) << 6) | self.extended_attributes.get_bits( 0b00111111 ) as u32;
+                self.inner.peek_video_rom( bank * 4 * 1024 + (address & 0x0FFF) as u32 )
+            }
+        }
+    }
+
+    fn clock_audio( &mut self ) {
+        self.is_on_odd_cycle = !self.is_on_odd_cycle;
+        if self.is_on_odd_cycle {
+            self.pulse_1.clock_timer();
+            self.pulse_2.clock_timer();
+        }
+
+        self.frame_counter += 1;
+        if self.frame_counter == FRAME_COUNTER_PERIOD {
+            self.frame_counter = 0;
+            self.pulse_1.clock_frame();
+            self.pulse_2.clock_frame();
+        }
+    }
+}
+
+impl Mapper for MapperMMC5 {
+    fn peek_rom( &self, address: u16 ) -> u8 {
+        self.inner.peek_rom( address )
+    }
+
+    fn poke_rom( &mut self, address: u16, value: u8 ) {
+        self.inner.poke_rom( address, value )
+    }
+
+    fn peek_sram( &self, address: u16 ) -> u8 {
+        self.inner.peek_sram( address )
+    }
+
+    fn poke_sram( &mut self, address: u16, value: u8 ) {
+        self.inner.poke_sram( address, value )
+    }
+
+    fn peek_expansion_rom( &mut self, address: u16 ) -> u8 {
+        match address {
+            0x5015 => {
+                let mut value = 0;
+                if self.pulse_1.length_counter != 0 {
+                    value |= 0b01;
+                }
+                if self.pulse_2.length_counter != 0 {
+                    value |= 0b10;
+                }
+                value
+            },
+            0x5204 => {
+                let value = ((self.irq_pending as u8) << 7) | ((self.in_frame as u8) << 6);
+                self.irq_pending = false;
+                value
+            },
+            0x5205 => (self.multiplicand as u16 * self.multiplier as u16) as u8,
+            0x5206 => ((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8,
+            0x5C00..=0x5FFF => {
+                if self.extended_ram_mode >= 2 {
+                    self.extended_ram[ (address - 0x5C00) as usize ]
+                } else {
+                    0
+                }
+            },
+            _ => {
+                #[cfg(feature = "log")]
+                warn!( "Unhandled read from the expansion ROM at 0x{:04X}", address );
+                0
+            }
+        }
+    }
+
+    fn poke_expansion_rom( &mut self, address: u16, value: u8 ) {
+        match address {
+            0x5000 => self.pulse_1.poke_control( value ),
+            0x5002 => self.pulse_1.poke_period_low( value ),
+            0x5003 => self.pulse_1.poke_period_high( value ),
+            0x5004 => self.pulse_2.poke_control( value ),
+            0x5006 => self.pulse_2.poke_period_low( value ),
+            0x5007 => self.pulse_2.poke_period_high( value ),
+            0x5010 => self.is_pcm_in_read_mode = is_b0_set( value ),
+            0x5011 => {
+                // Writing a zero doesn't change the output.
+                if self.is_pcm_in_read_mode == false && value != 0 {
+                    self.pcm_output = value;
+                }
+            },
+            0x5015 => {
+                self.pulse_1.set_enabled( is_b0_set( value ) );
+                self.pulse_2.set_enabled( is_b1_set( value ) );
+            },
+            0x5100 => {
+                self.rom_mode = value.get_bits( 0b11 );
+                self.update_rom_mapping();
+            },
+            0x5101 => {
+                self.vrom_mode = value.get_bits( 0b11 );
+                self.update_vrom_mapping();
+            },
+            0x5102 | 0x5103 => {
+                self.save_ram_protect[ (address - 0x5102) as usize ] = value;
+                self.update_rom_mapping();
+            },
+            0x5104 => self.extended_ram_mode = value.get_bits( 0b11 ),
+            0x5105 => self.nametable_mapping = value,
+            0x5106 => self.fill_tile = value,
+            0x5107 => self.fill_attributes = value.get_bits( 0b11 ) * 0x55,
+            0x5113..=0x5117 => {
+                self.selected_rom_banks[ (address - 0x5113) as usize ] = value;
+                self.update_rom_mapping();
+            },
+            0x5120..=0x512B => {
+                let index = (address - 0x5120) as usize;
+                self.selected_vrom_banks[ index ] = ((self.vrom_upper_bits as u16) << 8) | value as u16;
+                self.last_written_chr_set = if index < 8 { ChrSet::A } else { ChrSet::B };
+                self.update_vrom_mapping();
+            },
+            0x5130 => self.vrom_upper_bits = value.get_bits( 0b11 ),
+            0x5200 => self.split_control = value,
+            0x5201 => self.split_scroll = value,
+            0x5202 => self.split_vrom_bank = value,
+            0x5203 => self.irq_scanline = value,
+            0x5204 => self.irq_enabled = is_b7_set( value ),
+            0x5205 => self.multiplicand = value,
+            0x5206 => self.multiplier = value,
+            0x5C00..=0x5FFF => {
+                let offset = (address - 0x5C00) as usize;
+                match self.extended_ram_mode {
+                    // In these modes the ExRAM can only be written while rendering.
+                    0 | 1 => self.extended_ram[ offset ] = if self.in_frame { value } else { 0 },
+                    2 => self.extended_ram[ offset ] = value,
+                    _ => {}
+                }
+            },
+            _ => {
+                #[cfg(feature = "log")]
+                warn!( "Unhandled write to the expansion ROM at 0x{:04X} (value=0x{:02X})", address, value );
+            }
+        }
+    }
+
+    fn peek_video_memory( &self, address: u16 ) -> u8 {
+        if address < 0x2000 {
+            return self.inner.peek_video_memory( address );
+        }
+
+        let offset = (address & 0x3FF) as usize;
+        match (self.nametable_mapping >> ((address >> 10) & 0b11) * 2) & 0b11 {
+            0 => self.nametables[ offset ],
+            1 => self.nametables[ 0x400 + offset ],
+            2 => {
+                if self.extended_ram_mode <= 1 {
+                    self.extended_ram[ offset ]
+                } else {
+                    0
+                }
+            },
+            _ => {
+                if offset >= 0x3C0 {
+                    self.fill_attributes
+                } else {
+                    self.fill_tile
+                }
+            }
+        }
+    }
+
+    fn poke_video_memory( &mut self, address: u16, value: u8 ) {
+        if address < 0x2000 {
+            return self.inner.poke_video_memory( address, value );
+        }
+
+        let offset = (address & 0x3FF) as usize;
+        match (self.nametable_mapping >> ((address >> 10) & 0b11) * 2) & 0b11 {
+            0 => self.nametables[ offset ] = value,
+            1 => self.nametables[ 0x400 + offset ] = value,
+            2 => {
+                if self.extended_ram_mode <= 1 {
+                    self.extended_ram[ offset ] = value;
+                }
+            },
+            _ => {}
+        }
+    }
+
+    /*
+        On every rendering scanline the PPU makes the fetches in the following order:
+
+              0 - 127   - the nametable, attribute and two pattern fetches
+                          for each of the tiles from 2 to 33,
+            128 - 159   - the fetches for the sprites of the next scanline,
+            160 - 167   - the fetches for the first two tiles of the next scanline,
+            168 - 169   - two dummy nametable fetches.
+    */
+    fn fetch_video_memory( &mut self, address: u16 ) -> u8 {
+        self.detect_scanline( address );
+
+        if self.is_fetching == false {
+            return self.peek_video_memory( address );
+        }
+
+        let index = self.fetch_index;
+        self.fetch_index += 1;
+
+        if index >= 168 {
+            return self.peek_video_memory( address );
+        }
+
+        if index >= 128 && index < 160 {
+            if self.has_big_sprites {
+                self.select_chr_set( ChrSet::A );
+            }
+
+            return self.peek_video_memory( address );
+        }
+
+        if self.has_big_sprites {
+            self.select_chr_set( ChrSet::B );
+        }
+
+        let kind = index & 0b11;
+        let (column, line) = if index < 128 {
+            (index / 4 + 2, self.scanline)
+        } else {
+            ((index - 160) / 4, if self.scanline == 261 { 0 } else { self.scanline + 1 })
+        };
+
+        if self.is_in_split( column ) {
+            self.fetch_split( address, kind, column, line )
+        } else if self.extended_ram_mode == 1 {
+            self.fetch_with_extended_attributes( address, kind )
+        } else {
+            self.peek_video_memory( address )
+        }
+    }
+
+    fn on_cpu_cycle( &mut self ) {
+        if self.idle_cycle_count < 3 {
+            self.idle_cycle_count += 1;
+            if self.idle_cycle_count == 3 {
+                self.in_frame = false;
+            }
+        }
+
+        self.clock_audio();
+    }
+
+    fn on_ppu_register_write( &mut self, address: u16, value: u8 ) {
+        match address {
+            0x2000 => {
+                self.has_big_sprites = is_b5_set( value );
+                self.update_vrom_mapping();
+            },
+            0x2001 => {
+                self.is_rendering_enabled = value & 0b00011000 != 0;
+                if self.is_rendering_enabled == false {
+                    self.is_fetching = false;
+                    self.update_vrom_mapping();
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn on_ppu_scanline( &mut self, scanline: u16 ) {
+        self.scanline = scanline;
+        self.fetch_index = 0;
+        self.is_fetching = self.is_rendering_enabled && (scanline < 240 || scanline == 261);
+
+        if self.is_fetching == false {
+            let set = self.chr_set_outside_of_rendering();
+            self.select_chr_set( set );
+        }
+    }
+
+    fn irq_line( &self ) -> bool {
+        self.irq_pending && self.irq_enabled
+    }
+
+    fn audio_output( &self ) -> F32 {
+        let pulses = self.pulse_1.output() + self.pulse_2.output();
+        let output_pulses = if pulses == 0 {
+            f32!(0.0)
+        } else {
+            f32!(95.88) / (f32!(8128.0) / u8_to_f32( pulses ) + f32!(100.0))
+        };
+
+        output_pulses + u8_to_f32( self.pcm_output ) / f32!(600.0)
+    }
+
+    fn info( &self ) -> MapperInfo {
+        let mut info = self.inner.describe( "MMC5", Some( 5 ) );
+        info.add_register( "ROM mode", self.rom_mode as u32 );
+        info.add_register( "VROM mode", self.vrom_mode as u32 );
+        info.add_register( "ExRAM mode", self.extended_ram_mode as u32 );
+        info.add_register( "nametable mapping", self.nametable_mapping as u32 );
+        info.add_register( "fill tile", self.fill_tile as u32 );
+        info.add_register( "fill attributes", self.fill_attributes as u32 );
+        info.add_register( "VROM upper bits", self.vrom_upper_bits as u32 );
+        info.add_register( "split control", self.split_control as u32 );
+        info.add_register( "split scroll", self.split_scroll as u32 );
+        info.add_register( "split VROM bank", self.split_vrom_bank as u32 );
+        info.add_register( "IRQ scanline", self.irq_scanline as u32 );
+        info.add_register( "IRQ enabled", self.irq_enabled as u32 );
+        info.add_register( "IRQ pending", self.irq_pending as u32 );
+        info.add_register( "in frame", self.in_frame as u32 );
+        info.add_register( "scanline counter", self.scanline_counter as u32 );
+        info
+    }
+}
+
+// Makes the same fetches as the PPU does on a scanline, interleaved with the CPU cycles.
+#[cfg(test)]
+fn render_scanline( mapper: &mut MapperMMC5, scanline: u16 ) {
+    mapper.on_ppu_scanline( scanline );
+    if scanline >= 240 && scanline != 261 {
+        for _ in 0..114 {
+            mapper.on_cpu_cycle();
+        }
+
+        return;
+    }
+
+    for index in 0..170 {
+        let address = match index {
+            0..=127 => [0x2002 + index / 4, 0x23C0, 0x0000, 0x0008][ index as usize & 3 ],
+            128..=159 => [0x2000, 0x23C0, 0x1000, 0x1008][ index as usize & 3 ],
+            160..=167 => [0x2000 + (index - 160) / 4, 0x23C0, 0x0000, 0x0008][ index as usize & 3 ],
+            _ => 0x2002
+        };
+
+        mapper.fetch_video_memory( address );
+        if index % 3 != 2 {
+            mapper.on_cpu_cycle();
+        }
+    }
+}
+
+#[test]
+fn test_mmc5_rom_banks() {
+    use generic_mapper::{create_test_rom, test_rom_8k_bank};
+
+    let rom = create_test_rom( 5, 256 * 1024, 128 * 1024 );
+    let mut mapper = MapperMMC5::from_rom( rom ).unwrap();
+    let banks = |mapper: &MapperMMC5| [0x8000, 0xA000, 0xC000, 0xE000].map( |address| test_rom_8k_bank( mapper, address ) );
+
+    // On reset the last bank is selected.
+    mapper.poke_expansion_rom( 0x5100, 0 );
+    assert_eq!( banks( &mapper ), [28, 29, 30, 31] );
+    mapper.poke_expansion_rom( 0x5100, 3 );
+
+    mapper.poke_expansion_rom( 0x5114, 0x80 | 4 );
+    mapper.poke_expansion_rom( 0x5115, 0x80 | 7 );
+    mapper.poke_expansion_rom( 0x5116, 0x80 | 8 );
+    mapper.poke_expansion_rom( 0x5117, 11 );
+    assert_eq!( banks( &mapper ), [4, 7, 8, 11] );
+
+    mapper.poke_expansion_rom( 0x5100, 0 );
+    assert_eq!( banks( &mapper ), [8, 9, 10, 11] );
+
+    mapper.poke_expansion_rom( 0x5100, 1 );
+    assert_eq!( banks( &mapper ), [6, 7, 10, 11] );
+
+    mapper.poke_expansion_rom( 0x5100, 2 );
+    assert_eq!( banks( &mapper ), [6, 7, 8, 11] );
+}
+
+#[test]
+fn test_mmc5_save_ram_banks() {
+    use generic_mapper::create_test_rom;
+
+    let rom = create_test_rom( 5, 256 * 1024, 128 * 1024 );
+    let mut mapper = MapperMMC5::from_rom( rom ).unwrap();
+
+    // The RAM is write protected by default.
+    mapper.poke_sram( 0x6000, 0xAA );
+    assert_eq!( mapper.peek_sram( 0x6000 ), 0 );
+
+    mapper.poke_expansion_rom( 0x5102, 0b10 );
+    mapper.poke_expansion_rom( 0x5103, 0b01 );
+    mapper.poke_sram( 0x6000, 0xAA );
+    mapper.poke_expansion_rom( 0x5113, 3 );
+    mapper.poke_sram( 0x6000, 0xBB );
+
+    // The RAM banks can also be mapped in place of the ROM.
+    mapper.poke_expansion_rom( 0x5114, 0 );
+    mapper.poke_expansion_rom( 0x5115, 3 );
+    assert_eq!( mapper.peek_rom( 0x8000 ), 0xAA );
+    assert_eq!( mapper.peek_rom( 0xA000 ), 0xBB );
+
+    mapper.poke_rom( 0xA001, 0xCC );
+    assert_eq!( mapper.peek_sram( 0x6001 ), 0xCC );
+}
+
+#[test]
+fn test_mmc5_vrom_banks() {
+    use generic_mapper::{create_test_rom, test_vrom_1k_bank};
+
+    let rom = create_test_rom( 5, 256 * 1024, 128 * 1024 );
+    let mut mapper = MapperMMC5::from_rom( rom ).unwrap();
+    mapper.poke_expansion_rom( 0x5101, 3 );
+    mapper.poke_expansion_rom( 0x5120, 13 );
+    mapper.poke_expansion_rom( 0x5124, 21 );
+    assert_eq!( test_vrom_1k_bank( &mapper, 0x0000 ), 13 );
+    assert_eq!( test_vrom_1k_bank( &mapper, 0x1000 ), 21 );
+
+    mapper.poke_expansion_rom( 0x5101, 1 );
+    mapper.poke_expansion_rom( 0x5123, 7 );
+    mapper.poke_expansion_rom( 0x5127, 9 );
+    assert_eq!( test_vrom_1k_bank( &mapper, 0x0000 ), 28 );
+    assert_eq!( test_vrom_1k_bank( &mapper, 0x0C00 ), 31 );
+    assert_eq!( test_vrom_1k_bank( &mapper, 0x1000 ), 36 );
+
+    mapper.poke_expansion_rom( 0x5101, 0 );
+    mapper.poke_expansion_rom( 0x5127, 6 );
+    assert_eq!( test_vrom_1k_bank( &mapper, 0x0000 ), 48 );
+    assert_eq!( test_vrom_1k_bank( &mapper, 0x1C00 ), 55 );
+}
+
+#[test]
+fn test_mmc5_big_sprites_use_separate_vrom_banks() {
+    use generic_mapper::create_test_rom;
+
+    let rom = create_test_rom( 5, 256 * 1024, 128 * 1024 );
+    let mut mapper = MapperMMC5::from_rom( rom ).unwrap();
+    mapper.poke_expansion_rom( 0x5101, 1 );
+    mapper.poke_expansion_rom( 0x5127, 2 );
+    mapper.poke_expansion_rom( 0x512B, 3 );
+
+    // With 8x8 sprites only the set A is used.
+    assert_eq!( mapper.peek_video_memory( 0x1000 ), 2 );
+
+    mapper.on_ppu_register_write( 0x2000, 0b00100000 );
+    mapper.on_ppu_register_write( 0x2001, 0b00011000 );
+
+    // Outside of rendering the last written set is used.
+    assert_eq!( mapper.peek_video_memory( 0x1000 ), 3 );
+    mapper.poke_expansion_rom( 0x5127, 2 );
+    assert_eq!( mapper.peek_video_memory( 0x1000 ), 2 );
+
+    mapper.on_ppu_scanline( 0 );
+    for _ in 0..128 {
+        mapper.fetch_video_memory( 0x2000 );
+    }
+
+    assert_eq!( mapper.peek_video_memory( 0x1000 ), 3 );
+    assert_eq!( mapper.fetch_video_memory( 0x2000 ), 0 );
+    assert_eq!( mapper.peek_video_memory( 0x1000 ), 2 );
+    for _ in 129..160 {
+        mapper.fetch_video_memory( 0x2000 );
+    }
+
+    assert_eq!( mapper.peek_video_memory( 0x1000 ), 2 );
+    mapper.fetch_video_memory( 0x2000 );
+    assert_eq!( mapper.peek_video_memory( 0x1000 ), 3 );
+}
+
+#[test]
+fn test_mmc5_nametables() {
+    use generic_mapper::create_test_rom;
+
+    let rom = create_test_rom( 5, 256 * 1024, 128 * 1024 );
+    let mut mapper = MapperMMC5::from_rom( rom ).unwrap();
+
+    // 0x2000 - CIRAM page 0, 0x2400 - CIRAM page 1, 0x2800 - ExRAM, 0x2C00 - fill mode
+    mapper.poke_expansion_rom( 0x5105, 0b11_10_01_00 );
+    mapper.poke_expansion_rom( 0x5106, 0x12 );
+    mapper.poke_expansion_rom( 0x5107, 0x02 );
+    mapper.poke_video_memory( 0x2000, 1 );
+    mapper.poke_video_memory( 0x2400, 2 );
+    mapper.poke_video_memory( 0x2800, 3 );
+    mapper.poke_video_memory( 0x2C00, 4 );
+    assert_eq!( mapper.peek_video_memory( 0x2000 ), 1 );
+    assert_eq!( mapper.peek_video_memory( 0x2400 ), 2 );
+    assert_eq!( mapper.peek_video_memory( 0x2800 ), 3 );
+    assert_eq!( mapper.peek_video_memory( 0x2C00 ), 0x12 );
+    assert_eq!( mapper.peek_video_memory( 0x2FC0 ), 0xAA );
+
+    mapper.poke_expansion_rom( 0x5105, 0b01_01_01_01 );
+    assert_eq!( mapper.peek_video_memory( 0x2000 ), 2 );
+    assert_eq!( mapper.peek_video_memory( 0x2C00 ), 2 );
+}
+
+#[test]
+fn test_mmc5_extended_ram() {
+    use generic_mapper::create_test_rom;
+
+    let rom = create_test_rom( 5, 256 * 1024, 128 * 1024 );
+    let mut mapper = MapperMMC5::from_rom( rom ).unwrap();
+
+    // Outside of rendering only zeros can be written in the modes 0 and 1.
+    mapper.poke_expansion_rom( 0x5C00, 0x11 );
+    assert_eq!( mapper.peek_expansion_rom( 0x5C00 ), 0 );
+    mapper.poke_expansion_rom( 0x5104, 2 );
+    assert_eq!( mapper.peek_expansion_rom( 0x5C00 ), 0 );
+    mapper.poke_expansion_rom( 0x5C00, 0x11 );
+    assert_eq!( mapper.peek_expansion_rom( 0x5C00 ), 0x11 );
+
+    mapper.poke_expansion_rom( 0x5104, 3 );
+    mapper.poke_expansion_rom( 0x5C00, 0x22 );
+    assert_eq!( mapper.peek_expansion_rom( 0x5C00 ), 0x11 );
+
+    // In the extended attribute mode the palette and the VROM bank come from the ExRAM.
+    mapper.poke_expansion_rom( 0x5104, 2 );
+    mapper.poke_expansion_rom( 0x5C05, 0b10_000101 );
+    mapper.poke_expansion_rom( 0x5104, 1 );
+    mapper.on_ppu_register_write( 0x2001, 0b00011000 );
+    mapper.on_ppu_scanline( 0 );
+    for _ in 0..4 * 3 {
+        mapper.fetch_video_memory( 0x2000 );
+    }
+
+    mapper.fetch_video_memory( 0x2005 );
+    assert_eq!( mapper.fetch_video_memory( 0x23C0 ), 0xAA );
+    assert_eq!( mapper.fetch_video_memory( 0x0000 ), 5 );
+}
+
+#[test]
+fn test_mmc5_vertical_split() {
+    use generic_mapper::create_test_rom;
+
+    let rom = create_test_rom( 5, 256 * 1024, 128 * 1024 );
+    let mut mapper = MapperMMC5::from_rom( rom ).unwrap();
+    mapper.poke_expansion_rom( 0x5104, 2 );
+    mapper.poke_expansion_rom( 0x5C00 + 2 * 32 + 3, 0x10 );
+    mapper.poke_expansion_rom( 0x5C00 + 0x3C0, 0b01_00_00_00 );
+
+    // The left four tiles, scrolled down by 16 pixels, with the patterns from the 4kb bank 6.
+    mapper.poke_expansion_rom( 0x5200, 0x80 | 4 );
+    mapper.poke_expansion_rom( 0x5201, 16 );
+    mapper.poke_expansion_rom( 0x5202, 6 );
+    mapper.on_ppu_register_write( 0x2001, 0b00011000 );
+    mapper.on_ppu_scanline( 0 );
+
+    // The first fetched tile is the tile 2.
+    assert_eq!( mapper.fetch_video_memory( 0x2000 ), 0 );
+    for _ in 1..4 {
+        mapper.fetch_video_memory( 0x2000 );
+    }
+
+    assert_eq!( mapper.fetch_video_memory( 0x2000 ), 0x10 );
+    assert_eq!( mapper.fetch_video_memory( 0x23C0 ), 0x55 );
+    assert_eq!( mapper.fetch_video_memory( 0x0000 ), 0 );
+    mapper.fetch_video_memory( 0x0008 );
+
+    // The tile 4 is outside of the split.
+    mapper.poke_video_memory( 0x2000, 0x33 );
+    assert_eq!( mapper.fetch_video_memory( 0x2000 ), 0x33 );
+
+    // The split's patterns are fetched from its own bank.
+    mapper.poke_expansion_rom( 0x5C00 + 2 * 32 + 2, 0 );
+    mapper.on_ppu_scanline( 0 );
+    mapper.fetch_video_memory( 0x2000 );
+    mapper.fetch_video_memory( 0x23C0 );
+    assert_eq!( mapper.fetch_video_memory( 0x0000 ), 6 );
+}
+
+#[test]
+fn test_mmc5_scanline_irq() {
+    use generic_mapper::create_test_rom;
+
+    let rom = create_test_rom( 5, 256 * 1024, 128 * 1024 );
+    let mut mapper = MapperMMC5::from_rom( rom ).unwrap();
+    mapper.poke_expansion_rom( 0x5203, 100 );
+    mapper.poke_expansion_rom( 0x5204, 0x80 );
+    mapper.on_ppu_register_write( 0x2001, 0b00011000 );
+
+    render_scanline( &mut mapper, 261 );
+    assert_eq!( mapper.peek_expansion_rom( 0x5204 ), 0 );
+
+    for scanline in 0..100 {
+        render_scanline( &mut mapper, scanline );
+        assert!( mapper.irq_line() == false );
+    }
+
+    assert_eq!( mapper.peek_expansion_rom( 0x5204 ), 0x40 );
+    render_scanline( &mut mapper, 100 );
+    assert!( mapper.irq_line() );
+
+    // Reading the status acknowledges the IRQ.
+    assert_eq!( mapper.peek_expansion_rom( 0x5204 ), 0xC0 );
+    assert!( mapper.irq_line() == false );
+
+    render_scanline( &mut mapper, 240 );
+    assert_eq!( mapper.peek_expansion_rom( 0x5204 ), 0 );
+
+    // The scanlines are only detected through the fetches.
+    mapper.on_ppu_scanline( 0 );
+    mapper.on_cpu_cycle();
+    assert_eq!( mapper.peek_expansion_rom( 0x5204 ), 0 );
+}
+
+#[test]
+fn test_mmc5_multiplier() {
+    use generic_mapper::create_test_rom;
+
+    let rom = create_test_rom( 5, 256 * 1024, 128 * 1024 );
+    let mut mapper = MapperMMC5::from_rom( rom ).unwrap();
+    mapper.poke_expansion_rom( 0x5205, 200 );
+    mapper.poke_expansion_rom( 0x5206, 123 );
+    assert_eq!( mapper.peek_expansion_rom( 0x5205 ), (24600 & 0xFF) as u8 );
+    assert_eq!( mapper.peek_expansion_rom( 0x5206 ), (24600 >> 8) as u8 );
+}
+
+#[test]
+fn test_mmc5_audio() {
+    use generic_mapper::create_test_rom;
+
+    let rom = create_test_rom( 5, 256 * 1024, 128 * 1024 );
+    let mut mapper = MapperMMC5::from_rom( rom ).unwrap();
+    assert!( mapper.audio_output() == f32!(0.0) );
+
+    // A 50% duty cycle at a constant volume of 15.
+    mapper.poke_expansion_rom( 0x5015, 0b01 );
+    mapper.poke_expansion_rom( 0x5000, 0b10_1_1_1111 );
+    mapper.poke_expansion_rom( 0x5002, 0x10 );
+    mapper.poke_expansion_rom( 0x5003, 0x08 );
+    assert_eq!( mapper.peek_expansion_rom( 0x5015 ), 0b01 );
+
+    let mut was_high = false;
+    let mut was_low = false;
+    for _ in 0..1000 {
+        mapper.on_cpu_cycle();
+        if mapper.audio_output() > f32!(0.0) {
+            was_high = true;
+        } else {
+            was_low = true;
+        }
+    }
+
+    assert!( was_high && was_low );
+
+    mapper.poke_expansion_rom( 0x5015, 0 );
+    assert_eq!( mapper.peek_expansion_rom( 0x5015 ), 0 );
+    assert!( mapper.audio_output() == f32!(0.0) );
+
+    mapper.poke_expansion_rom( 0x5011, 0x80 );
+    assert!( mapper.audio_output() > f32!(0.0) );
+}
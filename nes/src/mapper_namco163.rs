@@ -0,0 +1,490 @@
+use emumisc::{BitExtra, is_b6_set, is_b7_set};
+use rom::{NesRom, LoadError};
+use mappers::{Mapper, AudioOptions};
+use mapper_info::MapperInfo;
+use generic_mapper::{bank, BankedGenericMapper};
+use float::{F32, i32_to_f32};
+
+/*
+    Namco 163 (used by Megami Tensei II, Rolling Thunder, King of Kings and others).
+
+    The registers are:
+
+        0x4800 - 0x4FFF   - the data port of the internal RAM,
+        0x5000 - 0x57FF   - the lower 8 bits of the IRQ counter,
+        0x5800 - 0x5FFF   - bits 0 - 6 - the upper 7 bits of the IRQ counter, bit 7 - IRQ enable,
+        0x8000 - 0xBFFF   - the VROM 1kb banks, one for every 0x800,
+        0xC000 - 0xDFFF   - the banks of the background tilemaps, one for every 0x800,
+        0xE000 - 0xE7FF   - bits 0 - 5 - the ROM bank at 0x8000, bit 6 - audio disable,
+        0xE800 - 0xEFFF   - bits 0 - 5 - the ROM bank at 0xA000,
+                            bit 6 - disables the nametable RAM at 0x0000 - 0x0FFF,
+                            bit 7 - disables the nametable RAM at 0x1000 - 0x1FFF,
+        0xF000 - 0xF7FF   - bits 0 - 5 - the ROM bank at 0xC000,
+        0xF800 - 0xFFFF   - bits 0 - 6 - the address of the internal RAM, bit 7 - auto-increment;
+                            the writes to the save RAM are only allowed when bits 4 - 7
+                            are equal to 0b0100, in which case bits 0 - 3 write-protect
+                            its four 2kb windows.
+
+    The bank at 0xE000 is fixed to the last bank.
+
+    The VROM banks with values of 0xE0 and above map the console's nametable
+    RAM instead (where bit 0 picks which of its two pages), unless disabled
+    by 0xE800; for the banks of the background tilemaps this is always the case.
+
+    Writes to the IRQ counter acknowledge the IRQ. While enabled the counter
+    is incremented on every CPU cycle until it reaches 0x7FFF, at which
+    point it stops and triggers the IRQ.
+*/
+
+// The audio chip updates one of its channels every 15 CPU cycles.
+const AUDIO_CLOCK_DIVIDER: u8 = 15;
+
+/*
+    The sound chip plays up to eight channels of 4-bit wavetable samples
+    stored in the same 128 bytes of internal RAM as its registers. It has
+    only a single DAC, so the enabled channels are output one after another.
+
+    The registers of every channel take 8 bytes, starting from 0x40 for the first
+    channel and ending at 0x78 for the eighth channel:
+
+        0x0               - bits 0 - 7 of the frequency,
+        0x1               - bits 0 - 7 of the phase,
+        0x2               - bits 8 - 15 of the frequency,
+        0x3               - bits 8 - 15 of the phase,
+        0x4               - bits 0 - 1 - bits 16 - 17 of the frequency,
+                            bits 2 - 7 - the length of the waveform, as 256 - 4 * value,
+        0x5               - bits 16 - 23 of the phase,
+        0x6               - the address of the waveform, in 4-bit samples,
+        0x7               - bits 0 - 3 - the volume; for the eighth channel
+                            bits 4 - 6 are also the number of enabled channels minus one.
+
+    The samples are packed two per byte, lower nibble first. Only the last
+    channels are enabled, e.g. if there are three channels enabled
+    then those are the sixth, the seventh and the eighth.
+*/
+struct Namco163Audio {
+    ram: [u8; 128],
+    is_enabled: bool,
+    is_multiplexed: bool,
+
+    divider: u8,
+    current_channel: u8,
+    outputs: [i32; 8]
+}
+
+impl Namco163Audio {
+    const fn new() -> Namco163Audio {
+        Namco163Audio {
+            ram: [0; 128],
+            is_enabled: true,
+            is_multiplexed: true,
+
+            divider: 0,
+            current_channel: 7,
+            outputs: [0; 8]
+        }
+    }
+
+    fn first_enabled_channel( &self ) -> u8 {
+        7 - self.ram[ 0x7F ].get_bits( 0b0111_0000 )
+    }
+
+    fn update_channel( &mut self, channel: u8 ) {
+        let base = 0x40 + channel as usize * 8;
+        let frequency = self.ram[ base ] as u32 | (self.ram[ base + 2 ] as u32) << 8 | (self.ram[ base + 4 ].get_bits( 0b0000_0011 ) as u32) << 16;
+        let phase = self.ram[ base + 1 ] as u32 | (self.ram[ base + 3 ] as u32) << 8 | (self.ram[ base + 5 ] as u32) << 16;
+        let length = 256 - (self.ram[ base + 4 ] & 0b1111_1100) as u32;
+        let offset = self.ram[ base + 6 ] as u32;
+        let volume = self.ram[ base + 7 ].get_bits( 0b0000_1111 ) as i32;
+
+        let phase = (phase + frequency) % (length << 16);
+        self.ram[ base + 1 ] = phase as u8;
+        self.ram[ base + 3 ] = (phase >> 8) as u8;
+        self.ram[ base + 5 ] = (phase >> 16) as u8;
+
+        let sample_address = ((phase >> 16) + offset) & 0xFF;
+        let sample = (self.ram[ (sample_address >> 1) as usize ] >> ((sample_address & 1) * 4)) & 0x0F;
+        self.outputs[ channel as usize ] = (sample as i32 - 8) * volume;
+    }
+
+    fn clock( &mut self ) {
+        if self.is_enabled == false {
+            return;
+        }
+
+        self.divider += 1;
+        if self.divider < AUDIO_CLOCK_DIVIDER {
+            return;
+        }
+        self.divider = 0;
+
+        let first_channel = self.first_enabled_channel();
+        if self.current_channel < first_channel {
+            self.current_channel = 7;
+        }
+
+        let channel = self.current_channel;
+        self.update_channel( channel );
+        self.current_channel = if channel == first_channel { 7 } else { channel - 1 };
+    }
+
+    fn output( &self ) -> F32 {
+        if self.is_enabled == false {
+            return f32!(0.0);
+        }
+
+        let first_channel = self.first_enabled_channel();
+        let output = if self.is_multiplexed {
+            // The channel which was updated last is the one which is currently being output.
+            let channel = if self.current_channel == 7 { first_channel } else { self.current_channel + 1 };
+            self.outputs[ channel as usize ]
+        } else {
+            let sum: i32 = self.outputs[ first_channel as usize.. ].iter().sum();
+            sum / (8 - first_channel as i32)
+        };
+
+        i32_to_f32( output ) / f32!(400.0)
+    }
+}
+
+static VIDEO_BANK_REGISTER_NAMES: [&str; 12] = [
+    "VROM bank 0x0000", "VROM bank 0x0400", "VROM bank 0x0800", "VROM bank 0x0C00",
+    "VROM bank 0x1000", "VROM bank 0x1400", "VROM bank 0x1800", "VROM bank 0x1C00",
+    "tilemap bank 0x2000", "tilemap bank 0x2400", "tilemap bank 0x2800", "tilemap bank 0x2C00"
+];
+
+pub struct MapperNamco163 {
+    inner: BankedGenericMapper,
+    is_video_rom_writable: bool,
+
+    video_banks: [u8; 12],
+    is_lower_nametable_ram_disabled: bool,
+    is_upper_nametable_ram_disabled: bool,
+
+    save_ram_write_protection: u8,
+    is_save_ram_writable: bool,
+
+    ram_address: u8,
+    is_ram_address_auto_incremented: bool,
+
+    irq_counter: u16,
+    is_irq_enabled: bool,
+    irq_pending: bool,
+
+    audio: Namco163Audio
+}
+
+impl MapperNamco163 {
+    pub fn from_rom( rom: NesRom ) -> Result< Self, LoadError > {
+        let is_video_rom_writable = rom.video_rom.is_empty();
+        let mut mapper = MapperNamco163 {
+            inner: BankedGenericMapper::from_rom( rom )?,
+            is_video_rom_writable: is_video_rom_writable,
+
+            // The tilemaps start out mapped to the nametable RAM, so that
+            // the games which never touch them still have something sensible.
+            video_banks: [0, 0, 0, 0, 0, 0, 0, 0, 0xE0, 0xE1, 0xE0, 0xE1],
+            is_lower_nametable_ram_disabled: false,
+            is_upper_nametable_ram_disabled: false,
+
+            save_ram_write_protection: 0,
+            is_save_ram_writable: false,
+
+            ram_address: 0,
+            is_ram_address_auto_incremented: false,
+
+            irq_counter: 0,
+            is_irq_enabled: false,
+            irq_pending: false,
+
+            audio: Namco163Audio::new()
+        };
+
+        let last_bank = mapper.inner.last_rom_8k_bank();
+        mapper.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::Ox8000, 0 );
+        mapper.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::OxA000, 0 );
+        mapper.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::OxC000, 0 );
+        mapper.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::OxE000, last_bank );
+
+        for index in 0..12 {
+            mapper.update_video_bank( index );
+        }
+
+        Ok( mapper )
+    }
+
+    fn update_video_bank( &mut self, index: usize ) {
+        let value = self.video_banks[ index ];
+        let (ppu_bank, is_nametable_ram_allowed) = match index {
+            0..=3 => (bank::PPU_1K_PATTERN_BANKS[ index ], self.is_lower_nametable_ram_disabled == false),
+            4..=7 => (bank::PPU_1K_PATTERN_BANKS[ index ], self.is_upper_nametable_ram_disabled == false),
+            _ => (bank::PPU_1K_TILEMAP_BANKS[ index - 8 ], true)
+        };
+
+        if value >= 0xE0 && is_nametable_ram_allowed {
+            self.inner.set_ppu_1k_bank_to_background_tilemap( ppu_bank, value );
+        } else {
+            self.inner.set_ppu_1k_bank_to_bank( ppu_bank, value as u16 );
+            self.inner.set_ppu_1k_writable( ppu_bank, self.is_video_rom_writable && index < 8 );
+        }
+    }
+
+    fn poke_ram_address( &mut self, value: u8 ) {
+        self.ram_address = value.get_bits( 0b0111_1111 );
+        self.is_ram_address_auto_incremented = is_b7_set( value );
+
+        self.is_save_ram_writable = value.get_bits( 0b1111_0000 ) == 0b0100;
+        self.save_ram_write_protection = value.get_bits( 0b0000_1111 );
+    }
+
+    fn access_ram( &mut self ) -> usize {
+        let address = self.ram_address as usize;
+        if self.is_ram_address_auto_incremented {
+            self.ram_address = (self.ram_address + 1) & 0x7F;
+        }
+
+        address
+    }
+}
+
+impl Mapper for MapperNamco163 {
+    fn peek_rom( &self, address: u16 ) -> u8 {
+        self.inner.peek_rom( address )
+    }
+
+    fn poke_rom( &mut self, address: u16, value: u8 ) {
+        match address {
+            0x8000..=0xDFFF => {
+                let index = ((address - 0x8000) >> 11) as usize;
+                self.video_banks[ index ] = value;
+                self.update_video_bank( index );
+            },
+            0xE000..=0xE7FF => {
+                self.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::Ox8000, value.get_bits( 0b0011_1111 ) );
+                self.audio.is_enabled = is_b6_set( value ) == false;
+            },
+            0xE800..=0xEFFF => {
+                self.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::OxA000, value.get_bits( 0b0011_1111 ) );
+                self.is_lower_nametable_ram_disabled = is_b6_set( value );
+                self.is_upper_nametable_ram_disabled = is_b7_set( value );
+                for index in 0..8 {
+                    self.update_video_bank( index );
+                }
+            },
+            0xF000..=0xF7FF => self.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::OxC000, value.get_bits( 0b0011_1111 ) ),
+            _ => self.poke_ram_address( value )
+        }
+    }
+
+    fn peek_expansion_rom( &mut self, address: u16 ) -> u8 {
+        match address {
+            0x4800..=0x4FFF => {
+                let address = self.access_ram();
+                self.audio.ram[ address ]
+            },
+            0x5000..=0x57FF => self.irq_counter as u8,
+            0x5800..=0x5FFF => (self.irq_counter >> 8) as u8 | (self.is_irq_enabled as u8) << 7,
+            _ => {
+                // Open bus.
+                (address >> 8) as u8
+            }
+        }
+    }
+
+    fn poke_expansion_rom( &mut self, address: u16, value: u8 ) {
+        match address {
+            0x4800..=0x4FFF => {
+                let address = self.access_ram();
+                self.audio.ram[ address ] = value;
+            },
+            0x5000..=0x57FF => {
+                self.irq_counter.replace_bits( 0x00FF, value as u16 );
+                self.irq_pending = false;
+            },
+            0x5800..=0x5FFF => {
+                self.irq_counter.replace_bits( 0x7F00, value.get_bits( 0b0111_1111 ) as u16 );
+                self.is_irq_enabled = is_b7_set( value );
+                self.irq_pending = false;
+            },
+            _ => {
+                #[cfg(feature = "log")]
+                warn!( "Unhandled write to the expansion ROM at 0x{:04X} (value=0x{:02X})", address, value );
+            }
+        }
+    }
+
+    fn peek_sram( &self, address: u16 ) -> u8 {
+        self.inner.peek_sram( address )
+    }
+
+    fn poke_sram( &mut self, address: u16, value: u8 ) {
+        let window = (address - 0x6000) >> 11;
+        if self.is_save_ram_writable && self.save_ram_write_protection & (1 << window) == 0 {
+            self.inner.poke_sram( address, value )
+        }
+    }
+
+    fn peek_video_memory( &self, address: u16 ) -> u8 {
+        self.inner.peek_video_memory( address )
+    }
+
+    fn poke_video_memory( &mut self, address: u16, value: u8 ) {
+        self.inner.poke_video_memory( address, value )
+    }
+
+    fn on_cpu_cycle( &mut self ) {
+        if self.is_irq_enabled && self.irq_counter < 0x7FFF {
+            self.irq_counter += 1;
+            if self.irq_counter == 0x7FFF {
+                self.irq_pending = true;
+            }
+        }
+
+        self.audio.clock();
+    }
+
+    fn irq_line( &self ) -> bool {
+        self.irq_pending
+    }
+
+    fn audio_output( &self ) -> F32 {
+        self.audio.output()
+    }
+
+    fn set_audio_options( &mut self, options: AudioOptions ) {
+        self.audio.is_multiplexed = options.namco_163_multiplexing;
+    }
+
+    fn info( &self ) -> MapperInfo {
+        let mut info = self.inner.describe( "Namco 163", Some( 19 ) );
+        for (index, &bank) in self.video_banks.iter().enumerate() {
+            info.add_register( VIDEO_BANK_REGISTER_NAMES[ index ], bank as u32 );
+        }
+
+        info.add_register( "RAM write protection", self.save_ram_write_protection as u32 );
+        info.add_register( "audio RAM address", self.ram_address as u32 );
+        info.add_register( "audio RAM auto-increment", self.is_ram_address_auto_incremented as u32 );
+        info.add_register( "IRQ counter", self.irq_counter as u32 );
+        info.add_register( "IRQ enabled", self.is_irq_enabled as u32 );
+        info
+    }
+}
+
+#[test]
+fn test_namco163_banks() {
+    use generic_mapper::{create_test_rom, test_rom_8k_bank, test_vrom_1k_bank};
+
+    let rom = create_test_rom( 19, 256 * 1024, 256 * 1024 );
+    let mut mapper = MapperNamco163::from_rom( rom ).unwrap();
+    mapper.poke_rom( 0xE000, 4 );
+    mapper.poke_rom( 0xE800, 7 );
+    mapper.poke_rom( 0xF000, 30 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0x8000 ), 4 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0xA000 ), 7 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0xC000 ), 30 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0xE000 ), 31 );
+
+    mapper.poke_rom( 0x8000, 5 );
+    mapper.poke_rom( 0xB800, 12 );
+    assert_eq!( test_vrom_1k_bank( &mapper, 0x0000 ), 5 );
+    assert_eq!( test_vrom_1k_bank( &mapper, 0x1C00 ), 12 );
+
+    // The nametable RAM can be mapped in place of the VROM...
+    mapper.poke_rom( 0x8000, 0xE1 );
+    mapper.poke_rom( 0xC000, 0xE1 );
+    mapper.poke_video_memory( 0x0000, 0xAA );
+    assert_eq!( mapper.peek_video_memory( 0x0000 ), 0xAA );
+    assert_eq!( mapper.peek_video_memory( 0x2000 ), 0xAA );
+
+    // ...unless it's disabled.
+    mapper.poke_rom( 0xE800, 0b0100_0000 );
+    assert_eq!( mapper.peek_video_memory( 0x0000 ), 0 );
+    assert_eq!( mapper.peek_video_memory( 0x2000 ), 0xAA );
+
+    // ...and the VROM can be mapped in place of the nametables.
+    mapper.poke_rom( 0xC000, 5 );
+    assert_eq!( test_vrom_1k_bank( &mapper, 0x2000 ), 5 );
+    mapper.poke_video_memory( 0x2001, 0xBB );
+    assert_eq!( test_vrom_1k_bank( &mapper, 0x2000 ), 5 );
+}
+
+#[test]
+fn test_namco163_save_ram_write_protection() {
+    use generic_mapper::create_test_rom;
+
+    let rom = create_test_rom( 19, 128 * 1024, 128 * 1024 );
+    let mut mapper = MapperNamco163::from_rom( rom ).unwrap();
+    mapper.poke_sram( 0x6000, 0xAA );
+    assert_eq!( mapper.peek_sram( 0x6000 ), 0x00 );
+
+    mapper.poke_rom( 0xF800, 0b0100_0001 );
+    mapper.poke_sram( 0x6000, 0xAA );
+    mapper.poke_sram( 0x6800, 0xBB );
+    assert_eq!( mapper.peek_sram( 0x6000 ), 0x00 );
+    assert_eq!( mapper.peek_sram( 0x6800 ), 0xBB );
+}
+
+#[test]
+fn test_namco163_internal_ram_and_irq() {
+    use generic_mapper::create_test_rom;
+
+    let rom = create_test_rom( 19, 128 * 1024, 128 * 1024 );
+    let mut mapper = MapperNamco163::from_rom( rom ).unwrap();
+
+    mapper.poke_rom( 0xF800, 0x80 | 0x7F );
+    mapper.poke_expansion_rom( 0x4800, 0x11 );
+    mapper.poke_expansion_rom( 0x4800, 0x22 );
+    mapper.poke_rom( 0xF800, 0x7F );
+    assert_eq!( mapper.peek_expansion_rom( 0x4800 ), 0x11 );
+    assert_eq!( mapper.peek_expansion_rom( 0x4800 ), 0x11 );
+    mapper.poke_rom( 0xF800, 0x00 );
+    assert_eq!( mapper.peek_expansion_rom( 0x4800 ), 0x22 );
+
+    mapper.poke_expansion_rom( 0x5000, 0xFD );
+    mapper.poke_expansion_rom( 0x5800, 0x80 | 0x7F );
+    assert_eq!( mapper.peek_expansion_rom( 0x5800 ), 0xFF );
+    mapper.on_cpu_cycle();
+    assert!( mapper.irq_line() == false );
+    mapper.on_cpu_cycle();
+    assert!( mapper.irq_line() );
+
+    // The counter stops once it reaches 0x7FFF.
+    mapper.on_cpu_cycle();
+    assert_eq!( mapper.peek_expansion_rom( 0x5000 ), 0xFF );
+
+    mapper.poke_expansion_rom( 0x5000, 0 );
+    assert!( mapper.irq_line() == false );
+}
+
+#[test]
+fn test_namco163_audio_mixing() {
+    let mut audio = Namco163Audio::new();
+
+    // Two channels with constant waveforms, one at the maximum and one at the minimum.
+    audio.ram[ 0x00 ] = 0xFF;
+    audio.ram[ 0x01 ] = 0xFF;
+    audio.ram[ 0x7C ] = 0b1111_1100;
+    audio.ram[ 0x7E ] = 0;
+    audio.ram[ 0x7F ] = 0b0001_1111;
+    audio.ram[ 0x74 ] = 0b1111_1100;
+    audio.ram[ 0x76 ] = 4;
+    audio.ram[ 0x77 ] = 0b0000_1111;
+
+    let mut outputs = [f32!(0.0); 4];
+    for output in outputs.iter_mut() {
+        for _ in 0..AUDIO_CLOCK_DIVIDER {
+            audio.clock();
+        }
+        *output = audio.output();
+    }
+
+    // The channels alternate...
+    assert!( outputs[ 2 ] > f32!(0.0) );
+    assert!( outputs[ 3 ] < f32!(0.0) );
+    assert!( outputs[ 2 ] == outputs[ 0 ] );
+    assert!( outputs[ 3 ] == outputs[ 1 ] );
+
+    // ...unless they're mixed together.
+    audio.is_multiplexed = false;
+    assert!( audio.output() == i32_to_f32( (7 * 15 - 8 * 15) / 2 ) / f32!(400.0) );
+}
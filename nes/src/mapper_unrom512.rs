@@ -1,24 +1,196 @@
-use emumisc::BitExtra;
-use rom::{NesRom, LoadError};
-use generic_mapper::BankedGenericMapper;
+use alloc::vec::Vec;
+
+use emumisc::{BitExtra, is_b7_set};
+use rom::{NesRom, LoadError, Mirroring};
+use generic_mapper::{bank, BankedGenericMapper};
 use mappers::Mapper;
+use mapper_info::MapperInfo;
+
+/*
+    UNROM 512 (used by a lot of the modern homebrew), a UxROM-like
+    board with 32kb of VRAM and an SST39SF040 flash chip instead of
+    a ROM, which the games can reprogram to save their progress.
+
+    The register is:
+
+        0x8000 - 0xFFFF   - bits 0 - 4 - the ROM bank at 0x8000,
+                            bits 5 - 6 - the 8kb VRAM bank,
+                            bit 7 - the nametable page, in the one screen mode.
+
+    The bank at 0xC000 is fixed to the last bank.
+
+    The battery bit of the header marks the boards which are flashable;
+    on those the register is only at 0xC000 - 0xFFFF, and the writes
+    to 0x8000 - 0xBFFF go to the flash chip. The others have bus conflicts.
+
+    The mirroring bits of the header select one of four nametable layouts:
+
+        horizontal, vertical  - the same as usual,
+        four-screen           - one screen, switched by bit 7 of the register,
+        four-screen, vertical - four screens, using the last 8kb of the VRAM.
+
+    The flash chip is programmed by writing a sequence of commands, in which
+    the addresses are the ones seen by the chip, that is the ROM bank
+    times 0x4000 plus the lower 14 bits of the CPU address:
+
+        0x5555 <- 0xAA, 0x2AAA <- 0x55, 0x5555 <- 0xA0, address <- value
+                                                - programs a single byte,
+        0x5555 <- 0xAA, 0x2AAA <- 0x55, 0x5555 <- 0x80,
+        0x5555 <- 0xAA, 0x2AAA <- 0x55, address <- 0x30
+                                                - erases a 4kb sector,
+        0x5555 <- 0xAA, 0x2AAA <- 0x55, 0x5555 <- 0x80,
+        0x5555 <- 0xAA, 0x2AAA <- 0x55, 0x5555 <- 0x10
+                                                - erases the whole chip,
+        0x5555 <- 0xAA, 0x2AAA <- 0x55, 0x5555 <- 0x90
+                                                - enters the software ID mode,
+        address <- 0xF0                         - leaves the software ID mode.
+
+    Programming can only clear bits; only erasing sets them back to one.
+    On the real hardware the operations take a while to complete, but
+    here they finish immediately.
+*/
+
+const FLASH_SECTOR_SIZE: usize = 4 * 1024;
+
+// What the chip returns in the software ID mode.
+const FLASH_MANUFACTURER_ID: u8 = 0xBF;
+const FLASH_DEVICE_ID: u8 = 0xB7;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum NametableLayout {
+    Fixed,
+    OneScreen,
+    FourScreen
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum FlashState {
+    Ready,
+    Unlocked,
+    Command,
+    Program,
+    EraseReady,
+    EraseUnlocked,
+    EraseCommand
+}
 
 pub struct MapperUNROM512 {
-    inner: BankedGenericMapper
+    inner: BankedGenericMapper,
+    nametable_layout: NametableLayout,
+    is_flashable: bool,
+    rom_bank: u8,
+
+    flash_state: FlashState,
+    is_in_software_id_mode: bool,
+    is_flash_modified: bool
 }
 
 impl MapperUNROM512 {
-    pub fn from_rom( rom: NesRom ) -> Result< Self, LoadError > {
+    pub fn from_rom( mut rom: NesRom ) -> Result< Self, LoadError > {
+        let nametable_layout = match (rom.mirroring, rom.mirroring_bit) {
+            (Mirroring::FourScreen, false) => NametableLayout::OneScreen,
+            (Mirroring::FourScreen, true) => NametableLayout::FourScreen,
+            _ => NametableLayout::Fixed
+        };
+
+        let has_video_ram = rom.video_rom.is_empty();
+        if has_video_ram {
+            rom.video_rom.resize( 32 * 1024, 0 );
+        }
+
+        let is_flashable = rom.has_battery;
         let mut mapper = MapperUNROM512 {
-            inner: BankedGenericMapper::from_rom( rom )?
+            inner: BankedGenericMapper::from_rom( rom )?,
+            nametable_layout: nametable_layout,
+            is_flashable: is_flashable,
+            rom_bank: 0,
+
+            flash_state: FlashState::Ready,
+            is_in_software_id_mode: false,
+            is_flash_modified: false
         };
 
+        if has_video_ram {
+            mapper.inner.set_ppu_4k_writable( bank::PPU_4K::Ox0000, true );
+            mapper.inner.set_ppu_4k_writable( bank::PPU_4K::Ox1000, true );
+        }
+
+        if nametable_layout == NametableLayout::FourScreen {
+            let first_bank = (mapper.inner.video_rom_1k_bank_count() - 8) as u16;
+            for (index, &ppu_bank) in bank::PPU_1K_TILEMAP_BANKS.iter().enumerate() {
+                mapper.inner.set_ppu_1k_bank_to_bank( ppu_bank, first_bank + index as u16 );
+                mapper.inner.set_ppu_1k_writable( ppu_bank, true );
+            }
+        }
+
         let last_bank = mapper.inner.last_rom_16k_bank();
-        mapper.inner.set_cpu_lower_16k_bank_to_bank( 0 );
         mapper.inner.set_cpu_upper_16k_bank_to_bank( last_bank );
+        mapper.poke_bank_register( 0 );
 
         Ok( mapper )
     }
+
+    fn poke_bank_register( &mut self, value: u8 ) {
+        self.rom_bank = value.get_bits( 0b0001_1111 );
+        self.inner.set_cpu_lower_16k_bank_to_bank( self.rom_bank );
+        self.inner.set_ppu_8k_bank_to_bank( value.get_bits( 0b0110_0000 ) );
+        if self.nametable_layout == NametableLayout::OneScreen {
+            if is_b7_set( value ) {
+                self.inner.set_only_upper_bank_mirroring();
+            } else {
+                self.inner.set_only_lower_bank_mirroring();
+            }
+        }
+    }
+
+    fn poke_flash( &mut self, address: u16, value: u8 ) {
+        let bank = self.rom_bank as usize % self.inner.rom_16k_bank_count() as usize;
+        let flash_address = bank * 16 * 1024 + (address & 0x3FFF) as usize;
+
+        // Only the lower 15 bits of the address are checked for the commands.
+        let command_address = flash_address & 0x7FFF;
+        self.flash_state = match (self.flash_state, command_address, value) {
+            (FlashState::Program, _, _) => {
+                self.inner.rom_mut()[ flash_address ] &= value;
+                self.is_flash_modified = true;
+                FlashState::Ready
+            },
+            (_, _, 0xF0) => {
+                self.is_in_software_id_mode = false;
+                FlashState::Ready
+            },
+            (FlashState::Ready, 0x5555, 0xAA) => FlashState::Unlocked,
+            (FlashState::Unlocked, 0x2AAA, 0x55) => FlashState::Command,
+            (FlashState::Command, 0x5555, 0xA0) => FlashState::Program,
+            (FlashState::Command, 0x5555, 0x80) => FlashState::EraseReady,
+            (FlashState::Command, 0x5555, 0x90) => {
+                self.is_in_software_id_mode = true;
+                FlashState::Ready
+            },
+            (FlashState::EraseReady, 0x5555, 0xAA) => FlashState::EraseUnlocked,
+            (FlashState::EraseUnlocked, 0x2AAA, 0x55) => FlashState::EraseCommand,
+            (FlashState::EraseCommand, _, 0x30) => {
+                let sector = flash_address & !(FLASH_SECTOR_SIZE - 1);
+                for byte in &mut self.inner.rom_mut()[ sector..sector + FLASH_SECTOR_SIZE ] {
+                    *byte = 0xFF;
+                }
+                self.is_flash_modified = true;
+                FlashState::Ready
+            },
+            (FlashState::EraseCommand, 0x5555, 0x10) => {
+                for byte in self.inner.rom_mut().iter_mut() {
+                    *byte = 0xFF;
+                }
+                self.is_flash_modified = true;
+                FlashState::Ready
+            },
+            _ => {
+                #[cfg(feature = "log")]
+                warn!( "Unhandled flash write to 0x{:05X} (value=0x{:02X})", flash_address, value );
+                FlashState::Ready
+            }
+        };
+    }
 }
 
 impl Mapper for MapperUNROM512 {
@@ -31,19 +203,21 @@ impl Mapper for MapperUNROM512 {
     }
 
     fn peek_rom( &self, address: u16 ) -> u8 {
+        if self.is_in_software_id_mode {
+            return if address & 1 == 0 { FLASH_MANUFACTURER_ID } else { FLASH_DEVICE_ID };
+        }
+
         self.inner.peek_rom( address )
     }
 
-    fn poke_rom( &mut self, _: u16, value: u8 ) {
-        let rom_bank = value.get_bits( 0b0001_1111 );
-        let vrom_bank = value.get_bits( 0b0110_0000 );
-        self.inner.set_cpu_lower_16k_bank_to_bank( rom_bank );
-        self.inner.set_ppu_8k_bank_to_bank( vrom_bank );
-        let one_screen_mirroring = value.get_bits( 0b1000_0000 ) != 0;
-        if one_screen_mirroring {
-            self.inner.set_only_lower_bank_mirroring();
+    fn poke_rom( &mut self, address: u16, value: u8 ) {
+        if self.is_flashable == false {
+            let value = self.inner.bus_conflict( address, value );
+            self.poke_bank_register( value );
+        } else if address < 0xC000 {
+            self.poke_flash( address, value );
         } else {
-            self.inner.set_default_mirroring();
+            self.poke_bank_register( value );
         }
     }
 
@@ -54,4 +228,184 @@ impl Mapper for MapperUNROM512 {
     fn poke_video_memory( &mut self, address: u16, value: u8 ) {
         self.inner.poke_video_memory( address, value )
     }
+
+    fn flash_memory( &self ) -> Option< Vec< u8 > > {
+        if self.is_flashable {
+            Some( self.inner.rom().to_vec() )
+        } else {
+            None
+        }
+    }
+
+    fn is_flash_modified( &self ) -> bool {
+        self.is_flash_modified
+    }
+
+    fn restore_flash_memory( &mut self, data: &[u8] ) -> Result< (), LoadError > {
+        if self.is_flashable == false {
+            return Err( LoadError::new( "the cartridge is not flashable" ) );
+        }
+
+        if data.len() != self.inner.rom().len() {
+            return Err( LoadError::new( "the size of the flash memory doesn't match the size of the ROM" ) );
+        }
+
+        self.inner.rom_mut().copy_from_slice( data );
+        self.is_flash_modified = false;
+        Ok(())
+    }
+
+    fn info( &self ) -> MapperInfo {
+        let mut info = self.inner.describe( "UNROM 512", Some( 30 ) );
+        info.add_register( "ROM bank", self.rom_bank as u32 );
+        info.add_register( "flashable", self.is_flashable as u32 );
+        info.add_register( "software ID mode", self.is_in_software_id_mode as u32 );
+        info
+    }
+}
+
+#[cfg(test)]
+fn create_test_mapper( mirroring: Mirroring, mirroring_bit: bool, is_flashable: bool ) -> MapperUNROM512 {
+    use generic_mapper::create_test_rom;
+
+    let mut rom = create_test_rom( 30, 512 * 1024, 0 );
+    rom.mirroring = mirroring;
+    rom.mirroring_bit = mirroring_bit;
+    rom.has_battery = is_flashable;
+    MapperUNROM512::from_rom( rom ).unwrap()
+}
+
+#[cfg(test)]
+fn unlock_flash( mapper: &mut MapperUNROM512 ) {
+    mapper.poke_rom( 0xC000, 1 );
+    mapper.poke_rom( 0x9555, 0xAA );
+    mapper.poke_rom( 0xC000, 0 );
+    mapper.poke_rom( 0xAAAA, 0x55 );
+}
+
+#[cfg(test)]
+fn write_flash_command( mapper: &mut MapperUNROM512, command: u8 ) {
+    unlock_flash( mapper );
+    mapper.poke_rom( 0xC000, 1 );
+    mapper.poke_rom( 0x9555, command );
+}
+
+#[test]
+fn test_unrom512_banking_with_bus_conflicts() {
+    let mut mapper = create_test_mapper( Mirroring::Vertical, true, false );
+    assert_eq!( mapper.peek_rom( 0x8000 ), 0 );
+    assert_eq!( mapper.peek_rom( 0xC000 ), 31 );
+
+    // The value is ANDed with what's in the ROM at the written address.
+    mapper.poke_rom( 0x8001, 0x05 );
+    assert_eq!( mapper.peek_rom( 0x8000 ), 0 );
+    mapper.inner.rom_mut()[ 31 * 16 * 1024 + 1 ] = 0xFF;
+    mapper.poke_rom( 0xC001, 0x05 );
+    assert_eq!( mapper.peek_rom( 0x8000 ), 5 );
+    assert_eq!( mapper.peek_rom( 0xC000 ), 31 );
+
+    // The flash can't be written to.
+    mapper.poke_rom( 0xC001, 0x05 );
+    write_flash_command( &mut mapper, 0x90 );
+    assert_eq!( mapper.is_in_software_id_mode, false );
+    assert!( mapper.flash_memory().is_none() );
+}
+
+#[test]
+fn test_unrom512_video_ram_banking() {
+    let mut mapper = create_test_mapper( Mirroring::Vertical, true, true );
+    for bank in 0..4 {
+        mapper.poke_rom( 0xC000, bank << 5 );
+        mapper.poke_video_memory( 0x0000, 10 + bank );
+    }
+
+    for bank in 0..4 {
+        mapper.poke_rom( 0xC000, bank << 5 );
+        assert_eq!( mapper.peek_video_memory( 0x0000 ), 10 + bank );
+    }
+}
+
+#[test]
+fn test_unrom512_flash_sector_erase_and_byte_program() {
+    let mut mapper = create_test_mapper( Mirroring::Vertical, true, true );
+    assert_eq!( mapper.is_flash_modified(), false );
+
+    write_flash_command( &mut mapper, 0x80 );
+    unlock_flash( &mut mapper );
+    mapper.poke_rom( 0xC000, 5 );
+    mapper.poke_rom( 0x8000, 0x30 );
+    assert_eq!( mapper.is_flash_modified(), true );
+
+    mapper.poke_rom( 0xC000, 5 );
+    assert_eq!( mapper.peek_rom( 0x8000 ), 0xFF );
+    assert_eq!( mapper.peek_rom( 0x8FFF ), 0xFF );
+    assert_eq!( mapper.peek_rom( 0x9000 ), 0x00 );
+
+    // Programming can only clear the bits.
+    write_flash_command( &mut mapper, 0xA0 );
+    mapper.poke_rom( 0xC000, 5 );
+    mapper.poke_rom( 0x8010, 0x5A );
+    write_flash_command( &mut mapper, 0xA0 );
+    mapper.poke_rom( 0xC000, 5 );
+    mapper.poke_rom( 0x8010, 0xF0 );
+    assert_eq!( mapper.peek_rom( 0x8010 ), 0x50 );
+
+    // A write without a command doesn't do anything.
+    mapper.poke_rom( 0x8011, 0x00 );
+    assert_eq!( mapper.peek_rom( 0x8011 ), 0xFF );
+
+    let flash = mapper.flash_memory().unwrap();
+    assert_eq!( flash[ 5 * 16 * 1024 + 0x10 ], 0x50 );
+
+    let mut fresh_mapper = create_test_mapper( Mirroring::Vertical, true, true );
+    fresh_mapper.restore_flash_memory( &flash ).unwrap();
+    fresh_mapper.poke_rom( 0xC000, 5 );
+    assert_eq!( fresh_mapper.peek_rom( 0x8010 ), 0x50 );
+    assert_eq!( fresh_mapper.is_flash_modified(), false );
+    assert!( fresh_mapper.restore_flash_memory( &flash[ 1.. ] ).is_err() );
+}
+
+#[test]
+fn test_unrom512_flash_software_id() {
+    let mut mapper = create_test_mapper( Mirroring::Vertical, true, true );
+    write_flash_command( &mut mapper, 0x90 );
+    assert_eq!( mapper.peek_rom( 0x8000 ), FLASH_MANUFACTURER_ID );
+    assert_eq!( mapper.peek_rom( 0x8001 ), FLASH_DEVICE_ID );
+    assert_eq!( mapper.peek_rom( 0xC000 ), FLASH_MANUFACTURER_ID );
+
+    mapper.poke_rom( 0x8000, 0xF0 );
+    assert_eq!( mapper.peek_rom( 0x8000 ), 1 );
+    assert_eq!( mapper.peek_rom( 0xC000 ), 31 );
+}
+
+#[test]
+fn test_unrom512_one_screen_mirroring() {
+    let mut mapper = create_test_mapper( Mirroring::FourScreen, false, true );
+    mapper.poke_video_memory( 0x2000, 1 );
+    assert_eq!( mapper.peek_video_memory( 0x2C00 ), 1 );
+
+    mapper.poke_rom( 0xC000, 0x80 );
+    assert_eq!( mapper.peek_video_memory( 0x2000 ), 0 );
+    mapper.poke_video_memory( 0x2400, 2 );
+    assert_eq!( mapper.peek_video_memory( 0x2800 ), 2 );
+
+    mapper.poke_rom( 0xC000, 0x00 );
+    assert_eq!( mapper.peek_video_memory( 0x2400 ), 1 );
+}
+
+#[test]
+fn test_unrom512_four_screen_mirroring() {
+    let mut mapper = create_test_mapper( Mirroring::FourScreen, true, true );
+    for index in 0..4 {
+        mapper.poke_video_memory( 0x2000 + index * 0x400, 1 + index as u8 );
+    }
+
+    for index in 0..4 {
+        assert_eq!( mapper.peek_video_memory( 0x2000 + index * 0x400 ), 1 + index as u8 );
+    }
+
+    // The nametables live in the last 8kb of the VRAM.
+    mapper.poke_rom( 0xC000, 3 << 5 );
+    assert_eq!( mapper.peek_video_memory( 0x0000 ), 1 );
+    assert_eq!( mapper.peek_video_memory( 0x0C00 ), 4 );
 }
@@ -1,6 +1,7 @@
 use rom::{NesRom, LoadError};
 use generic_mapper::BankedGenericMapper;
 use mappers::Mapper;
+use mapper_info::MapperInfo;
 
 // This is a very simple mapper. At 0x8000 - 0xBFFF
 // we have a switchable ROM bank, and the 0xC000 - 0xFFFF
@@ -8,13 +9,16 @@ use mappers::Mapper;
 // switched by writing to 0x8000 - 0xFFFF.
 
 pub struct MapperUxROM {
-    inner: BankedGenericMapper
+    inner: BankedGenericMapper,
+    has_bus_conflicts: bool
 }
 
 impl MapperUxROM {
     pub fn from_rom( rom: NesRom ) -> Result< Self, LoadError > {
+        let has_bus_conflicts = rom.has_bus_conflicts( false ); // See `NesRom::has_bus_conflicts`.
         let mut mapper = MapperUxROM {
-            inner: BankedGenericMapper::from_rom( rom )?
+            inner: BankedGenericMapper::from_rom( rom )?,
+            has_bus_conflicts: has_bus_conflicts
         };
 
         let last_bank = mapper.inner.last_rom_16k_bank();
@@ -38,7 +42,13 @@ impl Mapper for MapperUxROM {
         self.inner.peek_rom( address )
     }
 
-    fn poke_rom( &mut self, _: u16, value: u8 ) {
+    fn poke_rom( &mut self, address: u16, value: u8 ) {
+        let value = if self.has_bus_conflicts {
+            self.inner.bus_conflict( address, value )
+        } else {
+            value
+        };
+
         self.inner.set_cpu_lower_16k_bank_to_bank( value );
     }
 
@@ -49,4 +59,24 @@ impl Mapper for MapperUxROM {
     fn poke_video_memory( &mut self, address: u16, value: u8 ) {
         self.inner.poke_video_memory( address, value )
     }
+
+    fn info( &self ) -> MapperInfo {
+        self.inner.describe( "UxROM", Some( 2 ) )
+    }
+}
+
+#[test]
+fn test_uxrom_bus_conflicts() {
+    use generic_mapper::create_test_rom;
+
+    for &(submapper, has_bus_conflicts) in &[(0, false), (1, false), (2, true)] {
+        let mut rom = create_test_rom( 2, 128 * 1024, 0 );
+        rom.submapper = submapper;
+        rom.rom[ 7 * 16 * 1024 + 0x3FFF ] = 0x03;
+
+        let mut mapper = MapperUxROM::from_rom( rom ).unwrap();
+        mapper.poke_rom( 0xFFFF, 6 );
+        assert_eq!( mapper.peek_rom( 0x8000 ), if has_bus_conflicts { 2 } else { 6 } );
+        assert_eq!( mapper.peek_rom( 0xC000 ), 7 );
+    }
 }
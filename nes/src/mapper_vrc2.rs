@@ -0,0 +1,389 @@
+use alloc::format;
+
+use emumisc::{BitExtra, is_b0_set, is_b1_set};
+use rom::{NesRom, LoadError};
+use mappers::Mapper;
+use mapper_info::MapperInfo;
+use generic_mapper::{bank, BankedGenericMapper};
+use vrc_irq::VrcIrq;
+
+/*
+    Konami's VRC2 and VRC4 (used by Contra, Gradius II, Ganbare Goemon and many others).
+
+    The registers are:
+
+        0x8000 - 0x8003   - the ROM bank at 0x8000 (or 0xC000 when swapped),
+        0x9000 - 0x9001   - mirroring; 0 - vertical, 1 - horizontal,
+                            2 - one screen lower bank, 3 - one screen upper bank
+                            (VRC2 only has the lowest bit, at 0x9000 - 0x9003),
+        0x9002 - 0x9003   - (VRC4 only) the bit 1 swaps the ROM banks at 0x8000 and 0xC000,
+        0xA000 - 0xA003   - the ROM bank at 0xA000,
+        0xB000 - 0xE003   - the VROM 1kb banks; every bank has two registers where
+                            the first one sets the lower 4 bits and the second one
+                            the upper 5 bits (4 bits on VRC2),
+        0xF000 - 0xF003   - (VRC4 only) IRQ latch low, IRQ latch high, IRQ control, IRQ acknowledge.
+
+    The banks at 0xC000 (or 0x8000 when swapped) and 0xE000 are fixed
+    to the second to last and the last bank.
+
+    The chips come in multiple variants which differ in which
+    CPU address lines are connected to the register select pins:
+
+        mapper 21 - VRC4a (A1, A2), VRC4c (A6, A7),
+        mapper 22 - VRC2a (A1, A0), where the VROM banks are also shifted by one bit,
+        mapper 23 - VRC4f (A0, A1), VRC4e (A2, A3), VRC2b (A0, A1),
+        mapper 25 - VRC4b (A1, A0), VRC4d (A3, A2), VRC2c (A1, A0).
+
+    They are told apart by the NES 2.0 submapper. When there isn't one
+    we guess the chip from the rest of the header: the VRC2b games on
+    mapper 23 all have at most 128kb of ROM and no battery, and the only
+    VRC2c game on mapper 25 (Ganbare Goemon Gaiden) is the only small one
+    with a battery. For the VRC4 we then simply connect both of the possible
+    lines, which works since the games only ever write to the register's
+    base address plus the offsets of its own wiring.
+
+    On VRC2 there is a single bit latch at 0x6000 - 0x6FFF (the microwire
+    interface of the EEPROM which some of the boards had); we emulate it
+    in place of the RAM, unless the header says there is battery-backed RAM.
+*/
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Chip {
+    VRC2,
+    VRC4
+}
+
+pub struct MapperVRC2 {
+    inner: BankedGenericMapper,
+    chip: Chip,
+    mapper_number: u8,
+
+    register_select_masks: [u16; 2],
+    vrom_bank_shift: u8,
+
+    selected_rom_banks: [u8; 2],
+    selected_vrom_banks: [u16; 8],
+    are_rom_banks_swapped: bool,
+    has_microwire_latch: bool,
+    microwire_latch: u8,
+
+    irq: VrcIrq
+}
+
+impl MapperVRC2 {
+    pub fn from_rom( rom: NesRom ) -> Result< Self, LoadError > {
+        const A0: u16 = 1 << 0;
+        const A1: u16 = 1 << 1;
+        const A2: u16 = 1 << 2;
+        const A3: u16 = 1 << 3;
+        const A6: u16 = 1 << 6;
+        const A7: u16 = 1 << 7;
+
+        let is_small = rom.rom.len() <= 128 * 1024 && rom.video_rom.len() <= 256 * 1024;
+        let (chip, register_select_masks) = match (rom.mapper, rom.submapper) {
+            (21, 1) => (Chip::VRC4, [A1, A2]),
+            (21, 2) => (Chip::VRC4, [A6, A7]),
+            (21, _) => (Chip::VRC4, [A1 | A6, A2 | A7]),
+            (22, _) => (Chip::VRC2, [A1, A0]),
+            (23, 1) => (Chip::VRC4, [A0, A1]),
+            (23, 2) => (Chip::VRC4, [A2, A3]),
+            (23, 3) => (Chip::VRC2, [A0, A1]),
+            (23, 0) if is_small && rom.has_battery == false => (Chip::VRC2, [A0, A1]),
+            (23, _) => (Chip::VRC4, [A0 | A2, A1 | A3]),
+            (25, 1) => (Chip::VRC4, [A1, A0]),
+            (25, 2) => (Chip::VRC4, [A3, A2]),
+            (25, 3) => (Chip::VRC2, [A1, A0]),
+            (25, 0) if is_small && rom.has_battery => (Chip::VRC2, [A1, A0]),
+            (25, _) => (Chip::VRC4, [A1 | A3, A0 | A2]),
+            (mapper, _) => return Err( LoadError::new( format!( "Mapper {} is not a VRC2 nor a VRC4", mapper ) ) )
+        };
+
+        let mapper_number = rom.mapper;
+        let vrom_bank_shift = if rom.mapper == 22 { 1 } else { 0 };
+        let has_microwire_latch = chip == Chip::VRC2 && rom.has_battery == false;
+        let mut mapper = MapperVRC2 {
+            inner: BankedGenericMapper::from_rom( rom )?,
+            chip: chip,
+            mapper_number: mapper_number,
+
+            register_select_masks: register_select_masks,
+            vrom_bank_shift: vrom_bank_shift,
+
+            selected_rom_banks: [0, 0],
+            selected_vrom_banks: [0; 8],
+            are_rom_banks_swapped: false,
+            has_microwire_latch: has_microwire_latch,
+            microwire_latch: 0,
+
+            irq: VrcIrq::new()
+        };
+
+        mapper.update_rom_mapping();
+        mapper.update_vrom_mapping();
+        mapper.inner.set_vertical_mirroring();
+
+        Ok( mapper )
+    }
+
+    fn register_index( &self, address: u16 ) -> usize {
+        let mut index = 0;
+        if address & self.register_select_masks[ 0 ] != 0 {
+            index |= 1;
+        }
+        if address & self.register_select_masks[ 1 ] != 0 {
+            index |= 2;
+        }
+
+        index
+    }
+
+    fn update_rom_mapping( &mut self ) {
+        let last_bank = self.inner.last_rom_8k_bank();
+        let (lower_bank, upper_bank) = if self.are_rom_banks_swapped {
+            (last_bank - 1, self.selected_rom_banks[ 0 ])
+        } else {
+            (self.selected_rom_banks[ 0 ], last_bank - 1)
+        };
+
+        self.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::Ox8000, lower_bank );
+        self.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::OxA000, self.selected_rom_banks[ 1 ] );
+        self.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::OxC000, upper_bank );
+        self.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::OxE000, last_bank );
+    }
+
+    fn update_vrom_mapping( &mut self ) {
+        for index in 0..8 {
+            let bank = self.selected_vrom_banks[ index ] >> self.vrom_bank_shift;
+            self.inner.set_ppu_1k_bank_to_bank( bank::PPU_1K_PATTERN_BANKS[ index ], bank );
+        }
+    }
+
+    fn set_mirroring( &mut self, value: u8 ) {
+        match value {
+            0 => self.inner.set_vertical_mirroring(),
+            1 => self.inner.set_horizontal_mirroring(),
+            2 => self.inner.set_only_lower_bank_mirroring(),
+            _ => self.inner.set_only_upper_bank_mirroring()
+        }
+    }
+}
+
+impl Mapper for MapperVRC2 {
+    fn peek_rom( &self, address: u16 ) -> u8 {
+        self.inner.peek_rom( address )
+    }
+
+    fn poke_rom( &mut self, address: u16, value: u8 ) {
+        let register = self.register_index( address );
+        match (address & 0xF000, register, self.chip) {
+            (0x8000, _, _) => {
+                self.selected_rom_banks[ 0 ] = value.get_bits( 0b0001_1111 );
+                self.update_rom_mapping();
+            },
+            (0x9000, _, Chip::VRC2) => self.set_mirroring( value.get_bits( 0b0000_0001 ) ),
+            (0x9000, 0, Chip::VRC4) | (0x9000, 1, Chip::VRC4) => self.set_mirroring( value.get_bits( 0b0000_0011 ) ),
+            (0x9000, _, Chip::VRC4) => {
+                self.are_rom_banks_swapped = is_b1_set( value );
+                self.update_rom_mapping();
+            },
+            (0xA000, _, _) => {
+                self.selected_rom_banks[ 1 ] = value.get_bits( 0b0001_1111 );
+                self.update_rom_mapping();
+            },
+            (0xB000..=0xE000, _, _) => {
+                let index = (((address >> 12) - 0xB) * 2) as usize + (register >> 1);
+                let bank = &mut self.selected_vrom_banks[ index ];
+                if register & 1 == 0 {
+                    bank.replace_bits( 0b0000_0000_0000_1111, value.get_bits( 0b0000_1111 ) as u16 );
+                } else if self.chip == Chip::VRC4 {
+                    bank.replace_bits( 0b0000_0001_1111_0000, value.get_bits( 0b0001_1111 ) as u16 );
+                } else {
+                    bank.replace_bits( 0b0000_0000_1111_0000, value.get_bits( 0b0000_1111 ) as u16 );
+                }
+
+                self.update_vrom_mapping();
+            },
+            (0xF000, 0, Chip::VRC4) => {
+                let latch = (self.irq.latch() & 0xF0) | value.get_bits( 0b0000_1111 );
+                self.irq.poke_latch( latch );
+            },
+            (0xF000, 1, Chip::VRC4) => {
+                let latch = (self.irq.latch() & 0x0F) | (value.get_bits( 0b0000_1111 ) << 4);
+                self.irq.poke_latch( latch );
+            },
+            (0xF000, 2, Chip::VRC4) => self.irq.poke_control( value ),
+            (0xF000, 3, Chip::VRC4) => self.irq.acknowledge(),
+            _ => {
+                #[cfg(feature = "log")]
+                warn!( "Unhandled write to 0x{:04X} (value=0x{:02X})", address, value );
+            }
+        }
+    }
+
+    fn peek_sram( &self, address: u16 ) -> u8 {
+        if self.has_microwire_latch && address < 0x7000 {
+            // The rest of the bits are open bus.
+            ((address >> 8) as u8 & 0b1111_1110) | self.microwire_latch
+        } else {
+            self.inner.peek_sram( address )
+        }
+    }
+
+    fn poke_sram( &mut self, address: u16, value: u8 ) {
+        if self.has_microwire_latch && address < 0x7000 {
+            self.microwire_latch = is_b0_set( value ) as u8;
+        } else {
+            self.inner.poke_sram( address, value )
+        }
+    }
+
+    fn peek_video_memory( &self, address: u16 ) -> u8 {
+        self.inner.peek_video_memory( address )
+    }
+
+    fn poke_video_memory( &mut self, address: u16, value: u8 ) {
+        self.inner.poke_video_memory( address, value )
+    }
+
+    fn on_cpu_cycle( &mut self ) {
+        self.irq.clock();
+    }
+
+    fn irq_line( &self ) -> bool {
+        self.irq.irq_line()
+    }
+
+    fn info( &self ) -> MapperInfo {
+        let name = match self.chip {
+            Chip::VRC2 => "VRC2",
+            Chip::VRC4 => "VRC4"
+        };
+
+        let mut info = self.inner.describe( name, Some( self.mapper_number ) );
+        info.add_register( "ROM bank 0", self.selected_rom_banks[ 0 ] as u32 );
+        info.add_register( "ROM bank 1", self.selected_rom_banks[ 1 ] as u32 );
+        info.add_register( "ROM banks swapped", self.are_rom_banks_swapped as u32 );
+        match self.chip {
+            Chip::VRC2 if self.has_microwire_latch => info.add_register( "microwire latch", self.microwire_latch as u32 ),
+            Chip::VRC2 => {},
+            Chip::VRC4 => self.irq.describe( &mut info )
+        }
+
+        info
+    }
+}
+
+#[test]
+fn test_vrc4_banks() {
+    use generic_mapper::{create_test_rom, test_rom_8k_bank, test_vrom_1k_bank};
+
+    let mut rom = create_test_rom( 21, 256 * 1024, 256 * 1024 );
+    rom.submapper = 2;
+    let mut mapper = MapperVRC2::from_rom( rom ).unwrap();
+    assert_eq!( test_rom_8k_bank( &mapper, 0x8000 ), 0 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0xC000 ), 30 );
+
+    mapper.poke_rom( 0x8000, 4 );
+    mapper.poke_rom( 0xA000, 7 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0x8000 ), 4 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0xA000 ), 7 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0xC000 ), 30 );
+
+    // VRC4c has the register select lines connected to A6 and A7.
+    mapper.poke_rom( 0x9080, 0b10 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0x8000 ), 30 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0xC000 ), 4 );
+
+    mapper.poke_rom( 0xB000, 0x4 );
+    mapper.poke_rom( 0xB040, 0x1 );
+    mapper.poke_rom( 0xD000, 0x9 );
+    mapper.poke_rom( 0xD040, 0x0 );
+    assert_eq!( test_vrom_1k_bank( &mapper, 0x0000 ), 20 );
+    assert_eq!( test_vrom_1k_bank( &mapper, 0x1000 ), 9 );
+}
+
+#[test]
+fn test_vrc2a_banks() {
+    use generic_mapper::{create_test_rom, test_vrom_1k_bank};
+
+    let rom = create_test_rom( 22, 128 * 1024, 128 * 1024 );
+    let mut mapper = MapperVRC2::from_rom( rom ).unwrap();
+
+    // VRC2a ignores the lowest bit of the VROM bank.
+    mapper.poke_rom( 0xB000, 0xB );
+    mapper.poke_rom( 0xB002, 0x1 );
+    assert_eq!( test_vrom_1k_bank( &mapper, 0x0000 ), 13 );
+
+    // Registers are selected by A1 and A0, in that order.
+    mapper.poke_rom( 0xC001, 0xA );
+    assert_eq!( test_vrom_1k_bank( &mapper, 0x0C00 ), 5 );
+
+    mapper.poke_sram( 0x6000, 0xFF );
+    assert_eq!( mapper.peek_sram( 0x6100 ) & 1, 1 );
+    mapper.poke_sram( 0x6000, 0xFE );
+    assert_eq!( mapper.peek_sram( 0x6100 ) & 1, 0 );
+}
+
+#[test]
+fn test_vrc4_irq() {
+    use generic_mapper::create_test_rom;
+
+    let mut rom = create_test_rom( 25, 128 * 1024, 128 * 1024 );
+    rom.submapper = 1;
+    let mut mapper = MapperVRC2::from_rom( rom ).unwrap();
+
+    // VRC4b has the register select lines connected to A1 and A0.
+    mapper.poke_rom( 0xF000, 0x0E );
+    mapper.poke_rom( 0xF002, 0x0F );
+    mapper.poke_rom( 0xF001, 0b110 );
+    mapper.on_cpu_cycle();
+    assert!( mapper.irq_line() == false );
+    mapper.on_cpu_cycle();
+    assert!( mapper.irq_line() );
+
+    mapper.poke_rom( 0xF003, 0 );
+    assert!( mapper.irq_line() == false );
+}
+
+#[test]
+fn test_vrc2_without_submapper() {
+    use generic_mapper::{create_test_rom, test_rom_8k_bank};
+
+    // A small VRC2b game; on VRC4 a write to 0x9002 would swap the ROM banks.
+    let rom = create_test_rom( 23, 128 * 1024, 128 * 1024 );
+    let mut mapper = MapperVRC2::from_rom( rom ).unwrap();
+    mapper.poke_rom( 0x8000, 4 );
+    mapper.poke_rom( 0x9002, 0b11 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0x8000 ), 4 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0xC000 ), 14 );
+    mapper.poke_sram( 0x6000, 0xFF );
+    assert_eq!( mapper.peek_sram( 0x6100 ), 0x61 );
+
+    // Anything bigger must be a VRC4.
+    let rom = create_test_rom( 23, 256 * 1024, 128 * 1024 );
+    let mut mapper = MapperVRC2::from_rom( rom ).unwrap();
+    mapper.poke_rom( 0x8000, 4 );
+    mapper.poke_rom( 0x9002, 0b10 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0x8000 ), 30 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0xC000 ), 4 );
+}
+
+#[test]
+fn test_vrc2_with_battery_backed_ram() {
+    use generic_mapper::{create_test_rom, test_rom_8k_bank};
+
+    let mut rom = create_test_rom( 25, 128 * 1024, 256 * 1024 );
+    rom.has_battery = true;
+    let mut mapper = MapperVRC2::from_rom( rom ).unwrap();
+    assert_eq!( mapper.info().name, "VRC2" );
+
+    // VRC2c has the register select lines connected to A1 and A0.
+    mapper.poke_rom( 0x8000, 4 );
+    mapper.poke_rom( 0x9001, 0b11 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0x8000 ), 4 );
+
+    mapper.poke_sram( 0x6000, 0xAB );
+    mapper.poke_sram( 0x6FFF, 0xCD );
+    assert_eq!( mapper.peek_sram( 0x6000 ), 0xAB );
+    assert_eq!( mapper.peek_sram( 0x6FFF ), 0xCD );
+}
@@ -0,0 +1,259 @@
+use emumisc::{BitExtra, is_b6_set, is_b7_set};
+use rom::{NesRom, LoadError};
+use mappers::Mapper;
+use mapper_info::MapperInfo;
+use generic_mapper::{bank, BankedGenericMapper};
+use vrc_irq::VrcIrq;
+use opll::Opll;
+use float::F32;
+
+/*
+    Konami's VRC7 (used by Lagrange Point and Tiny Toon Adventures 2).
+
+    The registers are:
+
+        0x8000            - the ROM bank at 0x8000,
+        0x8010            - the ROM bank at 0xA000,
+        0x9000            - the ROM bank at 0xC000,
+        0x9010            - audio register select,
+        0x9030            - audio register write,
+        0xA000 - 0xD010   - the VROM 1kb banks, two per every 0x1000,
+        0xE000            - bits 0 - 1 - mirroring; 0 - vertical, 1 - horizontal,
+                            2 - one screen lower bank, 3 - one screen upper bank,
+                            bit 6 - RAM enable, bit 7 - audio reset,
+        0xE010            - IRQ latch,
+        0xF000            - IRQ control,
+        0xF010            - IRQ acknowledge.
+
+    The bank at 0xE000 is fixed to the last bank.
+
+    On VRC7a (submapper 2) the second register of every pair is selected
+    by A4 (0x10), and on VRC7b (submapper 1) by A3 (0x08); when
+    the submapper is not known we connect both.
+*/
+
+// The audio chip generates a sample every 36 CPU cycles.
+const AUDIO_CLOCK_DIVIDER: u8 = 36;
+
+pub struct MapperVRC7 {
+    inner: BankedGenericMapper,
+    register_select_mask: u16,
+
+    is_save_ram_enabled: bool,
+    is_audio_in_reset: bool,
+    audio_divider: u8,
+    audio: Opll,
+
+    irq: VrcIrq
+}
+
+impl MapperVRC7 {
+    pub fn from_rom( rom: NesRom ) -> Result< Self, LoadError > {
+        let register_select_mask = match rom.submapper {
+            1 => 0x08,
+            2 => 0x10,
+            _ => 0x18
+        };
+
+        let mut mapper = MapperVRC7 {
+            inner: BankedGenericMapper::from_rom( rom )?,
+            register_select_mask: register_select_mask,
+
+            is_save_ram_enabled: false,
+            is_audio_in_reset: false,
+            audio_divider: 0,
+            audio: Opll::new(),
+
+            irq: VrcIrq::new()
+        };
+
+        let last_bank = mapper.inner.last_rom_8k_bank();
+        mapper.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::Ox8000, 0 );
+        mapper.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::OxA000, 0 );
+        mapper.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::OxC000, 0 );
+        mapper.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::OxE000, last_bank );
+        mapper.inner.set_cpu_8k_writable( bank::CPU_8K::Ox6000, false );
+        mapper.inner.set_vertical_mirroring();
+
+        Ok( mapper )
+    }
+
+    fn poke_control( &mut self, value: u8 ) {
+        match value.get_bits( 0b0000_0011 ) {
+            0 => self.inner.set_vertical_mirroring(),
+            1 => self.inner.set_horizontal_mirroring(),
+            2 => self.inner.set_only_lower_bank_mirroring(),
+            _ => self.inner.set_only_upper_bank_mirroring()
+        }
+
+        self.is_save_ram_enabled = is_b6_set( value );
+        self.inner.set_cpu_8k_writable( bank::CPU_8K::Ox6000, self.is_save_ram_enabled );
+
+        self.is_audio_in_reset = is_b7_set( value );
+        if self.is_audio_in_reset {
+            self.audio.reset();
+        }
+    }
+}
+
+impl Mapper for MapperVRC7 {
+    fn peek_rom( &self, address: u16 ) -> u8 {
+        self.inner.peek_rom( address )
+    }
+
+    fn poke_rom( &mut self, address: u16, value: u8 ) {
+        let is_second = address & self.register_select_mask != 0;
+        match (address & 0xF000, is_second) {
+            _ if address & 0xF030 == 0x9010 => self.audio.select_register( value ),
+            _ if address & 0xF030 == 0x9030 => {
+                if self.is_audio_in_reset == false {
+                    self.audio.poke_register( value );
+                }
+            },
+            (0x8000, false) => self.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::Ox8000, value.get_bits( 0b0011_1111 ) ),
+            (0x8000, true) => self.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::OxA000, value.get_bits( 0b0011_1111 ) ),
+            (0x9000, false) => self.inner.set_cpu_8k_bank_to_bank( bank::CPU_8K::OxC000, value.get_bits( 0b0011_1111 ) ),
+            (0xA000..=0xD000, _) => {
+                let index = (((address >> 12) - 0xA) * 2) as usize + is_second as usize;
+                self.inner.set_ppu_1k_bank_to_bank( bank::PPU_1K_PATTERN_BANKS[ index ], value as u16 );
+            },
+            (0xE000, false) => self.poke_control( value ),
+            (0xE000, true) => self.irq.poke_latch( value ),
+            (0xF000, false) => self.irq.poke_control( value ),
+            (0xF000, true) => self.irq.acknowledge(),
+            _ => {
+                #[cfg(feature = "log")]
+                warn!( "Unhandled write to 0x{:04X} (value=0x{:02X})", address, value );
+            }
+        }
+    }
+
+    fn peek_sram( &self, address: u16 ) -> u8 {
+        if self.is_save_ram_enabled == false {
+            // Open bus.
+            return (address >> 8) as u8;
+        }
+
+        self.inner.peek_sram( address )
+    }
+
+    fn poke_sram( &mut self, address: u16, value: u8 ) {
+        self.inner.poke_sram( address, value )
+    }
+
+    fn peek_video_memory( &self, address: u16 ) -> u8 {
+        self.inner.peek_video_memory( address )
+    }
+
+    fn poke_video_memory( &mut self, address: u16, value: u8 ) {
+        self.inner.poke_video_memory( address, value )
+    }
+
+    fn on_cpu_cycle( &mut self ) {
+        self.irq.clock();
+
+        self.audio_divider += 1;
+        if self.audio_divider == AUDIO_CLOCK_DIVIDER {
+            self.audio_divider = 0;
+            if self.is_audio_in_reset == false {
+                self.audio.clock();
+            }
+        }
+    }
+
+    fn irq_line( &self ) -> bool {
+        self.irq.irq_line()
+    }
+
+    // The chip's output is held between the samples, and then
+    // gets resampled along with the rest of the audio by the APU.
+    fn audio_output( &self ) -> F32 {
+        self.audio.output()
+    }
+
+    fn info( &self ) -> MapperInfo {
+        let mut info = self.inner.describe( "VRC7", Some( 85 ) );
+        info.add_register( "RAM enabled", self.is_save_ram_enabled as u32 );
+        info.add_register( "audio reset", self.is_audio_in_reset as u32 );
+        self.irq.describe( &mut info );
+        info
+    }
+}
+
+#[test]
+fn test_vrc7_banks() {
+    use generic_mapper::{create_test_rom, test_rom_8k_bank, test_vrom_1k_bank};
+
+    let mut rom = create_test_rom( 85, 256 * 1024, 256 * 1024 );
+    rom.submapper = 2;
+    let mut mapper = MapperVRC7::from_rom( rom ).unwrap();
+    mapper.poke_rom( 0x8000, 4 );
+    mapper.poke_rom( 0x8010, 7 );
+    mapper.poke_rom( 0x9000, 30 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0x8000 ), 4 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0xA000 ), 7 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0xC000 ), 30 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0xE000 ), 31 );
+
+    // On VRC7a the A3 line is not connected.
+    mapper.poke_rom( 0x8008, 9 );
+    assert_eq!( test_rom_8k_bank( &mapper, 0x8000 ), 9 );
+
+    mapper.poke_rom( 0xA000, 5 );
+    mapper.poke_rom( 0xD010, 12 );
+    assert_eq!( test_vrom_1k_bank( &mapper, 0x0000 ), 5 );
+    assert_eq!( test_vrom_1k_bank( &mapper, 0x1C00 ), 12 );
+
+    // The RAM is disabled by default.
+    mapper.poke_sram( 0x6000, 0xAA );
+    assert_eq!( mapper.peek_sram( 0x6000 ), 0x60 );
+    mapper.poke_rom( 0xE000, 0x40 );
+    mapper.poke_sram( 0x6000, 0xAA );
+    assert_eq!( mapper.peek_sram( 0x6000 ), 0xAA );
+}
+
+#[test]
+fn test_vrc7_irq() {
+    use generic_mapper::create_test_rom;
+
+    let rom = create_test_rom( 85, 128 * 1024, 128 * 1024 );
+    let mut mapper = MapperVRC7::from_rom( rom ).unwrap();
+    mapper.poke_rom( 0xE010, 0xFE );
+    mapper.poke_rom( 0xF000, 0b110 );
+    mapper.on_cpu_cycle();
+    assert!( mapper.irq_line() == false );
+    mapper.on_cpu_cycle();
+    assert!( mapper.irq_line() );
+
+    mapper.poke_rom( 0xF010, 0 );
+    assert!( mapper.irq_line() == false );
+}
+
+#[test]
+fn test_vrc7_audio() {
+    use generic_mapper::create_test_rom;
+
+    let rom = create_test_rom( 85, 128 * 1024, 128 * 1024 );
+    let mut mapper = MapperVRC7::from_rom( rom ).unwrap();
+    mapper.poke_rom( 0x9010, 0x10 );
+    mapper.poke_rom( 0x9030, 0x22 );
+    mapper.poke_rom( 0x9010, 0x30 );
+    mapper.poke_rom( 0x9030, 0x10 );
+    mapper.poke_rom( 0x9010, 0x20 );
+    mapper.poke_rom( 0x9030, 0b0001_1001 );
+
+    let mut is_playing = false;
+    for _ in 0..36 * 1000 {
+        mapper.on_cpu_cycle();
+        if mapper.audio_output() != f32!(0.0) {
+            is_playing = true;
+        }
+    }
+
+    assert!( is_playing );
+
+    // The reset silences the audio.
+    mapper.poke_rom( 0xE000, 0x80 );
+    mapper.on_cpu_cycle();
+    assert!( mapper.audio_output() == f32!(0.0) );
+}
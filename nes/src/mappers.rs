@@ -1,12 +1,52 @@
 use alloc::boxed::Box;
+use alloc::vec::Vec;
 use alloc::format;
 
 use rom::{NesRom, LoadError};
+use float::F32;
+use mapper_info::MapperInfo;
 use generic_mapper::GenericMapper;
 use mapper_mmc1::MapperMMC1;
+use mapper_mmc2::MapperMMC2;
+use mapper_mmc5::MapperMMC5;
 use mapper_uxrom::MapperUxROM;
 use mapper_unrom512::MapperUNROM512;
 use mapper_axrom::MapperAxROM;
+use mapper_cnrom::MapperCNROM;
+use mapper_gxrom::MapperGxROM;
+use mapper_color_dreams::MapperColorDreams;
+use mapper_bnrom::MapperBNROM;
+use mapper_camerica::MapperCamerica;
+use mapper_vrc2::MapperVRC2;
+use mapper_fme7::MapperFME7;
+use mapper_vrc7::MapperVRC7;
+use mapper_namco163::MapperNamco163;
+
+/*
+    Options which only affect how the expansion audio of
+    some of the cartridges ends up being mixed.
+*/
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct AudioOptions {
+    // Whether the Namco 163's channels are output one after another, like on
+    // the real hardware, which produces an audible high-pitched whine when
+    // many channels are enabled, or are just averaged together instead.
+    pub namco_163_multiplexing: bool
+}
+
+impl AudioOptions {
+    pub const fn new() -> Self {
+        AudioOptions {
+            namco_163_multiplexing: true
+        }
+    }
+}
+
+impl Default for AudioOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 pub trait Mapper {
     fn peek_rom( &self, address: u16 ) -> u8;
@@ -23,19 +63,91 @@ pub trait Mapper {
         warn!( "Unhandled write to the save RAM at 0x{:04X} (value=0x{:02X})", _address, _value );
     }
 
-    fn peek_expansion_rom( &self, _address: u16 ) -> u8 {
+    fn peek_expansion_rom( &mut self, _address: u16 ) -> u8 {
         #[cfg(feature = "log")]
         warn!( "Unhandled read from the expansion ROM at 0x{:04X}", _address );
         0
     }
 
-    fn poke_expansion_rom( &self, _address: u16, _value: u8 ) {
+    fn poke_expansion_rom( &mut self, _address: u16, _value: u8 ) {
         #[cfg(feature = "log")]
         warn!( "Unhandled write to the expansion ROM at 0x{:04X} (value=0x{:02X})", _address, _value );
     }
 
     fn peek_video_memory( &self, address: u16 ) -> u8;
     fn poke_video_memory( &mut self, address: u16, value: u8 );
+
+    // Called when the PPU itself reads the video memory, either when
+    // rendering or through PPUDATA. Unlike `peek_video_memory` (which
+    // is also used by the debugging tools) this can have side effects.
+    fn fetch_video_memory( &mut self, address: u16 ) -> u8 {
+        self.peek_video_memory( address )
+    }
+
+    // Called once for every CPU cycle, before the memory access done on that cycle.
+    fn on_cpu_cycle( &mut self ) {}
+
+    // Called for every write to the PPU's registers, since
+    // some of the mappers snoop on them.
+    fn on_ppu_register_write( &mut self, _address: u16, _value: u8 ) {}
+
+    // Called at the start of every scanline; see `rp2c02::Context::on_scanline`.
+    fn on_ppu_scanline( &mut self, _scanline: u16 ) {}
+
+    // Whether the mapper is currently asserting the IRQ line.
+    fn irq_line( &self ) -> bool {
+        false
+    }
+
+    // The output of the mapper's expansion audio, if it has one;
+    // this is mixed in with the output of the APU.
+    fn audio_output( &self ) -> F32 {
+        f32!(0.0)
+    }
+
+    fn set_audio_options( &mut self, _options: AudioOptions ) {}
+
+    // The disk drive of the Famicom Disk System; `None` means that there is no disk inserted.
+    fn disk_side_count( &self ) -> usize {
+        0
+    }
+
+    fn disk_side( &self ) -> Option< usize > {
+        None
+    }
+
+    fn set_disk_side( &mut self, _side: Option< usize > ) {}
+
+    fn is_disk_modified( &self ) -> bool {
+        false
+    }
+
+    // The disk image with all of the writes applied, in the same format it was loaded from.
+    fn disk_image( &self ) -> Option< Vec< u8 > > {
+        None
+    }
+
+    fn restore_disk_image( &mut self, _data: &[u8] ) -> Result< (), LoadError > {
+        Err( LoadError::new( "there is no disk drive" ) )
+    }
+
+    // The contents of the flash memory of the boards which can reprogram their own ROM.
+    fn flash_memory( &self ) -> Option< Vec< u8 > > {
+        None
+    }
+
+    fn is_flash_modified( &self ) -> bool {
+        false
+    }
+
+    fn restore_flash_memory( &mut self, _data: &[u8] ) -> Result< (), LoadError > {
+        Err( LoadError::new( "the cartridge has no flash memory" ) )
+    }
+
+    // Describes the current state of the mapper; only used for debugging.
+    fn info( &self ) -> MapperInfo {
+        MapperInfo::new( "unknown", None )
+    }
 }
 
 pub struct MapperNull;
@@ -62,6 +174,10 @@ impl Mapper for MapperNull {
         #[cfg(feature = "log")]
         warn!( "Unhandled write to the VROM at 0x{:04X} (value=0x{:02X})", _address, _value );
     }
+
+    fn info( &self ) -> MapperInfo {
+        MapperInfo::new( "none", None )
+    }
 }
 
 pub fn create_mapper( rom: NesRom ) -> Result< Box< dyn Mapper >, LoadError > {
@@ -92,18 +208,90 @@ pub fn create_mapper( rom: NesRom ) -> Result< Box< dyn Mapper >, LoadError > {
                 boxed
             })
         },
+        3 => {
+            MapperCNROM::from_rom( rom ).map( |mapper| {
+                let boxed: Box< dyn Mapper > = Box::new( mapper );
+                boxed
+            })
+        },
+        5 => {
+            MapperMMC5::from_rom( rom ).map( |mapper| {
+                let boxed: Box< dyn Mapper > = Box::new( mapper );
+                boxed
+            })
+        },
         7 => {
             MapperAxROM::from_rom( rom ).map( |mapper| {
                 let boxed: Box< dyn Mapper > = Box::new( mapper );
                 boxed
             })
         },
+        9 => {
+            MapperMMC2::mmc2_from_rom( rom ).map( |mapper| {
+                let boxed: Box< dyn Mapper > = Box::new( mapper );
+                boxed
+            })
+        },
+        10 => {
+            MapperMMC2::mmc4_from_rom( rom ).map( |mapper| {
+                let boxed: Box< dyn Mapper > = Box::new( mapper );
+                boxed
+            })
+        },
+        11 => {
+            MapperColorDreams::from_rom( rom ).map( |mapper| {
+                let boxed: Box< dyn Mapper > = Box::new( mapper );
+                boxed
+            })
+        },
+        19 => {
+            MapperNamco163::from_rom( rom ).map( |mapper| {
+                let boxed: Box< dyn Mapper > = Box::new( mapper );
+                boxed
+            })
+        },
+        21 | 22 | 23 | 25 => {
+            MapperVRC2::from_rom( rom ).map( |mapper| {
+                let boxed: Box< dyn Mapper > = Box::new( mapper );
+                boxed
+            })
+        },
         30 => {
             MapperUNROM512::from_rom( rom ).map( |mapper| {
                 let boxed: Box< dyn Mapper > = Box::new( mapper );
                 boxed
             })
         },
+        34 => {
+            MapperBNROM::from_rom( rom ).map( |mapper| {
+                let boxed: Box< dyn Mapper > = Box::new( mapper );
+                boxed
+            })
+        },
+        66 => {
+            MapperGxROM::from_rom( rom ).map( |mapper| {
+                let boxed: Box< dyn Mapper > = Box::new( mapper );
+                boxed
+            })
+        },
+        69 => {
+            MapperFME7::from_rom( rom ).map( |mapper| {
+                let boxed: Box< dyn Mapper > = Box::new( mapper );
+                boxed
+            })
+        },
+        71 => {
+            MapperCamerica::from_rom( rom ).map( |mapper| {
+                let boxed: Box< dyn Mapper > = Box::new( mapper );
+                boxed
+            })
+        },
+        85 => {
+            MapperVRC7::from_rom( rom ).map( |mapper| {
+                let boxed: Box< dyn Mapper > = Box::new( mapper );
+                boxed
+            })
+        },
         _ => Err( LoadError::new( format!( "Unhandled mapper: {}", rom.mapper ) ) )
     }
 }
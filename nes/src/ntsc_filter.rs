@@ -0,0 +1,349 @@
+use std::f32::consts::PI;
+
+use rp2c02::Framebuffer;
+
+/*
+    An NTSC composite video filter.
+
+    The NES doesn't output RGB; the PPU generates a composite video signal directly,
+    as a square wave which alternates between two voltage levels, where the phase
+    of the wave determines the hue, the amplitude the saturation and the average
+    the brightness of the pixel. The emphasis bits attenuate the signal during
+    some of the phases.
+
+    This filter generates that signal (8 samples per pixel, 12 samples per
+    period of the color subcarrier) and then decodes it back into RGB just like
+    a TV would. Since the luma and chroma share the same signal they bleed into each
+    other, which results in the same artifact colors and fringing you'd see on the
+    real hardware. The phase of the color subcarrier shifts by a third of a period on
+    every scanline and alternates on every frame, which produces the dot crawl.
+
+    For details see:
+        http://wiki.nesdev.com/w/index.php/NTSC_video
+*/
+
+const SAMPLES_PER_PIXEL: usize = 8;
+const SAMPLES_PER_LINE: usize = 256 * SAMPLES_PER_PIXEL;
+pub const SUBCARRIER_PERIOD: usize = 12;
+
+// The signal's phase shifts by 341 * 8 samples on every scanline.
+const PHASE_SHIFT_PER_LINE: usize = (341 * SAMPLES_PER_PIXEL) % SUBCARRIER_PERIOD;
+
+// Voltage levels, relative to the sync voltage.
+const SIGNAL_LOW: [f32; 4] = [0.350, 0.518, 0.962, 1.550];
+const SIGNAL_HIGH: [f32; 4] = [1.094, 1.506, 1.962, 1.962];
+const SIGNAL_BLACK: f32 = 0.518;
+const SIGNAL_WHITE: f32 = 1.962;
+const EMPHASIS_ATTENUATION: f32 = 0.746;
+
+// These were picked to match the default palette as closely as possible.
+pub const HUE_OFFSET: f32 = 4.0;
+pub const CHROMA_SCALE: f32 = 1.5;
+
+const GAMMA_TABLE_SIZE: usize = 1024;
+
+// All of the parameters are in the -1.0...1.0 range, where 0.0 is the default.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct NtscSettings {
+    // -1.0 = -180 degrees, 1.0 = +180 degrees
+    pub hue: f32,
+    // -1.0 = grayscale, 1.0 = oversaturated
+    pub saturation: f32,
+    // -1.0 = dark, 1.0 = light
+    pub contrast: f32,
+    // -1.0 = dark, 1.0 = light
+    pub brightness: f32,
+    // -1.0 = dark, 1.0 = light
+    pub gamma: f32,
+    // -1.0 = blurry, 1.0 = sharp (with more luma artifacts)
+    pub sharpness: f32,
+    // -1.0 = no artifact colors, 1.0 = exaggerated artifact colors
+    pub artifacts: f32,
+    // -1.0 = sharp colors, 1.0 = colors bleeding into their neighbours
+    pub bleed: f32,
+    // Whether the subcarrier's phase alternates every frame.
+    pub dot_crawl: bool
+}
+
+impl Default for NtscSettings {
+    fn default() -> Self {
+        NtscSettings {
+            hue: 0.0,
+            saturation: 0.0,
+            contrast: 0.0,
+            brightness: 0.0,
+            gamma: 0.0,
+            sharpness: 0.0,
+            artifacts: 0.0,
+            bleed: 0.0,
+            dot_crawl: true
+        }
+    }
+}
+
+pub struct NtscFilter {
+    settings: NtscSettings,
+    signal_levels: Vec< [f32; SUBCARRIER_PERIOD] >,
+    cos_table: [f32; SUBCARRIER_PERIOD],
+    sin_table: [f32; SUBCARRIER_PERIOD],
+    gamma_table: Vec< u8 >,
+    signal: Vec< f32 >,
+    luma: Vec< f32 >,
+    prefix_signal: Vec< f32 >,
+    prefix_i: Vec< f32 >,
+    prefix_q: Vec< f32 >,
+    prefix_luma_i: Vec< f32 >,
+    prefix_luma_q: Vec< f32 >,
+    frame_phase: usize
+}
+
+pub fn generate_signal_level( pixel: u16, phase: usize ) -> f32 {
+    let color = (pixel & 0x0F) as usize;
+    let level = if color > 13 { 1 } else { ((pixel >> 4) & 3) as usize };
+    let emphasis = pixel >> 6;
+
+    let in_color_phase = |color: usize| (color + phase) % SUBCARRIER_PERIOD < 6;
+
+    let mut low = SIGNAL_LOW[ level ];
+    let mut high = SIGNAL_HIGH[ level ];
+    if color == 0 {
+        low = high;
+    }
+    if color > 12 {
+        high = low;
+    }
+
+    let mut signal = if in_color_phase( color ) { high } else { low };
+    if ((emphasis & 1) != 0 && in_color_phase( 0 )) ||
+       ((emphasis & 2) != 0 && in_color_phase( 4 )) ||
+       ((emphasis & 4) != 0 && in_color_phase( 8 )) {
+        signal *= EMPHASIS_ATTENUATION;
+    }
+
+    (signal - SIGNAL_BLACK) / (SIGNAL_WHITE - SIGNAL_BLACK)
+}
+
+// The YIQ to RGB conversion matrix, as sanctioned by the FCC.
+#[inline]
+pub fn yiq_to_rgb( y: f32, i: f32, q: f32 ) -> (f32, f32, f32) {
+    let r = y + 0.946882 * i + 0.623557 * q;
+    let g = y - 0.274788 * i - 0.635691 * q;
+    let b = y - 1.108545 * i + 1.709007 * q;
+
+    (r, g, b)
+}
+
+#[inline]
+pub fn gamma_exponent( gamma: f32 ) -> f32 {
+    (1.0 - gamma * 0.5).max( 0.1 )
+}
+
+#[inline]
+fn window( prefix: &[f32], center: usize, width: usize ) -> f32 {
+    let start = center.saturating_sub( width / 2 );
+    let end = (center + width / 2).min( SAMPLES_PER_LINE );
+    (prefix[ end ] - prefix[ start ]) / width as f32
+}
+
+// Picks which window to blend with depending on the sign of the parameter.
+#[inline]
+fn blend_width( parameter: f32, negative: usize, positive: usize ) -> usize {
+    if parameter >= 0.0 { positive } else { negative }
+}
+
+impl NtscFilter {
+    pub const OUTPUT_WIDTH: usize = 602;
+    pub const OUTPUT_HEIGHT: usize = 240;
+
+    pub fn new( settings: NtscSettings ) -> NtscFilter {
+        let mut signal_levels = Vec::with_capacity( 512 );
+        for pixel in 0..512 {
+            let mut levels = [0.0; SUBCARRIER_PERIOD];
+            for (phase, level) in levels.iter_mut().enumerate() {
+                *level = generate_signal_level( pixel, phase );
+            }
+            signal_levels.push( levels );
+        }
+
+        let mut filter = NtscFilter {
+            settings: settings,
+            signal_levels: signal_levels,
+            cos_table: [0.0; SUBCARRIER_PERIOD],
+            sin_table: [0.0; SUBCARRIER_PERIOD],
+            gamma_table: vec![ 0; GAMMA_TABLE_SIZE ],
+            signal: vec![ 0.0; SAMPLES_PER_LINE ],
+            luma: vec![ 0.0; SAMPLES_PER_LINE ],
+            prefix_signal: vec![ 0.0; SAMPLES_PER_LINE + 1 ],
+            prefix_i: vec![ 0.0; SAMPLES_PER_LINE + 1 ],
+            prefix_q: vec![ 0.0; SAMPLES_PER_LINE + 1 ],
+            prefix_luma_i: vec![ 0.0; SAMPLES_PER_LINE + 1 ],
+            prefix_luma_q: vec![ 0.0; SAMPLES_PER_LINE + 1 ],
+            frame_phase: 0
+        };
+
+        filter.set_settings( settings );
+        filter
+    }
+
+    #[inline]
+    pub fn settings( &self ) -> &NtscSettings {
+        &self.settings
+    }
+
+    pub fn set_settings( &mut self, settings: NtscSettings ) {
+        self.settings = settings;
+
+        let hue = HUE_OFFSET + settings.hue * (SUBCARRIER_PERIOD as f32 / 2.0);
+        for phase in 0..SUBCARRIER_PERIOD {
+            let angle = PI * (phase as f32 + hue) / (SUBCARRIER_PERIOD as f32 / 2.0);
+            self.cos_table[ phase ] = angle.cos();
+            self.sin_table[ phase ] = angle.sin();
+        }
+
+        let exponent = gamma_exponent( settings.gamma );
+        for (index, value) in self.gamma_table.iter_mut().enumerate() {
+            let x = index as f32 / (GAMMA_TABLE_SIZE - 1) as f32;
+            *value = (x.powf( exponent ) * 255.0 + 0.5) as u8;
+        }
+    }
+
+    // Converts the framebuffer into an image of `OUTPUT_WIDTH` x `OUTPUT_HEIGHT`
+    // pixels, in the same packed format as `Framebuffer::convert_to_abgr`.
+    pub fn apply< T: AsMut< [u32] >>( &mut self, framebuffer: &Framebuffer, mut output: T ) {
+        let output = output.as_mut();
+        assert_eq!( output.len(), NtscFilter::OUTPUT_WIDTH * NtscFilter::OUTPUT_HEIGHT );
+
+        let mut pixels = framebuffer.iter();
+        for (line, output_line) in output.chunks_mut( NtscFilter::OUTPUT_WIDTH ).enumerate() {
+            let phase = (self.frame_phase + line * PHASE_SHIFT_PER_LINE) % SUBCARRIER_PERIOD;
+            for x in 0..256 {
+                let levels = &self.signal_levels[ pixels.next().unwrap().full_color_index() as usize ];
+                let offset = x * SAMPLES_PER_PIXEL;
+                let mut sample_phase = (phase + offset) % SUBCARRIER_PERIOD;
+                for sample in self.signal[ offset..offset + SAMPLES_PER_PIXEL ].iter_mut() {
+                    *sample = levels[ sample_phase ];
+                    sample_phase += 1;
+                    if sample_phase == SUBCARRIER_PERIOD {
+                        sample_phase = 0;
+                    }
+                }
+            }
+
+            self.decode_line( phase, output_line );
+        }
+
+        if self.settings.dot_crawl {
+            self.frame_phase = (self.frame_phase + SUBCARRIER_PERIOD / 3) % (SUBCARRIER_PERIOD * 2 / 3);
+        } else {
+            self.frame_phase = 0;
+        }
+    }
+
+    fn decode_line( &mut self, phase: usize, output: &mut [u32] ) {
+        for index in 0..SAMPLES_PER_LINE {
+            self.prefix_signal[ index + 1 ] = self.prefix_signal[ index ] + self.signal[ index ];
+        }
+
+        // The luma, with the chroma completely filtered out.
+        for index in 0..SAMPLES_PER_LINE {
+            self.luma[ index ] = window( &self.prefix_signal, index, SUBCARRIER_PERIOD );
+        }
+
+        let mut sample_phase = phase;
+        for index in 0..SAMPLES_PER_LINE {
+            let cos = self.cos_table[ sample_phase ];
+            let sin = self.sin_table[ sample_phase ];
+            sample_phase += 1;
+            if sample_phase == SUBCARRIER_PERIOD {
+                sample_phase = 0;
+            }
+
+            self.prefix_i[ index + 1 ] = self.prefix_i[ index ] + self.signal[ index ] * cos;
+            self.prefix_q[ index + 1 ] = self.prefix_q[ index ] + self.signal[ index ] * sin;
+            self.prefix_luma_i[ index + 1 ] = self.prefix_luma_i[ index ] + self.luma[ index ] * cos;
+            self.prefix_luma_q[ index + 1 ] = self.prefix_luma_q[ index ] + self.luma[ index ] * sin;
+        }
+
+        let settings = self.settings;
+        let contrast = 1.0 + settings.contrast * 0.5;
+        let brightness = settings.brightness * 0.5;
+        let saturation = CHROMA_SCALE * (1.0 + settings.saturation) * contrast;
+
+        // When decoding the chroma we subtract some of the luma (or add some more)
+        // to control how strong are the artifacts caused by the luma changes.
+        let luma_in_chroma = -settings.artifacts;
+
+        let demodulate = |prefix: &[f32], prefix_luma: &[f32], center: usize, width: usize| {
+            if luma_in_chroma == 0.0 {
+                window( prefix, center, width )
+            } else {
+                window( prefix, center, width ) - luma_in_chroma * window( prefix_luma, center, width )
+            }
+        };
+
+        // Sharpness blends the luma with a narrower (or wider) window, and bleed does the same for the chroma.
+        let luma_width = SUBCARRIER_PERIOD;
+        let luma_blend_width = blend_width( settings.sharpness, SUBCARRIER_PERIOD * 2, SAMPLES_PER_PIXEL );
+        let luma_blend = settings.sharpness.abs();
+        let chroma_width = SUBCARRIER_PERIOD * 2;
+        let chroma_blend_width = blend_width( settings.bleed, SUBCARRIER_PERIOD, SUBCARRIER_PERIOD * 4 );
+        let chroma_blend = settings.bleed.abs();
+
+        for (x, out) in output.iter_mut().enumerate() {
+            let center = (x * SAMPLES_PER_LINE + SAMPLES_PER_LINE / 2) / NtscFilter::OUTPUT_WIDTH;
+
+            let mut y = window( &self.prefix_signal, center, luma_width );
+            if luma_blend != 0.0 {
+                y += (window( &self.prefix_signal, center, luma_blend_width ) - y) * luma_blend;
+            }
+
+            let mut i = demodulate( &self.prefix_i, &self.prefix_luma_i, center, chroma_width );
+            let mut q = demodulate( &self.prefix_q, &self.prefix_luma_q, center, chroma_width );
+            if chroma_blend != 0.0 {
+                i += (demodulate( &self.prefix_i, &self.prefix_luma_i, center, chroma_blend_width ) - i) * chroma_blend;
+                q += (demodulate( &self.prefix_q, &self.prefix_luma_q, center, chroma_blend_width ) - q) * chroma_blend;
+            }
+
+            let y = y * contrast + brightness;
+            let i = i * saturation;
+            let q = q * saturation;
+            let (r, g, b) = yiq_to_rgb( y, i, q );
+
+            *out = self.to_component( r ) |
+                   self.to_component( g ) << 8 |
+                   self.to_component( b ) << 16 |
+                   0xFF << 24;
+        }
+    }
+
+    #[inline]
+    fn to_component( &self, value: f32 ) -> u32 {
+        let value = value.max( 0.0 ).min( 1.0 );
+        self.gamma_table[ (value * (GAMMA_TABLE_SIZE - 1) as f32 + 0.5) as usize ] as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NtscFilter, NtscSettings};
+    use rp2c02::{Framebuffer, Palette};
+
+    #[test]
+    fn flat_gray() {
+        // The color 0x00 is gray, so it has no chroma, and since it's flat there are no artifacts.
+        let framebuffer = Framebuffer::default();
+        let mut filter = NtscFilter::new( NtscSettings::default() );
+        let mut output = vec![ 0; NtscFilter::OUTPUT_WIDTH * NtscFilter::OUTPUT_HEIGHT ];
+        filter.apply( &framebuffer, &mut output );
+
+        let (r, g, b) = Palette::default().get_rgb( 0x00 );
+        let pixel = output[ 120 * NtscFilter::OUTPUT_WIDTH + NtscFilter::OUTPUT_WIDTH / 2 ];
+        let component = |shift: u32| ((pixel >> shift) & 0xFF) as i32;
+        assert_eq!( pixel >> 24, 0xFF );
+        assert_eq!( component( 0 ), component( 8 ) );
+        assert_eq!( component( 8 ), component( 16 ) );
+        assert!( (component( 0 ) - r as i32).abs() <= 8 );
+        assert!( (component( 8 ) - g as i32).abs() <= 8 );
+        assert!( (component( 16 ) - b as i32).abs() <= 8 );
+    }
+}
@@ -0,0 +1,530 @@
+use core::cmp::min;
+
+use emumisc::{BitExtra, is_b4_set, is_b5_set};
+use float::{F32, i32_to_f32};
+
+/*
+    An emulation of the OPLL (YM2413) FM synthesizer, as found in the VRC7.
+
+    The VRC7's variant has only six melodic channels (no rhythm mode)
+    and its own set of the built-in instruments.
+
+    Every channel has two operators, a modulator and a carrier, where the output
+    of the modulator modulates the phase of the carrier. Just like the real chip
+    this works in the logarithmic domain; the sine wave is taken from a table
+    of log-sin values, the attenuation is added to it, and the result is
+    converted back into the linear domain with a table of powers of two.
+    The attenuation is expressed in 1/256ths of an octave (~0.0235dB).
+
+    The registers are:
+
+        0x00 - 0x07       - the custom instrument,
+        0x10 - 0x15       - the lower 8 bits of the channel's frequency,
+        0x20 - 0x25       - bit 0 - the highest bit of the frequency, bits 1 - 3 - the octave,
+                            bit 4 - key on, bit 5 - sustain,
+        0x30 - 0x35       - bits 0 - 3 - the volume, bits 4 - 7 - the instrument.
+
+    The instruments are eight bytes long:
+
+        0x0, 0x1          - for the modulator and the carrier; bits 0 - 3 - the frequency
+                            multiplier, bit 4 - key scale rate, bit 5 - sustained envelope,
+                            bit 6 - vibrato, bit 7 - tremolo,
+        0x2               - bits 0 - 5 - the modulator's total level, bits 6 - 7 - the modulator's key scale level,
+        0x3               - bits 0 - 2 - feedback, bit 3 - the modulator's half sine,
+                            bit 4 - the carrier's half sine, bits 6 - 7 - the carrier's key scale level,
+        0x4, 0x5          - bits 0 - 3 - decay rate, bits 4 - 7 - attack rate,
+        0x6, 0x7          - bits 0 - 3 - release rate, bits 4 - 7 - sustain level.
+*/
+
+// Taken from: https://www.nesdev.org/wiki/VRC7_audio
+static INSTRUMENTS: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27],
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12],
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12],
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27],
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28],
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4],
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07],
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17],
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01],
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02],
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12],
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16],
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02],
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6],
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06]
+];
+
+// -log2( sin( x ) ) * 256 for the first quarter of the sine wave.
+static LOG_SIN_TABLE: [u16; 256] = [
+    2137, 1731, 1543, 1419, 1326, 1252, 1190, 1137, 1091, 1050, 1013,  979,  949,  920,  894,  869,
+     846,  825,  804,  785,  767,  749,  732,  717,  701,  687,  672,  659,  646,  633,  621,  609,
+     598,  587,  576,  566,  556,  546,  536,  527,  518,  509,  501,  492,  484,  476,  468,  461,
+     453,  446,  439,  432,  425,  418,  411,  405,  399,  392,  386,  380,  375,  369,  363,  358,
+     352,  347,  341,  336,  331,  326,  321,  316,  311,  307,  302,  297,  293,  289,  284,  280,
+     276,  271,  267,  263,  259,  255,  251,  248,  244,  240,  236,  233,  229,  226,  222,  219,
+     215,  212,  209,  205,  202,  199,  196,  193,  190,  187,  184,  181,  178,  175,  172,  169,
+     167,  164,  161,  159,  156,  153,  151,  148,  146,  143,  141,  138,  136,  134,  131,  129,
+     127,  125,  122,  120,  118,  116,  114,  112,  110,  108,  106,  104,  102,  100,   98,   96,
+      94,   92,   91,   89,   87,   85,   83,   82,   80,   78,   77,   75,   74,   72,   70,   69,
+      67,   66,   64,   63,   62,   60,   59,   57,   56,   55,   53,   52,   51,   49,   48,   47,
+      46,   45,   43,   42,   41,   40,   39,   38,   37,   36,   35,   34,   33,   32,   31,   30,
+      29,   28,   27,   26,   25,   24,   23,   23,   22,   21,   20,   20,   19,   18,   17,   17,
+      16,   15,   15,   14,   13,   13,   12,   12,   11,   10,   10,    9,    9,    8,    8,    7,
+       7,    7,    6,    6,    5,    5,    5,    4,    4,    4,    3,    3,    3,    2,    2,    2,
+       2,    1,    1,    1,    1,    1,    1,    1,    0,    0,    0,    0,    0,    0,    0,    0
+];
+
+// 2 ^ ((255 - x) / 256) * 1024
+static EXP_TABLE: [u16; 256] = [
+    2042, 2037, 2031, 2026, 2020, 2015, 2010, 2004, 1999, 1993, 1988, 1983, 1977, 1972, 1966, 1961,
+    1956, 1951, 1945, 1940, 1935, 1930, 1924, 1919, 1914, 1909, 1904, 1898, 1893, 1888, 1883, 1878,
+    1873, 1868, 1863, 1858, 1853, 1848, 1843, 1838, 1833, 1828, 1823, 1818, 1813, 1808, 1803, 1798,
+    1794, 1789, 1784, 1779, 1774, 1769, 1765, 1760, 1755, 1750, 1746, 1741, 1736, 1732, 1727, 1722,
+    1717, 1713, 1708, 1704, 1699, 1694, 1690, 1685, 1681, 1676, 1672, 1667, 1663, 1658, 1654, 1649,
+    1645, 1640, 1636, 1631, 1627, 1623, 1618, 1614, 1609, 1605, 1601, 1596, 1592, 1588, 1584, 1579,
+    1575, 1571, 1566, 1562, 1558, 1554, 1550, 1545, 1541, 1537, 1533, 1529, 1525, 1520, 1516, 1512,
+    1508, 1504, 1500, 1496, 1492, 1488, 1484, 1480, 1476, 1472, 1468, 1464, 1460, 1456, 1452, 1448,
+    1444, 1440, 1436, 1433, 1429, 1425, 1421, 1417, 1413, 1409, 1406, 1402, 1398, 1394, 1391, 1387,
+    1383, 1379, 1376, 1372, 1368, 1364, 1361, 1357, 1353, 1350, 1346, 1342, 1339, 1335, 1332, 1328,
+    1324, 1321, 1317, 1314, 1310, 1307, 1303, 1300, 1296, 1292, 1289, 1286, 1282, 1279, 1275, 1272,
+    1268, 1265, 1261, 1258, 1255, 1251, 1248, 1244, 1241, 1238, 1234, 1231, 1228, 1224, 1221, 1218,
+    1214, 1211, 1208, 1205, 1201, 1198, 1195, 1192, 1188, 1185, 1182, 1179, 1176, 1172, 1169, 1166,
+    1163, 1160, 1157, 1154, 1150, 1147, 1144, 1141, 1138, 1135, 1132, 1129, 1126, 1123, 1120, 1117,
+    1114, 1111, 1108, 1105, 1102, 1099, 1096, 1093, 1090, 1087, 1084, 1081, 1078, 1075, 1072, 1069,
+    1066, 1064, 1061, 1058, 1055, 1052, 1049, 1046, 1044, 1041, 1038, 1035, 1032, 1030, 1027, 1024
+];
+
+// The frequency multipliers, times two.
+static MULTIPLIER_TABLE: [u32; 16] = [1, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 20, 24, 24, 30, 30];
+
+// The attenuation for the upper four bits of the frequency, in 0.75dB steps.
+static KEY_SCALE_LEVEL_TABLE: [u8; 16] = [0, 32, 40, 45, 48, 51, 53, 55, 56, 58, 59, 60, 61, 62, 63, 64];
+
+// How much the envelope changes on each update, for the lowest two bits of the rate.
+static ENVELOPE_INCREMENT_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 1, 0, 1, 0, 1],
+    [0, 1, 0, 1, 1, 1, 0, 1],
+    [0, 1, 1, 1, 0, 1, 1, 1],
+    [0, 1, 1, 1, 1, 1, 1, 1]
+];
+
+// The vibrato's offset of the frequency, for the upper three bits of the frequency.
+static VIBRATO_TABLE: [[i8; 8]; 8] = [
+    [0, 0, 0, 0, 0,  0,  0,  0],
+    [0, 0, 1, 0, 0,  0, -1,  0],
+    [0, 1, 2, 1, 0, -1, -2, -1],
+    [0, 1, 3, 1, 0, -1, -3, -1],
+    [0, 2, 4, 2, 0, -2, -4, -2],
+    [0, 2, 5, 2, 0, -2, -5, -2],
+    [0, 3, 6, 3, 0, -3, -6, -3],
+    [0, 3, 7, 3, 0, -3, -7, -3]
+];
+
+// The tremolo is a triangle wave with a depth of 4.8dB
+// which is advanced every 64 samples.
+const TREMOLO_STEP_COUNT: u32 = 210;
+const TREMOLO_DEPTH: u32 = 13;
+
+const MAXIMUM_ENVELOPE_LEVEL: u8 = 127;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum EnvelopeState {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Off
+}
+
+#[derive(Copy, Clone)]
+struct Operator {
+    phase: u32,
+    envelope_state: EnvelopeState,
+    envelope_level: u8,
+    output: i32,
+    previous_output: i32
+}
+
+impl Operator {
+    const fn new() -> Operator {
+        Operator {
+            phase: 0,
+            envelope_state: EnvelopeState::Off,
+            envelope_level: MAXIMUM_ENVELOPE_LEVEL,
+            output: 0,
+            previous_output: 0
+        }
+    }
+
+    fn key_on( &mut self ) {
+        self.phase = 0;
+        self.envelope_state = EnvelopeState::Attack;
+    }
+
+    fn key_off( &mut self ) {
+        if self.envelope_state != EnvelopeState::Off {
+            self.envelope_state = EnvelopeState::Release;
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Channel {
+    frequency: u16,
+    octave: u8,
+    is_key_on: bool,
+    is_sustain_on: bool,
+    instrument: u8,
+    volume: u8,
+    operators: [Operator; 2]
+}
+
+impl Channel {
+    const fn new() -> Channel {
+        Channel {
+            frequency: 0,
+            octave: 0,
+            is_key_on: false,
+            is_sustain_on: false,
+            instrument: 0,
+            volume: 0,
+            operators: [Operator::new(), Operator::new()]
+        }
+    }
+}
+
+// Converts the phase (with the lowest 10 bits being the position
+// within the sine wave) and the attenuation into the output.
+fn operator_output( phase: i32, attenuation: u32, is_half_sine: bool ) -> i32 {
+    let phase = phase as u32 & 0x3FF;
+    let is_negative = phase & 0x200 != 0;
+    if is_negative && is_half_sine {
+        return 0;
+    }
+
+    let mut index = phase & 0xFF;
+    if phase & 0x100 != 0 {
+        index = 0xFF - index;
+    }
+
+    let level = LOG_SIN_TABLE[ index as usize ] as u32 + attenuation;
+    if level >= 13 << 8 {
+        return 0;
+    }
+
+    let value = ((EXP_TABLE[ (level & 0xFF) as usize ] as i32) << 1) >> (level >> 8);
+    if is_negative {
+        -value
+    } else {
+        value
+    }
+}
+
+pub struct Opll {
+    selected_register: u8,
+    custom_instrument: [u8; 8],
+    channels: [Channel; 6],
+
+    envelope_counter: u32,
+    tremolo_counter: u32,
+    vibrato_counter: u32,
+    output: i32
+}
+
+impl Opll {
+    pub const fn new() -> Opll {
+        Opll {
+            selected_register: 0,
+            custom_instrument: [0; 8],
+            channels: [Channel::new(); 6],
+
+            envelope_counter: 0,
+            tremolo_counter: 0,
+            vibrato_counter: 0,
+            output: 0
+        }
+    }
+
+    pub fn reset( &mut self ) {
+        *self = Opll::new();
+    }
+
+    pub fn select_register( &mut self, value: u8 ) {
+        self.selected_register = value;
+    }
+
+    pub fn poke_register( &mut self, value: u8 ) {
+        let register = self.selected_register;
+        let index = (register & 0x0F) as usize;
+        match register {
+            0x00..=0x07 => self.custom_instrument[ index ] = value,
+            0x10..=0x15 => self.channels[ index ].frequency.replace_bits( 0x0FF, value as u16 ),
+            0x20..=0x25 => {
+                let channel = &mut self.channels[ index ];
+                channel.frequency.replace_bits( 0x100, value.get_bits( 0b0000_0001 ) as u16 );
+                channel.octave = value.get_bits( 0b0000_1110 );
+                channel.is_sustain_on = is_b5_set( value );
+
+                let is_key_on = is_b4_set( value );
+                if is_key_on && channel.is_key_on == false {
+                    channel.operators[ 0 ].key_on();
+                    channel.operators[ 1 ].key_on();
+                } else if is_key_on == false && channel.is_key_on {
+                    channel.operators[ 0 ].key_off();
+                    channel.operators[ 1 ].key_off();
+                }
+                channel.is_key_on = is_key_on;
+            },
+            0x30..=0x35 => {
+                let channel = &mut self.channels[ index ];
+                channel.instrument = value.get_bits( 0b1111_0000 );
+                channel.volume = value.get_bits( 0b0000_1111 );
+            },
+            _ => {}
+        }
+    }
+
+    fn instrument( &self, channel: &Channel ) -> [u8; 8] {
+        if channel.instrument == 0 {
+            self.custom_instrument
+        } else {
+            INSTRUMENTS[ (channel.instrument - 1) as usize ]
+        }
+    }
+
+    fn envelope_increment( &self, rate: u8 ) -> u8 {
+        if rate == 0 {
+            return 0;
+        }
+
+        let rate_high = (rate >> 2) as u32;
+        let rate_low = (rate & 0b11) as usize;
+        if rate_high < 12 {
+            let shift = 12 - rate_high;
+            if self.envelope_counter & ((1 << shift) - 1) != 0 {
+                return 0;
+            }
+
+            ENVELOPE_INCREMENT_TABLE[ rate_low ][ ((self.envelope_counter >> shift) & 7) as usize ]
+        } else {
+            ENVELOPE_INCREMENT_TABLE[ rate_low ][ (self.envelope_counter & 7) as usize ] << (rate_high - 12)
+        }
+    }
+
+    fn clock_envelope( &self, operator: &mut Operator, channel: &Channel, parameters: u8, rates: u8, levels: u8 ) {
+        let is_sustained = is_b5_set( parameters );
+
+        // Higher octaves have faster envelopes.
+        let key_scale = (channel.octave << 1) | (channel.frequency >> 8) as u8;
+        let key_scale = if is_b4_set( parameters ) { key_scale } else { key_scale >> 2 };
+        let rate = |value: u8| if value == 0 { 0 } else { min( 63, value * 4 + key_scale ) };
+
+        match operator.envelope_state {
+            EnvelopeState::Attack => {
+                let rate = rate( rates.get_bits( 0b1111_0000 ) );
+                if rate >= 60 {
+                    operator.envelope_level = 0;
+                } else {
+                    let increment = self.envelope_increment( rate ) as u32;
+                    let level = operator.envelope_level as u32;
+                    operator.envelope_level -= ((level * increment + 7) >> 3) as u8;
+                }
+
+                if operator.envelope_level == 0 {
+                    operator.envelope_state = EnvelopeState::Decay;
+                }
+            },
+            EnvelopeState::Decay => {
+                let increment = self.envelope_increment( rate( rates.get_bits( 0b0000_1111 ) ) );
+                operator.envelope_level = min( MAXIMUM_ENVELOPE_LEVEL, operator.envelope_level + increment );
+                if operator.envelope_level >= levels.get_bits( 0b1111_0000 ) * 8 {
+                    operator.envelope_state = EnvelopeState::Sustain;
+                }
+            },
+            EnvelopeState::Sustain | EnvelopeState::Release => {
+                let release_rate = levels.get_bits( 0b0000_1111 );
+                let rate = match (operator.envelope_state, is_sustained, channel.is_sustain_on) {
+                    (EnvelopeState::Sustain, true, _) => 0,
+                    (EnvelopeState::Sustain, false, _) => rate( release_rate ),
+                    (_, _, true) => rate( 5 ),
+                    (_, true, false) => rate( release_rate ),
+                    (_, false, false) => rate( 7 )
+                };
+
+                let increment = self.envelope_increment( rate );
+                operator.envelope_level = min( MAXIMUM_ENVELOPE_LEVEL, operator.envelope_level + increment );
+                if operator.envelope_level == MAXIMUM_ENVELOPE_LEVEL {
+                    operator.envelope_state = EnvelopeState::Off;
+                }
+            },
+            EnvelopeState::Off => {}
+        }
+    }
+
+    // Generates a single sample; the chip generates one every 72 cycles
+    // of its 3.58MHz clock, which is every 36 CPU cycles.
+    pub fn clock( &mut self ) {
+        self.envelope_counter = self.envelope_counter.wrapping_add( 1 );
+        self.tremolo_counter = (self.tremolo_counter + 1) % (TREMOLO_STEP_COUNT * 64);
+        self.vibrato_counter = (self.vibrato_counter + 1) % (8 * 1024);
+
+        let tremolo_step = self.tremolo_counter / 64;
+        let tremolo_step = if tremolo_step < TREMOLO_STEP_COUNT / 2 { tremolo_step } else { TREMOLO_STEP_COUNT - 1 - tremolo_step };
+        let tremolo = tremolo_step * TREMOLO_DEPTH / (TREMOLO_STEP_COUNT / 2 - 1);
+        let vibrato_step = (self.vibrato_counter / 1024) as usize;
+
+        let mut output = 0;
+        for index in 0..self.channels.len() {
+            let mut channel = self.channels[ index ];
+            let instrument = self.instrument( &channel );
+
+            let key_scale_level = KEY_SCALE_LEVEL_TABLE[ (channel.frequency >> 5) as usize ] as i32 - 8 * (7 - channel.octave as i32);
+            let key_scale_level = if key_scale_level < 0 { 0 } else { key_scale_level as u32 * 32 };
+
+            let mut modulation = 0;
+            for nth in 0..2 {
+                let parameters = instrument[ nth ];
+                let mut operator = channel.operators[ nth ];
+
+                let vibrato = if parameters & 0x40 != 0 {
+                    VIBRATO_TABLE[ (channel.frequency >> 6) as usize ][ vibrato_step ] as i32
+                } else {
+                    0
+                };
+
+                let frequency = ((channel.frequency as i32 + vibrato) as u32) << channel.octave;
+                operator.phase = (operator.phase + ((frequency * MULTIPLIER_TABLE[ (parameters & 0x0F) as usize ]) >> 1)) & 0x7FFFF;
+
+                self.clock_envelope( &mut operator, &channel, parameters, instrument[ 4 + nth ], instrument[ 6 + nth ] );
+
+                let mut attenuation = operator.envelope_level as u32 * 16;
+                attenuation += match instrument[ 2 + nth ] >> 6 {
+                    0 => 0,
+                    key_scale_level_shift => key_scale_level >> (3 - key_scale_level_shift)
+                };
+
+                if parameters & 0x80 != 0 {
+                    attenuation += tremolo * 16;
+                }
+
+                let phase = (operator.phase >> 9) as i32;
+                let is_silent = operator.envelope_state == EnvelopeState::Off;
+                if nth == 0 {
+                    attenuation += instrument[ 2 ].get_bits( 0b0011_1111 ) as u32 * 32;
+
+                    let feedback = instrument[ 3 ].get_bits( 0b0000_0111 );
+                    let feedback = if feedback == 0 {
+                        0
+                    } else {
+                        (operator.output + operator.previous_output) >> (9 - feedback)
+                    };
+
+                    operator.previous_output = operator.output;
+                    operator.output = if is_silent { 0 } else { operator_output( phase + feedback, attenuation, instrument[ 3 ] & 0x08 != 0 ) };
+                    modulation = operator.output >> 1;
+                } else {
+                    attenuation += channel.volume as u32 * 128;
+                    operator.output = if is_silent { 0 } else { operator_output( phase + modulation, attenuation, instrument[ 3 ] & 0x10 != 0 ) };
+                    output += operator.output;
+                }
+
+                channel.operators[ nth ] = operator;
+            }
+
+            self.channels[ index ] = channel;
+        }
+
+        self.output = output;
+    }
+
+    pub fn output( &self ) -> F32 {
+        i32_to_f32( self.output ) / f32!(24000.0)
+    }
+}
+
+#[test]
+fn test_opll_generates_sound() {
+    let mut opll = Opll::new();
+    fn poke( opll: &mut Opll, register: u8, value: u8 ) {
+        opll.select_register( register );
+        opll.poke_register( value );
+    }
+
+    // A pure sine wave: a silent modulator and a sustained carrier.
+    let instrument = [0x21, 0x21, 0x3F, 0x00, 0xF0, 0xF0, 0x0F, 0x0F];
+    for (register, &value) in instrument.iter().enumerate() {
+        poke( &mut opll, register as u8, value );
+    }
+
+    // An A4 (440Hz) with the custom instrument at the maximum volume.
+    poke( &mut opll, 0x10, 0x22 );
+    poke( &mut opll, 0x30, 0x00 );
+    poke( &mut opll, 0x20, 0b0001_1001 );
+
+    let mut positive = 0;
+    let mut negative = 0;
+    let mut zero_crossings = 0;
+    let mut last_output = 0;
+    for _ in 0..49716 / 10 {
+        opll.clock();
+        if opll.output > 0 {
+            positive += 1;
+        } else if opll.output < 0 {
+            negative += 1;
+        }
+
+        if (last_output < 0 && opll.output >= 0) || (last_output >= 0 && opll.output < 0) {
+            zero_crossings += 1;
+        }
+        last_output = opll.output;
+    }
+
+    assert!( positive > 1000 );
+    assert!( negative > 1000 );
+    assert!( zero_crossings >= 2 * 43 && zero_crossings <= 2 * 45 );
+
+    // The sound fades out after a key off.
+    poke( &mut opll, 0x20, 0b0000_1001 );
+    for _ in 0..49716 * 2 {
+        opll.clock();
+    }
+
+    assert_eq!( opll.output, 0 );
+    assert_eq!( opll.channels[ 0 ].operators[ 1 ].envelope_state, EnvelopeState::Off );
+}
+
+#[test]
+fn test_opll_modulator_feedback() {
+    let mut opll = Opll::new();
+    fn poke( opll: &mut Opll, register: u8, value: u8 ) {
+        opll.select_register( register );
+        opll.poke_register( value );
+    }
+
+    // A modulator at full volume with the maximum feedback.
+    let instrument = [0x21, 0x21, 0x00, 0x07, 0xF0, 0xF0, 0x0F, 0x0F];
+    for (register, &value) in instrument.iter().enumerate() {
+        poke( &mut opll, register as u8, value );
+    }
+
+    poke( &mut opll, 0x10, 0xFF );
+    poke( &mut opll, 0x30, 0x00 );
+    poke( &mut opll, 0x20, 0b0001_1111 );
+
+    // The feedback is the average of the last two outputs.
+    let mut outputs = Vec::new();
+    for _ in 0..8 {
+        let modulator = opll.channels[ 0 ].operators[ 0 ];
+        opll.clock();
+
+        let phase = (opll.channels[ 0 ].operators[ 0 ].phase >> 9) as i32;
+        let feedback = (modulator.output + modulator.previous_output) >> 2;
+        let output = opll.channels[ 0 ].operators[ 0 ].output;
+        assert_eq!( output, operator_output( phase + feedback, 0, false ) );
+        assert_eq!( opll.channels[ 0 ].operators[ 0 ].previous_output, modulator.output );
+        outputs.push( output );
+    }
+
+    assert_eq!( outputs, [2880, -1198, -3986, 4062, -3184, -957, -3116, 87] );
+}
@@ -0,0 +1,127 @@
+use std::f32::consts::PI;
+
+use rp2c02::Palette;
+use ntsc_filter::{SUBCARRIER_PERIOD, HUE_OFFSET, CHROMA_SCALE, generate_signal_level, yiq_to_rgb, gamma_exponent};
+
+/*
+    Generates a palette by decoding the composite video signal
+    of every color (including the emphasized ones) the same way
+    the NTSC filter does, except there are no neighbouring pixels
+    so there are no artifacts.
+*/
+
+// All of the parameters are in the -1.0...1.0 range, where 0.0 is the default.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct PaletteSettings {
+    // -1.0 = -180 degrees, 1.0 = +180 degrees
+    pub hue: f32,
+    // -1.0 = grayscale, 1.0 = oversaturated
+    pub saturation: f32,
+    // -1.0 = dark, 1.0 = light
+    pub contrast: f32,
+    // -1.0 = dark, 1.0 = light
+    pub brightness: f32,
+    // -1.0 = dark, 1.0 = light
+    pub gamma: f32
+}
+
+impl Default for PaletteSettings {
+    fn default() -> Self {
+        PaletteSettings {
+            hue: 0.0,
+            saturation: 0.0,
+            contrast: 0.0,
+            brightness: 0.0,
+            gamma: 0.0
+        }
+    }
+}
+
+fn to_component( value: f32, exponent: f32 ) -> u8 {
+    let value = value.max( 0.0 ).min( 1.0 );
+    (value.powf( exponent ) * 255.0 + 0.5) as u8
+}
+
+impl Palette {
+    pub fn generate( settings: &PaletteSettings ) -> Palette {
+        let contrast = 1.0 + settings.contrast * 0.5;
+        let brightness = settings.brightness * 0.5;
+        let saturation = CHROMA_SCALE * (1.0 + settings.saturation) * contrast;
+        let hue = HUE_OFFSET + settings.hue * (SUBCARRIER_PERIOD as f32 / 2.0);
+        let exponent = gamma_exponent( settings.gamma );
+
+        let mut data = Vec::with_capacity( 512 * 3 );
+        for pixel in 0..512 {
+            let mut y = 0.0;
+            let mut i = 0.0;
+            let mut q = 0.0;
+            for phase in 0..SUBCARRIER_PERIOD {
+                let level = generate_signal_level( pixel, phase );
+                let angle = PI * (phase as f32 + hue) / (SUBCARRIER_PERIOD as f32 / 2.0);
+                y += level;
+                i += level * angle.cos();
+                q += level * angle.sin();
+            }
+
+            let period = SUBCARRIER_PERIOD as f32;
+            let y = (y / period) * contrast + brightness;
+            let i = (i / period) * saturation;
+            let q = (q / period) * saturation;
+
+            let (r, g, b) = yiq_to_rgb( y, i, q );
+            data.push( to_component( r, exponent ) );
+            data.push( to_component( g, exponent ) );
+            data.push( to_component( b, exponent ) );
+        }
+
+        Palette::new( &data )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PaletteSettings;
+    use rp2c02::Palette;
+
+    #[test]
+    fn generated_palette_is_close_to_the_default_one() {
+        let generated = Palette::generate( &PaletteSettings::default() );
+        let default = Palette::default();
+        for index in 0..64 {
+            let (r0, g0, b0) = generated.get_rgb( index );
+            let (r1, g1, b1) = default.get_rgb( index );
+            let distance = (r0 as i32 - r1 as i32).abs() + (g0 as i32 - g1 as i32).abs() + (b0 as i32 - b1 as i32).abs();
+            assert!( distance < 80, "color 0x{:02X} differs too much: {:?} vs {:?}", index, (r0, g0, b0), (r1, g1, b1) );
+        }
+    }
+
+    #[test]
+    fn grayscale() {
+        let settings = PaletteSettings {
+            saturation: -1.0,
+            .. PaletteSettings::default()
+        };
+
+        let palette = Palette::generate( &settings );
+        for index in 0..512 {
+            let (r, g, b) = palette.get_rgb( index );
+            assert_eq!( r, g );
+            assert_eq!( g, b );
+        }
+    }
+
+    #[test]
+    fn pal_roundtrip() {
+        let palette = Palette::generate( &PaletteSettings::default() );
+
+        let data = palette.to_pal( true );
+        assert_eq!( data.len(), 1536 );
+        assert_eq!( Palette::from_pal( &data ).unwrap().to_pal( true ), data );
+
+        let data = palette.to_pal( false );
+        assert_eq!( data.len(), 192 );
+        assert_eq!( Palette::from_pal( &data ).unwrap().to_pal( false ), data );
+
+        assert!( Palette::from_pal( &data[ 1.. ] ).is_none() );
+    }
+}
@@ -0,0 +1,378 @@
+use std::io;
+use std::path::Path;
+
+use rp2c02::{Framebuffer, Palette};
+use hashes::crc32;
+
+/*
+    A minimal PNG encoder which only supports 8-bit RGB images.
+
+    The image data is compressed with a single fixed Huffman deflate block
+    with a simple hash chain based LZ77 matcher; this is nowhere near
+    as good as a real zlib implementation, but since the NES output
+    is very repetitive it still shrinks a typical screenshot by an
+    order of magnitude.
+*/
+
+// The number of pixels cut from every edge of the picture.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Overscan {
+    pub top: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize
+}
+
+impl Overscan {
+    pub const fn none() -> Self {
+        Overscan {
+            top: 0,
+            bottom: 0,
+            left: 0,
+            right: 0
+        }
+    }
+
+    // The part of the picture which is usually visible on an NTSC TV.
+    pub const fn ntsc() -> Self {
+        Overscan {
+            top: 8,
+            bottom: 8,
+            left: 0,
+            right: 0
+        }
+    }
+}
+
+impl Default for Overscan {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+fn adler32( data: &[u8] ) -> u32 {
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+    for chunk in data.chunks( 5552 ) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+
+        a %= 65521;
+        b %= 65521;
+    }
+
+    (b << 16) | a
+}
+
+struct BitWriter {
+    output: Vec< u8 >,
+    buffer: u32,
+    length: u32
+}
+
+impl BitWriter {
+    fn new( output: Vec< u8 > ) -> Self {
+        BitWriter {
+            output: output,
+            buffer: 0,
+            length: 0
+        }
+    }
+
+    // Writes the bits starting from the least significant one.
+    fn write_bits( &mut self, value: u32, length: u32 ) {
+        self.buffer |= value << self.length;
+        self.length += length;
+        while self.length >= 8 {
+            self.output.push( self.buffer as u8 );
+            self.buffer >>= 8;
+            self.length -= 8;
+        }
+    }
+
+    // Huffman codes are written starting from the most significant bit.
+    fn write_code( &mut self, code: u32, length: u32 ) {
+        let reversed = code.reverse_bits() >> (32 - length);
+        self.write_bits( reversed, length );
+    }
+
+    fn finish( mut self ) -> Vec< u8 > {
+        if self.length > 0 {
+            self.output.push( self.buffer as u8 );
+        }
+
+        self.output
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258
+];
+
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0
+];
+
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577
+];
+
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13
+];
+
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH_LENGTH: usize = 3;
+const MAX_MATCH_LENGTH: usize = 258;
+const MAX_CHAIN_LENGTH: usize = 64;
+const HASH_BITS: u32 = 15;
+
+fn write_literal( writer: &mut BitWriter, symbol: u16 ) {
+    match symbol {
+        0..=143 => writer.write_code( 0x30 + symbol as u32, 8 ),
+        144..=255 => writer.write_code( 0x190 + (symbol as u32 - 144), 9 ),
+        256..=279 => writer.write_code( symbol as u32 - 256, 7 ),
+        _ => writer.write_code( 0xC0 + (symbol as u32 - 280), 8 )
+    }
+}
+
+fn write_match( writer: &mut BitWriter, length: usize, distance: usize ) {
+    let code = LENGTH_BASE.iter().rposition( |&base| base as usize <= length ).unwrap();
+    write_literal( writer, 257 + code as u16 );
+    writer.write_bits( (length - LENGTH_BASE[ code ] as usize) as u32, LENGTH_EXTRA_BITS[ code ] as u32 );
+
+    let code = DISTANCE_BASE.iter().rposition( |&base| base as usize <= distance ).unwrap();
+    writer.write_code( code as u32, 5 );
+    writer.write_bits( (distance - DISTANCE_BASE[ code ] as usize) as u32, DISTANCE_EXTRA_BITS[ code ] as u32 );
+}
+
+#[inline]
+fn hash( data: &[u8] ) -> usize {
+    let value = (data[ 0 ] as u32) << 16 | (data[ 1 ] as u32) << 8 | data[ 2 ] as u32;
+    (value.wrapping_mul( 2654435761 ) >> (32 - HASH_BITS)) as usize
+}
+
+fn deflate( data: &[u8], output: Vec< u8 > ) -> Vec< u8 > {
+    let mut writer = BitWriter::new( output );
+
+    // BFINAL = 1, BTYPE = 01 (fixed Huffman codes)
+    writer.write_bits( 1, 1 );
+    writer.write_bits( 1, 2 );
+
+    const NONE: usize = !0;
+    let mut head = vec![ NONE; 1 << HASH_BITS ];
+    let mut previous = vec![ NONE; data.len() ];
+    let insert = |head: &mut [usize], previous: &mut [usize], position: usize| {
+        if position + MIN_MATCH_LENGTH <= data.len() {
+            let hash = hash( &data[ position.. ] );
+            previous[ position ] = head[ hash ];
+            head[ hash ] = position;
+        }
+    };
+
+    let mut position = 0;
+    while position < data.len() {
+        let mut best_length = 0;
+        let mut best_distance = 0;
+        if position + MIN_MATCH_LENGTH <= data.len() {
+            let max_length = MAX_MATCH_LENGTH.min( data.len() - position );
+            let mut candidate = head[ hash( &data[ position.. ] ) ];
+            let mut chain_length = 0;
+            while candidate != NONE && position - candidate <= WINDOW_SIZE && chain_length < MAX_CHAIN_LENGTH {
+                let length = data[ candidate.. ].iter().zip( &data[ position..position + max_length ] ).take_while( |&(a, b)| a == b ).count();
+                if length > best_length {
+                    best_length = length;
+                    best_distance = position - candidate;
+                    if length == max_length {
+                        break;
+                    }
+                }
+
+                candidate = previous[ candidate ];
+                chain_length += 1;
+            }
+        }
+
+        if best_length >= MIN_MATCH_LENGTH {
+            write_match( &mut writer, best_length, best_distance );
+            for offset in 0..best_length {
+                insert( &mut head, &mut previous, position + offset );
+            }
+            position += best_length;
+        } else {
+            write_literal( &mut writer, data[ position ] as u16 );
+            insert( &mut head, &mut previous, position );
+            position += 1;
+        }
+    }
+
+    write_literal( &mut writer, 256 );
+    writer.finish()
+}
+
+fn write_chunk( output: &mut Vec< u8 >, kind: &[u8; 4], data: &[u8] ) {
+    output.extend_from_slice( &(data.len() as u32).to_be_bytes() );
+    let start = output.len();
+    output.extend_from_slice( kind );
+    output.extend_from_slice( data );
+    let crc = crc32( &output[ start.. ] );
+    output.extend_from_slice( &crc.to_be_bytes() );
+}
+
+// Encodes an image where every pixel is stored as three bytes in R, G, B order.
+pub fn encode_png( width: usize, height: usize, rgb: &[u8] ) -> Vec< u8 > {
+    assert_eq!( rgb.len(), width * height * 3 );
+
+    let mut raw = Vec::with_capacity( (width * 3 + 1) * height );
+    for line in rgb.chunks( width * 3 ) {
+        raw.push( 0 ); // No filtering.
+        raw.extend_from_slice( line );
+    }
+
+    // CMF = deflate with a 32k window, FLG = no dictionary, fastest compression level
+    let mut zlib = deflate( &raw, vec![ 0x78, 0x01 ] );
+    zlib.extend_from_slice( &adler32( &raw ).to_be_bytes() );
+
+    let mut header = Vec::with_capacity( 13 );
+    header.extend_from_slice( &(width as u32).to_be_bytes() );
+    header.extend_from_slice( &(height as u32).to_be_bytes() );
+    header.push( 8 ); // Bit depth.
+    header.push( 2 ); // Color type: RGB.
+    header.push( 0 ); // Compression method: deflate.
+    header.push( 0 ); // Filter method: adaptive.
+    header.push( 0 ); // Interlace method: none.
+
+    let mut output = Vec::with_capacity( zlib.len() + 64 );
+    output.extend_from_slice( b"\x89PNG\r\n\x1A\n" );
+    write_chunk( &mut output, b"IHDR", &header );
+    write_chunk( &mut output, b"IDAT", &zlib );
+    write_chunk( &mut output, b"IEND", &[] );
+    output
+}
+
+impl Framebuffer {
+    pub fn to_png( &self, palette: &Palette, overscan: &Overscan ) -> Vec< u8 > {
+        assert!( overscan.left + overscan.right < 256 );
+        assert!( overscan.top + overscan.bottom < 240 );
+
+        let mut rgb = vec![ 0; 256 * 240 * 3 ];
+        self.convert_to_rgb24( palette, &mut rgb );
+
+        let width = 256 - overscan.left - overscan.right;
+        let height = 240 - overscan.top - overscan.bottom;
+        let mut cropped = Vec::with_capacity( width * height * 3 );
+        for line in rgb.chunks( 256 * 3 ).skip( overscan.top ).take( height ) {
+            cropped.extend_from_slice( &line[ overscan.left * 3..(overscan.left + width) * 3 ] );
+        }
+
+        encode_png( width, height, &cropped )
+    }
+
+    pub fn save_png< P: AsRef< Path > >( &self, path: P, palette: &Palette, overscan: &Overscan ) -> io::Result< () > {
+        std::fs::write( path, self.to_png( palette, overscan ) )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Overscan, encode_png, adler32};
+    use hashes::crc32;
+    use rp2c02::{Framebuffer, Palette};
+    use miniz_oxide::inflate::decompress_to_vec_zlib;
+
+    fn chunks( png: &[u8] ) -> Vec< ([u8; 4], &[u8]) > {
+        let mut output = Vec::new();
+        let mut position = 8;
+        while position < png.len() {
+            let length = u32::from_be_bytes( [png[ position ], png[ position + 1 ], png[ position + 2 ], png[ position + 3 ]] ) as usize;
+            let kind = [png[ position + 4 ], png[ position + 5 ], png[ position + 6 ], png[ position + 7 ]];
+            let data = &png[ position + 8..position + 8 + length ];
+            let crc = &png[ position + 8 + length..position + 12 + length ];
+            assert_eq!( crc, &crc32( &png[ position + 4..position + 8 + length ] ).to_be_bytes() );
+            output.push( (kind, data) );
+            position += length + 12;
+        }
+
+        output
+    }
+
+    // Returns the width, the height and the RGB pixels of a PNG written by `encode_png`.
+    fn decode( png: &[u8] ) -> (usize, usize, Vec< u8 >) {
+        let chunks = chunks( png );
+        let header = chunks[ 0 ].1;
+        let width = u32::from_be_bytes( [header[ 0 ], header[ 1 ], header[ 2 ], header[ 3 ]] ) as usize;
+        let height = u32::from_be_bytes( [header[ 4 ], header[ 5 ], header[ 6 ], header[ 7 ]] ) as usize;
+
+        let raw = decompress_to_vec_zlib( chunks[ 1 ].1 ).unwrap();
+        assert_eq!( raw.len(), (width * 3 + 1) * height );
+
+        let mut rgb = Vec::with_capacity( width * height * 3 );
+        for line in raw.chunks( width * 3 + 1 ) {
+            assert_eq!( line[ 0 ], 0 );
+            rgb.extend_from_slice( &line[ 1.. ] );
+        }
+
+        (width, height, rgb)
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!( adler32( b"Wikipedia" ), 0x11E60398 );
+    }
+
+    #[test]
+    fn png_structure() {
+        let rgb: Vec< u8 > = (0..16 * 4 * 3).map( |index| (index / 7) as u8 ).collect();
+        let png = encode_png( 16, 4, &rgb );
+        assert_eq!( &png[ 0..8 ], b"\x89PNG\r\n\x1A\n" );
+
+        let chunks = chunks( &png );
+        assert_eq!( chunks.len(), 3 );
+        assert_eq!( &chunks[ 0 ].0, b"IHDR" );
+        assert_eq!( chunks[ 0 ].1, &[0, 0, 0, 16, 0, 0, 0, 4, 8, 2, 0, 0, 0] );
+        assert_eq!( &chunks[ 1 ].0, b"IDAT" );
+        assert_eq!( &chunks[ 2 ].0, b"IEND" );
+
+        let mut raw = Vec::new();
+        for line in rgb.chunks( 16 * 3 ) {
+            raw.push( 0 );
+            raw.extend_from_slice( line );
+        }
+
+        let zlib = chunks[ 1 ].1;
+        assert_eq!( &zlib[ zlib.len() - 4.. ], &adler32( &raw ).to_be_bytes() );
+    }
+
+    #[test]
+    fn overscan_cropping() {
+        let png = Framebuffer::default().to_png( &Palette::default(), &Overscan { top: 8, bottom: 16, left: 4, right: 2 } );
+        let chunks = chunks( &png );
+        assert_eq!( &chunks[ 0 ].1[ 0..8 ], &[0, 0, 0, 250, 0, 0, 0, 216] );
+    }
+
+    #[test]
+    fn image_data() {
+        let rgb: Vec< u8 > = (0..64 * 32 * 3).map( |index| ((index * 7) ^ (index / 96)) as u8 ).collect();
+        assert_eq!( decode( &encode_png( 64, 32, &rgb ) ), (64, 32, rgb) );
+
+        let framebuffer = Framebuffer::from_fn( |x, y| ((x / 3) ^ (y * 5)) as u16 );
+        let palette = Palette::default();
+        let mut expected = vec![ 0; 256 * 240 * 3 ];
+        framebuffer.convert_to_rgb24( &palette, &mut expected );
+        assert_eq!( decode( &framebuffer.to_png( &palette, &Overscan::none() ) ), (256, 240, expected.clone()) );
+
+        let overscan = Overscan { top: 8, bottom: 16, left: 4, right: 2 };
+        let (width, height, rgb) = decode( &framebuffer.to_png( &palette, &overscan ) );
+        assert_eq!( (width, height), (256 - overscan.left - overscan.right, 240 - overscan.top - overscan.bottom) );
+        for (y, line) in rgb.chunks( width * 3 ).enumerate() {
+            let offset = ((y + overscan.top) * 256 + overscan.left) * 3;
+            assert_eq!( line, &expected[ offset..offset + width * 3 ] );
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use alloc::vec::Vec;
+
+use rp2c02::{Framebuffer, Palette};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PpuEventKind {
+    // An access to one of the $2000-$2007 registers or to $4014.
+    RegisterRead { address: u16, value: u8 },
+    RegisterWrite { address: u16, value: u8 },
+    Nmi,
+    Irq,
+    SpriteZeroHit
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct PpuEvent {
+    pub scanline: u16,
+    pub dot: u16,
+    pub kind: PpuEventKind
+}
+
+impl PpuEvent {
+    // The color with which the event is drawn on the overlay, packed as ABGR.
+    pub fn color( &self ) -> u32 {
+        let abgr = match self.kind {
+            PpuEventKind::RegisterRead { address, .. } | PpuEventKind::RegisterWrite { address, .. } => {
+                match address {
+                    0x2000 => 0x0000FF, // PPUCTRL
+                    0x2001 => 0x00FFFF, // PPUMASK
+                    0x2002 => 0x00FF00, // PPUSTATUS
+                    0x2003 => 0xFF8080, // OAMADDR
+                    0x2004 => 0xFF0080, // OAMDATA
+                    0x2005 => 0xFFFF00, // PPUSCROLL
+                    0x2006 => 0x0080FF, // PPUADDR
+                    0x2007 => 0xFF00FF, // PPUDATA
+                    _      => 0x808080  // OAMDMA
+                }
+            },
+            PpuEventKind::Nmi => 0xFFFFFF,
+            PpuEventKind::Irq => 0xFF0000,
+            PpuEventKind::SpriteZeroHit => 0x80FF80
+        };
+
+        abgr | 0xFF000000
+    }
+}
+
+/*
+    Collects the events which happen in the PPU during a single frame,
+    tagged with the scanline and the dot on which they happened.
+
+    The events should be fed into this from `Context::on_ppu_event`,
+    and `finish_frame` should be called from `Context::on_frame`.
+*/
+pub struct PpuEventLog {
+    current_frame: Vec< PpuEvent >,
+    last_frame: Vec< PpuEvent >
+}
+
+impl PpuEventLog {
+    pub const OVERLAY_WIDTH: usize = 341;
+    pub const OVERLAY_HEIGHT: usize = 262;
+
+    pub fn new() -> PpuEventLog {
+        PpuEventLog {
+            current_frame: Vec::new(),
+            last_frame: Vec::new()
+        }
+    }
+
+    pub fn clear( &mut self ) {
+        self.current_frame.clear();
+        self.last_frame.clear();
+    }
+
+    #[inline]
+    pub fn log( &mut self, event: PpuEvent ) {
+        self.current_frame.push( event );
+    }
+
+    pub fn finish_frame( &mut self ) {
+        core::mem::swap( &mut self.current_frame, &mut self.last_frame );
+        self.current_frame.clear();
+    }
+
+    // The events of the last finished frame.
+    #[inline]
+    pub fn events( &self ) -> &[PpuEvent] {
+        &self.last_frame
+    }
+
+    // Renders the events of the last finished frame on top of a dimmed framebuffer,
+    // where every pixel corresponds to a single dot. The output is packed the same
+    // way as in `Framebuffer::convert_to_abgr`.
+    pub fn render_overlay< T: AsMut< [u32] >>( &self, framebuffer: &Framebuffer, palette: &Palette, mut output: T ) {
+        let output = output.as_mut();
+        assert_eq!( output.len(), PpuEventLog::OVERLAY_WIDTH * PpuEventLog::OVERLAY_HEIGHT );
+
+        for pixel in output.iter_mut() {
+            *pixel = 0xFF202020;
+        }
+
+        let mut pixels = framebuffer.iter();
+        for y in 0..240 {
+            let line = &mut output[ y * PpuEventLog::OVERLAY_WIDTH..y * PpuEventLog::OVERLAY_WIDTH + 256 ];
+            for (out, pixel) in line.iter_mut().zip( &mut pixels ) {
+                *out = ((palette.get_packed_abgr( pixel.full_color_index() ) >> 1) & 0x7F7F7F7F) | 0xFF000000;
+            }
+        }
+
+        for event in &self.last_frame {
+            let x = event.dot as usize;
+            let y = event.scanline as usize;
+            if x < PpuEventLog::OVERLAY_WIDTH && y < PpuEventLog::OVERLAY_HEIGHT {
+                output[ y * PpuEventLog::OVERLAY_WIDTH + x ] = event.color();
+            }
+        }
+    }
+}
+
+impl Default for PpuEventLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PpuEventLog, PpuEvent, PpuEventKind};
+    use rp2c02::{Framebuffer, Palette};
+    use virtual_nes::{State, Context, Interface};
+
+    struct Instance {
+        state: State,
+        log: PpuEventLog
+    }
+
+    impl Context for Instance {
+        fn state_mut( &mut self ) -> &mut State {
+            &mut self.state
+        }
+
+        fn state( &self ) -> &State {
+            &self.state
+        }
+
+        fn on_ppu_event( &mut self, event: PpuEvent ) {
+            self.log.log( event );
+        }
+    }
+
+    #[test]
+    fn register_accesses_are_logged() {
+        let mut instance = Instance {
+            state: State::new(),
+            log: PpuEventLog::new()
+        };
+
+        instance.poke_memory( 0x2000, 0x80 );
+        instance.poke_memory( 0x200D, 0x12 );
+        instance.peek_memory( 0x2002 );
+        instance.poke_memory( 0x4014, 0x02 );
+        instance.poke_memory( 0x4015, 0x00 );
+        instance.log.finish_frame();
+
+        let kinds: Vec< _ > = instance.log.events().iter().map( |event| event.kind ).collect();
+        assert_eq!( kinds, vec![
+            PpuEventKind::RegisterWrite { address: 0x2000, value: 0x80 },
+            PpuEventKind::RegisterWrite { address: 0x2005, value: 0x12 },
+            PpuEventKind::RegisterRead { address: 0x2002, value: 0x00 },
+            PpuEventKind::RegisterWrite { address: 0x4014, value: 0x02 }
+        ]);
+    }
+
+    #[test]
+    fn overlay() {
+        let mut log = PpuEventLog::new();
+        let event = PpuEvent {
+            scanline: 100,
+            dot: 300,
+            kind: PpuEventKind::Nmi
+        };
+
+        log.log( event );
+        let mut output = vec![ 0; PpuEventLog::OVERLAY_WIDTH * PpuEventLog::OVERLAY_HEIGHT ];
+        log.render_overlay( &Framebuffer::default(), &Palette::default(), &mut output );
+        assert_ne!( output[ 100 * PpuEventLog::OVERLAY_WIDTH + 300 ], event.color() );
+
+        log.finish_frame();
+        assert_eq!( log.events(), &[event] );
+        log.render_overlay( &Framebuffer::default(), &Palette::default(), &mut output );
+        assert_eq!( output[ 100 * PpuEventLog::OVERLAY_WIDTH + 300 ], event.color() );
+
+        log.finish_frame();
+        assert!( log.events().is_empty() );
+    }
+}
@@ -0,0 +1,321 @@
+use rp2c02;
+use rp2c02::Palette;
+use mappers::Mapper;
+
+/*
+    A read-only view into the video memory of the PPU, meant for debugging.
+
+    Every `render_*` method renders the view into an image of the size given
+    by the corresponding constants, in the same packed format as
+    `Framebuffer::convert_to_abgr`. None of these have any side effects on the
+    emulated hardware; the video memory is read through the same path the PPU
+    uses, so the current mirroring and banking of the mapper are respected.
+*/
+pub struct PpuViewer< 'a > {
+    ppu: &'a rp2c02::State,
+    mapper: &'a dyn Mapper
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct SpriteInfo {
+    pub x: u8,
+    pub y: u8,
+    pub tile: u8,
+    pub attributes: u8
+}
+
+impl SpriteInfo {
+    #[inline]
+    pub fn palette_index( &self ) -> u8 {
+        (self.attributes & 0b11) + 4
+    }
+
+    #[inline]
+    pub fn is_behind_background( &self ) -> bool {
+        self.attributes & 0b00100000 != 0
+    }
+
+    #[inline]
+    pub fn is_flipped_horizontally( &self ) -> bool {
+        self.attributes & 0b01000000 != 0
+    }
+
+    #[inline]
+    pub fn is_flipped_vertically( &self ) -> bool {
+        self.attributes & 0b10000000 != 0
+    }
+}
+
+impl< 'a > PpuViewer< 'a > {
+    // All four nametables, laid out in a 2x2 grid.
+    pub const NAMETABLES_WIDTH: usize = 512;
+    pub const NAMETABLES_HEIGHT: usize = 480;
+
+    // A single pattern table, as a 16x16 grid of tiles.
+    pub const PATTERN_TABLE_WIDTH: usize = 128;
+    pub const PATTERN_TABLE_HEIGHT: usize = 128;
+
+    // One pixel per palette entry; the background palettes are on the first row, the sprite palettes on the second one.
+    pub const PALETTE_RAM_WIDTH: usize = 16;
+    pub const PALETTE_RAM_HEIGHT: usize = 2;
+
+    // All 64 sprites, as an 8x8 grid where every cell is 8x16 pixels big.
+    pub const SPRITES_WIDTH: usize = 64;
+    pub const SPRITES_HEIGHT: usize = 128;
+
+    pub fn new( ppu: &'a rp2c02::State, mapper: &'a dyn Mapper ) -> Self {
+        PpuViewer {
+            ppu: ppu,
+            mapper: mapper
+        }
+    }
+
+    #[inline]
+    pub fn palette_ram( &self ) -> &[u8; 32] {
+        self.ppu.palette_ram()
+    }
+
+    #[inline]
+    pub fn sprite( &self, index: u8 ) -> SpriteInfo {
+        debug_assert!( index < 64 );
+
+        let oam = &self.ppu.sprite_list_ram()[ index as usize * 4..index as usize * 4 + 4 ];
+        SpriteInfo {
+            y: oam[ 0 ],
+            tile: oam[ 1 ],
+            attributes: oam[ 2 ],
+            x: oam[ 3 ]
+        }
+    }
+
+    #[inline]
+    fn color( &self, palette: &Palette, palette_index: u8, value: u8 ) -> u32 {
+        let index = if value == 0 { 0 } else { palette_index * 4 + value };
+        palette.get_packed_abgr( (self.palette_ram()[ index as usize ] & 0x3F) as u16 )
+    }
+
+    fn draw_tile( &self, palette: &Palette, output: &mut [u32], stride: usize, x: usize, y: usize, tile_address: u16, palette_index: u8, flip_horizontally: bool, flip_vertically: bool ) {
+        for row in 0..8 {
+            let source_row = if flip_vertically { 7 - row } else { row };
+            let lo = self.mapper.peek_video_memory( tile_address + source_row as u16 );
+            let hi = self.mapper.peek_video_memory( tile_address + source_row as u16 + 8 );
+            for column in 0..8 {
+                let bit = if flip_horizontally { column } else { 7 - column };
+                let value = ((lo >> bit) & 1) | (((hi >> bit) & 1) << 1);
+                output[ (y + row) * stride + x + column ] = self.color( palette, palette_index, value );
+            }
+        }
+    }
+
+    pub fn render_nametables< T: AsMut< [u32] >>( &self, palette: &Palette, mut output: T ) {
+        let output = output.as_mut();
+        assert_eq!( output.len(), Self::NAMETABLES_WIDTH * Self::NAMETABLES_HEIGHT );
+
+        let pattern_table_address = self.ppu.background_pattern_table_address();
+        for nametable in 0..4 {
+            let base_address = 0x2000 + nametable as u16 * 0x400;
+            let base_x = (nametable & 1) * 256;
+            let base_y = (nametable >> 1) * 240;
+            for tile_y in 0..30 {
+                for tile_x in 0..32 {
+                    let tile = self.mapper.peek_video_memory( base_address + tile_y * 32 + tile_x );
+                    let attributes = self.mapper.peek_video_memory( base_address + 0x3C0 + (tile_y / 4) * 8 + tile_x / 4 );
+                    let shift = ((tile_y & 2) << 1) | (tile_x & 2);
+                    let palette_index = (attributes >> shift) & 0b11;
+
+                    self.draw_tile(
+                        palette,
+                        output,
+                        Self::NAMETABLES_WIDTH,
+                        base_x + tile_x as usize * 8,
+                        base_y + tile_y as usize * 8,
+                        pattern_table_address + tile as u16 * 16,
+                        palette_index,
+                        false,
+                        false
+                    );
+                }
+            }
+        }
+    }
+
+    // The `palette_index` selects one of the four background (0...3) or sprite (4...7) palettes.
+    pub fn render_pattern_table< T: AsMut< [u32] >>( &self, table: u8, palette_index: u8, palette: &Palette, mut output: T ) {
+        let output = output.as_mut();
+        assert_eq!( output.len(), Self::PATTERN_TABLE_WIDTH * Self::PATTERN_TABLE_HEIGHT );
+        assert!( table < 2 );
+        assert!( palette_index < 8 );
+
+        let base_address = table as u16 * 0x1000;
+        for tile in 0..256 {
+            self.draw_tile(
+                palette,
+                output,
+                Self::PATTERN_TABLE_WIDTH,
+                (tile % 16) * 8,
+                (tile / 16) * 8,
+                base_address + tile as u16 * 16,
+                palette_index,
+                false,
+                false
+            );
+        }
+    }
+
+    pub fn render_palette_ram< T: AsMut< [u32] >>( &self, palette: &Palette, mut output: T ) {
+        let output = output.as_mut();
+        assert_eq!( output.len(), Self::PALETTE_RAM_WIDTH * Self::PALETTE_RAM_HEIGHT );
+
+        for (index, out) in output.iter_mut().enumerate() {
+            *out = palette.get_packed_abgr( (self.palette_ram()[ index ] & 0x3F) as u16 );
+        }
+    }
+
+    pub fn render_sprites< T: AsMut< [u32] >>( &self, palette: &Palette, mut output: T ) {
+        let output = output.as_mut();
+        assert_eq!( output.len(), Self::SPRITES_WIDTH * Self::SPRITES_HEIGHT );
+
+        let background = self.color( palette, 0, 0 );
+        for out in output.iter_mut() {
+            *out = background;
+        }
+
+        let big_sprites = self.ppu.uses_big_sprites();
+        for index in 0..64 {
+            let sprite = self.sprite( index );
+            let x = (index as usize % 8) * 8;
+            let y = (index as usize / 8) * 16;
+            let flip_horizontally = sprite.is_flipped_horizontally();
+            let flip_vertically = sprite.is_flipped_vertically();
+
+            if big_sprites == false {
+                let tile_address = self.ppu.sprite_pattern_table_address() + sprite.tile as u16 * 16;
+                self.draw_tile( palette, output, Self::SPRITES_WIDTH, x, y, tile_address, sprite.palette_index(), flip_horizontally, flip_vertically );
+            } else {
+                // For 8x16 sprites the pattern table is selected by the lowest bit of the tile index.
+                let pattern_table_address = (sprite.tile as u16 & 1) * 0x1000;
+                let top_tile_address = pattern_table_address + (sprite.tile & !1) as u16 * 16;
+                let bottom_tile_address = top_tile_address + 16;
+                let (upper, lower) = if flip_vertically {
+                    (bottom_tile_address, top_tile_address)
+                } else {
+                    (top_tile_address, bottom_tile_address)
+                };
+
+                self.draw_tile( palette, output, Self::SPRITES_WIDTH, x, y, upper, sprite.palette_index(), flip_horizontally, flip_vertically );
+                self.draw_tile( palette, output, Self::SPRITES_WIDTH, x, y + 8, lower, sprite.palette_index(), flip_horizontally, flip_vertically );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PpuViewer;
+    use rp2c02::Palette;
+    use virtual_nes::{State, Context, Interface};
+
+    struct Instance {
+        state: State
+    }
+
+    impl Context for Instance {
+        fn state_mut( &mut self ) -> &mut State {
+            &mut self.state
+        }
+
+        fn state( &self ) -> &State {
+            &self.state
+        }
+    }
+
+    // An NROM cartridge with 16KB of PRG ROM, CHR RAM and horizontal mirroring.
+    fn create_instance() -> Instance {
+        let mut rom = vec![ 0; 16 + 16 * 1024 ];
+        rom[ 0..4 ].copy_from_slice( b"NES\x1A" );
+        rom[ 4 ] = 1;
+
+        let mut instance = Instance { state: State::new() };
+        instance.load_rom( &rom ).unwrap();
+        instance
+    }
+
+    fn write_video_memory( instance: &mut Instance, address: u16, data: &[u8] ) {
+        instance.poke_memory( 0x2006, (address >> 8) as u8 );
+        instance.poke_memory( 0x2006, address as u8 );
+        for &value in data {
+            instance.poke_memory( 0x2007, value );
+        }
+    }
+
+    #[test]
+    fn render_pattern_table() {
+        let mut instance = create_instance();
+
+        // The tile #1 has the color #1 on its first row, and the color #3 on its last row.
+        write_video_memory( &mut instance, 0x0010, &[0xFF, 0, 0, 0, 0, 0, 0, 0xFF, 0, 0, 0, 0, 0, 0, 0, 0xFF] );
+        write_video_memory( &mut instance, 0x3F00, &[0x0F, 0x16, 0x2A, 0x12] );
+
+        let palette = Palette::default();
+        let mut output = vec![ 0; PpuViewer::PATTERN_TABLE_WIDTH * PpuViewer::PATTERN_TABLE_HEIGHT ];
+        instance.state().ppu_viewer().render_pattern_table( 0, 0, &palette, &mut output );
+
+        let pixel = |x: usize, y: usize| output[ y * PpuViewer::PATTERN_TABLE_WIDTH + x ];
+        assert_eq!( pixel( 0, 0 ), palette.get_packed_abgr( 0x0F ) );
+        assert_eq!( pixel( 8, 0 ), palette.get_packed_abgr( 0x16 ) );
+        assert_eq!( pixel( 15, 0 ), palette.get_packed_abgr( 0x16 ) );
+        assert_eq!( pixel( 8, 1 ), palette.get_packed_abgr( 0x0F ) );
+        assert_eq!( pixel( 8, 7 ), palette.get_packed_abgr( 0x12 ) );
+    }
+
+    #[test]
+    fn render_nametables_with_mirroring() {
+        let mut instance = create_instance();
+
+        write_video_memory( &mut instance, 0x0010, &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF] );
+        write_video_memory( &mut instance, 0x3F00, &[0x0F, 0x16, 0x2A, 0x12, 0x0F, 0x21, 0x2A, 0x12] );
+
+        // The first tile of the first nametable, with the second palette.
+        write_video_memory( &mut instance, 0x2000, &[0x01] );
+        write_video_memory( &mut instance, 0x23C0, &[0x01] );
+
+        let palette = Palette::default();
+        let mut output = vec![ 0; PpuViewer::NAMETABLES_WIDTH * PpuViewer::NAMETABLES_HEIGHT ];
+        instance.state().ppu_viewer().render_nametables( &palette, &mut output );
+
+        let pixel = |x: usize, y: usize| output[ y * PpuViewer::NAMETABLES_WIDTH + x ];
+        assert_eq!( pixel( 0, 0 ), palette.get_packed_abgr( 0x21 ) );
+        assert_eq!( pixel( 8, 0 ), palette.get_packed_abgr( 0x0F ) );
+
+        // With horizontal mirroring the second nametable is a mirror of the first one.
+        assert_eq!( pixel( 256, 0 ), palette.get_packed_abgr( 0x21 ) );
+        assert_eq!( pixel( 0, 240 ), palette.get_packed_abgr( 0x0F ) );
+    }
+
+    #[test]
+    fn render_sprites() {
+        let mut instance = create_instance();
+
+        write_video_memory( &mut instance, 0x0020, &[0x80, 0, 0, 0, 0, 0, 0, 0] );
+        write_video_memory( &mut instance, 0x3F10, &[0x0F, 0x16, 0x2A, 0x12, 0x0F, 0x21] );
+
+        // The second sprite uses the tile #2, the second sprite palette and is flipped horizontally.
+        instance.poke_memory( 0x2003, 4 );
+        for &value in &[0x10, 0x02, 0b01000001, 0x20] {
+            instance.poke_memory( 0x2004, value );
+        }
+
+        let viewer = instance.state().ppu_viewer();
+        let sprite = viewer.sprite( 1 );
+        assert_eq!( (sprite.x, sprite.y, sprite.tile), (0x20, 0x10, 0x02) );
+        assert_eq!( sprite.palette_index(), 5 );
+        assert!( sprite.is_flipped_horizontally() );
+
+        let palette = Palette::default();
+        let mut output = vec![ 0; PpuViewer::SPRITES_WIDTH * PpuViewer::SPRITES_HEIGHT ];
+        viewer.render_sprites( &palette, &mut output );
+
+        assert_eq!( output[ 8 + 7 ], palette.get_packed_abgr( 0x21 ) );
+        assert_eq!( output[ 8 ], palette.get_packed_abgr( 0x0F ) );
+    }
+}
@@ -43,20 +43,35 @@ pub const VROM_BANK_SIZE: usize = 8 * 1024;
 
 pub struct NesRom {
     pub mapper: u8,
+    pub submapper: u8,
     pub rom: Vec< u8 >,
     pub video_rom: Vec< u8 >,
     pub save_ram_length: u32,
-    pub mirroring: Mirroring
+    pub mirroring: Mirroring,
+
+    // The raw mirroring bit of the header; some boards give it
+    // a different meaning when the four-screen bit is also set.
+    pub mirroring_bit: bool,
+    pub has_battery: bool,
+    pub has_trainer: bool,
+
+    // Only the NES 2.0 headers carry these; we only emulate an NTSC console
+    // with standard controllers, so they're purely informational.
+    pub region: Region,
+    pub input_device: u8
 }
 
 impl fmt::Debug for NesRom {
     fn fmt( &self, fmt: &mut fmt::Formatter ) -> fmt::Result {
-        write!( fmt, "<NesRom mapper={}, rom={}k, video_rom={}k, ram={}k, mirroring={:?}>",
+        write!( fmt, "<NesRom mapper={}, submapper={}, rom={}k, video_rom={}k, ram={}k, mirroring={:?}, battery={}, region={:?}>",
             self.mapper,
+            self.submapper,
             self.rom.len() / 1024,
             self.video_rom.len() / 1024,
             self.save_ram_length / 1024,
-            self.mirroring
+            self.mirroring,
+            self.has_battery,
+            self.region
         )?;
 
         Ok(())
@@ -70,6 +85,26 @@ pub enum Mirroring {
     FourScreen
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Region {
+    Ntsc,
+    Pal,
+    // The game runs on both NTSC and PAL consoles.
+    Multiple,
+    Dendy
+}
+
+impl Region {
+    fn from_nes2( value: u8 ) -> Self {
+        match value & 0b11 {
+            0 => Region::Ntsc,
+            1 => Region::Pal,
+            2 => Region::Multiple,
+            _ => Region::Dendy
+        }
+    }
+}
+
 impl NesRom {
     pub fn load( mut data: &[u8] ) -> Result< Self, LoadError > {
         if data.len() < 16 {
@@ -81,13 +116,39 @@ impl NesRom {
             return Err( LoadError::new( format!( "Not an INES ROM file: magic number mismatch (got: 0x{:08X})", magic ) ) );
         }
 
-        let rom_bank_count = data[ 4 ] as usize;
-        let video_rom_bank_count = data[ 5 ] as usize;
         let flags_1 = data[ 6 ];
         let flags_2 = data[ 7 ];
+        let is_nes2 = flags_2 & 0b1100 == 0b1000;
+
+        let mut rom_bank_count = data[ 4 ] as usize;
+        let mut video_rom_bank_count = data[ 5 ] as usize;
+        let submapper;
+        let save_ram_length;
+        let region;
+        let input_device;
+        if is_nes2 {
+            rom_bank_count |= ((data[ 9 ] & 0x0F) as usize) << 8;
+            video_rom_bank_count |= ((data[ 9 ] >> 4) as usize) << 8;
+            submapper = data[ 8 ] >> 4;
+
+            // The sizes of the volatile and the battery-backed RAM are stored as shift counts.
+            let ram_size = |shift: u8| if shift == 0 { 0 } else { 64 << shift as u32 };
+            let total_ram_size = ram_size( data[ 10 ] & 0x0F ) + ram_size( data[ 10 ] >> 4 );
+            save_ram_length = max( 8 * 1024, total_ram_size );
+
+            region = Region::from_nes2( data[ 12 ] );
+            input_device = data[ 15 ] & 0x3F;
+        } else {
+            submapper = 0;
+            region = Region::Ntsc;
+            input_device = 0;
 
-        // For compatibility with older INES files we assume there must be always one RAM bank.
-        let save_ram_length = max( 1, data[ 8 ] as u32 ) * 8 * 1024;
+            // The byte 8 is supposed to be the number of RAM banks, but hardly anyone
+            // ever set it, and plenty of old dumps have junk like "DiskDude!" in there,
+            // so we always assume a single bank; boards with more RAM need either
+            // a NES 2.0 header or an entry in the ROM database.
+            save_ram_length = 8 * 1024;
+        }
 
         let mirroring = {
             if flags_1 & 0b1000 != 0 {
@@ -99,6 +160,8 @@ impl NesRom {
             }
         };
 
+        let mirroring_bit = flags_1 & 0b1 != 0;
+        let has_battery = flags_1 & 0b10 != 0;
         let has_trainer = flags_1 & 0b100 != 0;
         let mapper = (flags_2 & 0xF0) | ((flags_1 & 0xF0) >> 4);
 
@@ -122,13 +185,45 @@ impl NesRom {
 
         Ok( NesRom {
             mapper: mapper,
+            submapper: submapper,
             rom: rom,
             video_rom: video_rom,
             save_ram_length: save_ram_length,
-            mirroring: mirroring
+            mirroring: mirroring,
+            mirroring_bit: mirroring_bit,
+            has_battery: has_battery,
+            has_trainer: has_trainer,
+            region: region,
+            input_device: input_device
         })
     }
 
+    // Some of the boards of the discrete logic mappers (UxROM, CNROM and AxROM)
+    // have bus conflicts and some don't; the NES 2.0 submappers 1 and 2 tell us
+    // which one it is, and otherwise we assume whatever is the most common
+    // for the given mapper, which is passed in as `default`.
+    pub fn has_bus_conflicts( &self, default: bool ) -> bool {
+        match self.submapper {
+            1 => false,
+            2 => true,
+            _ => default
+        }
+    }
+
+    // Where the ROM starts in the iNES file, after the header and the trainer.
+    pub fn rom_file_offset( &self ) -> u32 {
+        16 + if self.has_trainer { 512 } else { 0 }
+    }
+
+    // Where the VROM starts in the iNES file, if there is any; it's stored right after the ROM.
+    pub fn video_rom_file_offset( &self ) -> Option< u32 > {
+        if self.video_rom.is_empty() {
+            None
+        } else {
+            Some( self.rom_file_offset() + self.rom.len() as u32 )
+        }
+    }
+
     pub fn rom_bank_count( &self ) -> usize {
         self.rom.len() / ROM_BANK_SIZE
     }
@@ -161,3 +256,62 @@ impl NesRom {
         }
     }
 }
+
+#[test]
+fn test_load_nes2_header() {
+    let mut data = vec![ 0; 16 + 2 * ROM_BANK_SIZE + VROM_BANK_SIZE ];
+    data[ 0..4 ].copy_from_slice( b"NES\x1A" );
+    data[ 4 ] = 2;
+    data[ 5 ] = 1;
+    data[ 6 ] = 0x21;
+    data[ 7 ] = 0x08;
+    data[ 8 ] = 0x20;
+    data[ 10 ] = 0x90;
+    data[ 12 ] = 0x01;
+    data[ 15 ] = 0x08;
+
+    let rom = NesRom::load( &data ).unwrap();
+    assert_eq!( rom.mapper, 2 );
+    assert_eq!( rom.submapper, 2 );
+    assert_eq!( rom.rom_bank_count(), 2 );
+    assert_eq!( rom.video_rom_bank_count(), 1 );
+    assert_eq!( rom.save_ram_length, 32 * 1024 );
+    assert_eq!( rom.mirroring, Mirroring::Vertical );
+    assert_eq!( rom.has_battery, false );
+    assert_eq!( rom.region, Region::Pal );
+    assert_eq!( rom.input_device, 8 );
+    assert!( rom.has_bus_conflicts( false ) );
+
+    // In an iNES header the byte 8 can't be trusted.
+    data[ 7 ] = 0x00;
+    let rom = NesRom::load( &data ).unwrap();
+    assert_eq!( rom.submapper, 0 );
+    assert_eq!( rom.save_ram_length, 8 * 1024 );
+    assert_eq!( rom.region, Region::Ntsc );
+    assert_eq!( rom.input_device, 0 );
+}
+
+#[test]
+fn test_file_offsets() {
+    let mut data = vec![ 0; 16 + 512 + 2 * ROM_BANK_SIZE + VROM_BANK_SIZE ];
+    data[ 0..4 ].copy_from_slice( b"NES\x1A" );
+    data[ 4 ] = 2;
+    data[ 5 ] = 1;
+    data[ 6 ] = 0b100;
+    data[ 16 + 512 ] = 0xAA;
+    data[ 16 + 512 + 2 * ROM_BANK_SIZE ] = 0xBB;
+
+    let rom = NesRom::load( &data ).unwrap();
+    assert_eq!( rom.has_trainer, true );
+    assert_eq!( rom.rom[ 0 ], 0xAA );
+    assert_eq!( rom.video_rom[ 0 ], 0xBB );
+    assert_eq!( rom.rom_file_offset(), 16 + 512 );
+    assert_eq!( rom.video_rom_file_offset(), Some( 16 + 512 + 2 * ROM_BANK_SIZE as u32 ) );
+
+    data[ 5 ] = 0;
+    data[ 6 ] = 0;
+    let rom = NesRom::load( &data ).unwrap();
+    assert_eq!( rom.has_trainer, false );
+    assert_eq!( rom.rom_file_offset(), 16 );
+    assert_eq!( rom.video_rom_file_offset(), None );
+}
@@ -0,0 +1,565 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::format;
+use core::cmp::max;
+
+use rom::{NesRom, LoadError, Mirroring, Region};
+use hashes::{crc32, sha1};
+
+/*
+    A database of known dumps, used to correct the headers of the ROMs
+    which have a wrong mapper, mirroring or RAM size in them.
+
+    The database is read from a subset of the NES 2.0 XML database format:
+
+        <nes20db>
+            <game>
+                <!-- Game Title (World).nes -->
+                <prgrom size="32768" crc32="..."/>
+                <chrrom size="8192" crc32="..."/>
+                <rom size="40960" crc32="..." sha1="..."/>
+                <prgram size="8192"/>
+                <prgnvram size="8192"/>
+                <pcb mapper="0" submapper="0" mirroring="V" battery="0"/>
+                <console type="0" region="0"/>
+                <expansion type="1"/>
+            </game>
+        </nes20db>
+
+    The `rom` element describes the PRG ROM followed by the CHR ROM, that is
+    the whole file without its header; if it's missing the game is matched
+    using the `prgrom` and the `chrrom` checksums instead. Everything else
+    is optional, and unknown elements and attributes are ignored.
+*/
+
+static BUILTIN_DATABASE: &'static str = include_str!( "rom_database.xml" );
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RomDatabaseEntry {
+    pub title: String,
+
+    // The checksums of the PRG ROM and the CHR ROM together.
+    pub crc32: Option< u32 >,
+    pub sha1: Option< [u8; 20] >,
+    pub rom_crc32: Option< u32 >,
+    pub video_rom_crc32: Option< u32 >,
+
+    pub mapper: Option< u16 >,
+    pub submapper: Option< u8 >,
+    pub mirroring: Option< Mirroring >,
+    pub has_battery: Option< bool >,
+    pub save_ram_length: Option< u32 >,
+    pub region: Option< Region >,
+    pub input_device: Option< u8 >
+}
+
+impl RomDatabaseEntry {
+    fn new() -> Self {
+        RomDatabaseEntry {
+            title: String::new(),
+            crc32: None,
+            sha1: None,
+            rom_crc32: None,
+            video_rom_crc32: None,
+            mapper: None,
+            submapper: None,
+            mirroring: None,
+            has_battery: None,
+            save_ram_length: None,
+            region: None,
+            input_device: None
+        }
+    }
+
+    fn matches( &self, hashes: &mut RomHashes ) -> bool {
+        if let Some( sha1 ) = self.sha1 {
+            sha1 == hashes.sha1()
+        } else if let Some( crc32 ) = self.crc32 {
+            crc32 == hashes.crc32
+        } else if let Some( rom_crc32 ) = self.rom_crc32 {
+            rom_crc32 == hashes.rom_crc32 && self.video_rom_crc32.map( |crc32| crc32 == hashes.video_rom_crc32 ).unwrap_or( true )
+        } else {
+            false
+        }
+    }
+
+    // Overrides the information from the ROM's header with the one from the database.
+    pub fn apply( &self, rom: &mut NesRom ) -> Result< (), LoadError > {
+        if let Some( mapper ) = self.mapper {
+            if mapper > 0xFF {
+                return Err( LoadError::new( format!( "Unsupported mapper: {}", mapper ) ) );
+            }
+
+            rom.mapper = mapper as u8;
+        }
+
+        if let Some( submapper ) = self.submapper {
+            rom.submapper = submapper;
+        }
+
+        if let Some( mirroring ) = self.mirroring {
+            match mirroring {
+                Mirroring::Horizontal => rom.mirroring_bit = false,
+                Mirroring::Vertical => rom.mirroring_bit = true,
+                Mirroring::FourScreen => {}
+            }
+
+            rom.mirroring = mirroring;
+        }
+
+        if let Some( has_battery ) = self.has_battery {
+            rom.has_battery = has_battery;
+        }
+
+        if let Some( save_ram_length ) = self.save_ram_length {
+            rom.save_ram_length = save_ram_length;
+        }
+
+        if let Some( region ) = self.region {
+            rom.region = region;
+        }
+
+        if let Some( input_device ) = self.input_device {
+            rom.input_device = input_device;
+        }
+
+        Ok(())
+    }
+}
+
+struct RomHashes< 'a > {
+    rom: &'a NesRom,
+    crc32: u32,
+    rom_crc32: u32,
+    video_rom_crc32: u32,
+    sha1: Option< [u8; 20] >
+}
+
+impl< 'a > RomHashes< 'a > {
+    fn new( rom: &'a NesRom ) -> Self {
+        RomHashes {
+            rom: rom,
+            crc32: crc32( &Self::contents( rom ) ),
+            rom_crc32: crc32( &rom.rom ),
+            video_rom_crc32: crc32( &rom.video_rom ),
+            sha1: None
+        }
+    }
+
+    fn contents( rom: &NesRom ) -> Vec< u8 > {
+        let mut contents = Vec::with_capacity( rom.rom.len() + rom.video_rom.len() );
+        contents.extend_from_slice( &rom.rom );
+        contents.extend_from_slice( &rom.video_rom );
+        contents
+    }
+
+    // Calculating the SHA-1 is relatively expensive, so we only do it when necessary.
+    fn sha1( &mut self ) -> [u8; 20] {
+        if self.sha1.is_none() {
+            self.sha1 = Some( sha1( &Self::contents( self.rom ) ) );
+        }
+
+        self.sha1.unwrap()
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RomDatabase {
+    entries: Vec< RomDatabaseEntry >
+}
+
+impl RomDatabase {
+    pub const fn new() -> Self {
+        RomDatabase {
+            entries: Vec::new()
+        }
+    }
+
+    // The database which is embedded in the emulator.
+    pub fn builtin() -> Self {
+        Self::parse( BUILTIN_DATABASE ).unwrap()
+    }
+
+    pub fn parse( xml: &str ) -> Result< Self, LoadError > {
+        let mut entries = Vec::new();
+        let mut entry: Option< RomDatabaseEntry > = None;
+        let mut ram_length = 0;
+        let mut nvram_length = 0;
+        let mut has_ram = false;
+        let mut battery = false;
+        for token in Tokenizer::new( xml ) {
+            let token = token?;
+            let entry = match token {
+                Token::Open( "game", _ ) => {
+                    entry = Some( RomDatabaseEntry::new() );
+                    ram_length = 0;
+                    nvram_length = 0;
+                    has_ram = false;
+                    battery = false;
+                    continue;
+                },
+                Token::Close( "game" ) => {
+                    let mut entry = match entry.take() {
+                        Some( entry ) => entry,
+                        None => return Err( LoadError::new( "Malformed ROM database: unexpected </game>" ) )
+                    };
+
+                    // Just as in the NES 2.0 header there's always at least one RAM bank;
+                    // if the entry doesn't describe the board at all we keep the header's.
+                    if has_ram || entry.mapper.is_some() {
+                        entry.save_ram_length = Some( max( 8 * 1024, ram_length + nvram_length ) );
+                    }
+
+                    if entry.mapper.is_some() {
+                        entry.has_battery = Some( battery || nvram_length != 0 );
+                    }
+
+                    entries.push( entry );
+                    continue;
+                },
+                _ => match entry.as_mut() {
+                    Some( entry ) => entry,
+                    None => continue
+                }
+            };
+
+            match token {
+                Token::Comment( comment ) => {
+                    if entry.title.is_empty() {
+                        entry.title = parse_title( comment );
+                    }
+                },
+                Token::Open( "rom", attributes ) => {
+                    entry.crc32 = attributes.hex( "crc32" )?;
+                    entry.sha1 = attributes.sha1( "sha1" )?;
+                },
+                Token::Open( "prgrom", attributes ) => {
+                    entry.rom_crc32 = attributes.hex( "crc32" )?;
+                },
+                Token::Open( "chrrom", attributes ) => {
+                    entry.video_rom_crc32 = attributes.hex( "crc32" )?;
+                },
+                Token::Open( "prgram", attributes ) => {
+                    ram_length += attributes.number( "size" )?.unwrap_or( 0 );
+                    has_ram = true;
+                },
+                Token::Open( "prgnvram", attributes ) => {
+                    nvram_length += attributes.number( "size" )?.unwrap_or( 0 );
+                    has_ram = true;
+                },
+                Token::Open( "pcb", attributes ) => {
+                    entry.mapper = attributes.number( "mapper" )?.map( |value| value as u16 );
+                    entry.submapper = attributes.number( "submapper" )?.map( |value| value as u8 );
+                    entry.mirroring = match attributes.get( "mirroring" ) {
+                        Some( "H" ) => Some( Mirroring::Horizontal ),
+                        Some( "V" ) => Some( Mirroring::Vertical ),
+                        Some( "4" ) => Some( Mirroring::FourScreen ),
+                        // The rest are controlled by the mapper.
+                        _ => None
+                    };
+                    battery = attributes.number( "battery" )?.unwrap_or( 0 ) != 0;
+                },
+                Token::Open( "console", attributes ) => {
+                    entry.region = attributes.number( "region" )?.map( |value| match value {
+                        0 => Region::Ntsc,
+                        1 => Region::Pal,
+                        2 => Region::Multiple,
+                        _ => Region::Dendy
+                    });
+                },
+                Token::Open( "expansion", attributes ) => {
+                    entry.input_device = attributes.number( "type" )?.map( |value| value as u8 );
+                },
+                _ => {}
+            }
+        }
+
+        if entry.is_some() {
+            return Err( LoadError::new( "Malformed ROM database: missing </game>" ) );
+        }
+
+        Ok( RomDatabase {
+            entries: entries
+        })
+    }
+
+    pub fn len( &self ) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty( &self ) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries( &self ) -> &[RomDatabaseEntry] {
+        &self.entries
+    }
+
+    pub fn find( &self, rom: &NesRom ) -> Option< &RomDatabaseEntry > {
+        if self.entries.is_empty() {
+            return None;
+        }
+
+        let mut hashes = RomHashes::new( rom );
+        self.entries.iter().find( |entry| entry.matches( &mut hashes ) )
+    }
+}
+
+impl Default for RomDatabase {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The comments contain the path of the original file, e.g. "Licensed\Game Title (World).nes".
+fn parse_title( comment: &str ) -> String {
+    let title = comment.trim();
+    let title = title.rsplit( |character| character == '\\' || character == '/' ).next().unwrap_or( title );
+    let title = if title.ends_with( ".nes" ) { &title[ ..title.len() - 4 ] } else { title };
+    title.into()
+}
+
+enum Token< 'a > {
+    Comment( &'a str ),
+    // Self-closing elements are also returned as `Open`.
+    Open( &'a str, Attributes< 'a > ),
+    Close( &'a str )
+}
+
+struct Attributes< 'a > {
+    attributes: Vec< (&'a str, &'a str) >
+}
+
+impl< 'a > Attributes< 'a > {
+    fn parse( mut input: &'a str ) -> Result< Self, LoadError > {
+        let mut attributes = Vec::new();
+        loop {
+            input = input.trim_start();
+            if input.is_empty() {
+                break;
+            }
+
+            let error = || LoadError::new( format!( "Malformed ROM database: invalid attribute: {}", input ) );
+            let equals = input.find( '=' ).ok_or_else( error )?;
+            let name = input[ ..equals ].trim_end();
+            let rest = input[ equals + 1.. ].trim_start();
+            let quote = rest.chars().next().filter( |&quote| quote == '"' || quote == '\'' ).ok_or_else( error )?;
+            let length = rest[ 1.. ].find( quote ).ok_or_else( error )?;
+            attributes.push( (name, &rest[ 1..1 + length ]) );
+            input = &rest[ length + 2.. ];
+        }
+
+        Ok( Attributes {
+            attributes: attributes
+        })
+    }
+
+    fn get( &self, name: &str ) -> Option< &'a str > {
+        self.attributes.iter().find( |&&(key, _)| key == name ).map( |&(_, value)| value )
+    }
+
+    fn number( &self, name: &str ) -> Result< Option< u32 >, LoadError > {
+        match self.get( name ) {
+            Some( value ) => value.parse().map( Some ).map_err( |_| LoadError::new( format!( "Malformed ROM database: invalid {}: {}", name, value ) ) ),
+            None => Ok( None )
+        }
+    }
+
+    fn hex( &self, name: &str ) -> Result< Option< u32 >, LoadError > {
+        match self.get( name ) {
+            Some( value ) => u32::from_str_radix( value, 16 ).map( Some ).map_err( |_| LoadError::new( format!( "Malformed ROM database: invalid {}: {}", name, value ) ) ),
+            None => Ok( None )
+        }
+    }
+
+    fn sha1( &self, name: &str ) -> Result< Option< [u8; 20] >, LoadError > {
+        let value = match self.get( name ) {
+            Some( value ) => value,
+            None => return Ok( None )
+        };
+
+        let error = || LoadError::new( format!( "Malformed ROM database: invalid {}: {}", name, value ) );
+        if value.len() != 40 || value.is_ascii() == false {
+            return Err( error() );
+        }
+
+        let mut output = [0; 20];
+        for (index, byte) in output.iter_mut().enumerate() {
+            *byte = u8::from_str_radix( &value[ index * 2..index * 2 + 2 ], 16 ).map_err( |_| error() )?;
+        }
+
+        Ok( Some( output ) )
+    }
+}
+
+struct Tokenizer< 'a > {
+    input: &'a str
+}
+
+impl< 'a > Tokenizer< 'a > {
+    fn new( input: &'a str ) -> Self {
+        Tokenizer {
+            input: input
+        }
+    }
+
+    fn next_token( &mut self ) -> Result< Option< Token< 'a > >, LoadError > {
+        loop {
+            // We don't care about the text between the elements.
+            let start = match self.input.find( '<' ) {
+                Some( start ) => start,
+                None => return Ok( None )
+            };
+
+            self.input = &self.input[ start.. ];
+            if self.input.starts_with( "<!--" ) {
+                let end = self.input.find( "-->" ).ok_or_else( || LoadError::new( "Malformed ROM database: unterminated comment" ) )?;
+                let comment = &self.input[ 4..end ];
+                self.input = &self.input[ end + 3.. ];
+                return Ok( Some( Token::Comment( comment ) ) );
+            }
+
+            let end = self.input.find( '>' ).ok_or_else( || LoadError::new( "Malformed ROM database: unterminated element" ) )?;
+            let element = &self.input[ 1..end ];
+            self.input = &self.input[ end + 1.. ];
+
+            // Skip the XML declaration and the doctype.
+            if element.starts_with( '?' ) || element.starts_with( '!' ) {
+                continue;
+            }
+
+            if element.starts_with( '/' ) {
+                return Ok( Some( Token::Close( element[ 1.. ].trim() ) ) );
+            }
+
+            let element = if element.ends_with( '/' ) { &element[ ..element.len() - 1 ] } else { element };
+            let name_length = element.find( |character: char| character.is_whitespace() ).unwrap_or( element.len() );
+            let attributes = Attributes::parse( &element[ name_length.. ] )?;
+            return Ok( Some( Token::Open( &element[ ..name_length ], attributes ) ) );
+        }
+    }
+}
+
+impl< 'a > Iterator for Tokenizer< 'a > {
+    type Item = Result< Token< 'a >, LoadError >;
+    fn next( &mut self ) -> Option< Self::Item > {
+        match self.next_token() {
+            Ok( token ) => token.map( Ok ),
+            Err( error ) => {
+                self.input = "";
+                Some( Err( error ) )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_database( rom: &NesRom ) -> String {
+    format!( r#"<?xml version="1.0" encoding="UTF-8"?>
+<nes20db date="2020-01-01">
+<game>
+<!-- Licensed\Some Other Game (USA).nes -->
+<rom size="40960" crc32="00000000"/>
+<pcb mapper="1" submapper="0" mirroring="H" battery="0"/>
+</game>
+<game>
+<!-- Licensed\Test Game (Europe).nes -->
+<prgrom size="{}" crc32="{:08X}"/>
+<chrrom size="{}" crc32="{:08X}"/>
+<rom size="{}" crc32="{:08X}"/>
+<prgram size="8192"/>
+<prgnvram size="8192"/>
+<pcb mapper="4" submapper="1" mirroring="V" battery="1"/>
+<console type="0" region="1"/>
+<expansion type="8"/>
+</game>
+</nes20db>
+"#,
+        rom.rom.len(),
+        crc32( &rom.rom ),
+        rom.video_rom.len(),
+        crc32( &rom.video_rom ),
+        rom.rom.len() + rom.video_rom.len(),
+        crc32( &RomHashes::contents( rom ) )
+    )
+}
+
+#[test]
+fn test_builtin_database() {
+    let database = RomDatabase::builtin();
+    assert!( database.entries().iter().all( |entry| entry.title.is_empty() == false && entry.sha1.is_some() ) );
+
+    let rom = NesRom::load( include_bytes!( "../../pinky-web/static/roms/lawn_mower.nes" ) ).unwrap();
+    let entry = database.find( &rom ).unwrap();
+    assert_eq!( entry.title, "Lawn Mower" );
+    assert_eq!( entry.mapper, Some( 0 ) );
+    assert_eq!( entry.mirroring, Some( Mirroring::Vertical ) );
+}
+
+#[test]
+fn test_find_and_apply() {
+    use generic_mapper::create_test_rom;
+
+    let mut rom = create_test_rom( 0, 32 * 1024, 8 * 1024 );
+    let database = RomDatabase::parse( &test_database( &rom ) ).unwrap();
+    assert_eq!( database.len(), 2 );
+
+    let entry = database.find( &rom ).unwrap().clone();
+    assert_eq!( entry.title, "Test Game (Europe)" );
+    assert_eq!( entry.mapper, Some( 4 ) );
+    entry.apply( &mut rom ).unwrap();
+
+    assert_eq!( rom.mapper, 4 );
+    assert_eq!( rom.submapper, 1 );
+    assert_eq!( rom.mirroring, Mirroring::Vertical );
+    assert_eq!( rom.mirroring_bit, true );
+    assert_eq!( rom.has_battery, true );
+    assert_eq!( rom.save_ram_length, 16 * 1024 );
+    assert_eq!( rom.region, Region::Pal );
+    assert_eq!( rom.input_device, 8 );
+
+    rom.rom[ 1 ] = 0xFF;
+    assert!( database.find( &rom ).is_none() );
+}
+
+#[test]
+fn test_find_by_sha1_and_split_checksums() {
+    use generic_mapper::create_test_rom;
+
+    let rom = create_test_rom( 0, 32 * 1024, 8 * 1024 );
+    let sha1 = sha1( &RomHashes::contents( &rom ) );
+    let sha1: String = sha1.iter().map( |byte| format!( "{:02x}", byte ) ).collect();
+
+    // The SHA-1 takes precedence over the CRC32.
+    let xml = format!( r#"<game><rom crc32="00000000" sha1="{}"/><pcb mapper="2"/></game>"#, sha1 );
+    assert_eq!( RomDatabase::parse( &xml ).unwrap().find( &rom ).unwrap().mapper, Some( 2 ) );
+
+    let xml = format!( r#"<game><prgrom crc32="{:08x}"/><pcb mapper="2"/></game>"#, crc32( &rom.rom ) );
+    assert_eq!( RomDatabase::parse( &xml ).unwrap().find( &rom ).unwrap().mapper, Some( 2 ) );
+
+    let xml = format!( r#"<game><prgrom crc32="{:08x}"/><chrrom crc32="00000000"/><pcb mapper="2"/></game>"#, crc32( &rom.rom ) );
+    assert!( RomDatabase::parse( &xml ).unwrap().find( &rom ).is_none() );
+    // Without a `pcb` or any RAM elements the entry doesn't override the header's RAM size.
+    let xml = format!( r#"<game><prgrom crc32="{:08x}"/><console region="1"/></game>"#, crc32( &rom.rom ) );
+    let database = RomDatabase::parse( &xml ).unwrap();
+    assert_eq!( database.entries()[ 0 ].save_ram_length, None );
+    assert_eq!( database.entries()[ 0 ].has_battery, None );
+
+    let xml = format!( r#"<game><prgrom crc32="{:08x}"/><prgram size="32768"/></game>"#, crc32( &rom.rom ) );
+    assert_eq!( RomDatabase::parse( &xml ).unwrap().entries()[ 0 ].save_ram_length, Some( 32 * 1024 ) );
+}
+
+#[test]
+fn test_invalid_database() {
+    use generic_mapper::create_test_rom;
+
+    assert!( RomDatabase::parse( "<game><pcb mapper=\"x\"/></game>" ).is_err() );
+    assert!( RomDatabase::parse( "<game><pcb mapper=4/></game>" ).is_err() );
+    assert!( RomDatabase::parse( "<game><rom sha1=\"1234\"/></game>" ).is_err() );
+    assert!( RomDatabase::parse( "<game>" ).is_err() );
+    assert!( RomDatabase::parse( "<game><!-- title" ).is_err() );
+
+    let mut rom = create_test_rom( 0, 32 * 1024, 8 * 1024 );
+    let database = RomDatabase::parse( "<game><prgrom crc32=\"0\"/><pcb mapper=\"256\"/></game>" ).unwrap();
+    assert!( database.entries()[ 0 ].apply( &mut rom ).is_err() );
+}
@@ -0,0 +1,430 @@
+use alloc::vec::Vec;
+use alloc::format;
+
+use rom::LoadError;
+use hashes::crc32;
+
+/*
+    Soft-patching of the ROM files, so that the translations and the hacks
+    don't have to be applied to the original ROM before loading it.
+
+    The patches are applied to the whole file, header included, and the format
+    is detected automatically from the magic number:
+
+        IPS - a list of (offset, data) records, optionally run-length encoded;
+              also supports the truncation extension, where the "EOF" marker
+              is followed by the 24-bit size of the patched file.
+        UPS - the XOR of the original and of the patched file, with the CRC32
+              of the original file, the patched file and the patch itself.
+        BPS - a list of copy commands from the original file, the patched file
+              and the patch itself; has the same checksums as UPS.
+*/
+
+// The sizes in the UPS and BPS headers come from an untrusted file, so we refuse
+// to allocate anything bigger than this; it's far bigger than any real NES ROM.
+const MAXIMUM_OUTPUT_SIZE: usize = 16 * 1024 * 1024;
+
+pub fn apply_patch( data: &[u8], patch: &[u8] ) -> Result< Vec< u8 >, LoadError > {
+    if patch.starts_with( b"PATCH" ) {
+        apply_ips( data, patch )
+    } else if patch.starts_with( b"UPS1" ) {
+        apply_ups( data, patch )
+    } else if patch.starts_with( b"BPS1" ) {
+        apply_bps( data, patch )
+    } else {
+        Err( LoadError::new( "Unknown patch format" ) )
+    }
+}
+
+struct Reader< 'a > {
+    format: &'static str,
+    data: &'a [u8],
+    position: usize
+}
+
+impl< 'a > Reader< 'a > {
+    fn new( format: &'static str, data: &'a [u8], position: usize ) -> Self {
+        Reader {
+            format: format,
+            data: data,
+            position: position
+        }
+    }
+
+    fn error( &self, message: &str ) -> LoadError {
+        LoadError::new( format!( "Invalid {} patch: {}", self.format, message ) )
+    }
+
+    fn is_empty( &self ) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn bytes( &mut self, length: usize ) -> Result< &'a [u8], LoadError > {
+        if self.data.len() - self.position < length {
+            return Err( self.error( "unexpected end of file" ) );
+        }
+
+        let bytes = &self.data[ self.position..self.position + length ];
+        self.position += length;
+        Ok( bytes )
+    }
+
+    fn u8( &mut self ) -> Result< u8, LoadError > {
+        Ok( self.bytes( 1 )?[ 0 ] )
+    }
+
+    fn u16_be( &mut self ) -> Result< usize, LoadError > {
+        let bytes = self.bytes( 2 )?;
+        Ok( (bytes[ 0 ] as usize) << 8 | bytes[ 1 ] as usize )
+    }
+
+    fn u24_be( &mut self ) -> Result< usize, LoadError > {
+        let bytes = self.bytes( 3 )?;
+        Ok( (bytes[ 0 ] as usize) << 16 | (bytes[ 1 ] as usize) << 8 | bytes[ 2 ] as usize )
+    }
+
+    fn u32_le( &mut self ) -> Result< u32, LoadError > {
+        let bytes = self.bytes( 4 )?;
+        Ok( u32::from_le_bytes( [bytes[ 0 ], bytes[ 1 ], bytes[ 2 ], bytes[ 3 ]] ) )
+    }
+
+    // The variable length numbers used by both UPS and BPS; every byte carries
+    // 7 bits of the value, and the last one has its highest bit set.
+    fn number( &mut self ) -> Result< usize, LoadError > {
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let byte = self.u8()?;
+            value = ((byte & 0x7F) as usize).checked_mul( shift ).and_then( |bits| bits.checked_add( value ) ).ok_or_else( || self.error( "number is too big" ) )?;
+            if byte & 0x80 != 0 {
+                break;
+            }
+
+            shift = shift.checked_mul( 0x80 ).ok_or_else( || self.error( "number is too big" ) )?;
+            value = value.checked_add( shift ).ok_or_else( || self.error( "number is too big" ) )?;
+        }
+
+        Ok( value )
+    }
+
+    fn output_size( &mut self ) -> Result< usize, LoadError > {
+        let size = self.number()?;
+        if size > MAXIMUM_OUTPUT_SIZE {
+            return Err( self.error( "the output is too big" ) );
+        }
+
+        Ok( size )
+    }
+}
+
+fn apply_ips( data: &[u8], patch: &[u8] ) -> Result< Vec< u8 >, LoadError > {
+    const EOF: usize = 0x454F46;
+
+    let mut output = data.to_vec();
+    let mut reader = Reader::new( "IPS", patch, 5 );
+    loop {
+        let offset = reader.u24_be()?;
+        if offset == EOF {
+            break;
+        }
+
+        let length = reader.u16_be()?;
+        if length == 0 {
+            let length = reader.u16_be()?;
+            let value = reader.u8()?;
+            if output.len() < offset + length {
+                output.resize( offset + length, 0 );
+            }
+
+            for byte in &mut output[ offset..offset + length ] {
+                *byte = value;
+            }
+        } else {
+            let bytes = reader.bytes( length )?;
+            if output.len() < offset + length {
+                output.resize( offset + length, 0 );
+            }
+
+            output[ offset..offset + length ].copy_from_slice( bytes );
+        }
+    }
+
+    if reader.is_empty() == false {
+        let length = reader.u24_be()?;
+        output.truncate( length );
+    }
+
+    Ok( output )
+}
+
+// Verifies the checksums at the end of UPS and BPS patches and returns them.
+fn checksums( format: &'static str, data: &[u8], patch: &[u8] ) -> Result< (usize, u32), LoadError > {
+    let reader = Reader::new( format, patch, 0 );
+    if patch.len() < 4 + 12 {
+        return Err( reader.error( "unexpected end of file" ) );
+    }
+
+    let footer = patch.len() - 12;
+    let mut footer_reader = Reader::new( format, patch, footer );
+    let input_crc32 = footer_reader.u32_le()?;
+    let output_crc32 = footer_reader.u32_le()?;
+    let patch_crc32 = footer_reader.u32_le()?;
+
+    if crc32( &patch[ ..patch.len() - 4 ] ) != patch_crc32 {
+        return Err( reader.error( "patch checksum mismatch" ) );
+    }
+
+    if crc32( data ) != input_crc32 {
+        return Err( reader.error( "the patch is not meant for this ROM; checksum mismatch" ) );
+    }
+
+    Ok( (footer, output_crc32) )
+}
+
+fn verify_output( format: &'static str, output: &[u8], output_crc32: u32 ) -> Result< (), LoadError > {
+    if crc32( output ) != output_crc32 {
+        return Err( Reader::new( format, &[], 0 ).error( "patched ROM checksum mismatch" ) );
+    }
+
+    Ok(())
+}
+
+fn apply_ups( data: &[u8], patch: &[u8] ) -> Result< Vec< u8 >, LoadError > {
+    let (footer, output_crc32) = checksums( "UPS", data, patch )?;
+    let mut reader = Reader::new( "UPS", &patch[ ..footer ], 4 );
+    let input_size = reader.number()?;
+    let output_size = reader.output_size()?;
+    if input_size != data.len() {
+        return Err( reader.error( "the patch is not meant for this ROM; size mismatch" ) );
+    }
+
+    let mut output = data.to_vec();
+    output.resize( output_size, 0 );
+
+    let mut position: usize = 0;
+    while reader.is_empty() == false {
+        let skip = reader.number()?;
+        position = position.checked_add( skip ).ok_or_else( || reader.error( "offset out of bounds" ) )?;
+        loop {
+            let byte = reader.u8()?;
+            if byte != 0 && position < output.len() {
+                output[ position ] ^= byte;
+            }
+
+            position = position.checked_add( 1 ).ok_or_else( || reader.error( "offset out of bounds" ) )?;
+            if byte == 0 {
+                break;
+            }
+        }
+    }
+
+    verify_output( "UPS", &output, output_crc32 )?;
+    Ok( output )
+}
+
+fn apply_bps( data: &[u8], patch: &[u8] ) -> Result< Vec< u8 >, LoadError > {
+    const SOURCE_READ: usize = 0;
+    const TARGET_READ: usize = 1;
+    const SOURCE_COPY: usize = 2;
+    const TARGET_COPY: usize = 3;
+
+    let (footer, output_crc32) = checksums( "BPS", data, patch )?;
+    let mut reader = Reader::new( "BPS", &patch[ ..footer ], 4 );
+    let input_size = reader.number()?;
+    let output_size = reader.output_size()?;
+    let metadata_size = reader.number()?;
+    reader.bytes( metadata_size )?;
+
+    if input_size != data.len() {
+        return Err( reader.error( "the patch is not meant for this ROM; size mismatch" ) );
+    }
+
+    let mut output = Vec::with_capacity( output_size );
+    let mut source_offset: isize = 0;
+    let mut target_offset: isize = 0;
+    while reader.is_empty() == false {
+        let command = reader.number()?;
+        let length = (command >> 2) + 1;
+        if output.len() + length > output_size {
+            return Err( reader.error( "the output is too big" ) );
+        }
+
+        match command & 3 {
+            SOURCE_READ => {
+                let position = output.len();
+                let bytes = data.get( position..position + length ).ok_or_else( || reader.error( "read out of bounds" ) )?;
+                output.extend_from_slice( bytes );
+            },
+            TARGET_READ => {
+                output.extend_from_slice( reader.bytes( length )? );
+            },
+            kind => {
+                let offset = reader.number()?;
+                let offset = if offset & 1 != 0 { -((offset >> 1) as isize) } else { (offset >> 1) as isize };
+                if kind == SOURCE_COPY {
+                    source_offset = source_offset.checked_add( offset ).ok_or_else( || reader.error( "copy out of bounds" ) )?;
+                    if source_offset < 0 || source_offset as usize + length > data.len() {
+                        return Err( reader.error( "copy out of bounds" ) );
+                    }
+
+                    output.extend_from_slice( &data[ source_offset as usize..source_offset as usize + length ] );
+                    source_offset += length as isize;
+                } else {
+                    debug_assert_eq!( kind, TARGET_COPY );
+                    target_offset = target_offset.checked_add( offset ).ok_or_else( || reader.error( "copy out of bounds" ) )?;
+
+                    // The ranges can overlap, so this has to be done byte by byte.
+                    for _ in 0..length {
+                        if target_offset < 0 || target_offset as usize >= output.len() {
+                            return Err( reader.error( "copy out of bounds" ) );
+                        }
+
+                        let byte = output[ target_offset as usize ];
+                        output.push( byte );
+                        target_offset += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    if output.len() != output_size {
+        return Err( reader.error( "unexpected end of file" ) );
+    }
+
+    verify_output( "BPS", &output, output_crc32 )?;
+    Ok( output )
+}
+
+#[cfg(test)]
+fn encode_number( output: &mut Vec< u8 >, mut value: usize ) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            output.push( byte | 0x80 );
+            break;
+        }
+
+        output.push( byte );
+        value -= 1;
+    }
+}
+
+#[cfg(test)]
+fn append_checksums( patch: &mut Vec< u8 >, input: &[u8], output: &[u8] ) {
+    patch.extend_from_slice( &crc32( input ).to_le_bytes() );
+    patch.extend_from_slice( &crc32( output ).to_le_bytes() );
+    let patch_crc32 = crc32( patch );
+    patch.extend_from_slice( &patch_crc32.to_le_bytes() );
+}
+
+#[test]
+fn test_number_encoding() {
+    for &value in &[0, 1, 0x7F, 0x80, 0x1234, 0x4080, 0x123456] {
+        let mut encoded = Vec::new();
+        encode_number( &mut encoded, value );
+        assert_eq!( Reader::new( "BPS", &encoded, 0 ).number().unwrap(), value );
+    }
+}
+
+#[test]
+fn test_ips() {
+    let data = b"0123456789";
+    let mut patch = b"PATCH".to_vec();
+    patch.extend_from_slice( &[0x00, 0x00, 0x02, 0x00, 0x02, b'A', b'B'] );
+    // A run-length encoded record.
+    patch.extend_from_slice( &[0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x03, b'x'] );
+    // A record which extends the file.
+    patch.extend_from_slice( &[0x00, 0x00, 0x0A, 0x00, 0x01, b'!'] );
+    patch.extend_from_slice( b"EOF" );
+    assert_eq!( apply_patch( data, &patch ).unwrap(), b"01AB45xxx9!" );
+
+    // The truncation extension.
+    patch.extend_from_slice( &[0x00, 0x00, 0x05] );
+    assert_eq!( apply_patch( data, &patch ).unwrap(), b"01AB4" );
+
+    assert!( apply_patch( data, b"PATCH\x00\x00\x02\x00\x05AB" ).is_err() );
+    assert!( apply_patch( data, b"PATCH\x00\x00\x02" ).is_err() );
+    assert!( apply_patch( data, b"NOT A PATCH" ).is_err() );
+}
+
+#[test]
+fn test_ups() {
+    let input = b"Hello, world!";
+    let output = b"Hello, World!!";
+
+    let mut patch = b"UPS1".to_vec();
+    encode_number( &mut patch, input.len() );
+    encode_number( &mut patch, output.len() );
+    encode_number( &mut patch, 7 );
+    patch.extend_from_slice( &[b'w' ^ b'W', 0x00] );
+    encode_number( &mut patch, 4 );
+    patch.extend_from_slice( &[b'!', 0x00] );
+    append_checksums( &mut patch, input, output );
+
+    assert_eq!( apply_patch( input, &patch ).unwrap(), &output[..] );
+    assert!( apply_patch( b"Hello, there!", &patch ).is_err() );
+
+    let last = patch.len() - 1;
+    patch[ last ] ^= 1;
+    assert!( apply_patch( input, &patch ).is_err() );
+}
+
+#[test]
+fn test_bps() {
+    let input = b"abcdefgh";
+    let output = b"abcdXYXYXYgh-efgh";
+
+    let mut patch = b"BPS1".to_vec();
+    encode_number( &mut patch, input.len() );
+    encode_number( &mut patch, output.len() );
+    encode_number( &mut patch, 3 );
+    patch.extend_from_slice( b"xyz" );
+    // Source read of "abcd".
+    encode_number( &mut patch, (4 - 1) << 2 | 0 );
+    // Target read of "XY".
+    encode_number( &mut patch, (2 - 1) << 2 | 1 );
+    patch.extend_from_slice( b"XY" );
+    // Overlapping target copy of "XYXY" from offset 4.
+    encode_number( &mut patch, (4 - 1) << 2 | 3 );
+    encode_number( &mut patch, 4 << 1 );
+    // Source copy of "gh" from offset 6.
+    encode_number( &mut patch, (2 - 1) << 2 | 2 );
+    encode_number( &mut patch, 6 << 1 );
+    // Target read of "-".
+    encode_number( &mut patch, (1 - 1) << 2 | 1 );
+    patch.push( b'-' );
+    // Source copy of "efgh" from offset 4, relative to the previous one.
+    encode_number( &mut patch, (4 - 1) << 2 | 2 );
+    encode_number( &mut patch, 4 << 1 | 1 );
+    append_checksums( &mut patch, input, output );
+
+    assert_eq!( apply_patch( input, &patch ).unwrap(), &output[..] );
+    assert!( apply_patch( b"abcdefgX", &patch ).is_err() );
+}
+
+#[test]
+fn test_malicious_patches() {
+    let input = b"Hello, world!";
+    for &magic in &[b"UPS1", b"BPS1"] {
+        let mut patch = magic.to_vec();
+        encode_number( &mut patch, input.len() );
+        encode_number( &mut patch, usize::max_value() / 2 );
+        encode_number( &mut patch, 0 );
+        append_checksums( &mut patch, input, input );
+        assert!( apply_patch( input, &patch ).is_err() );
+    }
+
+    // A huge offset which would overflow the position.
+    let mut patch = b"UPS1".to_vec();
+    encode_number( &mut patch, input.len() );
+    encode_number( &mut patch, input.len() );
+    for _ in 0..2 {
+        encode_number( &mut patch, usize::max_value() / 2 + 1 );
+        patch.push( 0x00 );
+    }
+
+    append_checksums( &mut patch, input, input );
+    assert_eq!( format!( "{}", apply_patch( input, &patch ).unwrap_err() ), "Unable to load ROM - invalid UPS patch: offset out of bounds" );
+}
@@ -1,6 +1,7 @@
 use core::mem;
 use core::default::Default;
 use core::slice;
+use alloc::vec::Vec;
 
 use emumisc::{WrappingExtra, BitExtra, HiLoAccess, PeekPoke, At, is_b5_set, is_b6_set, is_b7_set, reverse_bits};
 
@@ -9,10 +10,20 @@ pub trait Context: Sized {
     fn state( &self ) -> &State;
 
     fn on_cycle( &mut self ) {}
+    fn on_sprite_zero_hit( &mut self ) {}
+    // Called at the very start of every scanline, where 0...239 are the visible
+    // scanlines and 261 is the prerender scanline.
+    fn on_scanline( &mut self, _scanline: u16 ) {}
     fn on_frame_was_generated( &mut self );
     fn set_vblank_nmi( &mut self, value: bool );
     fn peek_video_memory( &self, offset: u16 ) -> u8;
     fn poke_video_memory( &mut self, offset: u16, value: u8 );
+
+    // Called for the reads the PPU actually does on its bus, which
+    // some of the mappers watch; `peek_video_memory` is side effect free.
+    fn fetch_video_memory( &mut self, offset: u16 ) -> u8 {
+        self.peek_video_memory( offset )
+    }
 }
 
 pub trait Interface: Sized + Context {
@@ -113,6 +124,9 @@ pub struct State {
     bg_palette_index_hi_shift_register: u16,
 
     sprites: [Sprite; 8],
+    extra_sprites: [ExtraSprite; 56],
+    extra_sprite_count: u8,
+    rendering_options: RenderingOptions,
 
     sprite_list_address: u8,
     ppudata_read_buffer: u8,
@@ -178,6 +192,9 @@ impl State {
             bg_palette_index_hi_shift_register: 0,
 
             sprites: [Sprite::new(); 8],
+            extra_sprites: [ExtraSprite::new(); 56],
+            extra_sprite_count: 0,
+            rendering_options: RenderingOptions::new(),
 
             sprite_list_address: 0,
             ppudata_read_buffer: 0,
@@ -221,6 +238,66 @@ impl State {
     pub fn framebuffer( &mut self ) -> &mut Framebuffer {
         self.framebuffer.get_or_insert_with( Framebuffer::default )
     }
+
+    #[inline]
+    pub fn rendering_options( &self ) -> &RenderingOptions {
+        &self.rendering_options
+    }
+
+    #[inline]
+    pub fn set_rendering_options( &mut self, options: RenderingOptions ) {
+        self.rendering_options = options;
+    }
+
+    #[inline]
+    pub fn current_address( &self ) -> u16 {
+        self.current_address
+    }
+
+    #[inline]
+    pub fn temporary_address( &self ) -> u16 {
+        self.temporary_address
+    }
+
+    #[inline]
+    pub fn fine_horizontal_scroll( &self ) -> u8 {
+        self.fine_horizontal_scroll
+    }
+
+    #[inline]
+    pub fn scanline( &self ) -> u16 {
+        self.n_scanline
+    }
+
+    #[inline]
+    pub fn dot( &self ) -> u16 {
+        self.n_dot
+    }
+
+    #[inline]
+    pub fn palette_ram( &self ) -> &[u8; 32] {
+        &self.palette_ram
+    }
+
+    #[inline]
+    pub fn sprite_list_ram( &self ) -> &[u8; 256] {
+        &self.sprite_list_ram
+    }
+
+    #[inline]
+    pub fn background_pattern_table_address( &self ) -> u16 {
+        self.ppuctrl.background_pattern_table_address()
+    }
+
+    #[inline]
+    pub fn sprite_pattern_table_address( &self ) -> u16 {
+        self.ppuctrl.sprite_pattern_table_address()
+    }
+
+    #[inline]
+    pub fn uses_big_sprites( &self ) -> bool {
+        self.ppuctrl.big_sprite_mode()
+    }
 }
 
 #[derive(Copy, Clone, PartialEq, Eq)]
@@ -486,6 +563,46 @@ impl Framebuffer {
             *out = palette.get_packed_abgr( pixel.full_color_index() );
         }
     }
+
+    // Every pixel is converted into three bytes in R, G, B order.
+    pub fn convert_to_rgb24< T: AsMut< [u8] >>( &self, palette: &Palette, mut output: T ) {
+        self.convert_to_bytes( palette, output.as_mut(), 3, |(r, g, b), out| {
+            out.copy_from_slice( &[r, g, b] );
+        });
+    }
+
+    // Every pixel is converted into four bytes in R, G, B, A order.
+    pub fn convert_to_rgba< T: AsMut< [u8] >>( &self, palette: &Palette, mut output: T ) {
+        self.convert_to_bytes( palette, output.as_mut(), 4, |(r, g, b), out| {
+            out.copy_from_slice( &[r, g, b, 0xFF] );
+        });
+    }
+
+    // Every pixel is converted into four bytes in B, G, R, A order.
+    pub fn convert_to_bgra< T: AsMut< [u8] >>( &self, palette: &Palette, mut output: T ) {
+        self.convert_to_bytes( palette, output.as_mut(), 4, |(r, g, b), out| {
+            out.copy_from_slice( &[b, g, r, 0xFF] );
+        });
+    }
+
+    // Every pixel is packed as 0bRRRRRGGGGGGBBBBB.
+    pub fn convert_to_rgb565< T: AsMut< [u16] >>( &self, palette: &Palette, mut output: T ) {
+        let array = output.as_mut();
+        assert_eq!( array.len(), 256 * 240 );
+
+        for (pixel, out) in self.iter().zip( array.iter_mut() ) {
+            let (r, g, b) = palette.get_rgb( pixel.full_color_index() );
+            *out = ((r as u16 >> 3) << 11) | ((g as u16 >> 2) << 5) | (b as u16 >> 3);
+        }
+    }
+
+    fn convert_to_bytes< F: Fn( (u8, u8, u8), &mut [u8] ) >( &self, palette: &Palette, array: &mut [u8], bytes_per_pixel: usize, callback: F ) {
+        assert_eq!( array.len(), 256 * 240 * bytes_per_pixel );
+
+        for (pixel, out) in self.iter().zip( array.chunks_mut( bytes_per_pixel ) ) {
+            callback( palette.get_rgb( pixel.full_color_index() ), out );
+        }
+    }
 }
 
 #[derive(Copy, Clone, PartialEq, Eq)]
@@ -540,6 +657,19 @@ impl Default for Framebuffer {
     }
 }
 
+#[cfg(test)]
+impl Framebuffer {
+    // Fills the framebuffer with full color indexes generated from the pixel's coordinates.
+    pub fn from_fn< F: Fn( usize, usize ) -> u16 >( callback: F ) -> Framebuffer {
+        let mut framebuffer = Framebuffer::default();
+        for (index, pixel) in framebuffer.buffer.iter_mut().enumerate() {
+            *pixel = callback( index % 256, index / 256 ) & 0b111111111;
+        }
+
+        framebuffer
+    }
+}
+
 /*
     The NES doesn't output an RGB signal; it directly outputs analog video signal, hence
     there is a multitude of ways of interpreting the colors it generates.
@@ -636,6 +766,45 @@ impl Palette {
     }
 }
 
+impl Palette {
+    // Loads a palette from the contents of a `.pal` file, which is either
+    // 64 (192 bytes) or 512 (1536 bytes, with the emphasis colors) RGB triplets.
+    pub fn from_pal( data: &[u8] ) -> Option< Palette > {
+        if data.len() != 192 && data.len() != 1536 {
+            return None;
+        }
+
+        Some( Palette::new( data ) )
+    }
+
+    pub fn to_pal( &self, with_emphasis: bool ) -> Vec< u8 > {
+        let count = if with_emphasis { 512 } else { 64 };
+        let mut output = Vec::with_capacity( count * 3 );
+        for index in 0..count {
+            let (r, g, b) = self.get_rgb( index as u16 );
+            output.push( r );
+            output.push( g );
+            output.push( b );
+        }
+
+        output
+    }
+}
+
+#[cfg(feature = "std")]
+impl Palette {
+    pub fn load< P: AsRef< std::path::Path > >( path: P ) -> std::io::Result< Palette > {
+        let data = std::fs::read( path )?;
+        Palette::from_pal( &data ).ok_or_else( || {
+            std::io::Error::new( std::io::ErrorKind::InvalidData, "a palette file must be either 192 or 1536 bytes long" )
+        })
+    }
+
+    pub fn save< P: AsRef< std::path::Path > >( &self, path: P, with_emphasis: bool ) -> std::io::Result< () > {
+        std::fs::write( path, self.to_pal( with_emphasis ) )
+    }
+}
+
 impl Palette {
     pub fn get_default() -> &'static Palette {
         // Source: http://forums.nesdev.com/viewtopic.php?p=150239#p150239
@@ -693,11 +862,65 @@ impl Default for Sprite {
     }
 }
 
+/*
+    Options which only affect what ends up in the framebuffer; the sprite 0 hit
+    flag is always calculated as if these were left at their defaults,
+    so they're invisible to the emulated software.
+*/
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct RenderingOptions {
+    pub show_background: bool,
+    pub show_sprites: bool,
+    // Whenever the leftmost 8 pixels are hidden when requested by PPUMASK.
+    pub clip_leftmost_pixels: bool,
+    // Whenever only the first 8 sprites on a scanline are drawn.
+    pub sprite_limit: bool
+}
+
+impl RenderingOptions {
+    pub const fn new() -> Self {
+        RenderingOptions {
+            show_background: true,
+            show_sprites: true,
+            clip_leftmost_pixels: true,
+            sprite_limit: true
+        }
+    }
+}
+
+impl Default for RenderingOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// A sprite which wouldn't be drawn by the hardware due to the 8 sprites per scanline limit.
+#[derive(Copy, Clone)]
+struct ExtraSprite {
+    x: u8,
+    pattern_lo: u8,
+    pattern_hi: u8,
+    attributes: u8
+}
+
+impl ExtraSprite {
+    #[inline]
+    const fn new() -> Self {
+        ExtraSprite {
+            x: 0,
+            pattern_lo: 0,
+            pattern_hi: 0,
+            attributes: 0
+        }
+    }
+}
+
 ppu_scheduling_logic!();
 
 trait Private: Sized + Context {
     fn fetch( &mut self ) -> u8 {
-        self.peek( self.state().address )
+        let address = self.state().address;
+        self.read( address )
     }
 
     fn sprite_evaluation( &mut self ) {
@@ -1025,8 +1248,8 @@ trait Private: Sized + Context {
         self.state().ppuctrl.background_pattern_table_address() + index as u16 * 16 + 8 + self.local_pixel_coordinate_y() as u16
     }
 
-    fn background_pixel( &self ) -> (u8, u8) {
-        if self.state().ppumask.show_background() && (self.state().ppumask.show_background_in_leftmost_8_pixels() || self.state().n_dot >= 8) {
+    fn background_pixel( &self, clip_leftmost_pixels: bool ) -> (u8, u8) {
+        if self.state().ppumask.show_background() && (self.state().ppumask.show_background_in_leftmost_8_pixels() || clip_leftmost_pixels == false || self.state().n_dot >= 8) {
             let shift = 15 - self.local_pixel_coordinate_x();
             let pattern_lo = (self.state().bg_pattern_lo_shift_register >> shift) & 1; // << (7 - self.local_pixel_coordinate_x())) & 1;
             let pattern_hi = (self.state().bg_pattern_hi_shift_register >> shift) & 1; // (7 - self.local_pixel_coordinate_x())) & 1;
@@ -1042,8 +1265,8 @@ trait Private: Sized + Context {
         }
     }
 
-    fn sprite_pixel( &self ) -> (u8, u8, bool, bool) {
-        if self.state().ppumask.show_sprites() && (self.state().ppumask.show_sprites_in_leftmost_8_pixels() || self.state().n_dot >= 8) {
+    fn sprite_pixel( &self, clip_leftmost_pixels: bool ) -> (u8, u8, bool, bool) {
+        if self.state().ppumask.show_sprites() && (self.state().ppumask.show_sprites_in_leftmost_8_pixels() || clip_leftmost_pixels == false || self.state().n_dot >= 8) {
             for (nth, sprite) in self.state().sprites.iter().enumerate() {
                 if sprite.dots_until_is_displayed != 0 {
                     continue;
@@ -1065,9 +1288,89 @@ trait Private: Sized + Context {
         (0, 0, true, false)
     }
 
+    // Finds the sprites on the current scanline that the hardware would skip.
+    fn evaluate_extra_sprites( &mut self ) {
+        self.state_mut().extra_sprite_count = 0;
+        if self.state().n_scanline == 0 {
+            return;
+        }
+
+        let line = self.state().n_scanline - 1;
+        let height = if self.state().ppuctrl.big_sprite_mode() { 16 } else { 8 };
+        let mut in_range_count = 0;
+        for index in 0..64 {
+            let y = self.state().sprite_list_ram[ index * 4 ] as u16;
+            if line < y || line >= y + height {
+                continue;
+            }
+
+            in_range_count += 1;
+            if in_range_count <= 8 {
+                continue;
+            }
+
+            let tile = self.state().sprite_list_ram[ index * 4 + 1 ];
+            let attributes = self.state().sprite_list_ram[ index * 4 + 2 ];
+            let x = self.state().sprite_list_ram[ index * 4 + 3 ];
+
+            let mut row = line - y;
+            if is_b7_set( attributes ) {
+                row = height - 1 - row;
+            }
+
+            let address = if height == 8 {
+                self.state().ppuctrl.sprite_pattern_table_address() + tile as u16 * 16 + row
+            } else {
+                let pattern_table_address = (tile as u16 & 1) * 0x1000;
+                pattern_table_address + (tile & !1) as u16 * 16 + (row / 8) * 16 + (row & 7)
+            };
+
+            let mut pattern_lo = self.peek_video_memory( address );
+            let mut pattern_hi = self.peek_video_memory( address + 8 );
+            if is_b6_set( attributes ) {
+                pattern_lo = reverse_bits( pattern_lo );
+                pattern_hi = reverse_bits( pattern_hi );
+            }
+
+            let count = self.state().extra_sprite_count;
+            self.state_mut().extra_sprites[ count as usize ] = ExtraSprite {
+                x: x,
+                pattern_lo: pattern_lo,
+                pattern_hi: pattern_hi,
+                attributes: attributes
+            };
+            self.state_mut().extra_sprite_count += 1;
+        }
+    }
+
+    fn extra_sprite_pixel( &self, clip_leftmost_pixels: bool ) -> (u8, u8, bool, bool) {
+        if self.state().ppumask.show_sprites() && (self.state().ppumask.show_sprites_in_leftmost_8_pixels() || clip_leftmost_pixels == false || self.state().n_dot >= 8) {
+            let dot = self.state().n_dot;
+            for sprite in &self.state().extra_sprites[ ..self.state().extra_sprite_count as usize ] {
+                if dot < sprite.x as u16 || dot >= sprite.x as u16 + 8 {
+                    continue;
+                }
+
+                let shift = 7 - (dot - sprite.x as u16);
+                let pattern_lo = (sprite.pattern_lo >> shift) & 1;
+                let pattern_hi = (sprite.pattern_hi >> shift) & 1;
+                if pattern_lo == 0 && pattern_hi == 0 {
+                    continue;
+                }
+
+                let color_in_palette_index = pattern_lo | (pattern_hi << 1);
+                let palette_index = 4 + (sprite.attributes & 0b11);
+
+                return (palette_index, color_in_palette_index, is_b5_set( sprite.attributes ), false);
+            }
+        }
+
+        (0, 0, true, false)
+    }
+
     fn pixel( &mut self ) -> (u8, u8) {
-        let (background_palette_index, background_color_index) = self.background_pixel();
-        let (sprite_palette_index, sprite_color_index, display_sprite_behind_background, is_sprite_zero) = self.sprite_pixel();
+        let (background_palette_index, background_color_index) = self.background_pixel( true );
+        let (sprite_palette_index, sprite_color_index, display_sprite_behind_background, is_sprite_zero) = self.sprite_pixel( true );
 
         // Since sprite 0 hit doesn't happen on dot 255 I'm also assuming
         // that we're not supposed to draw any sprites that have their
@@ -1075,12 +1378,64 @@ trait Private: Sized + Context {
         // usually get this ugly vertical line there since the sprites'
         // position is cleared to be 0xFF by default, so any unused sprites
         // end up on dot 255.
-        if self.state().n_dot == 255 || self.state().n_scanline == 0 {
-            return (background_palette_index, background_color_index);
+        if self.state().n_dot != 255 && self.state().n_scanline != 0 {
+            if self.state().first_sprite_is_sprite_zero_on_current_scanline && sprite_color_index != 0 && background_color_index != 0 && is_sprite_zero {
+                if self.state().ppustatus.sprite_0_hit() == false {
+                    self.state_mut().ppustatus.modify_sprite_0_hit( true );
+                    self.on_sprite_zero_hit();
+                }
+            }
+        }
+
+        let background = (background_palette_index, background_color_index);
+        let sprite = (sprite_palette_index, sprite_color_index, display_sprite_behind_background);
+
+        let options = self.state().rendering_options;
+        if options == RenderingOptions::new() {
+            return self.compose_pixel( background, sprite );
         }
 
-        if self.state().first_sprite_is_sprite_zero_on_current_scanline && sprite_color_index != 0 && background_color_index != 0 && is_sprite_zero {
-            self.state_mut().ppustatus.modify_sprite_0_hit( true );
+        // Everything below affects only what we display, so it must be done after the sprite 0 hit check.
+        if options.sprite_limit == false && self.state().n_dot == 0 {
+            self.evaluate_extra_sprites();
+        }
+
+        let background = if options.show_background == false {
+            (0, 0)
+        } else if options.clip_leftmost_pixels == false {
+            self.background_pixel( false )
+        } else {
+            background
+        };
+
+        let sprite = if options.show_sprites == false {
+            (0, 0, true)
+        } else {
+            let (mut palette_index, mut color_index, mut behind_background, _) = if options.clip_leftmost_pixels == false {
+                self.sprite_pixel( false )
+            } else {
+                (sprite_palette_index, sprite_color_index, display_sprite_behind_background, is_sprite_zero)
+            };
+
+            if color_index == 0 && options.sprite_limit == false {
+                let (extra_palette_index, extra_color_index, extra_behind_background, _) = self.extra_sprite_pixel( options.clip_leftmost_pixels );
+                palette_index = extra_palette_index;
+                color_index = extra_color_index;
+                behind_background = extra_behind_background;
+            }
+
+            (palette_index, color_index, behind_background)
+        };
+
+        self.compose_pixel( background, sprite )
+    }
+
+    fn compose_pixel( &self, background: (u8, u8), sprite: (u8, u8, bool) ) -> (u8, u8) {
+        let (background_palette_index, background_color_index) = background;
+        let (sprite_palette_index, sprite_color_index, display_sprite_behind_background) = sprite;
+
+        if self.state().n_dot == 255 || self.state().n_scanline == 0 {
+            return (background_palette_index, background_color_index);
         }
 
         match (background_color_index, sprite_color_index, display_sprite_behind_background) {
@@ -1123,14 +1478,24 @@ trait Private: Sized + Context {
     fn execute( &mut self ) {
         self.state_mut().framebuffer(); // Make sure the framebuffer is initialized.
 
-        self.execute_next_action();
+        self.execute_next_action_and_track_scanline();
         self.on_cycle();
 
         if self.state().skip_next_cycle {
             // Technically we're supposed to skip the next cycle,
             // but we might just as well execute it.
             self.state_mut().skip_next_cycle = false;
-            self.execute_next_action();
+            self.execute_next_action_and_track_scanline();
+        }
+    }
+
+    #[inline]
+    fn execute_next_action_and_track_scanline( &mut self ) {
+        let scanline = self.state().n_scanline;
+        self.execute_next_action();
+        if self.state().n_scanline != scanline {
+            let scanline = self.state().n_scanline;
+            self.on_scanline( scanline );
         }
     }
 
@@ -1250,7 +1615,7 @@ trait Private: Sized + Context {
 
     fn peek_ppudata( &mut self ) -> u8 {
         let address = self.state().current_address & 0x3FFF;
-        let mut value = self.peek( address );
+        let mut value = self.read( address );
 
         self.increment_current_address();
         if address <= 0x3EFF {
@@ -1319,6 +1684,15 @@ trait Private: Sized + Context {
         }
     }
 
+    // Same as `peek`, except the read is visible to the cartridge.
+    fn read( &mut self, address: u16 ) -> u8 {
+        if address <= 0x3EFF {
+            self.fetch_video_memory( address )
+        } else {
+            self.peek( address )
+        }
+    }
+
     fn poke( &mut self, mut address: u16, mut value: u8 ) {
         if address <= 0x3EFF {
             self.poke_video_memory( address, value );
@@ -1346,7 +1720,7 @@ trait Private: Sized + Context {
 
 #[cfg(test)]
 mod tests {
-    use super::{State, Context, Private};
+    use super::{State, Context, Private, RenderingOptions, Framebuffer, Palette};
 
     struct DummyPPU {
         cycle: usize,
@@ -1450,6 +1824,71 @@ mod tests {
         assert_eq!( ppu.nmi_cleared_cycle.unwrap() - ppu.nmi_set_cycle.unwrap(), 6820 );
     }
 
+    #[test]
+    fn on_scanline() {
+        struct TestPPU {
+            scanlines: Vec< u16 >,
+            state: State
+        }
+
+        impl Context for TestPPU {
+            fn state_mut( &mut self ) -> &mut State {
+                &mut self.state
+            }
+
+            fn state( &self ) -> &State {
+                &self.state
+            }
+
+            fn on_scanline( &mut self, scanline: u16 ) {
+                assert_eq!( self.state.dot(), 0 );
+                self.scanlines.push( scanline );
+            }
+
+            fn on_frame_was_generated( &mut self ) {}
+            fn set_vblank_nmi( &mut self, _: bool ) {}
+            fn peek_video_memory( &self, _: u16 ) -> u8 { 0 }
+            fn poke_video_memory( &mut self, _: u16, _: u8 ) {}
+        }
+
+        let mut ppu = TestPPU {
+            scanlines: Vec::new(),
+            state: State::new()
+        };
+
+        for _ in 0..(341 * 262) {
+            ppu.execute();
+        }
+
+        let expected: Vec< u16 > = (0..262).collect();
+        assert_eq!( ppu.scanlines, expected );
+    }
+
+    #[test]
+    fn framebuffer_conversions() {
+        let framebuffer = Framebuffer::default();
+        let palette = Palette::default();
+        let (r, g, b) = palette.get_rgb( 0 );
+
+        let mut rgb24 = vec![ 0; 256 * 240 * 3 ];
+        framebuffer.convert_to_rgb24( &palette, &mut rgb24 );
+        assert_eq!( &rgb24[ rgb24.len() - 3.. ], &[r, g, b] );
+
+        let mut rgba = vec![ 0; 256 * 240 * 4 ];
+        framebuffer.convert_to_rgba( &palette, &mut rgba );
+        assert_eq!( &rgba[ rgba.len() - 4.. ], &[r, g, b, 0xFF] );
+
+        let mut bgra = vec![ 0; 256 * 240 * 4 ];
+        framebuffer.convert_to_bgra( &palette, &mut bgra );
+        assert_eq!( &bgra[ bgra.len() - 4.. ], &[b, g, r, 0xFF] );
+
+        let mut rgb565 = vec![ 0; 256 * 240 ];
+        framebuffer.convert_to_rgb565( &palette, &mut rgb565 );
+        assert_eq!( rgb565[ 0 ] >> 11, r as u16 >> 3 );
+        assert_eq!( (rgb565[ 0 ] >> 5) & 0x3F, g as u16 >> 2 );
+        assert_eq!( rgb565[ 0 ] & 0x1F, b as u16 >> 3 );
+    }
+
     #[test]
     fn poke_ppuaddr_once() {
         let mut ppu = DummyPPU::new();
@@ -1690,6 +2129,75 @@ mod tests {
         assert_eq!( ppu.peek( 0x3F00 + 30 ), 30 );
         assert_eq!( ppu.peek( 0x3F00 + 31 ), 31 );
     }
+
+    // Ten opaque sprites on the same scanline over a transparent background.
+    fn setup_ten_sprites_on_a_scanline( ppu: &mut DummyPPU ) {
+        for address in 0x1000..0x2000 {
+            ppu.memory[ address ] = 0xFF;
+        }
+
+        ppu.poke( 0x3F00, 0x0F );
+        ppu.poke( 0x3F13, 0x30 );
+
+        for index in 0..64 {
+            ppu.state.sprite_list_ram[ index * 4 ] = 0xFF;
+        }
+
+        for index in 0..10 {
+            ppu.state.sprite_list_ram[ index * 4 + 0 ] = 10;
+            ppu.state.sprite_list_ram[ index * 4 + 1 ] = 0;
+            ppu.state.sprite_list_ram[ index * 4 + 2 ] = 0;
+            ppu.state.sprite_list_ram[ index * 4 + 3 ] = index as u8 * 8;
+        }
+
+        ppu.poke_ppuctrl( 0b00001000 );
+        ppu.poke_ppumask( 0b00011110 );
+    }
+
+    fn render_two_frames( ppu: &mut DummyPPU ) -> Vec< u8 > {
+        for _ in 0..(341 * 262 * 2) {
+            ppu.execute();
+        }
+
+        ppu.state.framebuffer().iter().map( |pixel| pixel.base_color_index() ).collect()
+    }
+
+    #[test]
+    fn sprite_limit() {
+        let mut ppu = DummyPPU::new();
+        setup_ten_sprites_on_a_scanline( &mut ppu );
+        let pixels = render_two_frames( &mut ppu );
+        assert_eq!( pixels[ 12 * 256 + 4 ], 0x30 );
+        assert_eq!( pixels[ 12 * 256 + 8 * 7 + 4 ], 0x30 );
+        assert_eq!( pixels[ 12 * 256 + 8 * 8 + 4 ], 0x0F );
+        assert_eq!( pixels[ 12 * 256 + 8 * 9 + 4 ], 0x0F );
+
+        let mut ppu = DummyPPU::new();
+        setup_ten_sprites_on_a_scanline( &mut ppu );
+        ppu.state.set_rendering_options( RenderingOptions {
+            sprite_limit: false,
+            .. RenderingOptions::new()
+        });
+
+        let pixels = render_two_frames( &mut ppu );
+        assert_eq!( pixels[ 12 * 256 + 4 ], 0x30 );
+        assert_eq!( pixels[ 12 * 256 + 8 * 8 + 4 ], 0x30 );
+        assert_eq!( pixels[ 12 * 256 + 8 * 9 + 4 ], 0x30 );
+        assert_eq!( pixels[ 12 * 256 + 8 * 10 + 4 ], 0x0F );
+    }
+
+    #[test]
+    fn hidden_sprites() {
+        let mut ppu = DummyPPU::new();
+        setup_ten_sprites_on_a_scanline( &mut ppu );
+        ppu.state.set_rendering_options( RenderingOptions {
+            show_sprites: false,
+            .. RenderingOptions::new()
+        });
+
+        let pixels = render_two_frames( &mut ppu );
+        assert!( pixels.iter().all( |&pixel| pixel == 0x0F ) );
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,290 @@
+/*
+    Software upscalers for pixel art.
+
+    They operate on images packed the same way as the output of
+    `Framebuffer::convert_to_abgr`, although since the red and blue
+    channels are always treated the same they work just as well
+    with ARGB. The alpha channel is passed through unmodified.
+*/
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Scaler {
+    // The AdvMAME2x/EPX algorithm; scales by 2x and keeps the colors intact.
+    Scale2x,
+    // The AdvMAME3x algorithm; scales by 3x and keeps the colors intact.
+    Scale3x,
+    // A simplified version of the 2xBR algorithm; scales by 2x and smooths out diagonal edges.
+    Xbr2x,
+    // Scales by 2x with every other line darkened to emulate a CRT TV.
+    CrtScanlines
+}
+
+#[inline]
+fn blend( a: u32, b: u32 ) -> u32 {
+    (a & b) + (((a ^ b) & 0xFEFEFEFE) >> 1)
+}
+
+#[inline]
+fn darken( pixel: u32 ) -> u32 {
+    (pixel & 0xFF000000) | ((pixel >> 1) & 0x007F7F7F)
+}
+
+// A perceptual-ish distance between two colors where the green channel is weighted twice as much.
+#[inline]
+fn distance( a: u32, b: u32 ) -> u32 {
+    let channel = |shift: u32| ((a >> shift) & 0xFF).max( (b >> shift) & 0xFF ) - ((a >> shift) & 0xFF).min( (b >> shift) & 0xFF );
+    channel( 0 ) + channel( 8 ) * 2 + channel( 16 )
+}
+
+struct Image< 'a > {
+    pixels: &'a [u32],
+    width: usize,
+    height: usize
+}
+
+impl< 'a > Image< 'a > {
+    // Pixels outside of the image are clamped to the edge.
+    #[inline]
+    fn get( &self, x: isize, y: isize ) -> u32 {
+        let x = x.max( 0 ).min( self.width as isize - 1 ) as usize;
+        let y = y.max( 0 ).min( self.height as isize - 1 ) as usize;
+        self.pixels[ y * self.width + x ]
+    }
+}
+
+impl Scaler {
+    pub fn from_name( name: &str ) -> Option< Scaler > {
+        match name {
+            "scale2x" => Some( Scaler::Scale2x ),
+            "scale3x" => Some( Scaler::Scale3x ),
+            "xbr" => Some( Scaler::Xbr2x ),
+            "crt" => Some( Scaler::CrtScanlines ),
+            _ => None
+        }
+    }
+
+    pub fn factor( &self ) -> usize {
+        match *self {
+            Scaler::Scale3x => 3,
+            Scaler::Scale2x | Scaler::Xbr2x | Scaler::CrtScanlines => 2
+        }
+    }
+
+    pub fn output_size( &self, width: usize, height: usize ) -> (usize, usize) {
+        (width * self.factor(), height * self.factor())
+    }
+
+    // Scales an image of `width` x `height` pixels into an image of `output_size( width, height )` pixels.
+    pub fn apply< T: AsMut< [u32] >>( &self, input: &[u32], width: usize, height: usize, mut output: T ) {
+        let output = output.as_mut();
+        let (output_width, output_height) = self.output_size( width, height );
+        assert_eq!( input.len(), width * height );
+        assert_eq!( output.len(), output_width * output_height );
+
+        let image = Image {
+            pixels: input,
+            width: width,
+            height: height
+        };
+
+        match *self {
+            Scaler::Scale2x => scale2x( &image, output ),
+            Scaler::Scale3x => scale3x( &image, output ),
+            Scaler::Xbr2x => xbr2x( &image, output ),
+            Scaler::CrtScanlines => crt_scanlines( &image, output )
+        }
+    }
+}
+
+fn scale2x( image: &Image, output: &mut [u32] ) {
+    let stride = image.width * 2;
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let (sx, sy) = (x as isize, y as isize);
+            let b = image.get( sx, sy - 1 );
+            let d = image.get( sx - 1, sy );
+            let e = image.get( sx, sy );
+            let f = image.get( sx + 1, sy );
+            let h = image.get( sx, sy + 1 );
+
+            let (e0, e1, e2, e3) = if b != h && d != f {
+                (
+                    if d == b { d } else { e },
+                    if b == f { f } else { e },
+                    if d == h { d } else { e },
+                    if h == f { f } else { e }
+                )
+            } else {
+                (e, e, e, e)
+            };
+
+            let index = y * 2 * stride + x * 2;
+            output[ index ] = e0;
+            output[ index + 1 ] = e1;
+            output[ index + stride ] = e2;
+            output[ index + stride + 1 ] = e3;
+        }
+    }
+}
+
+fn scale3x( image: &Image, output: &mut [u32] ) {
+    let stride = image.width * 3;
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let (sx, sy) = (x as isize, y as isize);
+            let a = image.get( sx - 1, sy - 1 );
+            let b = image.get( sx, sy - 1 );
+            let c = image.get( sx + 1, sy - 1 );
+            let d = image.get( sx - 1, sy );
+            let e = image.get( sx, sy );
+            let f = image.get( sx + 1, sy );
+            let g = image.get( sx - 1, sy + 1 );
+            let h = image.get( sx, sy + 1 );
+            let i = image.get( sx + 1, sy + 1 );
+
+            let mut block = [e; 9];
+            if b != h && d != f {
+                block[ 0 ] = if d == b { d } else { e };
+                block[ 1 ] = if (d == b && e != c) || (b == f && e != a) { b } else { e };
+                block[ 2 ] = if b == f { f } else { e };
+                block[ 3 ] = if (d == b && e != g) || (d == h && e != a) { d } else { e };
+                block[ 5 ] = if (b == f && e != i) || (h == f && e != c) { f } else { e };
+                block[ 6 ] = if d == h { d } else { e };
+                block[ 7 ] = if (d == h && e != i) || (h == f && e != g) { h } else { e };
+                block[ 8 ] = if h == f { f } else { e };
+            }
+
+            let index = y * 3 * stride + x * 3;
+            for row in 0..3 {
+                output[ index + row * stride..index + row * stride + 3 ].copy_from_slice( &block[ row * 3..row * 3 + 3 ] );
+            }
+        }
+    }
+}
+
+/*
+    Every output pixel is processed as if it was the bottom right one,
+    with the neighbourhood mirrored horizontally and/or vertically
+    for the other three. Named after the original algorithm:
+
+           B  C
+        D  E  F  F4
+        G  H  I  I4
+              H5 I5
+*/
+fn xbr2x( image: &Image, output: &mut [u32] ) {
+    let stride = image.width * 2;
+    for y in 0..image.height {
+        for x in 0..image.width {
+            for &(mx, my) in &[(-1, -1), (1, -1), (-1, 1), (1, 1)] {
+                let get = |dx: isize, dy: isize| image.get( x as isize + dx * mx, y as isize + dy * my );
+                let e = get( 0, 0 );
+                let f = get( 1, 0 );
+                let h = get( 0, 1 );
+                let i = get( 1, 1 );
+
+                let mut pixel = e;
+                if e != f && e != h {
+                    let b = get( 0, -1 );
+                    let c = get( 1, -1 );
+                    let d = get( -1, 0 );
+                    let g = get( -1, 1 );
+                    let f4 = get( 2, 0 );
+                    let i4 = get( 2, 1 );
+                    let h5 = get( 0, 2 );
+                    let i5 = get( 1, 2 );
+
+                    let weight_along_edge = distance( e, c ) + distance( e, g ) + distance( i, f4 ) + distance( i, h5 ) + 4 * distance( h, f );
+                    let weight_across_edge = distance( h, d ) + distance( h, i5 ) + distance( f, i4 ) + distance( f, b ) + 4 * distance( e, i );
+                    if weight_along_edge < weight_across_edge {
+                        let closer = if distance( e, f ) <= distance( e, h ) { f } else { h };
+                        pixel = blend( e, closer );
+                    }
+                }
+
+                let ox = x * 2 + if mx > 0 { 1 } else { 0 };
+                let oy = y * 2 + if my > 0 { 1 } else { 0 };
+                output[ oy * stride + ox ] = pixel;
+            }
+        }
+    }
+}
+
+fn crt_scanlines( image: &Image, output: &mut [u32] ) {
+    let stride = image.width * 2;
+    for y in 0..image.height {
+        let line = &mut output[ y * 2 * stride..(y * 2 + 1) * stride ];
+        for x in 0..image.width {
+            let pixel = image.get( x as isize, y as isize );
+            line[ x * 2 ] = pixel;
+            line[ x * 2 + 1 ] = blend( pixel, image.get( x as isize + 1, y as isize ) );
+        }
+    }
+
+    // The gaps between the scanlines.
+    for y in 0..image.height {
+        let next_y = if y + 1 < image.height { y + 1 } else { y };
+        for x in 0..stride {
+            let above = output[ y * 2 * stride + x ];
+            let below = output[ next_y * 2 * stride + x ];
+            output[ (y * 2 + 1) * stride + x ] = darken( blend( above, below ) );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Scaler;
+
+    const SCALERS: [Scaler; 4] = [Scaler::Scale2x, Scaler::Scale3x, Scaler::Xbr2x, Scaler::CrtScanlines];
+
+    fn scale( scaler: Scaler, input: &[u32], width: usize, height: usize ) -> Vec< u32 > {
+        let (output_width, output_height) = scaler.output_size( width, height );
+        let mut output = vec![ 0; output_width * output_height ];
+        scaler.apply( input, width, height, &mut output );
+        output
+    }
+
+    #[test]
+    fn flat_image_stays_flat() {
+        let input = vec![ 0xFF123456; 16 * 8 ];
+        for &scaler in &[Scaler::Scale2x, Scaler::Scale3x, Scaler::Xbr2x] {
+            assert!( scale( scaler, &input, 16, 8 ).iter().all( |&pixel| pixel == 0xFF123456 ) );
+        }
+
+        let output = scale( Scaler::CrtScanlines, &input, 16, 8 );
+        assert!( output[ ..32 ].iter().all( |&pixel| pixel == 0xFF123456 ) );
+        assert!( output[ 32..64 ].iter().all( |&pixel| pixel == 0xFF091A2B ) );
+    }
+
+    #[test]
+    fn scale2x_diagonal() {
+        const W: u32 = 0xFFFFFFFF;
+        const K: u32 = 0xFF000000;
+
+        // A diagonal line from the bottom left to the top right.
+        let input = [
+            K, K, W,
+            K, W, K,
+            W, K, K
+        ];
+
+        let output = scale( Scaler::Scale2x, &input, 3, 3 );
+
+        // The bottom right corner of the top middle pixel is filled in to smooth the line.
+        assert_eq!( &output[ 2..4 ], &[K, K] );
+        assert_eq!( &output[ 6 + 2..6 + 4 ], &[K, W] );
+    }
+
+    #[test]
+    fn output_sizes() {
+        for &scaler in &SCALERS {
+            let (width, height) = scaler.output_size( 256, 240 );
+            assert_eq!( (width, height), (256 * scaler.factor(), 240 * scaler.factor()) );
+            assert_eq!( scale( scaler, &vec![ 0; 256 * 240 ], 256, 240 ).len(), width * height );
+        }
+
+        assert_eq!( Scaler::from_name( "scale3x" ), Some( Scaler::Scale3x ) );
+        assert_eq!( Scaler::from_name( "bilinear" ), None );
+    }
+}
@@ -1,5 +1,6 @@
 use std::error::Error;
 use nes_testsuite;
+use audio_recorder::float_to_i16;
 
 mod nes {
     pub use virtual_nes::{State, Interface, Context};
@@ -30,6 +31,11 @@ impl nes::Context for Instance {
     fn on_frame( &mut self ) {
         nes_testsuite::on_frame( self );
     }
+
+    #[inline]
+    fn on_audio_sample( &mut self, sample: f32 ) {
+        nes_testsuite::EmulatorInterface::on_audio_sample( self, float_to_i16( sample ) );
+    }
 }
 
 impl nes_testsuite::EmulatorInterface for Instance {
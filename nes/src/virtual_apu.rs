@@ -11,6 +11,11 @@ pub trait Context: Sized {
     fn on_sample( &mut self, sample: F32 );
     fn set_irq_line( &mut self, state: bool );
     fn activate_dma( &mut self, address: u16 );
+
+    // The output of the cartridge's own sound chip.
+    fn expansion_audio_output( &self ) -> F32 {
+        f32!(0.0)
+    }
 }
 
 pub trait Interface: Sized + Context {
@@ -284,13 +289,13 @@ enum SequencerMode {
 }
 
 // Taken from: http://wiki.nesdev.com/w/index.php/APU_Length_Counter
-static LENGTH_COUNTER_LOOKUP_TABLE: &'static [u8; 32] = &[
+pub static LENGTH_COUNTER_LOOKUP_TABLE: &'static [u8; 32] = &[
     10, 254, 20,  2, 40,  4, 80,  6, 160,  8, 60, 10, 14, 12, 26, 14,
     12,  16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30
 ];
 
 // Taken from: http://wiki.nesdev.com/w/index.php/APU_Pulse
-static SQUARE_CHANNEL_DUTY_TABLE: &'static [[u8; 8]; 4] = &[
+pub static SQUARE_CHANNEL_DUTY_TABLE: &'static [[u8; 8]; 4] = &[
     [0, 1, 0, 0, 0, 0, 0, 0],
     [0, 1, 1, 0, 0, 0, 0, 0],
     [0, 1, 1, 1, 1, 0, 0, 0],
@@ -357,7 +362,7 @@ enum Direction {
     Negative
 }
 
-struct VolumeGenerator {
+pub struct VolumeGenerator {
     is_manually_controlled: bool,
     should_loop: bool,
 
@@ -368,7 +373,7 @@ struct VolumeGenerator {
 }
 
 impl VolumeGenerator {
-    const fn new() -> VolumeGenerator {
+    pub const fn new() -> VolumeGenerator {
         VolumeGenerator {
             is_manually_controlled: false,
             should_loop: false,
@@ -380,17 +385,17 @@ impl VolumeGenerator {
         }
     }
 
-    fn poke_control( &mut self, value: u8 ) {
+    pub fn poke_control( &mut self, value: u8 ) {
         self.period = value.get_bits( 0b00001111 );
         self.is_manually_controlled = is_b4_set( value );
         self.should_loop = is_b5_set( value ); // This is also mapped to the length_counter_disabled.
     }
 
-    fn reset( &mut self ) {
+    pub fn reset( &mut self ) {
         self.reset_on_next_clock = true;
     }
 
-    fn volume( &self ) -> u8 {
+    pub fn volume( &self ) -> u8 {
         let value = if self.is_manually_controlled {
             self.period
         } else {
@@ -401,7 +406,7 @@ impl VolumeGenerator {
         value
     }
 
-    fn clock( &mut self ) {
+    pub fn clock( &mut self ) {
         if self.reset_on_next_clock == true {
             self.reset_on_next_clock = false;
             self.generated_volume = 15;
@@ -979,7 +984,7 @@ trait Private: Sized + Context {
             f32!(159.79) / (f32!(1.0) / ((u8_to_f32( raw_sample_triangle ) / f32!(8227.0)) + (u8_to_f32( raw_sample_noise ) / f32!(12241.0)) + (u8_to_f32( raw_sample_dmc ) / f32!(22638.0))) + f32!(100.0))
         };
 
-        return output_square + output_rest;
+        return output_square + output_rest + self.expansion_audio_output();
     }
 
     fn clock_output( &mut self ) {
@@ -1,12 +1,19 @@
 use core::mem;
 use alloc::boxed::Box;
+use alloc::vec::Vec;
 
 use mos6502;
 use rp2c02;
 use virtual_apu;
 use dma;
-use mappers::{Mapper, MapperNull, create_mapper};
+use mappers::{Mapper, MapperNull, AudioOptions, create_mapper};
+use mapper_info::MapperInfo;
 use rom::{NesRom, LoadError};
+use rom_database::{RomDatabase, RomDatabaseEntry};
+use rom_patch::apply_patch;
+use mapper_fds::MapperFDS;
+use ppu_viewer::PpuViewer;
+use ppu_event_log::{PpuEvent, PpuEventKind};
 use emumisc::{WrappingExtra, PeekPoke, copy_memory};
 use memory_map::{translate_address_ram, translate_address_ioreg_ppu, translate_address_ioreg_other};
 
@@ -43,8 +50,13 @@ pub trait Context: Sized {
 
     fn on_cycle( &mut self ) {}
     fn on_frame( &mut self ) {}
+    // Called at the start of every scanline; see `rp2c02::Context::on_scanline`.
+    fn on_scanline( &mut self, _scanline: u16 ) {}
     fn on_audio_sample( &mut self, _: f32 ) {}
     fn on_audio_frame( &mut self ) {}
+    fn on_apu_register_write( &mut self, _cpu_cycle: u64, _address: u16, _value: u8 ) {}
+    fn on_dmc_sample_fetch( &mut self, _cpu_cycle: u64, _address: u16, _value: u8 ) {}
+    fn on_ppu_event( &mut self, _event: PpuEvent ) {}
 }
 
 pub trait Interface: Sized + Context {
@@ -52,6 +64,79 @@ pub trait Interface: Sized + Context {
         Private::load_rom( self, buffer )
     }
 
+    // Applies the given IPS, UPS or BPS patches, in order, to the ROM file before loading it.
+    fn load_rom_with_patches( &mut self, buffer: &[u8], patches: &[&[u8]] ) -> Result< (), LoadError > {
+        Private::load_rom_with_patches( self, buffer, patches )
+    }
+
+    // Loads a Famicom Disk System disk image; the BIOS has to be supplied by the user.
+    fn load_fds( &mut self, bios: &[u8], disk_image: &[u8] ) -> Result< (), LoadError > {
+        Private::load_fds( self, bios, disk_image )
+    }
+
+    fn disk_side_count( &self ) -> usize {
+        self.state().mapper().disk_side_count()
+    }
+
+    fn disk_side( &self ) -> Option< usize > {
+        self.state().mapper().disk_side()
+    }
+
+    // Switches to the given side of the disk, or ejects it when `None` is given.
+    fn set_disk_side( &mut self, side: Option< usize > ) {
+        self.state_mut().mapper_mut().set_disk_side( side )
+    }
+
+    // Whether the game has written anything to the disk, in which
+    // case the `disk_image` should be saved to keep the changes.
+    fn is_disk_modified( &self ) -> bool {
+        self.state().mapper().is_disk_modified()
+    }
+
+    fn disk_image( &self ) -> Option< Vec< u8 > > {
+        self.state().mapper().disk_image()
+    }
+
+    // Restores the disk image saved from a previous session; should be called right after `load_fds`.
+    fn restore_disk_image( &mut self, data: &[u8] ) -> Result< (), LoadError > {
+        self.state_mut().mapper_mut().restore_disk_image( data )
+    }
+
+    // The same as with the disks, but for the cartridges which
+    // can reprogram their own flash memory to save the progress.
+    fn is_flash_modified( &self ) -> bool {
+        self.state().mapper().is_flash_modified()
+    }
+
+    fn flash_memory( &self ) -> Option< Vec< u8 > > {
+        self.state().mapper().flash_memory()
+    }
+
+    // Restores the flash memory saved from a previous session; should be called right after loading the ROM.
+    fn restore_flash_memory( &mut self, data: &[u8] ) -> Result< (), LoadError > {
+        self.state_mut().mapper_mut().restore_flash_memory( data )
+    }
+
+    // Supplies an external ROM database, which takes precedence over the builtin
+    // one when loading the ROMs; should be called before `load_rom`.
+    fn set_rom_database( &mut self, database: RomDatabase ) {
+        self.state_mut().rom_database = database;
+    }
+
+    // The database entry which was used to correct the header of the currently loaded ROM.
+    fn rom_database_entry( &self ) -> Option< &RomDatabaseEntry > {
+        self.state().rom_database_entry.as_ref()
+    }
+
+    fn mapper_info( &self ) -> MapperInfo {
+        let mapper = self.state().mapper();
+        let mut info = mapper.info();
+        info.irq_line = mapper.irq_line();
+        info.rom_file_offset = self.state().rom_file_offset;
+        info.video_rom_file_offset = self.state().video_rom_file_offset;
+        info
+    }
+
     fn hard_reset( &mut self ) {
         Private::hard_reset( self )
     }
@@ -122,7 +207,7 @@ pub struct State {
     mapper: Option< Box< dyn Mapper > >,
     error: Option< Error >,
     ready: bool,
-    cpu_cycle: u32,
+    cpu_cycle: u64,
     frame_counter: u32,
     full_frame_counter: u32,
     audio_samples_counter: u32,
@@ -130,7 +215,15 @@ pub struct State {
     gamepad_2: Button,
     gamepad_shift_register_1: u8,
     gamepad_shift_register_2: u8,
-    gamepad_shift_register_update: bool
+    gamepad_shift_register_update: bool,
+    apu_irq_line: bool,
+    audio_options: AudioOptions,
+    rom_database: RomDatabase,
+    rom_database_entry: Option< RomDatabaseEntry >,
+    // Parsed on the first use, since the parsing isn't free.
+    builtin_rom_database: Option< RomDatabase >,
+    rom_file_offset: Option< u32 >,
+    video_rom_file_offset: Option< u32 >
 }
 
 impl State {
@@ -153,7 +246,14 @@ impl State {
             gamepad_2: Button::empty(),
             gamepad_shift_register_1: 0,
             gamepad_shift_register_2: 0,
-            gamepad_shift_register_update: false
+            gamepad_shift_register_update: false,
+            apu_irq_line: false,
+            audio_options: AudioOptions::new(),
+            rom_database: RomDatabase::new(),
+            rom_database_entry: None,
+            builtin_rom_database: None,
+            rom_file_offset: None,
+            video_rom_file_offset: None
         }
     }
 
@@ -162,6 +262,50 @@ impl State {
         self.ppu_state.framebuffer()
     }
 
+    #[inline]
+    pub fn ppu_viewer< 'a >( &'a self ) -> PpuViewer< 'a > {
+        PpuViewer::new( &self.ppu_state, self.mapper() )
+    }
+
+    // The PPU's internal `v` register.
+    #[inline]
+    pub fn current_address( &self ) -> u16 {
+        self.ppu_state.current_address()
+    }
+
+    // The PPU's internal `t` register.
+    #[inline]
+    pub fn temporary_address( &self ) -> u16 {
+        self.ppu_state.temporary_address()
+    }
+
+    // The PPU's internal `x` register.
+    #[inline]
+    pub fn fine_horizontal_scroll( &self ) -> u8 {
+        self.ppu_state.fine_horizontal_scroll()
+    }
+
+    #[inline]
+    pub fn rendering_options( &self ) -> &rp2c02::RenderingOptions {
+        self.ppu_state.rendering_options()
+    }
+
+    #[inline]
+    pub fn set_rendering_options( &mut self, options: rp2c02::RenderingOptions ) {
+        self.ppu_state.set_rendering_options( options );
+    }
+
+    #[inline]
+    pub fn audio_options( &self ) -> &AudioOptions {
+        &self.audio_options
+    }
+
+    #[inline]
+    pub fn set_audio_options( &mut self, options: AudioOptions ) {
+        self.audio_options = options;
+        self.mapper_mut().set_audio_options( options );
+    }
+
     #[inline]
     fn mapper( &self ) -> &dyn Mapper {
         self.mapper.as_ref().map( |mapper| &**mapper ).unwrap_or( &self.mapper_null )
@@ -252,8 +396,22 @@ impl< C: Context > rp2c02::Context for Orphan< C > {
         Context::on_frame( self.as_mut() );
     }
 
+    #[inline]
+    fn on_scanline( &mut self, scanline: u16 ) {
+        self.as_mut().state_mut().mapper_mut().on_ppu_scanline( scanline );
+        Context::on_scanline( self.as_mut(), scanline );
+    }
+
+    #[inline]
+    fn on_sprite_zero_hit( &mut self ) {
+        Private::log_ppu_event( self.as_mut(), PpuEventKind::SpriteZeroHit );
+    }
+
     #[inline]
     fn set_vblank_nmi( &mut self, state: bool ) {
+        if state && mos6502::Interface::nmi_latch( self ) == false {
+            Private::log_ppu_event( self.as_mut(), PpuEventKind::Nmi );
+        }
         mos6502::Interface::set_nmi_latch( self, state );
     }
 
@@ -266,6 +424,11 @@ impl< C: Context > rp2c02::Context for Orphan< C > {
     fn poke_video_memory( &mut self, address: u16, value: u8 ) {
         self.as_mut().state_mut().mapper_mut().poke_video_memory( address, value );
     }
+
+    #[inline]
+    fn fetch_video_memory( &mut self, address: u16 ) -> u8 {
+        self.as_mut().state_mut().mapper_mut().fetch_video_memory( address )
+    }
 }
 
 impl< C: Context > virtual_apu::Context for Orphan< C > {
@@ -296,7 +459,13 @@ impl< C: Context > virtual_apu::Context for Orphan< C > {
 
     #[inline]
     fn set_irq_line( &mut self, state: bool ) {
-        mos6502::Interface::set_irq_line( self, state );
+        self.as_mut().state_mut().apu_irq_line = state;
+        Private::update_irq_line( self.as_mut() );
+    }
+
+    #[inline]
+    fn expansion_audio_output( &self ) -> F32 {
+        self.as_ref().state().mapper().audio_output()
     }
 
     #[inline]
@@ -336,7 +505,9 @@ impl< C: Context > dma::Context for Orphan< C > {
     }
 
     #[inline]
-    fn on_delta_modulation_dma_finished( &mut self, value: u8 ) {
+    fn on_delta_modulation_dma_finished( &mut self, address: u16, value: u8 ) {
+        let cpu_cycle = self.as_ref().state().cpu_cycle;
+        Context::on_dmc_sample_fetch( self.as_mut(), cpu_cycle, address, value );
         virtual_apu::Interface::on_delta_modulation_channel_dma_finished( self, value );
     }
 }
@@ -350,37 +521,140 @@ trait Private: Sized + Context {
         Orphan::< Self >::cast_mut( self )
     }
 
+    fn log_ppu_event( &mut self, kind: PpuEventKind ) {
+        let event = PpuEvent {
+            scanline: self.state().ppu_state.scanline(),
+            dot: self.state().ppu_state.dot(),
+            kind: kind
+        };
+
+        Context::on_ppu_event( self, event );
+    }
+
     fn load_rom( &mut self, buffer: &[u8] ) -> Result< (), LoadError > {
         let rom = NesRom::load( buffer )?;
+        let entry = self.find_in_rom_database( &rom );
+        self.load_nes_rom( rom, entry )
+    }
+
+    fn load_rom_with_patches( &mut self, buffer: &[u8], patches: &[&[u8]] ) -> Result< (), LoadError > {
+        let original_rom = NesRom::load( buffer ).ok();
+
+        let mut buffer = buffer.to_vec();
+        for patch in patches {
+            buffer = apply_patch( &buffer, patch )?;
+        }
+
+        let rom = NesRom::load( &buffer )?;
+        let mut entry = self.find_in_rom_database( &rom );
+
+        // The database usually only knows about the original dumps, so unless
+        // the patched ROM is in there too we use the entry of the unpatched one,
+        // but only if the patch hasn't changed the board the game expects.
+        if entry.is_none() {
+            if let Some( original_rom ) = original_rom {
+                if original_rom.mapper == rom.mapper &&
+                   original_rom.rom.len() == rom.rom.len() &&
+                   original_rom.video_rom.len() == rom.video_rom.len()
+                {
+                    entry = self.find_in_rom_database( &original_rom );
+                }
+            }
+        }
+
+        self.load_nes_rom( rom, entry )
+    }
+
+    fn load_nes_rom( &mut self, mut rom: NesRom, entry: Option< RomDatabaseEntry > ) -> Result< (), LoadError > {
+        let rom_file_offset = rom.rom_file_offset();
+        let video_rom_file_offset = rom.video_rom_file_offset();
+        if let Some( ref entry ) = entry {
+            #[cfg(feature = "log")]
+            info!( "Found the ROM in the database: {}", entry.title );
+
+            entry.apply( &mut rom )?;
+        }
 
         #[cfg(feature = "log")]
         info!( "Loaded ROM: {:?}", rom );
 
         let mapper = create_mapper( rom )?;
+        self.insert_mapper( mapper );
+        self.state_mut().rom_database_entry = entry;
+        self.state_mut().rom_file_offset = Some( rom_file_offset );
+        self.state_mut().video_rom_file_offset = video_rom_file_offset;
+
+        Ok(())
+    }
+
+    // The database supplied by the user takes precedence over the builtin one.
+    fn find_in_rom_database( &mut self, rom: &NesRom ) -> Option< RomDatabaseEntry > {
+        if let Some( entry ) = self.state().rom_database.find( rom ) {
+            return Some( entry.clone() );
+        }
+
+        if self.state().builtin_rom_database.is_none() {
+            self.state_mut().builtin_rom_database = Some( RomDatabase::builtin() );
+        }
+
+        self.state().builtin_rom_database.as_ref().and_then( |database| database.find( rom ) ).cloned()
+    }
+
+    fn load_fds( &mut self, bios: &[u8], disk_image: &[u8] ) -> Result< (), LoadError > {
+        let mapper = MapperFDS::new( bios, disk_image )?;
+
+        #[cfg(feature = "log")]
+        info!( "Loaded a FDS disk image with {} side(s)", mapper.disk_side_count() );
+
+        self.insert_mapper( Box::new( mapper ) );
+        self.state_mut().rom_database_entry = None;
+        self.state_mut().rom_file_offset = None;
+        self.state_mut().video_rom_file_offset = None;
+
+        Ok(())
+    }
+
+    fn insert_mapper( &mut self, mut mapper: Box< dyn Mapper > ) {
+        mapper.set_audio_options( self.state().audio_options );
 
         self.state_mut().mapper = Some( mapper );
         self.state_mut().ready = true;
         self.hard_reset();
-
-        Ok(())
     }
 
     fn hard_reset( &mut self ) {
         let mut mapper: Option< Box< dyn Mapper + 'static > > = None;
         mem::swap( &mut mapper, &mut self.state_mut().mapper );
         let ready = self.state().ready;
+        let rendering_options = *self.state().ppu_state.rendering_options();
+        let audio_options = self.state().audio_options;
+        let rom_database = mem::replace( &mut self.state_mut().rom_database, RomDatabase::new() );
+        let rom_database_entry = self.state_mut().rom_database_entry.take();
+        let builtin_rom_database = self.state_mut().builtin_rom_database.take();
+        let rom_file_offset = self.state().rom_file_offset;
+        let video_rom_file_offset = self.state().video_rom_file_offset;
 
         // FIXME: This doesn't reset the mapper.
         *self.state_mut() = State::new();
         self.state_mut().mapper = mapper;
         self.state_mut().ready = ready;
+        self.state_mut().ppu_state.set_rendering_options( rendering_options );
+        self.state_mut().audio_options = audio_options;
+        self.state_mut().rom_database = rom_database;
+        self.state_mut().rom_database_entry = rom_database_entry;
+        self.state_mut().builtin_rom_database = builtin_rom_database;
+        self.state_mut().rom_file_offset = rom_file_offset;
+        self.state_mut().video_rom_file_offset = video_rom_file_offset;
         self.soft_reset();
     }
 
     fn soft_reset( &mut self ) {
         // TODO: This is probably not accurate.
+        let rendering_options = *self.state().ppu_state.rendering_options();
         self.state_mut().ppu_state = rp2c02::State::new();
+        self.state_mut().ppu_state.set_rendering_options( rendering_options );
         self.state_mut().apu_state = virtual_apu::State::new();
+        self.state_mut().apu_irq_line = false;
         self.state_mut().dma_state = dma::State::new();
 
         mos6502::Interface::reset( self.newtype_mut() );
@@ -475,7 +749,8 @@ trait Private: Sized + Context {
             {
                 self.state().ram.peek( translate_address_ram( address ) )
             }, {
-                match translate_address_ioreg_ppu( address ) {
+                let register = translate_address_ioreg_ppu( address );
+                let value = match register {
                     2 => rp2c02::Interface::peek_ppustatus( self.newtype_mut() ),
                     4 => rp2c02::Interface::peek_oamdata( self.newtype_mut() ),
                     7 => rp2c02::Interface::peek_ppudata( self.newtype_mut() ),
@@ -484,9 +759,17 @@ trait Private: Sized + Context {
                         warn!( "Unhandled read from PPU register 0x{:04X}", address );
                         0
                     }
-                }
+                };
+
+                self.log_ppu_event( PpuEventKind::RegisterRead { address: 0x2000 | register, value: value } );
+                value
             }, {
-                match translate_address_ioreg_other( address ) {
+                let register = translate_address_ioreg_other( address );
+                if register == 20 {
+                    self.log_ppu_event( PpuEventKind::RegisterRead { address: 0x4014, value: 0 } );
+                }
+
+                match register {
                      0 |  1 |  2 |  3 |  4 |  5 |  6 |  7 |  8 |  9 |
                     10 | 11 | 12 | 13 | 14 | 15 | 16 | 17 | 18 | 19 |
                     20 => { 0 }, // Write-only or unused.
@@ -497,7 +780,7 @@ trait Private: Sized + Context {
                     _ => unsafe { fast_unreachable!() }
                 }
             }, {
-                self.state().mapper().peek_expansion_rom( address )
+                self.state_mut().mapper_mut().peek_expansion_rom( address )
             }, {
                 self.state().mapper().peek_sram( address )
             }, {
@@ -512,7 +795,11 @@ trait Private: Sized + Context {
             {
                 self.state_mut().ram.poke( translate_address_ram( address ), value );
             }, {
-                match translate_address_ioreg_ppu( address ) {
+                let register = translate_address_ioreg_ppu( address );
+                self.log_ppu_event( PpuEventKind::RegisterWrite { address: 0x2000 | register, value: value } );
+                self.state_mut().mapper_mut().on_ppu_register_write( 0x2000 | register, value );
+
+                match register {
                     0 => rp2c02::Interface::poke_ppuctrl( self.newtype_mut(), value ),
                     1 => rp2c02::Interface::poke_ppumask( self.newtype_mut(), value ),
                     3 => rp2c02::Interface::poke_oamaddr( self.newtype_mut(), value ),
@@ -526,7 +813,17 @@ trait Private: Sized + Context {
                     }
                 }
             }, {
-                match translate_address_ioreg_other( address ) {
+                let register = translate_address_ioreg_other( address );
+                if register <= 23 {
+                    let cpu_cycle = self.state().cpu_cycle;
+                    Context::on_apu_register_write( self, cpu_cycle, 0x4000 | register, value );
+                }
+
+                if register == 20 {
+                    self.log_ppu_event( PpuEventKind::RegisterWrite { address: 0x4014, value: value } );
+                }
+
+                match register {
                      0 => virtual_apu::Interface::poke_square_1_ctrl( self.newtype_mut(), value ),
                      1 => virtual_apu::Interface::poke_square_1_frequency_generator( self.newtype_mut(), value ),
                      2 => virtual_apu::Interface::poke_square_1_period_low( self.newtype_mut(), value ),
@@ -569,7 +866,7 @@ trait Private: Sized + Context {
                     }
                 }
             }, {
-                self.state().mapper().poke_expansion_rom( address, value );
+                self.state_mut().mapper_mut().poke_expansion_rom( address, value );
             }, {
                 self.state_mut().mapper_mut().poke_sram( address, value );
             }, {
@@ -630,11 +927,90 @@ trait Private: Sized + Context {
 
     fn on_cpu_cycle( &mut self ) {
         self.state_mut().cpu_cycle.wrapping_inc();
+        self.state_mut().mapper_mut().on_cpu_cycle();
         virtual_apu::Interface::execute( self.newtype_mut() );
         for _ in 0..3 {
             rp2c02::Interface::execute( self.newtype_mut() );
         }
 
+        self.update_irq_line();
         Context::on_cycle( self );
     }
-}
\ No newline at end of file
+
+    // Both the APU and the mapper can assert the IRQ line.
+    fn update_irq_line( &mut self ) {
+        let state = self.state().apu_irq_line || self.state().mapper().irq_line();
+        if state && mos6502::Interface::irq_line( self.newtype() ) == false {
+            self.log_ppu_event( PpuEventKind::Irq );
+        }
+        mos6502::Interface::set_irq_line( self.newtype_mut(), state );
+    }
+}
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use alloc::format;
+    use super::{State, Context, Interface};
+    use rom_database::RomDatabase;
+    use hashes::crc32;
+
+    struct Instance {
+        state: State
+    }
+
+    impl Context for Instance {
+        fn state_mut( &mut self ) -> &mut State {
+            &mut self.state
+        }
+
+        fn state( &self ) -> &State {
+            &self.state
+        }
+    }
+
+    #[test]
+    fn patched_roms_are_looked_up_in_the_database_before_patching() {
+        let mut data = Vec::new();
+        data.extend_from_slice( b"NES\x1A\x02\x01\x00\x00" );
+        data.resize( 16 + 2 * 16 * 1024 + 8 * 1024, 0 );
+        let prg_crc32 = crc32( &data[ 16..16 + 2 * 16 * 1024 ] );
+
+        let xml = format!( r#"<game><!-- Test Game (USA).nes --><prgrom crc32="{:08X}"/><pcb mapper="2"/></game>"#, prg_crc32 );
+        let mut instance = Instance {
+            state: State::new()
+        };
+
+        instance.set_rom_database( RomDatabase::parse( &xml ).unwrap() );
+
+        // Changes the first byte of the PRG ROM.
+        let patch = b"PATCH\x00\x00\x10\x00\x01\xFFEOF";
+        instance.load_rom_with_patches( &data, &[patch] ).unwrap();
+        assert_eq!( instance.rom_database_entry().unwrap().title, "Test Game (USA)" );
+
+        let info = instance.mapper_info();
+        assert_eq!( info.number, Some( 2 ) );
+        assert_eq!( info.rom_file_offset, Some( 16 ) );
+        assert_eq!( instance.peek_memory( 0x8000 ), 0xFF );
+    }
+
+    #[test]
+    fn patched_roms_with_a_different_board_are_not_matched_with_the_original() {
+        let mut data = Vec::new();
+        data.extend_from_slice( b"NES\x1A\x02\x01\x00\x00" );
+        data.resize( 16 + 2 * 16 * 1024 + 8 * 1024, 0 );
+        let prg_crc32 = crc32( &data[ 16..16 + 2 * 16 * 1024 ] );
+
+        let xml = format!( r#"<game><!-- Test Game (USA).nes --><prgrom crc32="{:08X}"/><pcb mapper="2"/></game>"#, prg_crc32 );
+        let mut instance = Instance {
+            state: State::new()
+        };
+
+        instance.set_rom_database( RomDatabase::parse( &xml ).unwrap() );
+
+        // Changes the mapper in the header to CNROM and the first byte of the PRG ROM.
+        let patch = b"PATCH\x00\x00\x06\x00\x01\x30\x00\x00\x10\x00\x01\xFFEOF";
+        instance.load_rom_with_patches( &data, &[patch] ).unwrap();
+        assert!( instance.rom_database_entry().is_none() );
+        assert_eq!( instance.mapper_info().number, Some( 3 ) );
+    }
+}
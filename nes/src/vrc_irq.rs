@@ -0,0 +1,146 @@
+use emumisc::{is_b0_set, is_b1_set, is_b2_set};
+use mapper_info::MapperInfo;
+
+/*
+    The IRQ counter used by Konami's VRC4, VRC6 and VRC7.
+
+    It's an 8-bit counter which counts up, and when it overflows
+    it's reloaded from the latch and an IRQ is triggered. It's clocked either
+    on every CPU cycle (the cycle mode) or on every scanline (the scanline mode),
+    where the scanlines are approximated with a prescaler which is
+    decremented by 3 on every CPU cycle and reloaded with 341.
+
+    The control register:
+
+        bit 0 - whether to enable the IRQ again after it's acknowledged,
+        bit 1 - IRQ enable,
+        bit 2 - 0 - scanline mode, 1 - cycle mode.
+*/
+
+pub struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+    enabled: bool,
+    enabled_after_acknowledge: bool,
+    is_in_cycle_mode: bool,
+    pending: bool
+}
+
+impl VrcIrq {
+    pub const fn new() -> VrcIrq {
+        VrcIrq {
+            latch: 0,
+            counter: 0,
+            prescaler: 341,
+            enabled: false,
+            enabled_after_acknowledge: false,
+            is_in_cycle_mode: false,
+            pending: false
+        }
+    }
+
+    pub fn latch( &self ) -> u8 {
+        self.latch
+    }
+
+    pub fn poke_latch( &mut self, value: u8 ) {
+        self.latch = value;
+    }
+
+    pub fn poke_control( &mut self, value: u8 ) {
+        self.pending = false;
+        self.enabled_after_acknowledge = is_b0_set( value );
+        self.enabled = is_b1_set( value );
+        self.is_in_cycle_mode = is_b2_set( value );
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = 341;
+        }
+    }
+
+    pub fn acknowledge( &mut self ) {
+        self.pending = false;
+        self.enabled = self.enabled_after_acknowledge;
+    }
+
+    // Should be called on every CPU cycle.
+    pub fn clock( &mut self ) {
+        if self.enabled == false {
+            return;
+        }
+
+        if self.is_in_cycle_mode {
+            self.clock_counter();
+        } else {
+            self.prescaler -= 3;
+            if self.prescaler <= 0 {
+                self.prescaler += 341;
+                self.clock_counter();
+            }
+        }
+    }
+
+    fn clock_counter( &mut self ) {
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+
+    pub fn irq_line( &self ) -> bool {
+        self.pending
+    }
+
+    pub fn describe( &self, info: &mut MapperInfo ) {
+        info.add_register( "IRQ latch", self.latch as u32 );
+        info.add_register( "IRQ counter", self.counter as u32 );
+        info.add_register( "IRQ enabled", self.enabled as u32 );
+        info.add_register( "IRQ cycle mode", self.is_in_cycle_mode as u32 );
+    }
+}
+
+#[test]
+fn test_vrc_irq_cycle_mode() {
+    let mut irq = VrcIrq::new();
+    irq.poke_latch( 0xFD );
+    irq.poke_control( 0b111 );
+    irq.clock();
+    irq.clock();
+    assert!( irq.irq_line() == false );
+    irq.clock();
+    assert!( irq.irq_line() );
+
+    // The counter was reloaded.
+    irq.acknowledge();
+    irq.clock();
+    irq.clock();
+    assert!( irq.irq_line() == false );
+    irq.clock();
+    assert!( irq.irq_line() );
+
+    // The IRQ stays disabled after it's acknowledged.
+    irq.poke_control( 0b110 );
+    irq.acknowledge();
+    for _ in 0..1000 {
+        irq.clock();
+    }
+    assert!( irq.irq_line() == false );
+}
+
+#[test]
+fn test_vrc_irq_scanline_mode() {
+    let mut irq = VrcIrq::new();
+    irq.poke_latch( 0xFE );
+    irq.poke_control( 0b010 );
+
+    // Two scanlines, where every scanline is 113 2/3 CPU cycles long.
+    for _ in 0..227 {
+        irq.clock();
+    }
+    assert!( irq.irq_line() == false );
+    irq.clock();
+    assert!( irq.irq_line() );
+}
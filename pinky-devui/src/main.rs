@@ -14,6 +14,7 @@ extern crate nes;
 mod user_interface;
 mod renderer;
 mod frame_limiter;
+mod ppu_viewer;
 
 fn main() {
     env_logger::init().unwrap();
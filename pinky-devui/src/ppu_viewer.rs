@@ -0,0 +1,124 @@
+use sdl2;
+
+use nes;
+use nes::{Palette, PpuViewer, PpuEventLog, Framebuffer};
+use renderer::{Renderer, Texture, ImageBuffer};
+
+struct ViewerWindow {
+    renderer: Renderer,
+    texture: Texture,
+    image_buffer: ImageBuffer
+}
+
+impl ViewerWindow {
+    fn new( video_context: &sdl2::VideoSubsystem, title: &str, width: usize, height: usize, scale: u32 ) -> ViewerWindow {
+        let (width, height) = (width as u32, height as u32);
+        let window = video_context.window( title, width * scale, height * scale ).opengl().build().unwrap();
+
+        let mut renderer = Renderer::new( window.renderer().build().unwrap() );
+        renderer.set_logical_size( width, height ).unwrap();
+        let texture = Texture::new_streaming( &mut renderer, width, height );
+
+        ViewerWindow {
+            renderer: renderer,
+            texture: texture,
+            image_buffer: ImageBuffer::new( width, height )
+        }
+    }
+
+    fn id( &self ) -> Option< u32 > {
+        self.renderer.window().map( |window| window.id() )
+    }
+
+    fn present( &mut self ) {
+        self.texture.update( &self.image_buffer );
+        self.renderer.clear();
+        self.renderer.blit( &self.texture );
+        self.renderer.present();
+    }
+}
+
+/*
+    A set of windows which show the nametables, the pattern tables,
+    the palette RAM and the sprites, refreshed after every frame.
+*/
+pub struct PpuViewerWindows {
+    nametables: ViewerWindow,
+    pattern_tables: ViewerWindow,
+    palette_ram: ViewerWindow,
+    sprites: ViewerWindow,
+    pattern_table_buffer: Vec< u32 >,
+    pattern_table_palette_index: u8
+}
+
+impl PpuViewerWindows {
+    pub fn new( video_context: &sdl2::VideoSubsystem ) -> PpuViewerWindows {
+        PpuViewerWindows {
+            nametables: ViewerWindow::new( video_context, "Nametables", PpuViewer::NAMETABLES_WIDTH, PpuViewer::NAMETABLES_HEIGHT, 2 ),
+            pattern_tables: ViewerWindow::new( video_context, "Pattern tables", PpuViewer::PATTERN_TABLE_WIDTH * 2, PpuViewer::PATTERN_TABLE_HEIGHT, 3 ),
+            palette_ram: ViewerWindow::new( video_context, "Palette RAM", PpuViewer::PALETTE_RAM_WIDTH, PpuViewer::PALETTE_RAM_HEIGHT, 32 ),
+            sprites: ViewerWindow::new( video_context, "Sprites", PpuViewer::SPRITES_WIDTH, PpuViewer::SPRITES_HEIGHT, 4 ),
+            pattern_table_buffer: vec![ 0; PpuViewer::PATTERN_TABLE_WIDTH * PpuViewer::PATTERN_TABLE_HEIGHT ],
+            pattern_table_palette_index: 0
+        }
+    }
+
+    pub fn owns_window( &self, window_id: u32 ) -> bool {
+        let window_id = Some( window_id );
+        self.nametables.id() == window_id ||
+        self.pattern_tables.id() == window_id ||
+        self.palette_ram.id() == window_id ||
+        self.sprites.id() == window_id
+    }
+
+    // Switches the palette with which the pattern tables are shown.
+    pub fn next_pattern_table_palette( &mut self ) {
+        self.pattern_table_palette_index = (self.pattern_table_palette_index + 1) % 8;
+    }
+
+    pub fn update( &mut self, state: &nes::State, palette: &Palette ) {
+        let viewer = state.ppu_viewer();
+
+        viewer.render_nametables( palette, &mut self.nametables.image_buffer );
+        viewer.render_palette_ram( palette, &mut self.palette_ram.image_buffer );
+        viewer.render_sprites( palette, &mut self.sprites.image_buffer );
+
+        let stride = PpuViewer::PATTERN_TABLE_WIDTH * 2;
+        for table in 0..2 {
+            viewer.render_pattern_table( table, self.pattern_table_palette_index, palette, &mut self.pattern_table_buffer );
+
+            let output = self.pattern_tables.image_buffer.as_mut();
+            let offset = table as usize * PpuViewer::PATTERN_TABLE_WIDTH;
+            for (y, row) in self.pattern_table_buffer.chunks( PpuViewer::PATTERN_TABLE_WIDTH ).enumerate() {
+                output[ y * stride + offset..y * stride + offset + PpuViewer::PATTERN_TABLE_WIDTH ].copy_from_slice( row );
+            }
+        }
+
+        self.nametables.present();
+        self.pattern_tables.present();
+        self.palette_ram.present();
+        self.sprites.present();
+    }
+}
+
+// Shows the PPU events of the last frame, where every pixel corresponds to a single dot.
+pub struct PpuEventWindow {
+    window: ViewerWindow
+}
+
+impl PpuEventWindow {
+    pub fn new( video_context: &sdl2::VideoSubsystem ) -> PpuEventWindow {
+        PpuEventWindow {
+            window: ViewerWindow::new( video_context, "PPU events", PpuEventLog::OVERLAY_WIDTH, PpuEventLog::OVERLAY_HEIGHT, 3 )
+        }
+    }
+
+    pub fn owns_window( &self, window_id: u32 ) -> bool {
+        self.window.id() == Some( window_id )
+    }
+
+    pub fn update( &mut self, log: &PpuEventLog, framebuffer: &Framebuffer, palette: &Palette ) {
+        log.render_overlay( framebuffer, palette, &mut self.window.image_buffer );
+        self.window.present();
+    }
+}
@@ -1,5 +1,8 @@
 use std::env;
+use std::fs::File;
+use std::io::BufWriter;
 use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use sdl2;
 use sdl2::keyboard::Keycode;
@@ -8,9 +11,10 @@ use sdl2::audio::{AudioQueue, AudioSpecDesired};
 use serde_json;
 
 use nes;
-use nes::{Interface, Framebuffer, ControllerPort, Button, Palette};
+use nes::{Interface, Framebuffer, ControllerPort, Button, Palette, PaletteSettings, AudioRecorder, AudioFormat, ApuLogger, NtscFilter, NtscSettings, PpuEventLog, PpuEvent, PpuEventKind, Overscan, Scaler, MapperInfo, BankMapping, MemoryKind};
 use frame_limiter::FrameLimiter;
 use renderer::{Renderer, Texture, ImageBuffer};
+use ppu_viewer::{PpuViewerWindows, PpuEventWindow};
 
 macro_rules! json_object {
     ( $( $key:expr => $value:expr ),* ) => ({
@@ -43,7 +47,9 @@ struct VirtualNES {
     state: nes::State,
     cycle: u64,
     frame: u64,
-    audio_buffer: Vec< f32 >
+    audio_buffer: Vec< f32 >,
+    apu_logger: Option< ApuLogger >,
+    ppu_event_log: Option< PpuEventLog >
 }
 
 impl nes::Context for VirtualNES {
@@ -61,12 +67,91 @@ impl nes::Context for VirtualNES {
 
     fn on_frame( &mut self ) {
         self.frame += 1;
+        if let Some( log ) = self.ppu_event_log.as_mut() {
+            log.finish_frame();
+        }
     }
 
     #[inline]
     fn on_audio_sample( &mut self, sample: f32 ) {
         self.audio_buffer.push( sample );
     }
+
+    #[inline]
+    fn on_apu_register_write( &mut self, cpu_cycle: u64, address: u16, value: u8 ) {
+        if let Some( logger ) = self.apu_logger.as_mut() {
+            logger.log_register_write( cpu_cycle, address, value );
+        }
+    }
+
+    #[inline]
+    fn on_dmc_sample_fetch( &mut self, cpu_cycle: u64, address: u16, value: u8 ) {
+        if let Some( logger ) = self.apu_logger.as_mut() {
+            logger.log_dmc_sample_fetch( cpu_cycle, address, value );
+        }
+    }
+
+    #[inline]
+    fn on_ppu_event( &mut self, event: PpuEvent ) {
+        if let Some( log ) = self.ppu_event_log.as_mut() {
+            log.log( event );
+        }
+    }
+}
+
+fn print_ppu_events( events: &[PpuEvent] ) {
+    for event in events {
+        let description = match event.kind {
+            PpuEventKind::RegisterRead { address, value } => format!( "read  ${:04X} -> ${:02X}", address, value ),
+            PpuEventKind::RegisterWrite { address, value } => format!( "write ${:04X} <- ${:02X}", address, value ),
+            PpuEventKind::Nmi => "NMI".to_owned(),
+            PpuEventKind::Irq => "IRQ".to_owned(),
+            PpuEventKind::SpriteZeroHit => "sprite 0 hit".to_owned()
+        };
+
+        println!( "{:3}:{:3} {}", event.scanline, event.dot, description );
+    }
+}
+
+fn print_mapper_info( info: &MapperInfo ) {
+    fn print_bank( info: &MapperInfo, bank: &BankMapping ) {
+        let file_offset = match bank.memory {
+            Some( MemoryKind::Rom ) => info.rom_file_offset,
+            Some( MemoryKind::VideoMemory ) => info.video_rom_file_offset,
+            _ => None
+        };
+
+        let file_offset = match file_offset {
+            Some( file_offset ) => format!( " (file ${:05X})", file_offset + bank.offset ),
+            None => String::new()
+        };
+
+        match bank.memory {
+            Some( memory ) => println!( "    ${:04X}: {:?} +${:05X}{}{}", bank.address, memory, bank.offset, file_offset, if bank.is_writable { " [w]" } else { "" } ),
+            None => println!( "    ${:04X}: unmapped", bank.address )
+        }
+    }
+
+    match info.number {
+        Some( number ) => println!( "Mapper: {} (#{})", info.name, number ),
+        None => println!( "Mapper: {}", info.name )
+    }
+
+    println!( "CPU banks:" );
+    for bank in &info.cpu_banks {
+        print_bank( info, bank );
+    }
+
+    println!( "PPU banks:" );
+    for bank in &info.ppu_banks {
+        print_bank( info, bank );
+    }
+
+    println!( "Mirroring: {:?}", info.mirroring );
+    println!( "IRQ line: {}", info.irq_line );
+    for &(name, value) in &info.registers {
+        println!( "{}: ${:02X}", name, value );
+    }
 }
 
 fn md5sum< T: AsRef< [u8] > >( data: T ) -> String {
@@ -94,10 +179,19 @@ pub struct UserInterface {
     palette: Palette,
     nes: VirtualNES,
     rom_filename: PathBuf,
+    fds_bios_filename: Option< PathBuf >,
     replaying: bool,
     recording: Vec< Button >,
     recording_position: usize,
     no_limiter: bool,
+    audio_recorder: Option< AudioRecorder< BufWriter< File > > >,
+    audio_recording_format: AudioFormat,
+    ntsc_filter: Option< NtscFilter >,
+    scaler: Option< Scaler >,
+    scaler_input: Vec< u32 >,
+    ppu_viewer: Option< PpuViewerWindows >,
+    ppu_event_window: Option< PpuEventWindow >,
+    screenshot_overscan: Overscan,
 }
 
 impl UserInterface {
@@ -132,13 +226,24 @@ impl UserInterface {
                 cycle: 0,
                 frame: 0,
                 state: nes::State::new(),
-                audio_buffer: Vec::new()
+                audio_buffer: Vec::new(),
+                apu_logger: None,
+                ppu_event_log: None
             },
             rom_filename: PathBuf::new(),
+            fds_bios_filename: None,
             replaying: false,
             recording: Vec::new(),
             recording_position: 0,
             no_limiter: false,
+            audio_recorder: None,
+            audio_recording_format: AudioFormat::Wav16,
+            ntsc_filter: None,
+            scaler: None,
+            scaler_input: Vec::new(),
+            ppu_viewer: None,
+            ppu_event_window: None,
+            screenshot_overscan: Overscan::none(),
         }
 
     }
@@ -175,7 +280,9 @@ impl UserInterface {
             cycle: 0,
             frame: 0,
             state: nes::State::new(),
-            audio_buffer: Vec::new()
+            audio_buffer: Vec::new(),
+            apu_logger: None,
+            ppu_event_log: None
         };
         self.nes.load_rom( &std::fs::read( &self.rom_filename ).unwrap() ).unwrap();
 
@@ -221,20 +328,251 @@ impl UserInterface {
         writeln!( &mut fp, "" ).unwrap();
     }
 
+    fn timestamped_output_path( &self, extension: &str ) -> PathBuf {
+        let timestamp = SystemTime::now().duration_since( UNIX_EPOCH ).map( |duration| duration.as_secs() ).unwrap_or( 0 );
+        let stem = self.rom_filename.file_stem().map( |stem| stem.to_string_lossy().into_owned() ).unwrap_or( "pinky".to_owned() );
+        let mut path = PathBuf::from( format!( "{}-{}", stem, timestamp ) );
+        path.set_extension( extension );
+        path
+    }
+
+    fn save_screenshot( &mut self ) {
+        let framebuffer = match self.last_framebuffer.as_ref() {
+            Some( framebuffer ) => framebuffer,
+            None => return
+        };
+
+        let path = self.timestamped_output_path( "png" );
+        match framebuffer.save_png( &path, &self.palette, &self.screenshot_overscan ) {
+            Ok( _ ) => println!( "Screenshot saved to '{}'", path.display() ),
+            Err( error ) => println!( "Failed to save the screenshot: {}", error )
+        }
+    }
+
+    fn switch_disk_side( &mut self ) {
+        let side_count = self.nes.disk_side_count();
+        if side_count == 0 {
+            return;
+        }
+
+        let side = self.nes.disk_side().map( |side| (side + 1) % side_count ).unwrap_or( 0 );
+        self.nes.set_disk_side( Some( side ) );
+        println!( "Inserted disk side #{}", side + 1 );
+    }
+
+    // The original disk image is left untouched, and the modified one is kept in a separate file next to it.
+    fn disk_filename( &self ) -> PathBuf {
+        self.rom_filename.with_extension( "fds.sav" )
+    }
+
+    fn restore_disk( &mut self ) {
+        let path = self.disk_filename();
+        if self.nes.disk_image().is_none() || path.exists() == false {
+            return;
+        }
+
+        println!( "Loading the modified disk image from '{}'...", path.display() );
+        let data = std::fs::read( &path ).unwrap();
+        if let Err( error ) = self.nes.restore_disk_image( &data ) {
+            println!( "Failed to restore the disk image: {}", error );
+        }
+    }
+
+    fn save_disk( &mut self ) {
+        if self.nes.is_disk_modified() == false {
+            return;
+        }
+
+        let image = match self.nes.disk_image() {
+            Some( image ) => image,
+            None => return
+        };
+
+        let path = self.disk_filename();
+        match std::fs::write( &path, &image ) {
+            Ok( _ ) => println!( "Disk image saved to '{}'", path.display() ),
+            Err( error ) => println!( "Failed to save the disk image: {}", error )
+        }
+    }
+
+    // A patch with the same name as the ROM is applied automatically.
+    fn load_patch( &self ) -> Option< Vec< u8 > > {
+        let path = ["ips", "ups", "bps"].iter()
+            .map( |extension| self.rom_filename.with_extension( extension ) )
+            .find( |path| path.exists() )?;
+
+        println!( "Applying the patch from '{}'...", path.display() );
+        Some( std::fs::read( &path ).unwrap() )
+    }
+
+    // The flash memory of the self-flashing cartridges is kept in a separate file next to the ROM.
+    fn flash_filename( &self ) -> PathBuf {
+        self.rom_filename.with_extension( "flash" )
+    }
+
+    fn restore_flash( &mut self ) {
+        let path = self.flash_filename();
+        if self.nes.flash_memory().is_none() || path.exists() == false {
+            return;
+        }
+
+        println!( "Loading the flash memory from '{}'...", path.display() );
+        let data = std::fs::read( &path ).unwrap();
+        if let Err( error ) = self.nes.restore_flash_memory( &data ) {
+            println!( "Failed to restore the flash memory: {}", error );
+        }
+    }
+
+    fn save_flash( &mut self ) {
+        if self.nes.is_flash_modified() == false {
+            return;
+        }
+
+        let data = match self.nes.flash_memory() {
+            Some( data ) => data,
+            None => return
+        };
+
+        let path = self.flash_filename();
+        match std::fs::write( &path, &data ) {
+            Ok( _ ) => println!( "Flash memory saved to '{}'", path.display() ),
+            Err( error ) => println!( "Failed to save the flash memory: {}", error )
+        }
+    }
+
+    fn toggle_apu_logging( &mut self ) {
+        if let Some( logger ) = self.nes.apu_logger.take() {
+            let path = self.timestamped_output_path( "vgm" );
+            match logger.save_vgm( &path ) {
+                Ok( _ ) => println!( "APU log saved to '{}' ({} register writes)", path.display(), logger.register_writes().len() ),
+                Err( error ) => println!( "Failed to save the APU log: {}", error )
+            }
+
+            return;
+        }
+
+        println!( "Logging APU register writes..." );
+        self.nes.apu_logger = Some( ApuLogger::new() );
+    }
+
+    fn toggle_audio_recording( &mut self ) {
+        if let Some( recorder ) = self.audio_recorder.take() {
+            let sample_count = recorder.sample_count();
+            match recorder.finish() {
+                Ok( _ ) => println!( "Audio recording stopped ({} samples)", sample_count ),
+                Err( error ) => println!( "Failed to finish the audio recording: {}", error )
+            }
+
+            return;
+        }
+
+        let path = self.timestamped_output_path( self.audio_recording_format.extension() );
+        match AudioRecorder::create( &path, self.audio_recording_format, 44100 ) {
+            Ok( recorder ) => {
+                println!( "Recording audio to '{}'...", path.display() );
+                self.audio_recorder = Some( recorder );
+            },
+            Err( error ) => println!( "Failed to start the audio recording: {}", error )
+        }
+    }
+
+    fn toggle_ntsc_filter( &mut self ) {
+        if self.ntsc_filter.is_some() {
+            self.ntsc_filter = None;
+        } else {
+            self.ntsc_filter = Some( NtscFilter::new( NtscSettings::default() ) );
+        }
+
+        self.recreate_texture();
+    }
+
+    // The size of the image before it's scaled.
+    fn source_size( &self ) -> (usize, usize) {
+        if self.ntsc_filter.is_some() {
+            (NtscFilter::OUTPUT_WIDTH, NtscFilter::OUTPUT_HEIGHT)
+        } else {
+            (256, 240)
+        }
+    }
+
+    fn recreate_texture( &mut self ) {
+        let (width, height) = self.source_size();
+        let (width, height) = self.scaler.map( |scaler| scaler.output_size( width, height ) ).unwrap_or( (width, height) );
+
+        self.texture = Texture::new_streaming( &mut self.renderer, width as u32, height as u32 );
+        self.image_buffer = ImageBuffer::new( width as u32, height as u32 );
+        self.texture.update( &self.image_buffer );
+    }
+
+    fn toggle_ppu_viewer( &mut self ) {
+        if self.ppu_viewer.is_some() {
+            self.ppu_viewer = None;
+        } else {
+            let video_context = self.sdl_context.video().unwrap();
+            self.ppu_viewer = Some( PpuViewerWindows::new( &video_context ) );
+        }
+    }
+
+    fn toggle_ppu_event_viewer( &mut self ) {
+        if self.ppu_event_window.is_some() {
+            self.ppu_event_window = None;
+            self.nes.ppu_event_log = None;
+        } else {
+            let video_context = self.sdl_context.video().unwrap();
+            self.ppu_event_window = Some( PpuEventWindow::new( &video_context ) );
+            self.nes.ppu_event_log = Some( PpuEventLog::new() );
+        }
+    }
+
     fn handle_sdl2_event( &mut self, event: sdl2::event::Event ) {
-        use sdl2::event::Event;
+        use sdl2::event::{Event, WindowEvent};
 
         match event {
             Event::Quit {..} | Event::KeyDown { keycode: Some( Keycode::Escape ), .. } => {
                 self.running = false
             },
+            Event::Window { window_id, win_event: WindowEvent::Close, .. } => {
+                // Since we can have more than one window open we won't get
+                // a quit event when the main window is closed.
+                if self.ppu_viewer.as_ref().map( |viewer| viewer.owns_window( window_id ) ).unwrap_or( false ) {
+                    self.ppu_viewer = None;
+                } else if self.ppu_event_window.as_ref().map( |window| window.owns_window( window_id ) ).unwrap_or( false ) {
+                    self.toggle_ppu_event_viewer();
+                } else {
+                    self.running = false;
+                }
+            },
             Event::KeyDown { keycode: keycode @ Some( .. ), .. } => {
                 if let Some( button ) = keycode_to_button( keycode.unwrap() ) {
                     if !self.replaying {
                         self.nes.press( ControllerPort::First, button );
                     }
+                } else if keycode == Some( Keycode::F2 ) {
+                    self.toggle_ppu_viewer();
+                } else if keycode == Some( Keycode::F3 ) {
+                    if let Some( viewer ) = self.ppu_viewer.as_mut() {
+                        viewer.next_pattern_table_palette();
+                    }
+                } else if keycode == Some( Keycode::F4 ) {
+                    self.toggle_ppu_event_viewer();
+                } else if keycode == Some( Keycode::F5 ) {
+                    if let Some( log ) = self.nes.ppu_event_log.as_ref() {
+                        print_ppu_events( log.events() );
+                    }
+                } else if keycode == Some( Keycode::F6 ) {
+                    self.toggle_ntsc_filter();
+                } else if keycode == Some( Keycode::F7 ) {
+                    self.toggle_apu_logging();
+                } else if keycode == Some( Keycode::F8 ) {
+                    self.toggle_audio_recording();
+                } else if keycode == Some( Keycode::F9 ) {
+                    self.switch_disk_side();
+                } else if keycode == Some( Keycode::F11 ) {
+                    print_mapper_info( &self.nes.mapper_info() );
                 } else if keycode == Some( Keycode::F10 ) {
                     self.generate_testfile();
+                } else if keycode == Some( Keycode::F12 ) {
+                    self.save_screenshot();
                 }
             },
             Event::KeyUp { keycode: keycode @ Some( .. ), .. } => {
@@ -267,10 +605,36 @@ impl UserInterface {
 
         let last_framebuffer = self.last_framebuffer.take().unwrap();
         let framebuffer = self.nes.swap_framebuffer( last_framebuffer );
-        framebuffer.convert_to_abgr( &self.palette, &mut self.image_buffer );
+        let (width, height) = self.source_size();
+        {
+            let output: &mut [u32] = if self.scaler.is_some() {
+                self.scaler_input.resize( width * height, 0 );
+                &mut self.scaler_input[..]
+            } else {
+                self.image_buffer.as_mut()
+            };
+
+            if let Some( filter ) = self.ntsc_filter.as_mut() {
+                filter.apply( &framebuffer, output );
+            } else {
+                framebuffer.convert_to_abgr( &self.palette, output );
+            }
+        }
+
+        if let Some( scaler ) = self.scaler {
+            scaler.apply( &self.scaler_input, width, height, &mut self.image_buffer );
+        }
 
         self.texture.update( &self.image_buffer );
         self.last_framebuffer = Some( framebuffer );
+
+        if let Some( viewer ) = self.ppu_viewer.as_mut() {
+            viewer.update( &self.nes.state, &self.palette );
+        }
+
+        if let (Some( window ), Some( log )) = (self.ppu_event_window.as_mut(), self.nes.ppu_event_log.as_ref()) {
+            window.update( log, self.last_framebuffer.as_ref().unwrap(), &self.palette );
+        }
     }
 
     pub fn run( &mut self ) {
@@ -288,11 +652,104 @@ impl UserInterface {
                 continue;
             }
 
+            if arg == "--ntsc" {
+                if self.ntsc_filter.is_none() {
+                    self.toggle_ntsc_filter();
+                }
+                continue;
+            }
+
+            if arg == "--ppu-viewer" {
+                if self.ppu_viewer.is_none() {
+                    self.toggle_ppu_viewer();
+                }
+                continue;
+            }
+
+            if arg.starts_with( "--scaler=" ) {
+                let name = &arg[ "--scaler=".len().. ];
+                match Scaler::from_name( name ) {
+                    Some( scaler ) => {
+                        self.scaler = Some( scaler );
+                        self.recreate_texture();
+                    },
+                    None => println!( "Unknown scaler '{}'; available scalers: scale2x, scale3x, xbr, crt", name )
+                }
+                continue;
+            }
+
+            if arg == "--crop-overscan" {
+                self.screenshot_overscan = Overscan::ntsc();
+                continue;
+            }
+
+            if arg == "--generated-palette" {
+                self.palette = Palette::generate( &PaletteSettings::default() );
+                continue;
+            }
+
+            if arg.starts_with( "--rom-database=" ) {
+                let path = &arg[ "--rom-database=".len().. ];
+                println!( "Loading the ROM database from '{}'...", path );
+                let database = nes::RomDatabase::parse( &std::fs::read_to_string( path ).unwrap() ).unwrap();
+                println!( "Loaded {} ROM database entries", database.len() );
+                self.nes.set_rom_database( database );
+                continue;
+            }
+
+            if arg.starts_with( "--fds-bios=" ) {
+                self.fds_bios_filename = Some( PathBuf::from( &arg[ "--fds-bios=".len().. ] ) );
+                continue;
+            }
+
+            if arg.ends_with( ".pal" ) {
+                println!( "Loading palette '{}'...", arg );
+                self.palette = Palette::load( &arg ).unwrap();
+                continue;
+            }
+
+            if arg == "--record-float" {
+                self.audio_recording_format = match self.audio_recording_format {
+                    AudioFormat::Raw16 | AudioFormat::RawFloat => AudioFormat::RawFloat,
+                    AudioFormat::Wav16 | AudioFormat::WavFloat => AudioFormat::WavFloat
+                };
+                continue;
+            }
+
+            if arg == "--record-raw" {
+                self.audio_recording_format = match self.audio_recording_format {
+                    AudioFormat::Raw16 | AudioFormat::Wav16 => AudioFormat::Raw16,
+                    AudioFormat::RawFloat | AudioFormat::WavFloat => AudioFormat::RawFloat
+                };
+                continue;
+            }
+
             println!( "Loading '{}'...", arg );
             let data = std::fs::read( &arg ).unwrap();
             if data.len() >= 16 && u32::from_le_bytes( [data[0], data[1], data[2], data[3]] ) == 0x1a53454e {
                 self.rom_filename = PathBuf::from( &arg );
-                self.nes.load_rom( &data ).unwrap();
+                match self.load_patch() {
+                    Some( patch ) => self.nes.load_rom_with_patches( &data, &[&patch] ).unwrap(),
+                    None => self.nes.load_rom( &data ).unwrap()
+                }
+
+                if let Some( entry ) = self.nes.rom_database_entry() {
+                    println!( "Found in the ROM database: {}", entry.title );
+                }
+
+                self.restore_flash();
+
+                self.is_emulating = true;
+                self.image_buffer.clear();
+                self.texture.update( &self.image_buffer );
+            } else if data.starts_with( b"FDS\x1A" ) || data.starts_with( b"\x01*NINTENDO-HVC*" ) {
+                // Unless specified otherwise the BIOS is expected to be next to the disk image.
+                self.rom_filename = PathBuf::from( &arg );
+                let bios_filename = self.fds_bios_filename.clone().unwrap_or_else( || self.rom_filename.with_file_name( "disksys.rom" ) );
+                println!( "Loading the FDS BIOS from '{}'...", bios_filename.display() );
+                let bios = std::fs::read( &bios_filename ).unwrap();
+                self.nes.load_fds( &bios, &data ).unwrap();
+                self.restore_disk();
 
                 self.is_emulating = true;
                 self.image_buffer.clear();
@@ -379,6 +836,9 @@ impl UserInterface {
                 self.frame_limiter.end();
             }
         }
+
+        self.save_disk();
+        self.save_flash();
     }
 
     fn run_for_a_frame( &mut self ) {
@@ -395,10 +855,17 @@ impl UserInterface {
             self.renderer.blit( &self.texture );
         }
 
+        if let Some( recorder ) = self.audio_recorder.as_mut() {
+            if let Err( error ) = recorder.push_samples( self.nes.audio_buffer.as_slice() ) {
+                println!( "Failed to record audio: {}", error );
+                self.audio_recorder = None;
+            }
+        }
+
         if let Some( audio_device ) = self.audio_device.as_mut() {
             audio_device.queue( self.nes.audio_buffer.as_slice() );
-            self.nes.audio_buffer.clear();
         }
+        self.nes.audio_buffer.clear();
 
         if self.audio_device.as_ref().map( |device| device.size() < 44100 / 2 ).unwrap_or( false ) && self.nes.frame % 2 == 0 {
             return;
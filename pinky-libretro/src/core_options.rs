@@ -0,0 +1,143 @@
+use std::ffi::CStr;
+use std::ptr;
+use std::path::PathBuf;
+
+use libretro_backend::libc;
+use libretro_backend::libretro_sys;
+
+use nes::{RenderingOptions, AudioOptions, Scaler};
+
+use exports::call_environment;
+
+/*
+    Every option is a NUL terminated key followed by a NUL terminated
+    description in the format expected by the frontend, that is
+    "Human readable name; first value|second value|...", where the
+    first value is the default one.
+*/
+const OPTIONS: &'static [(&'static [u8], &'static [u8])] = &[
+    (b"pinky_video_filter\0", b"Video filter; None|NTSC composite|Scale2x|Scale3x|xBR 2x|CRT scanlines\0"),
+    (b"pinky_palette\0", b"Palette; Default|Generated|Generated (vivid)|Generated (grayscale)|Custom (pinky.pal in the system directory)\0"),
+    (b"pinky_show_background\0", b"Show background; enabled|disabled\0"),
+    (b"pinky_show_sprites\0", b"Show sprites; enabled|disabled\0"),
+    (b"pinky_clip_left_edge\0", b"Hide the leftmost 8 pixels when requested by the game; enabled|disabled\0"),
+    (b"pinky_sprite_limit\0", b"Limit sprites to 8 per scanline; enabled|disabled\0"),
+    (b"pinky_namco_163_multiplexing\0", b"Namco 163 channel multiplexing; enabled|disabled\0")
+];
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PaletteKind {
+    Default,
+    Generated,
+    GeneratedVivid,
+    GeneratedGrayscale,
+    Custom
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum VideoFilter {
+    None,
+    Ntsc,
+    Scaler( Scaler )
+}
+
+pub fn register() {
+    let mut variables: Vec< libretro_sys::Variable > = OPTIONS.iter().map( |&(key, value)| {
+        libretro_sys::Variable {
+            key: key.as_ptr() as *const libc::c_char,
+            value: value.as_ptr() as *const libc::c_char
+        }
+    }).collect();
+
+    variables.push( libretro_sys::Variable {
+        key: ptr::null(),
+        value: ptr::null()
+    });
+
+    unsafe {
+        call_environment( libretro_sys::ENVIRONMENT_SET_VARIABLES, &mut variables[ 0 ] );
+    }
+}
+
+pub fn have_changed() -> bool {
+    let mut updated = false;
+    unsafe {
+        call_environment( libretro_sys::ENVIRONMENT_GET_VARIABLE_UPDATE, &mut updated ) && updated
+    }
+}
+
+fn get( key: &'static [u8] ) -> Option< String > {
+    let mut variable = libretro_sys::Variable {
+        key: key.as_ptr() as *const libc::c_char,
+        value: ptr::null()
+    };
+
+    unsafe {
+        if call_environment( libretro_sys::ENVIRONMENT_GET_VARIABLE, &mut variable ) == false || variable.value.is_null() {
+            return None;
+        }
+
+        Some( CStr::from_ptr( variable.value ).to_string_lossy().into_owned() )
+    }
+}
+
+pub fn video_filter() -> VideoFilter {
+    match get( b"pinky_video_filter\0" ).as_ref().map( |value| value.as_str() ) {
+        Some( "NTSC composite" ) => VideoFilter::Ntsc,
+        Some( "Scale2x" ) => VideoFilter::Scaler( Scaler::Scale2x ),
+        Some( "Scale3x" ) => VideoFilter::Scaler( Scaler::Scale3x ),
+        Some( "xBR 2x" ) => VideoFilter::Scaler( Scaler::Xbr2x ),
+        Some( "CRT scanlines" ) => VideoFilter::Scaler( Scaler::CrtScanlines ),
+        _ => VideoFilter::None
+    }
+}
+
+pub fn palette() -> PaletteKind {
+    match get( b"pinky_palette\0" ).as_ref().map( |value| value.as_str() ) {
+        Some( "Generated" ) => PaletteKind::Generated,
+        Some( "Generated (vivid)" ) => PaletteKind::GeneratedVivid,
+        Some( "Generated (grayscale)" ) => PaletteKind::GeneratedGrayscale,
+        Some( value ) if value.starts_with( "Custom" ) => PaletteKind::Custom,
+        _ => PaletteKind::Default
+    }
+}
+
+fn is_enabled( key: &'static [u8] ) -> bool {
+    get( key ).map( |value| value != "disabled" ).unwrap_or( true )
+}
+
+pub fn rendering_options() -> RenderingOptions {
+    RenderingOptions {
+        show_background: is_enabled( b"pinky_show_background\0" ),
+        show_sprites: is_enabled( b"pinky_show_sprites\0" ),
+        clip_leftmost_pixels: is_enabled( b"pinky_clip_left_edge\0" ),
+        sprite_limit: is_enabled( b"pinky_sprite_limit\0" )
+    }
+}
+
+pub fn audio_options() -> AudioOptions {
+    AudioOptions {
+        namco_163_multiplexing: is_enabled( b"pinky_namco_163_multiplexing\0" )
+    }
+}
+
+fn get_directory( command: libc::c_uint ) -> Option< PathBuf > {
+    let mut path: *const libc::c_char = ptr::null();
+    unsafe {
+        if call_environment( command, &mut path ) == false || path.is_null() {
+            return None;
+        }
+
+        Some( PathBuf::from( CStr::from_ptr( path ).to_string_lossy().into_owned() ) )
+    }
+}
+
+// Where the FDS BIOS, the custom palette and the ROM database are.
+pub fn system_directory() -> Option< PathBuf > {
+    get_directory( libretro_sys::ENVIRONMENT_GET_SYSTEM_DIRECTORY )
+}
+
+// Where the modified disks and flash memory are saved; next to the game if the frontend doesn't have one.
+pub fn save_directory() -> Option< PathBuf > {
+    get_directory( libretro_sys::ENVIRONMENT_GET_SAVE_DIRECTORY )
+}
@@ -0,0 +1,205 @@
+use std::mem;
+
+use libretro_backend;
+use libretro_backend::libc;
+use libretro_backend::libretro_sys;
+
+use core_options;
+use PinkyCore;
+
+/*
+    These are the same exports as the ones generated by the `libretro_core!` macro,
+    except that we also keep the environment and video refresh callbacks for ourselves,
+    since the backend doesn't expose them and we need them to support core options
+    and to be able to change the size of the video frames at runtime.
+*/
+
+static mut ENVIRONMENT_CALLBACK: Option< libretro_sys::EnvironmentFn > = None;
+static mut VIDEO_REFRESH_CALLBACK: Option< libretro_sys::VideoRefreshFn > = None;
+
+pub unsafe fn call_environment< T >( command: libc::c_uint, data: &mut T ) -> bool {
+    match ENVIRONMENT_CALLBACK {
+        Some( callback ) => callback( command, data as *mut T as *mut libc::c_void ),
+        None => false
+    }
+}
+
+// Tells the frontend that the size of the video frames has changed;
+// the maximum size can't be changed this way, so it's ignored.
+pub fn set_geometry( width: u32, height: u32, aspect_ratio: f32 ) -> bool {
+    let mut geometry = libretro_sys::GameGeometry {
+        base_width: width,
+        base_height: height,
+        max_width: width,
+        max_height: height,
+        aspect_ratio: aspect_ratio
+    };
+
+    unsafe {
+        call_environment( libretro_sys::ENVIRONMENT_SET_GEOMETRY, &mut geometry )
+    }
+}
+
+pub fn upload_video_frame( data: &[u32], width: usize, height: usize ) {
+    assert_eq!( data.len(), width * height );
+    unsafe {
+        if let Some( callback ) = VIDEO_REFRESH_CALLBACK {
+            callback( data.as_ptr() as *const libc::c_void, width as libc::c_uint, height as libc::c_uint, width * mem::size_of::< u32 >() );
+        }
+    }
+}
+
+static mut LIBRETRO_INSTANCE: *mut libretro_backend::Retro< PinkyCore > = 0 as *mut libretro_backend::Retro< PinkyCore >;
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> libc::c_uint {
+    return libretro_sys::API_VERSION;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_init() {
+    assert!( LIBRETRO_INSTANCE.is_null() );
+    let retro = libretro_backend::construct::< PinkyCore >();
+    LIBRETRO_INSTANCE = Box::into_raw( Box::new( retro ) );
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_deinit() {
+    assert!( LIBRETRO_INSTANCE.is_null() == false );
+    let instance = Box::from_raw( LIBRETRO_INSTANCE );
+    LIBRETRO_INSTANCE = 0 as *mut _;
+    ::std::mem::drop( instance );
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_set_environment( callback: libretro_sys::EnvironmentFn ) {
+    ENVIRONMENT_CALLBACK = Some( callback );
+    core_options::register();
+    libretro_backend::Retro::< PinkyCore >::on_set_environment( callback )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_set_video_refresh( callback: libretro_sys::VideoRefreshFn ) {
+    assert!( LIBRETRO_INSTANCE.is_null() == false );
+    VIDEO_REFRESH_CALLBACK = Some( callback );
+    (&mut *LIBRETRO_INSTANCE).on_set_video_refresh( callback )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_set_audio_sample( callback: libretro_sys::AudioSampleFn ) {
+    assert!( LIBRETRO_INSTANCE.is_null() == false );
+    (&mut *LIBRETRO_INSTANCE).on_set_audio_sample( callback )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_set_audio_sample_batch( callback: libretro_sys::AudioSampleBatchFn ) {
+    assert!( LIBRETRO_INSTANCE.is_null() == false );
+    (&mut *LIBRETRO_INSTANCE).on_set_audio_sample_batch( callback )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_set_input_poll( callback: libretro_sys::InputPollFn ) {
+    assert!( LIBRETRO_INSTANCE.is_null() == false );
+    (&mut *LIBRETRO_INSTANCE).on_set_input_poll( callback )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_set_input_state( callback: libretro_sys::InputStateFn ) {
+    assert!( LIBRETRO_INSTANCE.is_null() == false );
+    (&mut *LIBRETRO_INSTANCE).on_set_input_state( callback )
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_system_info( info: *mut libretro_sys::SystemInfo ) {
+    libretro_backend::Retro::< PinkyCore >::on_get_system_info( info )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info( info: *mut libretro_sys::SystemAvInfo ) {
+    assert!( LIBRETRO_INSTANCE.is_null() == false );
+    (&mut *LIBRETRO_INSTANCE).on_get_system_av_info( info )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_set_controller_port_device( port: libc::c_uint, device: libc::c_uint ) {
+    assert!( LIBRETRO_INSTANCE.is_null() == false );
+    (&mut *LIBRETRO_INSTANCE).on_set_controller_port_device( port, device )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_reset() {
+    assert!( LIBRETRO_INSTANCE.is_null() == false );
+    (&mut *LIBRETRO_INSTANCE).on_reset()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_run() {
+    assert!( LIBRETRO_INSTANCE.is_null() == false );
+    (&mut *LIBRETRO_INSTANCE).on_run()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize_size() -> libc::size_t {
+    assert!( LIBRETRO_INSTANCE.is_null() == false );
+    (&mut *LIBRETRO_INSTANCE).on_serialize_size()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize( data: *mut libc::c_void, size: libc::size_t ) -> bool {
+    assert!( LIBRETRO_INSTANCE.is_null() == false );
+    (&mut *LIBRETRO_INSTANCE).on_serialize( data, size )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize( data: *const libc::c_void, size: libc::size_t ) -> bool {
+    assert!( LIBRETRO_INSTANCE.is_null() == false );
+    (&mut *LIBRETRO_INSTANCE).on_unserialize( data, size )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_cheat_reset() {
+    assert!( LIBRETRO_INSTANCE.is_null() == false );
+    (&mut *LIBRETRO_INSTANCE).on_cheat_reset()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_cheat_set( index: libc::c_uint, is_enabled: bool, code: *const libc::c_char ) {
+    assert!( LIBRETRO_INSTANCE.is_null() == false );
+    (&mut *LIBRETRO_INSTANCE).on_cheat_set( index, is_enabled, code )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game( game: *const libretro_sys::GameInfo ) -> bool {
+    assert!( LIBRETRO_INSTANCE.is_null() == false );
+    (&mut *LIBRETRO_INSTANCE).on_load_game( game )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game_special( game_type: libc::c_uint, info: *const libretro_sys::GameInfo, num_info: libc::size_t ) -> bool {
+    assert!( LIBRETRO_INSTANCE.is_null() == false );
+    (&mut *LIBRETRO_INSTANCE).on_load_game_special( game_type, info, num_info )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_unload_game() {
+    assert!( LIBRETRO_INSTANCE.is_null() == false );
+    (&mut *LIBRETRO_INSTANCE).on_unload_game()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_region() -> libc::c_uint {
+    assert!( LIBRETRO_INSTANCE.is_null() == false );
+    (&mut *LIBRETRO_INSTANCE).on_get_region()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_memory_data( id: libc::c_uint ) -> *mut libc::c_void {
+    assert!( LIBRETRO_INSTANCE.is_null() == false );
+    (&mut *LIBRETRO_INSTANCE).on_get_memory_data( id )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_memory_size( id: libc::c_uint ) -> libc::size_t {
+    assert!( LIBRETRO_INSTANCE.is_null() == false );
+    (&mut *LIBRETRO_INSTANCE).on_get_memory_size( id )
+}
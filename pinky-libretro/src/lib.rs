@@ -1,16 +1,28 @@
 extern crate nes;
 extern crate emumisc;
 
-#[macro_use]
 extern crate libretro_backend;
 
+#[macro_use]
+extern crate log;
+
+mod core_options;
+mod exports;
+mod logger;
+
 use libretro_backend::{CoreInfo, AudioVideoInfo, PixelFormat, GameData, LoadGameResult, Region, RuntimeHandle, JoypadButton};
-use nes::{Palette, ControllerPort, Button};
+use nes::{Palette, PaletteSettings, ControllerPort, Button, NtscFilter, NtscSettings, Scaler};
+
+use core_options::{VideoFilter, PaletteKind};
 
-struct PinkyCore {
+pub struct PinkyCore {
     state: nes::State,
     palette: [u32; 512],
-    framebuffer: [u32; 256 * 240],
+    palette_kind: PaletteKind,
+    framebuffer: Vec< u32 >,
+    ntsc_filter: Option< NtscFilter >,
+    scaler: Option< Scaler >,
+    scaler_input: Vec< u32 >,
     audio_buffer: Vec< i16 >,
     game_data: Option< GameData >
 }
@@ -53,16 +65,206 @@ fn palette_to_argb( palette: &Palette ) -> [u32; 512] {
     output
 }
 
+fn load_palette( kind: PaletteKind ) -> Palette {
+    let generate = |settings: PaletteSettings| Palette::generate( &settings );
+    match kind {
+        PaletteKind::Default => Palette::default(),
+        PaletteKind::Generated => generate( PaletteSettings::default() ),
+        PaletteKind::GeneratedVivid => generate( PaletteSettings {
+            saturation: 0.3,
+            contrast: 0.2,
+            .. PaletteSettings::default()
+        }),
+        PaletteKind::GeneratedGrayscale => generate( PaletteSettings {
+            saturation: -1.0,
+            .. PaletteSettings::default()
+        }),
+        PaletteKind::Custom => {
+            let path = core_options::system_directory().map( |directory| directory.join( "pinky.pal" ) );
+            match path.map( |path| Palette::load( &path ).map_err( |error| (path, error) ) ) {
+                Some( Ok( palette ) ) => palette,
+                Some( Err( (path, error) ) ) => {
+                    warn!( "Failed to load the palette from {:?}: {}", path, error );
+                    Palette::default()
+                },
+                None => {
+                    warn!( "Failed to load the custom palette: no system directory" );
+                    Palette::default()
+                }
+            }
+        }
+    }
+}
+
 impl PinkyCore {
     fn new() -> PinkyCore {
+        logger::initialize();
+
         PinkyCore {
             state: nes::State::new(),
             palette: palette_to_argb( &Palette::default() ),
-            framebuffer: [0; 256 * 240],
+            palette_kind: PaletteKind::Default,
+            framebuffer: vec![ 0; 256 * 240 ],
+            ntsc_filter: None,
+            scaler: None,
+            scaler_input: vec![ 0; 256 * 240 ],
             audio_buffer: Vec::with_capacity( 44100 ),
             game_data: None
         }
     }
+
+    fn apply_core_options( &mut self ) {
+        self.state.set_rendering_options( core_options::rendering_options() );
+        self.state.set_audio_options( core_options::audio_options() );
+
+        let palette_kind = core_options::palette();
+        if palette_kind != self.palette_kind {
+            self.palette_kind = palette_kind;
+            self.palette = palette_to_argb( &load_palette( palette_kind ) );
+        }
+
+        let video_filter = core_options::video_filter();
+        self.scaler = match video_filter {
+            VideoFilter::Scaler( scaler ) => Some( scaler ),
+            VideoFilter::None | VideoFilter::Ntsc => None
+        };
+
+        if video_filter == VideoFilter::Ntsc && self.ntsc_filter.is_none() {
+            self.ntsc_filter = Some( NtscFilter::new( NtscSettings::default() ) );
+        } else if video_filter != VideoFilter::Ntsc {
+            self.ntsc_filter = None;
+        }
+
+        // The frontend is told the size of the video frames when the game is loaded,
+        // and through `exports::set_geometry` when it changes afterwards.
+        let (width, height) = self.video_size();
+        self.framebuffer = vec![ 0; width * height ];
+    }
+
+    fn load( &mut self, data: &[u8], path: Option< &str > ) -> Result< (), nes::LoadError > {
+        let is_disk_image = data.starts_with( b"FDS\x1A" ) || data.starts_with( b"\x01*NINTENDO-HVC*" );
+        if is_disk_image == false {
+            self.load_rom_database();
+
+            // A patch with the same name as the ROM is applied automatically. We ask
+            // the frontend for the path of the game instead of its contents, so it
+            // doesn't apply the patch itself and the ROM database gets to see the
+            // unpatched ROM.
+            let patch = path.and_then( |path| {
+                ["ips", "ups", "bps"].iter()
+                    .map( |extension| std::path::Path::new( path ).with_extension( extension ) )
+                    .find_map( |path| std::fs::read( path ).ok() )
+            });
+
+            return match patch {
+                Some( patch ) => nes::Interface::load_rom_with_patches( self, data, &[&patch] ),
+                None => nes::Interface::load_rom( self, data )
+            };
+        }
+
+        // The Famicom Disk System's BIOS has to be supplied by the user.
+        let path = core_options::system_directory().map( |directory| directory.join( "disksys.rom" ) );
+        let bios = match path.as_ref().map( std::fs::read ) {
+            Some( Ok( bios ) ) => bios,
+            Some( Err( error ) ) => {
+                warn!( "Failed to load the Famicom Disk System BIOS from {:?}: {}", path.unwrap(), error );
+                return Err( nes::LoadError::new( "missing Famicom Disk System BIOS" ) );
+            },
+            None => {
+                warn!( "Failed to load the Famicom Disk System BIOS: no system directory" );
+                return Err( nes::LoadError::new( "missing Famicom Disk System BIOS" ) );
+            }
+        };
+
+        nes::Interface::load_fds( self, &bios, data )
+    }
+
+    // The NES 2.0 XML database can optionally be put into the system directory,
+    // in which case it's used on top of the builtin one to correct the ROM headers.
+    fn load_rom_database( &mut self ) {
+        let path = match core_options::system_directory() {
+            Some( directory ) => directory.join( "nes20db.xml" ),
+            None => return
+        };
+
+        let xml = match std::fs::read_to_string( &path ) {
+            Ok( xml ) => xml,
+            Err( _ ) => return
+        };
+
+        match nes::RomDatabase::parse( &xml ) {
+            Ok( database ) => nes::Interface::set_rom_database( self, database ),
+            Err( error ) => warn!( "Failed to load the ROM database from {:?}: {}", path, error )
+        }
+    }
+
+    // The flash memory of the self-flashing cartridges and the modified disk images
+    // are kept in separate files, so that the original game is never overwritten.
+    fn save_path( &self, extension: &str ) -> Option< std::path::PathBuf > {
+        let path = std::path::Path::new( self.game_data.as_ref()?.path()? ).with_extension( extension );
+        match core_options::save_directory() {
+            Some( directory ) => path.file_name().map( |name| directory.join( name ) ),
+            None => Some( path )
+        }
+    }
+
+    fn restore_disk( &mut self ) {
+        if nes::Interface::disk_image( self ).is_none() {
+            return;
+        }
+
+        if let Some( data ) = self.save_path( "fds.sav" ).and_then( |path| std::fs::read( path ).ok() ) {
+            if let Err( error ) = nes::Interface::restore_disk_image( self, &data ) {
+                warn!( "Failed to restore the disk image: {}", error );
+            }
+        }
+    }
+
+    fn save_disk( &mut self ) {
+        if nes::Interface::is_disk_modified( self ) == false {
+            return;
+        }
+
+        if let (Some( path ), Some( image )) = (self.save_path( "fds.sav" ), nes::Interface::disk_image( self )) {
+            if let Err( error ) = std::fs::write( &path, image ) {
+                error!( "Failed to save the disk image to {:?}: {}", path, error );
+            }
+        }
+    }
+
+    fn restore_flash( &mut self ) {
+        if nes::Interface::flash_memory( self ).is_none() {
+            return;
+        }
+
+        if let Some( data ) = self.save_path( "flash" ).and_then( |path| std::fs::read( path ).ok() ) {
+            if let Err( error ) = nes::Interface::restore_flash_memory( self, &data ) {
+                warn!( "Failed to restore the flash memory: {}", error );
+            }
+        }
+    }
+
+    fn save_flash( &mut self ) {
+        if nes::Interface::is_flash_modified( self ) == false {
+            return;
+        }
+
+        if let (Some( path ), Some( data )) = (self.save_path( "flash" ), nes::Interface::flash_memory( self )) {
+            if let Err( error ) = std::fs::write( &path, data ) {
+                error!( "Failed to save the flash memory to {:?}: {}", path, error );
+            }
+        }
+    }
+
+    fn video_size( &self ) -> (usize, usize) {
+        if self.ntsc_filter.is_some() {
+            (NtscFilter::OUTPUT_WIDTH, NtscFilter::OUTPUT_HEIGHT)
+        } else if let Some( scaler ) = self.scaler {
+            scaler.output_size( 256, 240 )
+        } else {
+            (256, 240)
+        }
+    }
 }
 
 impl Default for PinkyCore {
@@ -75,6 +277,9 @@ impl libretro_backend::Core for PinkyCore {
     fn info() -> CoreInfo {
         CoreInfo::new( "Pinky", env!( "CARGO_PKG_VERSION" ) )
             .supports_roms_with_extension( "nes" )
+            .supports_roms_with_extension( "fds" )
+            // The patches are applied by us; see `load`.
+            .requires_path_when_loading_roms()
     }
 
     fn on_load_game( &mut self, game_data: GameData ) -> LoadGameResult {
@@ -82,16 +287,17 @@ impl libretro_backend::Core for PinkyCore {
             return LoadGameResult::Failed( game_data );
         }
 
-        let result = if let Some( data ) = game_data.data() {
-            nes::Interface::load_rom( self, data )
-        } else if let Some( path ) = game_data.path() {
+        let result = if let Some( path ) = game_data.path() {
             let data = match std::fs::read( path ) {
                 Ok( data ) => data,
                 Err( _ ) => {
                     return LoadGameResult::Failed( game_data );
                 }
             };
-            nes::Interface::load_rom( self, &data )
+            self.load( &data, Some( path ) )
+        } else if let Some( data ) = game_data.data() {
+            // The data might have been already patched by the frontend.
+            self.load( data, None )
         } else {
             unreachable!();
         };
@@ -99,8 +305,15 @@ impl libretro_backend::Core for PinkyCore {
         match result {
             Ok( _ ) => {
                 self.game_data = Some( game_data );
+                self.restore_disk();
+                self.restore_flash();
+                self.apply_core_options();
+
+                let (width, height) = self.video_size();
                 let av_info = AudioVideoInfo::new()
-                    .video( 256, 240, 60.0, PixelFormat::ARGB8888 )
+                    .video( width as u32, height as u32, 60.0, PixelFormat::ARGB8888 )
+                    .max_video_size( 256 * 3, 240 * 3 )
+                    .aspect_ratio( 4.0 / 3.0 )
                     .audio( 44100.0 )
                     .region( Region::NTSC );
 
@@ -113,10 +326,23 @@ impl libretro_backend::Core for PinkyCore {
     }
 
     fn on_unload_game( &mut self ) -> GameData {
+        self.save_disk();
+        self.save_flash();
         self.game_data.take().unwrap()
     }
 
     fn on_run( &mut self, handle: &mut RuntimeHandle ) {
+        if core_options::have_changed() {
+            let old_video_size = self.video_size();
+            self.apply_core_options();
+
+            // The video filters have different output sizes.
+            let (width, height) = self.video_size();
+            if (width, height) != old_video_size {
+                exports::set_geometry( width as u32, height as u32, 4.0 / 3.0 );
+            }
+        }
+
         macro_rules! update_controllers {
             ( $( $button:ident ),+ ) => (
                 $(
@@ -129,17 +355,32 @@ impl libretro_backend::Core for PinkyCore {
         update_controllers!( A, B, Start, Select, Left, Up, Right, Down );
 
         if let Err( error ) = nes::Interface::execute_for_a_frame( self ) {
-            println!( "Execution error: {}", error );
+            error!( "Execution error: {}", error );
             return;
         }
 
-        let framebuffer = self.state.framebuffer();
-        for (pixel_in, pixel_out) in framebuffer.iter().zip( self.framebuffer.iter_mut() ) {
-            *pixel_out = self.palette[ pixel_in.full_color_index() as usize ];
+        if let Some( filter ) = self.ntsc_filter.as_mut() {
+            filter.apply( self.state.framebuffer(), &mut self.framebuffer[..] );
+
+            // The filter outputs ABGR, while we need ARGB.
+            for pixel in self.framebuffer.iter_mut() {
+                let value = *pixel;
+                *pixel = (value & 0xFF00FF00) | ((value >> 16) & 0xFF) | ((value & 0xFF) << 16);
+            }
+        } else {
+            let output = if self.scaler.is_some() { &mut self.scaler_input } else { &mut self.framebuffer };
+            let framebuffer = self.state.framebuffer();
+            for (pixel_in, pixel_out) in framebuffer.iter().zip( output.iter_mut() ) {
+                *pixel_out = self.palette[ pixel_in.full_color_index() as usize ];
+            }
+
+            if let Some( scaler ) = self.scaler {
+                scaler.apply( &self.scaler_input, 256, 240, &mut self.framebuffer[..] );
+            }
         }
 
-        let video_frame = emumisc::as_bytes( &self.framebuffer[..] );
-        handle.upload_video_frame( video_frame );
+        let (width, height) = self.video_size();
+        exports::upload_video_frame( &self.framebuffer[..], width, height );
 
         handle.upload_audio_frame( &self.audio_buffer[..] );
         self.audio_buffer.clear();
@@ -149,5 +390,3 @@ impl libretro_backend::Core for PinkyCore {
         nes::Interface::soft_reset( self );
     }
 }
-
-libretro_core!( PinkyCore );
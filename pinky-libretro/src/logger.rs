@@ -0,0 +1,31 @@
+use log::{self, Log, Metadata, Record, LevelFilter};
+
+/*
+    The backend doesn't give us access to the frontend's log interface,
+    so the messages end up on the standard error like the frontend's own.
+*/
+
+struct Logger;
+
+static LOGGER: Logger = Logger;
+
+impl Log for Logger {
+    fn enabled( &self, metadata: &Metadata ) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log( &self, record: &Record ) {
+        if self.enabled( record.metadata() ) {
+            eprintln!( "[pinky] [{}] {}", record.level(), record.args() );
+        }
+    }
+
+    fn flush( &self ) {}
+}
+
+pub fn initialize() {
+    // This fails if the logger was already set, e.g. when the core is loaded again.
+    if log::set_logger( &LOGGER ).is_ok() {
+        log::set_max_level( LevelFilter::Info );
+    }
+}
@@ -63,7 +63,8 @@ fn palette_to_abgr( palette: &nes::Palette ) -> [u32; 512] {
 struct PinkyWeb {
     state: nes::State,
     palette: [u32; 512],
-    framebuffer: [u32; 256 * 240],
+    framebuffer: Vec< u32 >,
+    ntsc_filter: Option< nes::NtscFilter >,
     audio_buffer: Vec< f32 >,
     audio_chunk_counter: u32,
     audio_underrun: Option< usize >,
@@ -248,9 +249,12 @@ fn setup_webgl( canvas: &Element ) -> Value {
 
         var texture = gl.createTexture();
         gl.bindTexture( gl.TEXTURE_2D, texture );
-        gl.texImage2D( gl.TEXTURE_2D, 0, gl.RGBA, 256, 256, 0, gl.RGBA, gl.UNSIGNED_BYTE, new Uint8Array( 256 * 256 * 4 ) );
+        // The texture is wide enough to also fit the output of the NTSC filter.
+        gl.texImage2D( gl.TEXTURE_2D, 0, gl.RGBA, 1024, 256, 0, gl.RGBA, gl.UNSIGNED_BYTE, new Uint8Array( 1024 * 256 * 4 ) );
         gl.texParameteri( gl.TEXTURE_2D, gl.TEXTURE_MAG_FILTER, gl.NEAREST );
         gl.texParameteri( gl.TEXTURE_2D, gl.TEXTURE_MIN_FILTER, gl.NEAREST );
+        gl.texParameteri( gl.TEXTURE_2D, gl.TEXTURE_WRAP_S, gl.CLAMP_TO_EDGE );
+        gl.texParameteri( gl.TEXTURE_2D, gl.TEXTURE_WRAP_T, gl.CLAMP_TO_EDGE );
 
         var vertex_buffer = gl.createBuffer();
         gl.bindBuffer( gl.ARRAY_BUFFER, vertex_buffer );
@@ -265,14 +269,18 @@ fn setup_webgl( canvas: &Element ) -> Value {
 
         var texcoord_buffer = gl.createBuffer();
         gl.bindBuffer( gl.ARRAY_BUFFER, texcoord_buffer );
-        var texcoords = [
-            0.0, 0.0,
-            0.0, 240.0 / 256.0,
-            1.0, 0.0,
-            1.0, 240.0 / 256.0
-        ];
-        gl.bufferData( gl.ARRAY_BUFFER, new Float32Array( texcoords ), gl.STATIC_DRAW );
-        gl.vertexAttribPointer( texcoord_attr, 2, gl.FLOAT, false, 0, 0 );
+        gl.set_texture_width = function( width ) {
+            var texcoords = [
+                0.0, 0.0,
+                0.0, 240.0 / 256.0,
+                width / 1024.0, 0.0,
+                width / 1024.0, 240.0 / 256.0
+            ];
+            gl.bindBuffer( gl.ARRAY_BUFFER, texcoord_buffer );
+            gl.bufferData( gl.ARRAY_BUFFER, new Float32Array( texcoords ), gl.STATIC_DRAW );
+            gl.vertexAttribPointer( texcoord_attr, 2, gl.FLOAT, false, 0, 0 );
+        };
+        gl.set_texture_width( 256 );
 
         var index_buffer = gl.createBuffer();
         gl.bindBuffer( gl.ELEMENT_ARRAY_BUFFER, index_buffer );
@@ -325,7 +333,8 @@ impl PinkyWeb {
         PinkyWeb {
             state: nes::State::new(),
             palette: palette_to_abgr( &nes::Palette::default() ),
-            framebuffer: [0; 256 * 240],
+            framebuffer: vec![ 0; 256 * 240 ],
+            ntsc_filter: None,
             audio_buffer: Vec::with_capacity( 44100 ),
             audio_chunk_counter: 0,
             audio_underrun: None,
@@ -375,20 +384,48 @@ impl PinkyWeb {
         }
     }
 
+    // The NTSC filter is only supported when we're using WebGL
+    // since we rely on the GPU to scale its output back down.
+    fn toggle_ntsc_filter( &mut self ) {
+        let has_webgl: bool = js!( return !!@{&self.js_ctx}.gl; ).try_into().unwrap();
+        if has_webgl == false {
+            return;
+        }
+
+        let width = if self.ntsc_filter.is_some() {
+            self.ntsc_filter = None;
+            256
+        } else {
+            self.ntsc_filter = Some( nes::NtscFilter::new( nes::NtscSettings::default() ) );
+            nes::NtscFilter::OUTPUT_WIDTH
+        };
+
+        self.framebuffer = vec![ 0; width * 240 ];
+        js! {
+            @{&self.js_ctx}.gl.set_texture_width( @{width as u32} );
+        }
+    }
+
     fn draw( &mut self ) {
         let framebuffer = self.state.framebuffer();
         if !self.paused {
-            for (pixel_in, pixel_out) in framebuffer.iter().zip( self.framebuffer.iter_mut() ) {
-                *pixel_out = self.palette[ pixel_in.full_color_index() as usize ];
+            if let Some( filter ) = self.ntsc_filter.as_mut() {
+                filter.apply( framebuffer, &mut self.framebuffer[..] );
+            } else {
+                for (pixel_in, pixel_out) in framebuffer.iter().zip( self.framebuffer.iter_mut() ) {
+                    *pixel_out = self.palette[ pixel_in.full_color_index() as usize ];
+                }
             }
         }
 
+        let width = (self.framebuffer.len() / 240) as u32;
         js! {
             var h = @{&self.js_ctx};
             var framebuffer = @{unsafe { UnsafeTypedArray::new( &self.framebuffer ) }};
+            var width = @{width};
             if( h.gl ) {
                 var data = new Uint8Array( framebuffer.buffer, framebuffer.byteOffset, framebuffer.byteLength );
-                h.gl.texSubImage2D( h.gl.TEXTURE_2D, 0, 0, 0, 256, 240, h.gl.RGBA, h.gl.UNSIGNED_BYTE, data );
+                h.gl.texSubImage2D( h.gl.TEXTURE_2D, 0, 0, 0, width, 240, h.gl.RGBA, h.gl.UNSIGNED_BYTE, data );
                 h.gl.drawElements( h.gl.TRIANGLES, 6, h.gl.UNSIGNED_SHORT, 0 );
             } else {
                 h.buffer.set( framebuffer );
@@ -398,6 +435,13 @@ impl PinkyWeb {
     }
 
     fn on_key( &mut self, key: &str, location: KeyboardLocation, is_pressed: bool ) -> bool {
+        if key == "n" {
+            if is_pressed {
+                self.toggle_ntsc_filter();
+            }
+            return true;
+        }
+
         let button = match (key, location) {
             ("Enter", _) => nes::Button::Start,
             ("Shift", KeyboardLocation::Right) => nes::Button::Select,